@@ -8,7 +8,7 @@ extern crate rustc_hex;
 use enigma_crypto::{KeyPair, hash::Keccak256};
 use serde::{Deserialize, Serialize};
 use eng_wasm::*;
-use eng_wasm_derive::pub_interface;
+use eng_wasm_derive::{pub_interface, EngAbi};
 use rustc_hex::ToHex;
 
 
@@ -19,10 +19,9 @@ static USER_ID: &str = "ID_";
 
 pub type Id = String;
 pub type Pass = String;
-pub type AccountInfo = (Id, Pass, H160);
 
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Default, EngAbi)]
 pub struct Account {
     id: Id,
     pass: Pass,
@@ -98,6 +97,14 @@ impl Contract {
         }
     }
 
+    //guard of the functions that require the id password
+    fn is_authorized_by_pass(id: &Id, pass: &Pass) -> bool {
+        match Self::authorize_by_pass(id, pass) {
+            Authorize::ACCEPT => true,
+            Authorize::DENY => false,
+        }
+    }
+
     //authorize by using id password(for login)
     fn authorize_by_pass( id: &Id, pass: &Pass) -> Authorize {
         if &Self::get_by_id(id).unwrap_or_default().pass == pass {
@@ -115,39 +122,24 @@ impl Contract {
         return Authorize::DENY;
     }
 
-    fn reset_pass(id: &Id, pass: &Pass, new_pass: &Pass) -> Result<Account, &'static str> {
-        match Self::authorize_by_pass(id, pass) {
-            Authorize::ACCEPT => {
-                let mut account = Self::get_by_id(id).unwrap();
-                account.id = id.to_string();
-                account.pass = new_pass.to_string();
-                Ok(account)
-            },
-            Authorize::DENY => {Err("Id or Pass is incorrect.")}
-        }
+    // the caller is authorized by the access control attributes of ContractInterface
+    fn reset_pass(id: &Id, new_pass: &Pass) -> Result<Account, &'static str> {
+        let mut account = Self::get_by_id(id).ok_or("Id is not registered.")?;
+        account.pass = new_pass.to_string();
+        Ok(account)
     }
 
-    fn reset_address(id: &Id, pass: &Pass, new_address: &H160) -> Result<Account, &'static str> {
-        match Self::authorize_by_pass(id, pass) {
-            Authorize::ACCEPT => {
-                let mut account = Self::get_by_id(id).unwrap();
-                account.current_address = *new_address;
-                Ok(account)
-            },
-            Authorize::DENY => {Err("Id or Pass is incorrect.")}
-        }
+    fn reset_address(id: &Id, new_address: &H160) -> Result<Account, &'static str> {
+        let mut account = Self::get_by_id(id).ok_or("Id is not registered.")?;
+        account.current_address = *new_address;
+        Ok(account)
     }
 
-    fn reset_pass_by_addr(address: H160, new_id: Id, new_pass: Pass, sig: Vec<u8>) -> Result<Account, &'static str> {
-        match Self::authorize_by_address(address, sig) {
-            Authorize::ACCEPT => {
-                let mut account = Self::get_by_address(&address).unwrap();
-                account.id = new_id.to_string();
-                account.pass = new_pass.to_string();
-                Ok(account)
-            },
-            Authorize::DENY => {Err("Id or Pass is incorrect.")}
-        }
+    fn reset_pass_by_addr(address: H160, new_id: Id, new_pass: Pass) -> Result<Account, &'static str> {
+        let mut account = Self::get_by_address(&address).ok_or("Address is not registered.")?;
+        account.id = new_id.to_string();
+        account.pass = new_pass.to_string();
+        Ok(account)
     }
 
     fn get_by_id (id: &Id) -> Option<Account> {
@@ -193,38 +185,42 @@ impl Contract {
         write_state!(address_string => id);
     }
 
-    fn show_data(id: &Id, pass: &Pass) -> Option<AccountInfo> {
-        match Self::authorize_by_pass(id, pass) {
-            Authorize::ACCEPT => {
-                let account = &Self::get_by_id(id).unwrap();
-                Some((account.id.to_string(), account.pass.to_string(), account.current_address))
-            },
-            Authorize::DENY => None
-        }
+    fn show_data(id: &Id) -> Result<Account, &'static str> {
+        Self::get_by_id(id).ok_or("Id is not registered.")
     }
 }
 
 // Public trait defining public-facing secret contract functions
+// Functions returning `Err` revert the task, so nothing is written to the state,
+// and `#[view]` functions can only read the state.
+// Calls rejected by `#[guard]` or `#[requires_signature]` are reverted with the "unauthorized" error
 #[pub_interface]
 pub trait ContractInterface {
-    fn pub_register(id: Id, pass: Pass, address: H160) -> bool;
+    fn pub_register(id: Id, pass: Pass, address: H160) -> Result<bool, String>;
     // fn registor_without_pass(id: Id) -> Pass;
+    #[view]
     fn pub_authorize_by_pass(id: Id, pass: Pass) -> bool;
+    #[view]
     fn pub_authorize_by_address(address: H160, sig: Vec<u8>) -> bool;
-    fn pub_reset_pass(id:Id, pass: Pass, new_pass: Pass) -> bool;
-    fn pub_reset_address(id: Id, pass: Pass, new_address: H160) -> bool;
-    fn pub_reset_pass_by_addr(address: H160, new_id: Id, new_pass: Pass, sig: Vec<u8>) -> bool;
-    fn pub_show_data(id: Id, pass: Pass) -> AccountInfo;
+    #[guard(Self::is_authorized_by_pass(id, pass))]
+    fn pub_reset_pass(id:Id, pass: Pass, new_pass: Pass) -> Result<bool, String>;
+    #[guard(Self::is_authorized_by_pass(id, pass))]
+    fn pub_reset_address(id: Id, pass: Pass, new_address: H160) -> Result<bool, String>;
+    #[requires_signature(signer = "address", signature = "sig", message = "Authentication by you")]
+    fn pub_reset_pass_by_addr(address: H160, new_id: Id, new_pass: Pass, sig: Vec<u8>) -> Result<bool, String>;
+    #[view]
+    #[guard(Self::is_authorized_by_pass(id, pass))]
+    fn pub_show_data(id: Id, pass: Pass) -> Result<Account, String>;
 } 
 
 // Implementation of the public-facing secret contract functions defined in the ContractInterface
 // trait implementation for the Contract struct above
 impl ContractInterface for Contract {
     #[no_mangle]
-    fn pub_register(id: Id, pass: Pass, address: H160) -> bool{
-        let new_account = Self::register(&id, &pass, &address).unwrap_or_default();
-        Self::register_in_state(new_account); 
-        return true
+    fn pub_register(id: Id, pass: Pass, address: H160) -> Result<bool, String>{
+        let new_account = Self::register(&id, &pass, &address).map_err(String::from)?;
+        Self::register_in_state(new_account);
+        Ok(true)
     }
     
     #[no_mangle]
@@ -244,28 +240,28 @@ impl ContractInterface for Contract {
     }
 
     #[no_mangle]
-    fn pub_reset_pass(id:Id, pass: Pass, new_pass: Pass) -> bool {
-        let new_account = Self::reset_pass(&id, &pass, &new_pass).unwrap_or_default();
-        Self::register_in_state(new_account); 
-        return true
+    fn pub_reset_pass(id:Id, _pass: Pass, new_pass: Pass) -> Result<bool, String> {
+        let new_account = Self::reset_pass(&id, &new_pass).map_err(String::from)?;
+        Self::register_in_state(new_account);
+        Ok(true)
     }
 
     #[no_mangle]
-    fn pub_reset_address(id: Id, pass: Pass, new_address: H160) -> bool {
-        let new_account = Self::reset_address(&id, &pass, &new_address).unwrap_or_default();
-        Self::register_in_state(new_account); 
-        return true
+    fn pub_reset_address(id: Id, _pass: Pass, new_address: H160) -> Result<bool, String> {
+        let new_account = Self::reset_address(&id, &new_address).map_err(String::from)?;
+        Self::register_in_state(new_account);
+        Ok(true)
     }
 
     #[no_mangle]
-    fn pub_reset_pass_by_addr(address: H160, new_id: Id, new_pass: Pass, sig: Vec<u8>) -> bool {
-        let new_account = Self::reset_pass_by_addr(address, new_id, new_pass, sig).unwrap_or_default();
+    fn pub_reset_pass_by_addr(address: H160, new_id: Id, new_pass: Pass, _sig: Vec<u8>) -> Result<bool, String> {
+        let new_account = Self::reset_pass_by_addr(address, new_id, new_pass).map_err(String::from)?;
         Self::register_in_state(new_account);
-        return true
+        Ok(true)
     }
 
     #[no_mangle]
-    fn pub_show_data(id: Id, pass: Pass) -> AccountInfo {
-        Self::show_data(&id, &pass).unwrap_or_default()
+    fn pub_show_data(id: Id, _pass: Pass) -> Result<Account, String> {
+        Self::show_data(&id).map_err(String::from)
     }
 }
\ No newline at end of file
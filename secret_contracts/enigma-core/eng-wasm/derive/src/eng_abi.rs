@@ -0,0 +1,345 @@
+//! This module implements `#[derive(EngAbi)]`
+//!
+//! The derived type implements both `eng_wasm::EngAbi`, so it can be a field of other derived types,
+//! and `AbiType`, so it can be used directly in the signatures of `#[pub_interface]` functions.
+//! See `eng-wasm/src/abi_wasm.rs` for the encoding.
+
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+
+use parse_display::Display;
+
+/// Failures that can happen while parsing the derive input.
+#[derive(Display, Debug)]
+pub(crate) enum ParseError {
+    #[display("EngAbi can not be derived for unions")]
+    Union,
+}
+
+pub(crate) fn impl_eng_abi(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let input = parse_macro_input2!(input as syn::DeriveInput);
+
+    let (encode_body, decode_body) = match &input.data {
+        syn::Data::Struct(data_struct) => generate_struct_bodies(&input.ident, &data_struct.fields),
+        syn::Data::Enum(data_enum) => generate_enum_bodies(&input.ident, data_enum),
+        syn::Data::Union(data_union) => {
+            return syn::Error::new_spanned(data_union.union_token, ParseError::Union).to_compile_error()
+        }
+    };
+
+    let name = &input.ident;
+    let generics = add_trait_bounds(input.generics.clone());
+    let (impl_generics, type_generics, where_clause) = generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics eng_wasm::EngAbi for #name #type_generics #where_clause {
+            fn push(self, sink: &mut eng_wasm::eng_pwasm_abi::eth::Sink) {
+                sink.push(self)
+            }
+
+            fn pop(stream: &mut eng_wasm::eng_pwasm_abi::eth::Stream) -> Result<Self, eng_wasm::eng_pwasm_abi::eth::Error> {
+                stream.pop()
+            }
+        }
+
+        impl #impl_generics eng_wasm::eng_pwasm_abi::eth::AbiType for #name #type_generics #where_clause {
+            fn decode(stream: &mut eng_wasm::eng_pwasm_abi::eth::Stream) -> Result<Self, eng_wasm::eng_pwasm_abi::eth::Error> {
+                let bytes = eng_wasm::decode_abi_bytes(stream)?;
+                #decode_body
+            }
+
+            fn encode(self, sink: &mut eng_wasm::eng_pwasm_abi::eth::Sink) {
+                let bytes = #encode_body;
+                eng_wasm::encode_abi_bytes(bytes, sink)
+            }
+
+            const IS_FIXED: bool = false;
+        }
+    }
+}
+
+/// Every type parameter has to be `EngAbi` for the derived type to be `EngAbi`
+fn add_trait_bounds(mut generics: syn::Generics) -> syn::Generics {
+    for param in &mut generics.params {
+        if let syn::GenericParam::Type(type_param) = param {
+            type_param.bounds.push(syn::parse_quote!(eng_wasm::EngAbi));
+        }
+    }
+    generics
+}
+
+/// Generate the pattern that binds all the fields, and the identifiers bound to them.
+///
+/// Named fields are bound to their own names, and unnamed fields to `field_0`, `field_1`, etc.
+fn generate_fields_pattern(
+    path: proc_macro2::TokenStream,
+    fields: &syn::Fields,
+) -> (proc_macro2::TokenStream, Vec<syn::Ident>) {
+    match fields {
+        syn::Fields::Named(named) => {
+            let idents: Vec<syn::Ident> = named.named.iter().map(|field| field.ident.clone().unwrap()).collect();
+            (quote!(#path { #(#idents),* }), idents)
+        }
+        syn::Fields::Unnamed(unnamed) => {
+            let idents: Vec<syn::Ident> =
+                (0..unnamed.unnamed.len()).map(|index| format_ident!("field_{}", index)).collect();
+            (quote!(#path ( #(#idents),* )), idents)
+        }
+        syn::Fields::Unit => (path, vec![]),
+    }
+}
+
+/// Generate the expression that constructs the value by popping the fields from `fields`.
+fn generate_fields_constructor(path: proc_macro2::TokenStream, fields: &syn::Fields) -> proc_macro2::TokenStream {
+    match fields {
+        syn::Fields::Named(named) => {
+            let assignments = named.named.iter().map(|field| {
+                let ident = &field.ident;
+                quote_spanned_pop(field, quote!(#ident: ))
+            });
+            quote!(#path { #(#assignments),* })
+        }
+        syn::Fields::Unnamed(unnamed) => {
+            let pops = unnamed.unnamed.iter().map(|field| quote_spanned_pop(field, quote!()));
+            quote!(#path ( #(#pops),* ))
+        }
+        syn::Fields::Unit => path,
+    }
+}
+
+/// Pop a single field, the span makes errors about fields that aren't `EngAbi` point at the field.
+fn quote_spanned_pop(field: &syn::Field, prefix: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let type_ = &field.ty;
+    let pop = quote::quote_spanned!(type_.span()=> <#type_ as eng_wasm::EngAbi>::pop(fields)?);
+    quote!(#prefix #pop)
+}
+
+fn generate_struct_bodies(
+    name: &syn::Ident,
+    fields: &syn::Fields,
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let (pattern, idents) = generate_fields_pattern(quote!(#name), fields);
+    let count = idents.len();
+    let constructor = generate_fields_constructor(quote!(#name), fields);
+    // Don't generate an unused closure argument for structs without fields
+    let fields_arg = if count == 0 { quote!(_) } else { quote!(fields) };
+
+    let encode_body = quote! {{
+        let #pattern = self;
+        eng_wasm::encode_fields(#count, |#fields_arg| {
+            #(eng_wasm::EngAbi::push(#idents, fields);)*
+        })
+    }};
+    let decode_body = quote! {
+        eng_wasm::decode_fields(&bytes, |#fields_arg| Ok(#constructor))
+    };
+    (encode_body, decode_body)
+}
+
+fn generate_enum_bodies(
+    name: &syn::Ident,
+    data_enum: &syn::DataEnum,
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let mut encode_arms = Vec::with_capacity(data_enum.variants.len());
+    let mut decode_arms = Vec::with_capacity(data_enum.variants.len());
+    for (index, variant) in data_enum.variants.iter().enumerate() {
+        let index = index as u32;
+        let variant_ident = &variant.ident;
+        let (pattern, idents) = generate_fields_pattern(quote!(#name::#variant_ident), &variant.fields);
+        // The variant index is the first field
+        let count = idents.len() + 1;
+        let constructor = generate_fields_constructor(quote!(#name::#variant_ident), &variant.fields);
+
+        encode_arms.push(quote! {
+            #pattern => eng_wasm::encode_fields(#count, |fields| {
+                fields.push(#index);
+                #(eng_wasm::EngAbi::push(#idents, fields);)*
+            }),
+        });
+        decode_arms.push(quote! {
+            #index => Ok(#constructor),
+        });
+    }
+
+    let encode_body = quote! {
+        match self {
+            #(#encode_arms)*
+        }
+    };
+    let decode_body = quote! {
+        eng_wasm::decode_fields(&bytes, |fields| match fields.pop::<u32>()? {
+            #(#decode_arms)*
+            _ => Err(eng_wasm::unknown_variant_error()),
+        })
+    };
+    (encode_body, decode_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn struct_generation() -> syn::Result<()> {
+        let input = quote!(
+            pub struct Account {
+                id: String,
+                balances: Vec<U256>,
+                owner: Option<H160>,
+            }
+        );
+
+        let expected_output = quote!(
+            impl eng_wasm::eng_pwasm_abi::eth::AbiType for Account {
+                fn decode(stream: &mut eng_wasm::eng_pwasm_abi::eth::Stream) -> Result<Self, eng_wasm::eng_pwasm_abi::eth::Error> {
+                    let bytes = eng_wasm::decode_abi_bytes(stream)?;
+                    eng_wasm::decode_fields(&bytes, |fields| Ok(Account {
+                        id: <String as eng_wasm::EngAbi>::pop(fields)?,
+                        balances: <Vec<U256> as eng_wasm::EngAbi>::pop(fields)?,
+                        owner: <Option<H160> as eng_wasm::EngAbi>::pop(fields)?
+                    }))
+                }
+
+                fn encode(self, sink: &mut eng_wasm::eng_pwasm_abi::eth::Sink) {
+                    let bytes = {
+                        let Account { id, balances, owner } = self;
+                        eng_wasm::encode_fields(3usize, |fields| {
+                            eng_wasm::EngAbi::push(id, fields);
+                            eng_wasm::EngAbi::push(balances, fields);
+                            eng_wasm::EngAbi::push(owner, fields);
+                        })
+                    };
+                    eng_wasm::encode_abi_bytes(bytes, sink)
+                }
+
+                const IS_FIXED: bool = false;
+            }
+        );
+
+        let output = syn::parse2::<syn::File>(impl_eng_abi(input))?;
+        let expected_output = syn::parse2::<syn::ItemImpl>(expected_output)?;
+
+        assert_eq!(output.items.len(), 2);
+        assert_eq!(output.items[1], syn::Item::Impl(expected_output));
+        Ok(())
+    }
+
+    #[test]
+    fn enum_generation() -> syn::Result<()> {
+        let input = quote!(
+            enum AccountError {
+                NotFound,
+                WrongPass(String),
+                Taken { address: H160 },
+            }
+        );
+
+        let expected_output = quote!(
+            impl eng_wasm::eng_pwasm_abi::eth::AbiType for AccountError {
+                fn decode(stream: &mut eng_wasm::eng_pwasm_abi::eth::Stream) -> Result<Self, eng_wasm::eng_pwasm_abi::eth::Error> {
+                    let bytes = eng_wasm::decode_abi_bytes(stream)?;
+                    eng_wasm::decode_fields(&bytes, |fields| match fields.pop::<u32>()? {
+                        0u32 => Ok(AccountError::NotFound),
+                        1u32 => Ok(AccountError::WrongPass(<String as eng_wasm::EngAbi>::pop(fields)?)),
+                        2u32 => Ok(AccountError::Taken { address: <H160 as eng_wasm::EngAbi>::pop(fields)? }),
+                        _ => Err(eng_wasm::unknown_variant_error()),
+                    })
+                }
+
+                fn encode(self, sink: &mut eng_wasm::eng_pwasm_abi::eth::Sink) {
+                    let bytes = match self {
+                        AccountError::NotFound => eng_wasm::encode_fields(1usize, |fields| {
+                            fields.push(0u32);
+                        }),
+                        AccountError::WrongPass(field_0) => eng_wasm::encode_fields(2usize, |fields| {
+                            fields.push(1u32);
+                            eng_wasm::EngAbi::push(field_0, fields);
+                        }),
+                        AccountError::Taken { address } => eng_wasm::encode_fields(2usize, |fields| {
+                            fields.push(2u32);
+                            eng_wasm::EngAbi::push(address, fields);
+                        }),
+                    };
+                    eng_wasm::encode_abi_bytes(bytes, sink)
+                }
+
+                const IS_FIXED: bool = false;
+            }
+        );
+
+        let output = syn::parse2::<syn::File>(impl_eng_abi(input))?;
+        let expected_output = syn::parse2::<syn::ItemImpl>(expected_output)?;
+
+        assert_eq!(output.items[1], syn::Item::Impl(expected_output));
+        Ok(())
+    }
+
+    #[test]
+    fn generic_struct_bounds() -> syn::Result<()> {
+        let input = quote!(
+            struct Wrapper<T>(T);
+        );
+
+        let output = syn::parse2::<syn::File>(impl_eng_abi(input))?;
+        let expected_impl_header: syn::ItemImpl = syn::parse_quote!(
+            impl<T: eng_wasm::EngAbi> eng_wasm::EngAbi for Wrapper<T> {
+                fn push(self, sink: &mut eng_wasm::eng_pwasm_abi::eth::Sink) {
+                    sink.push(self)
+                }
+
+                fn pop(stream: &mut eng_wasm::eng_pwasm_abi::eth::Stream) -> Result<Self, eng_wasm::eng_pwasm_abi::eth::Error> {
+                    stream.pop()
+                }
+            }
+        );
+
+        assert_eq!(output.items[0], syn::Item::Impl(expected_impl_header));
+        Ok(())
+    }
+
+    #[test]
+    fn unit_struct_generation() -> syn::Result<()> {
+        let input = quote!(
+            struct Empty;
+        );
+
+        let expected_output = quote!(
+            impl eng_wasm::eng_pwasm_abi::eth::AbiType for Empty {
+                fn decode(stream: &mut eng_wasm::eng_pwasm_abi::eth::Stream) -> Result<Self, eng_wasm::eng_pwasm_abi::eth::Error> {
+                    let bytes = eng_wasm::decode_abi_bytes(stream)?;
+                    eng_wasm::decode_fields(&bytes, |_| Ok(Empty))
+                }
+
+                fn encode(self, sink: &mut eng_wasm::eng_pwasm_abi::eth::Sink) {
+                    let bytes = {
+                        let Empty = self;
+                        eng_wasm::encode_fields(0usize, |_| {})
+                    };
+                    eng_wasm::encode_abi_bytes(bytes, sink)
+                }
+
+                const IS_FIXED: bool = false;
+            }
+        );
+
+        let output = syn::parse2::<syn::File>(impl_eng_abi(input))?;
+        let expected_output = syn::parse2::<syn::ItemImpl>(expected_output)?;
+
+        assert_eq!(output.items[1], syn::Item::Impl(expected_output));
+        Ok(())
+    }
+
+    #[test]
+    fn union_error() {
+        let input = quote!(
+            union Foo {
+                a: u32,
+                b: f32,
+            }
+        );
+
+        let output = impl_eng_abi(input).to_string();
+
+        assert!(output.contains(&ParseError::Union.to_string()));
+    }
+}
@@ -1,155 +1,465 @@
+//! `#[eth_contract("Contract.json")]` generates typed bindings to the Ethereum contract with the given
+//! JSON ABI, which secret contracts use to build the payloads of the Ethereum bridge.
+//!
+//! For each function of the contract it generates a method which sends a call to the bridge,
+//! and a `decode_<function>_output` function decoding its return values. For each event it generates
+//! an `encode_<event>_event` method building the log that the contract would emit.
+//!
+//! ABI types are mapped to these Rust types:
+//! * `address` is `Address`, `bool` is `bool`, `string` is `String` and `bytes` is `Vec<u8>`.
+//! * `uintN`/`intN` are the smallest Rust integer holding them, `uint256` and `int256` are `U256`
+//!   (in two's complement for `int256`).
+//! * `bytes32` is `H256`, other `bytesN` are `[u8; N]`.
+//! * `T[]` is `Vec<T>`, `T[N]` is `[T; N]` and tuples (Solidity structs) are Rust tuples.
+//!
+//! Overloaded functions and events get the types of their inputs appended to their Rust names,
+//! `transfer(address,uint256)` becomes `transfer_address_uint256`.
+//!
+//! Unsupported types, like `fixed`, are compile errors.
 use std::fs::File;
 
-use quote::quote;
-
-use ethabi::{Contract, ParamType};
+use quote::{format_ident, quote};
+use serde_json::Value;
 
+mod abi;
 mod errors;
 mod ethereum;
 
+use abi::{ContractAbi, Event, Function, Param, ParamType};
 use errors::EngWasmError;
-use ethereum::short_signature;
+use ethereum::{event_topic, short_signature};
 
-trait Write {
-    fn write(&self) -> String;
-    fn error(&self) -> String;
+fn read_contract_abi(file_path: String) -> Result<Value, EngWasmError> {
+    let file = File::open(file_path)?;
+    Ok(serde_json::from_reader(file)?)
 }
 
-impl Write for ParamType {
-    /// Returns string which is a formatted representation of param.
-    fn write(&self) -> String {
-        match *self {
-            ParamType::Address => "Address".to_owned(),
-            ParamType::Bytes => "Vec<u8>".to_owned(),
-            ParamType::FixedBytes(len) => format!("u8[{}]", len),
-            ParamType::Int(len) => match len {
-                32 | 64 => format!("i{}", len),
-                _ => panic!("{}", self.error()),
-            },
-            ParamType::Uint(len) => match len {
-                32 | 64 => format!("u{}", len),
-                256 => "U256".to_owned(),
-                _ => panic!("{}", self.error()),
-            },
-            ParamType::Bool => "bool".to_owned(),
-            ParamType::String => "String".to_owned(),
-            ParamType::FixedArray(ref param, len) => format!("{}[{}]", param.write(), len),
-            ParamType::Array(ref param) => format!("Vec<{}>", param.write()),
-        }
-    }
-    fn error(&self) -> String {
-        format!("The type {} is not supported", self.to_string())
+/// The Rust type of values of the ABI type.
+fn rust_type(kind: &ParamType) -> proc_macro2::TokenStream {
+    match kind {
+        ParamType::Address => quote!(eng_wasm::Address),
+        ParamType::Bool => quote!(bool),
+        ParamType::Int(bits) => match *bits {
+            0..=8 => quote!(i8),
+            9..=16 => quote!(i16),
+            17..=32 => quote!(i32),
+            33..=64 => quote!(i64),
+            65..=128 => quote!(i128),
+            _ => quote!(eng_wasm::U256),
+        },
+        ParamType::Uint(bits) => match *bits {
+            0..=8 => quote!(u8),
+            9..=16 => quote!(u16),
+            17..=32 => quote!(u32),
+            33..=64 => quote!(u64),
+            65..=128 => quote!(u128),
+            _ => quote!(eng_wasm::U256),
+        },
+        ParamType::FixedBytes(32) => quote!(eng_wasm::H256),
+        ParamType::FixedBytes(len) => {
+            let len = proc_macro2::Literal::usize_unsuffixed(*len);
+            quote!([u8; #len])
+        }
+        ParamType::Bytes => quote!(eng_wasm::Vec<u8>),
+        ParamType::String => quote!(eng_wasm::String),
+        ParamType::Array(param) => {
+            let element = rust_type(param);
+            quote!(eng_wasm::Vec<#element>)
+        }
+        ParamType::FixedArray(param, len) => {
+            let element = rust_type(param);
+            let len = proc_macro2::Literal::usize_unsuffixed(*len);
+            quote!([#element; #len])
+        }
+        ParamType::Tuple(params) => {
+            let fields = params.iter().map(rust_type);
+            quote!((#(#fields,)*))
+        }
     }
 }
 
-struct FunctionAst {
-    name: syn::Ident,
-    args_ast_types: Vec<syn::Type>,
-    args_types: Vec<ParamType>,
+/// An expression converting `value`, a reference to a value of the ABI type, into an `eth_abi::Token`.
+fn token_expr(kind: &ParamType, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    match kind {
+        ParamType::Bytes => quote!(eng_wasm::eth_abi::Token::Bytes((#value).to_vec())),
+        ParamType::String => quote!(eng_wasm::eth_abi::Token::Bytes((#value).as_bytes().to_vec())),
+        ParamType::Array(param) => {
+            let element = token_expr(param, quote!(item));
+            quote!(eng_wasm::eth_abi::Token::Array((#value).iter().map(|item| #element).collect()))
+        }
+        ParamType::FixedArray(param, _len) => {
+            let element = token_expr(param, quote!(item));
+            quote!(eng_wasm::eth_abi::Token::FixedArray((#value).iter().map(|item| #element).collect()))
+        }
+        ParamType::Tuple(params) => {
+            let names: Vec<_> = (0..params.len()).map(|i| format_ident!("field_{}", i)).collect();
+            let fields = params
+                .iter()
+                .zip(&names)
+                .map(|(param, name)| token_expr(param, quote!(#name)));
+            let fields_number = params.len();
+            quote!({
+                let (#(ref #names,)*) = *(#value);
+                let mut fields = eng_wasm::Vec::with_capacity(#fields_number);
+                #(fields.push(#fields);)*
+                eng_wasm::eth_abi::Token::Tuple(fields)
+            })
+        }
+        _ => quote!(eng_wasm::eth_abi::Token::Word(eng_wasm::eth_abi::Word::to_word(#value))),
+    }
 }
 
-fn read_contract_file(file_path: String) -> Result<Box<File>, EngWasmError> {
-    let file = File::open(file_path)?;
-    let contents = Box::new(file);
-    Ok(contents)
+/// An expression decoding the next value of the ABI type from the `eth_abi::Decoder` in `decoder`.
+fn decode_expr(kind: &ParamType, decoder: &syn::Ident) -> proc_macro2::TokenStream {
+    let items = format_ident!("items");
+    match kind {
+        ParamType::Bytes => quote!(#decoder.bytes()?),
+        ParamType::String => quote!(#decoder.string()?),
+        ParamType::Array(param) => {
+            let element = decode_expr(param, &items);
+            quote!({
+                let (len, mut #items) = #decoder.array()?;
+                let mut array = eng_wasm::Vec::with_capacity(len);
+                for _ in 0..len {
+                    array.push(#element);
+                }
+                array
+            })
+        }
+        // Static arrays and tuples are encoded in place, dynamic ones in the tail
+        ParamType::FixedArray(param, len) => {
+            let source = if kind.is_dynamic() { &items } else { decoder };
+            let elements = (0..*len).map(|_| decode_expr(param, source));
+            with_dynamic_decoder(kind, decoder, quote!([#(#elements),*]))
+        }
+        ParamType::Tuple(params) => {
+            let source = if kind.is_dynamic() { &items } else { decoder };
+            let fields = params.iter().map(|param| decode_expr(param, source));
+            with_dynamic_decoder(kind, decoder, quote!((#(#fields,)*)))
+        }
+        _ => {
+            let type_ = rust_type(kind);
+            quote!(#decoder.word::<#type_>()?)
+        }
+    }
 }
 
-fn generate_eth_functions(
-    contract: &Contract,
-) -> Result<Vec<proc_macro2::TokenStream>, EngWasmError> {
-    let mut functions: Vec<FunctionAst> = Vec::new();
-    for function in &contract.functions {
-        let mut args_ast_types = Vec::new();
-        for input in &function.1.inputs {
-            let arg_type: syn::Type = syn::parse_str(&input.kind.clone().write())?;
-            args_ast_types.push(arg_type);
-        }
-        let args_types = function
-            .1
-            .inputs
-            .iter()
-            .map(|input| input.kind.clone())
-            .collect();
-
-        let name = syn::Ident::new(&function.1.name, proc_macro2::Span::call_site());
-        functions.push(FunctionAst {
-            name,
-            args_types,
-            args_ast_types,
+fn with_dynamic_decoder(
+    kind: &ParamType,
+    decoder: &syn::Ident,
+    value: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    if kind.is_dynamic() {
+        quote!({
+            let mut items = #decoder.dynamic()?;
+            #value
         })
+    } else {
+        value
     }
+}
 
-    let result: Vec<proc_macro2::TokenStream> = functions
-        .iter()
-        .map(|function| {
-            let function_name = &function.name;
-            let args_ast_types = function.args_ast_types.clone();
-            let sig_u32 = short_signature(&function_name.to_string(), &function.args_types);
-            let sig = syn::Lit::Int(syn::LitInt::new(
-                &format!("{}_u32", sig_u32 as u32),
-                proc_macro2::Span::call_site(),
-            ));
-            let args_number = syn::Lit::Int(syn::LitInt::new(
-                &format!("{}_usize", args_ast_types.len() as usize),
-                proc_macro2::Span::call_site(),
-            ));
-            let args_names: Vec<syn::Ident> = function
-                .args_ast_types
-                .iter()
-                .enumerate()
-                .map(|item| {
-                    let mut arg = String::from("arg");
-                    arg.push_str(item.0.to_string().as_str());
-                    syn::Ident::new(&arg, proc_macro2::Span::call_site())
-                })
-                .collect();
-            let args_names_copy = args_names.clone();
-            quote! {
-                fn #function_name(&self, #(#args_names: #args_ast_types),*){
-                    #![allow(unused_mut)]
-                    #![allow(unused_variables)]
-                    let mut payload = Vec::with_capacity(4 + #args_number * 32);
-                    payload.push((#sig >> 24) as u8);
-                    payload.push((#sig >> 16) as u8);
-                    payload.push((#sig >> 8) as u8);
-                    payload.push(#sig as u8);
-
-                    let mut sink = eng_pwasm_abi::eth::Sink::new(#args_number);
-                    #(sink.push(#args_names_copy);)*
-                    sink.drain_to(&mut payload);
-                    write_ethereum_bridge(&payload, &self.addr);
-                }
+fn to_snake_case(name: &str) -> String {
+    let mut snake_case = String::with_capacity(name.len());
+    for (i, c) in name.char_indices() {
+        if c.is_uppercase() {
+            if i > 0 && !name[..i].ends_with('_') {
+                snake_case.push('_');
             }
-        })
-        .collect();
+            snake_case.extend(c.to_lowercase());
+        } else {
+            snake_case.push(c);
+        }
+    }
+    snake_case
+}
 
-    Ok(result)
+fn arg_names(params: &[Param]) -> Vec<syn::Ident> {
+    (0..params.len()).map(|i| format_ident!("arg{}", i)).collect()
 }
 
-pub fn impl_eth_contract(
-    args: proc_macro2::TokenStream,
-    input: proc_macro2::TokenStream,
-) -> proc_macro2::TokenStream {
-    let input_tokens = parse_macro_input2!(input as syn::ItemStruct);
-    let struct_name = input_tokens.ident;
-    let file_path = parse_macro_input2!(args as syn::LitStr);
-    let contents: Box<File> = read_contract_file(file_path.value()).expect("Bad contract file");
-    let contract = Contract::load(contents).unwrap();
-    let it: Vec<proc_macro2::TokenStream> = generate_eth_functions(&contract).unwrap();
+fn param_types(params: &[Param]) -> Vec<ParamType> {
+    params.iter().map(|param| param.kind.clone()).collect()
+}
+
+fn generate_eth_function(function: &Function) -> proc_macro2::TokenStream {
+    let function_name = format_ident!("{}", function.rust_name);
+    let args_names = arg_names(&function.inputs);
+    let args_types = function.inputs.iter().map(|input| rust_type(&input.kind));
+    let tokens = function
+        .inputs
+        .iter()
+        .zip(&args_names)
+        .map(|(input, name)| token_expr(&input.kind, quote!(&#name)));
+    let selector = short_signature(&function.name, &param_types(&function.inputs))
+        .iter()
+        .map(|byte| proc_macro2::Literal::u8_suffixed(*byte))
+        .collect::<Vec<_>>();
+
+    let call = quote! {
+        #[allow(non_snake_case)]
+        fn #function_name(&self, #(#args_names: #args_types),*) {
+            let payload = eng_wasm::eth_abi::encode_call([#(#selector),*], &[#(#tokens),*]);
+            eng_wasm::write_ethereum_bridge(&payload, &self.addr);
+        }
+    };
+    if function.outputs.is_empty() {
+        return call;
+    }
+
+    let decode_name = format_ident!("decode_{}_output", to_snake_case(&function.rust_name));
+    let decoder = format_ident!("decoder");
+    let (output_type, output) = match function.outputs.as_slice() {
+        [output] => (rust_type(&output.kind), decode_expr(&output.kind, &decoder)),
+        outputs => {
+            let types = outputs.iter().map(|output| rust_type(&output.kind));
+            let values = outputs.iter().map(|output| decode_expr(&output.kind, &decoder));
+            (quote!((#(#types,)*)), quote!((#(#values,)*)))
+        }
+    };
+    quote! {
+        #call
+
+        fn #decode_name(output: &[u8]) -> Result<#output_type, eng_wasm::eth_abi::DecodeError> {
+            #![allow(unused_mut)]
+            let mut #decoder = eng_wasm::eth_abi::Decoder::new(output);
+            Ok(#output)
+        }
+    }
+}
+
+fn generate_eth_event(event: &Event) -> proc_macro2::TokenStream {
+    let encode_name = format_ident!("encode_{}_event", to_snake_case(&event.rust_name));
+    let args_names = arg_names(&event.inputs);
+    let args_types = event.inputs.iter().map(|input| rust_type(&input.kind));
+
+    let signature_topic = if event.anonymous {
+        None
+    } else {
+        let topic = event_topic(&event.name, &param_types(&event.inputs));
+        let topic = topic.iter().map(|byte| proc_macro2::Literal::u8_suffixed(*byte));
+        Some(quote!(topics.push(eng_wasm::H256::from([#(#topic),*]));))
+    };
+    let (indexed, data): (Vec<_>, Vec<_>) = event
+        .inputs
+        .iter()
+        .zip(&args_names)
+        .partition(|(input, _name)| input.indexed);
+    let topics_number = indexed.len() + signature_topic.iter().count();
+    let indexed = indexed
+        .into_iter()
+        .map(|(input, name)| token_expr(&input.kind, quote!(&#name)));
+    let data = data
+        .into_iter()
+        .map(|(input, name)| token_expr(&input.kind, quote!(&#name)));
+
+    quote! {
+        fn #encode_name(&self, #(#args_names: #args_types),*) -> eng_wasm::eth_abi::EventLog {
+            let mut topics = eng_wasm::Vec::with_capacity(#topics_number);
+            #signature_topic
+            #(topics.push(#indexed.topic());)*
+            let data = eng_wasm::eth_abi::encode(&[#(#data),*]);
+            eng_wasm::eth_abi::EventLog { address: self.addr, topics, data }
+        }
+    }
+}
+
+fn generate_eth_contract(item: &syn::ItemStruct, contract: &ContractAbi) -> proc_macro2::TokenStream {
+    let struct_name = &item.ident;
+    let vis = &item.vis;
+    let functions = contract.functions.iter().map(generate_eth_function);
+    let events = contract.events.iter().map(generate_eth_event);
 
     quote! {
-        struct #struct_name {
-            addr: Address,
+        #vis struct #struct_name {
+            addr: eng_wasm::Address,
         }
-        impl EthContract{
+        // Bindings are generated for the whole contract, even if only some of them are used
+        #[allow(dead_code)]
+        impl #struct_name {
             fn new(addr_str: /*Address*/&str) -> Self {
                 use core::str::FromStr;
 
                 // Ethereum Addresses need to start with `0x` so we remove the first two characters
-                let addr = Address::from_str(&addr_str[2..]).expect("Failed converting the address from hex");
-                EthContract{ addr }
+                let addr = eng_wasm::Address::from_str(&addr_str[2..]).expect("Failed converting the address from hex");
+                #struct_name { addr }
             }
-             #(#it)*
+            #(#functions)*
+            #(#events)*
         }
     }
 }
+
+pub fn impl_eth_contract(
+    args: proc_macro2::TokenStream,
+    input: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let input_tokens = parse_macro_input2!(input as syn::ItemStruct);
+    let file_path = parse_macro_input2!(args as syn::LitStr);
+    let contract = read_contract_abi(file_path.value()).and_then(|abi| ContractAbi::parse(&abi));
+    match contract {
+        Ok(contract) => generate_eth_contract(&input_tokens, &contract),
+        // Errors in the ABI point at the path of its file
+        Err(error) => syn::Error::new_spanned(&file_path, error).to_compile_error(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn contract_tokens(abi: Value) -> syn::Result<syn::File> {
+        let item: syn::ItemStruct = syn::parse_quote!(struct Token;);
+        let contract = ContractAbi::parse(&abi).unwrap();
+        syn::parse2(generate_eth_contract(&item, &contract))
+    }
+
+    #[test]
+    fn signatures_hashing() {
+        let transfer = [ParamType::Address, ParamType::Uint(256)];
+        assert_eq!(short_signature("transfer", &transfer), [0xa9, 0x05, 0x9c, 0xbb]);
+        assert_eq!(
+            event_topic("Transfer", &[ParamType::Address, ParamType::Address, ParamType::Uint(256)]),
+            [
+                0xdd, 0xf2, 0x52, 0xad, 0x1b, 0xe2, 0xc8, 0x9b, 0x69, 0xc2, 0xb0, 0x68, 0xfc, 0x37, 0x8d, 0xaa,
+                0x95, 0x2b, 0xa7, 0xf1, 0x63, 0xc4, 0xa1, 0x16, 0x28, 0xf5, 0x5a, 0x4d, 0xf5, 0x23, 0xb3, 0xef,
+            ]
+        );
+    }
+
+    #[test]
+    fn snake_case_names() {
+        assert_eq!(to_snake_case("Transfer"), "transfer");
+        assert_eq!(to_snake_case("getBryn"), "get_bryn");
+        assert_eq!(to_snake_case("OwnershipTransferred"), "ownership_transferred");
+        assert_eq!(to_snake_case("validate_Vote"), "validate_vote");
+    }
+
+    #[test]
+    fn function_generation() -> syn::Result<()> {
+        let abi = json!([{
+            "type": "function",
+            "name": "transfer",
+            "inputs": [{ "name": "to", "type": "address" }, { "name": "value", "type": "uint256" }],
+            "outputs": [{ "name": "", "type": "bool" }, { "name": "", "type": "string" }],
+        }]);
+
+        #[rustfmt::skip]
+        let expected_output = quote!(
+            struct Token {
+                addr: eng_wasm::Address,
+            }
+            #[allow(dead_code)]
+            impl Token {
+                fn new(addr_str: &str) -> Self {
+                    use core::str::FromStr;
+
+                    let addr = eng_wasm::Address::from_str(&addr_str[2..]).expect("Failed converting the address from hex");
+                    Token { addr }
+                }
+                #[allow(non_snake_case)]
+                fn transfer(&self, arg0: eng_wasm::Address, arg1: eng_wasm::U256) {
+                    let payload = eng_wasm::eth_abi::encode_call(
+                        [169u8, 5u8, 156u8, 187u8],
+                        &[
+                            eng_wasm::eth_abi::Token::Word(eng_wasm::eth_abi::Word::to_word(&arg0)),
+                            eng_wasm::eth_abi::Token::Word(eng_wasm::eth_abi::Word::to_word(&arg1))
+                        ]
+                    );
+                    eng_wasm::write_ethereum_bridge(&payload, &self.addr);
+                }
+                fn decode_transfer_output(output: &[u8]) -> Result<(bool, eng_wasm::String,), eng_wasm::eth_abi::DecodeError> {
+                    #![allow(unused_mut)]
+                    let mut decoder = eng_wasm::eth_abi::Decoder::new(output);
+                    Ok((decoder.word::<bool>()?, decoder.string()?,))
+                }
+            }
+        );
+
+        assert_eq!(contract_tokens(abi)?, syn::parse2::<syn::File>(expected_output)?);
+        Ok(())
+    }
+
+    #[test]
+    fn event_generation() -> syn::Result<()> {
+        let abi = json!([{
+            "type": "event",
+            "name": "Deposit",
+            "anonymous": true,
+            "inputs": [
+                { "name": "owner", "type": "address", "indexed": true },
+                {
+                    "name": "deposit",
+                    "type": "tuple",
+                    "components": [{ "name": "amount", "type": "uint64" }, { "name": "memo", "type": "bytes" }],
+                    "indexed": false
+                },
+            ],
+        }]);
+
+        #[rustfmt::skip]
+        let expected_output = quote!(
+            struct Token {
+                addr: eng_wasm::Address,
+            }
+            #[allow(dead_code)]
+            impl Token {
+                fn new(addr_str: &str) -> Self {
+                    use core::str::FromStr;
+
+                    let addr = eng_wasm::Address::from_str(&addr_str[2..]).expect("Failed converting the address from hex");
+                    Token { addr }
+                }
+                fn encode_deposit_event(
+                    &self,
+                    arg0: eng_wasm::Address,
+                    arg1: (u64, eng_wasm::Vec<u8>,)
+                ) -> eng_wasm::eth_abi::EventLog {
+                    let mut topics = eng_wasm::Vec::with_capacity(1usize);
+                    topics.push(eng_wasm::eth_abi::Token::Word(eng_wasm::eth_abi::Word::to_word(&arg0)).topic());
+                    let data = eng_wasm::eth_abi::encode(&[{
+                        let (ref field_0, ref field_1,) = *(&arg1);
+                        let mut fields = eng_wasm::Vec::with_capacity(2usize);
+                        fields.push(eng_wasm::eth_abi::Token::Word(eng_wasm::eth_abi::Word::to_word(field_0)));
+                        fields.push(eng_wasm::eth_abi::Token::Bytes((field_1).to_vec()));
+                        eng_wasm::eth_abi::Token::Tuple(fields)
+                    }]);
+                    eng_wasm::eth_abi::EventLog { address: self.addr, topics, data }
+                }
+            }
+        );
+
+        assert_eq!(contract_tokens(abi)?, syn::parse2::<syn::File>(expected_output)?);
+        Ok(())
+    }
+
+    #[test]
+    fn nested_output_decoding_generation() -> syn::Result<()> {
+        let kind = ParamType::Array(Box::new(ParamType::Tuple(vec![
+            ParamType::FixedBytes(32),
+            ParamType::String,
+        ])));
+
+        #[rustfmt::skip]
+        let expected_output = quote!({
+            let (len, mut items) = decoder.array()?;
+            let mut array = eng_wasm::Vec::with_capacity(len);
+            for _ in 0..len {
+                array.push({
+                    let mut items = items.dynamic()?;
+                    (items.word::<eng_wasm::H256>()?, items.string()?,)
+                });
+            }
+            array
+        });
+
+        let output = decode_expr(&kind, &format_ident!("decoder"));
+        assert_eq!(
+            syn::parse2::<syn::Expr>(output)?,
+            syn::parse2::<syn::Expr>(expected_output)?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,343 @@
+//! Parsing of the JSON ABI of the Ethereum contract given to `#[eth_contract]`.
+//!
+//! `ethabi` doesn't support tuples, so the parts of the ABI that we generate code for are parsed here.
+use serde_json::Value;
+
+use super::errors::EngWasmError;
+
+/// An ABI type, see https://solidity.readthedocs.io/en/latest/abi-spec.html#types
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamType {
+    Address,
+    Bool,
+    Int(usize),
+    Uint(usize),
+    FixedBytes(usize),
+    Bytes,
+    String,
+    Array(Box<ParamType>),
+    FixedArray(Box<ParamType>, usize),
+    Tuple(Vec<ParamType>),
+}
+
+impl ParamType {
+    /// The canonical name of the type, as used in function and event signatures.
+    pub fn canonical(&self) -> String {
+        match self {
+            ParamType::Address => "address".to_owned(),
+            ParamType::Bool => "bool".to_owned(),
+            ParamType::Int(bits) => format!("int{}", bits),
+            ParamType::Uint(bits) => format!("uint{}", bits),
+            ParamType::FixedBytes(len) => format!("bytes{}", len),
+            ParamType::Bytes => "bytes".to_owned(),
+            ParamType::String => "string".to_owned(),
+            ParamType::Array(param) => format!("{}[]", param.canonical()),
+            ParamType::FixedArray(param, len) => format!("{}[{}]", param.canonical(), len),
+            ParamType::Tuple(params) => format!(
+                "({})",
+                params.iter().map(ParamType::canonical).collect::<Vec<_>>().join(",")
+            ),
+        }
+    }
+
+    /// Dynamic values are encoded in the tail, with their offset in the head.
+    pub fn is_dynamic(&self) -> bool {
+        match self {
+            ParamType::Bytes | ParamType::String | ParamType::Array(_) => true,
+            ParamType::FixedArray(param, _) => param.is_dynamic(),
+            ParamType::Tuple(params) => params.iter().any(ParamType::is_dynamic),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub kind: ParamType,
+    pub indexed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    /// The name of the generated Rust function, overloads get the types of their inputs appended.
+    pub rust_name: String,
+    pub inputs: Vec<Param>,
+    pub outputs: Vec<Param>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub name: String,
+    /// The name the generated encoder is derived from, overloads get the types of their inputs appended.
+    pub rust_name: String,
+    pub inputs: Vec<Param>,
+    pub anonymous: bool,
+}
+
+/// The functions and events of a contract, in the order of the ABI.
+#[derive(Debug, Default, PartialEq)]
+pub struct ContractAbi {
+    pub functions: Vec<Function>,
+    pub events: Vec<Event>,
+}
+
+impl ContractAbi {
+    /// Parse the JSON ABI, the constructor and fallback function are ignored.
+    /// Overloaded functions and events are told apart by the types of their inputs, e.g. `transfer_address_uint256`.
+    pub fn parse(abi: &Value) -> Result<ContractAbi, EngWasmError> {
+        let entries = abi.as_array().ok_or_else(|| bad_abi("the ABI is not an array"))?;
+        let mut contract = ContractAbi::default();
+        for entry in entries {
+            // Entries without a type are functions
+            match entry.get("type").and_then(Value::as_str).unwrap_or("function") {
+                "function" => {
+                    let name = parse_name(entry)?;
+                    contract.functions.push(Function {
+                        rust_name: name.clone(),
+                        name,
+                        inputs: parse_params(entry.get("inputs"))?,
+                        outputs: parse_params(entry.get("outputs"))?,
+                    });
+                }
+                "event" => {
+                    let name = parse_name(entry)?;
+                    contract.events.push(Event {
+                        rust_name: name.clone(),
+                        name,
+                        inputs: parse_params(entry.get("inputs"))?,
+                        anonymous: entry.get("anonymous").and_then(Value::as_bool).unwrap_or(false),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let names: Vec<_> = contract.functions.iter().map(|function| function.name.clone()).collect();
+        for function in &mut contract.functions {
+            if names.iter().filter(|name| **name == function.name).count() > 1 {
+                function.rust_name = overload_name(&function.name, &function.inputs);
+            }
+        }
+        let names: Vec<_> = contract.events.iter().map(|event| event.name.clone()).collect();
+        for event in &mut contract.events {
+            if names.iter().filter(|name| **name == event.name).count() > 1 {
+                event.rust_name = overload_name(&event.name, &event.inputs);
+            }
+        }
+        // Only a name that is already taken by the suffix of an overload can still clash
+        check_unique(contract.functions.iter().map(|function| &function.rust_name))?;
+        check_unique(contract.events.iter().map(|event| &event.rust_name))?;
+        Ok(contract)
+    }
+}
+
+/// The name of an overload, the types of its inputs are appended as identifiers,
+/// `transfer(address,uint256[2])` is `transfer_address_uint256_2`.
+fn overload_name(name: &str, inputs: &[Param]) -> String {
+    let mut rust_name = name.to_owned();
+    for input in inputs {
+        rust_name.push('_');
+        for c in input.kind.canonical().chars() {
+            match c {
+                '(' => rust_name.push_str("tuple_"),
+                '[' | ',' => rust_name.push('_'),
+                ')' | ']' => {}
+                c => rust_name.push(c),
+            }
+        }
+    }
+    rust_name
+}
+
+fn check_unique<'a>(names: impl Iterator<Item = &'a String>) -> Result<(), EngWasmError> {
+    let mut seen = Vec::new();
+    for name in names {
+        if seen.contains(&name) {
+            return Err(EngWasmError::Overloaded { name: name.clone() });
+        }
+        seen.push(name);
+    }
+    Ok(())
+}
+
+fn bad_abi(error: &str) -> EngWasmError {
+    EngWasmError::JsonError {
+        error: error.to_owned(),
+    }
+}
+
+fn parse_name(entry: &Value) -> Result<String, EngWasmError> {
+    entry
+        .get("name")
+        .and_then(Value::as_str)
+        .map(str::to_owned)
+        .ok_or_else(|| bad_abi("a function or event has no name"))
+}
+
+fn parse_params(params: Option<&Value>) -> Result<Vec<Param>, EngWasmError> {
+    let params = match params {
+        Some(params) => params.as_array().ok_or_else(|| bad_abi("the parameters are not an array"))?,
+        None => return Ok(Vec::new()),
+    };
+    params
+        .iter()
+        .map(|param| {
+            Ok(Param {
+                kind: parse_param_type(param)?,
+                indexed: param.get("indexed").and_then(Value::as_bool).unwrap_or(false),
+            })
+        })
+        .collect()
+}
+
+/// Parse the type of a parameter, tuples take the types of their fields from its `components`.
+pub fn parse_param_type(param: &Value) -> Result<ParamType, EngWasmError> {
+    let type_ = param
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or_else(|| bad_abi("a parameter has no type"))?;
+    parse_type(type_, param)
+}
+
+fn parse_type(type_: &str, param: &Value) -> Result<ParamType, EngWasmError> {
+    let unsupported = || EngWasmError::UnsupportedType {
+        type_: type_.to_owned(),
+    };
+
+    // Array dimensions are read from the right, `uint[2][]` is a dynamic array of `uint[2]`
+    if type_.ends_with(']') {
+        let open = type_.rfind('[').ok_or_else(unsupported)?;
+        let element = Box::new(parse_type(&type_[..open], param)?);
+        let len = &type_[open + 1..type_.len() - 1];
+        return if len.is_empty() {
+            Ok(ParamType::Array(element))
+        } else {
+            let len = len.parse().map_err(|_| unsupported())?;
+            Ok(ParamType::FixedArray(element, len))
+        };
+    }
+
+    let parse_width = |width: &str, default: usize| -> Option<usize> {
+        if width.is_empty() {
+            Some(default)
+        } else {
+            width.parse().ok()
+        }
+    };
+
+    match type_ {
+        "address" => Ok(ParamType::Address),
+        "bool" => Ok(ParamType::Bool),
+        "string" => Ok(ParamType::String),
+        "bytes" => Ok(ParamType::Bytes),
+        "tuple" => {
+            let components = param
+                .get("components")
+                .and_then(Value::as_array)
+                .ok_or_else(|| bad_abi("a tuple has no components"))?;
+            components
+                .iter()
+                .map(parse_param_type)
+                .collect::<Result<_, _>>()
+                .map(ParamType::Tuple)
+        }
+        _ if type_.starts_with("uint") => match parse_width(&type_[4..], 256) {
+            Some(bits) if bits % 8 == 0 && bits > 0 && bits <= 256 => Ok(ParamType::Uint(bits)),
+            _ => Err(unsupported()),
+        },
+        _ if type_.starts_with("int") => match parse_width(&type_[3..], 256) {
+            Some(bits) if bits % 8 == 0 && bits > 0 && bits <= 256 => Ok(ParamType::Int(bits)),
+            _ => Err(unsupported()),
+        },
+        _ if type_.starts_with("bytes") => match type_[5..].parse() {
+            Ok(len) if len > 0 && len <= 32 => Ok(ParamType::FixedBytes(len)),
+            _ => Err(unsupported()),
+        },
+        // `fixed`, `ufixed` and `function`
+        _ => Err(unsupported()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn parse(type_: &str) -> Result<ParamType, EngWasmError> { parse_param_type(&json!({ "type": type_ })) }
+
+    #[test]
+    fn elementary_types_parsing() {
+        assert_eq!(parse("address").unwrap(), ParamType::Address);
+        assert_eq!(parse("uint").unwrap(), ParamType::Uint(256));
+        assert_eq!(parse("int24").unwrap(), ParamType::Int(24));
+        assert_eq!(parse("uint128").unwrap(), ParamType::Uint(128));
+        assert_eq!(parse("bytes32").unwrap(), ParamType::FixedBytes(32));
+        assert_eq!(parse("bytes").unwrap(), ParamType::Bytes);
+    }
+
+    #[test]
+    fn nested_types_parsing() {
+        let param = json!({
+            "type": "tuple[2][]",
+            "components": [
+                { "name": "owner", "type": "address" },
+                { "name": "amounts", "type": "uint64[]" },
+            ]
+        });
+        let tuple = ParamType::Tuple(vec![
+            ParamType::Address,
+            ParamType::Array(Box::new(ParamType::Uint(64))),
+        ]);
+
+        let kind = parse_param_type(&param).unwrap();
+
+        assert_eq!(
+            kind,
+            ParamType::Array(Box::new(ParamType::FixedArray(Box::new(tuple), 2)))
+        );
+        assert_eq!(kind.canonical(), "(address,uint64[])[2][]");
+        assert!(kind.is_dynamic());
+    }
+
+    #[test]
+    fn unsupported_types_errors() {
+        for type_ in &["int7", "uint264", "bytes0", "bytes33", "fixed128x18", "function", "uint[x]"] {
+            assert_eq!(
+                parse(type_).unwrap_err().to_string(),
+                format!("The type {} is not supported", type_)
+            );
+        }
+    }
+
+    #[test]
+    fn overloaded_functions_names() {
+        let abi = json!([
+            { "type": "function", "name": "transfer", "inputs": [{ "type": "address" }] },
+            { "type": "function", "name": "transfer", "inputs": [{ "type": "bytes32" }, { "type": "uint256[2][]" }] },
+            { "type": "function", "name": "transfer", "inputs": [] },
+            { "type": "function", "name": "approve", "inputs": [{ "type": "address" }] },
+            { "type": "event", "name": "Transfer", "inputs": [{ "type": "tuple", "components": [{ "type": "address" }] }] },
+            { "type": "event", "name": "Transfer", "inputs": [{ "type": "address" }] },
+        ]);
+        let contract = ContractAbi::parse(&abi).unwrap();
+        let function_names: Vec<_> = contract.functions.iter().map(|function| function.rust_name.as_str()).collect();
+        assert_eq!(function_names, ["transfer_address", "transfer_bytes32_uint256_2_", "transfer", "approve"]);
+        let event_names: Vec<_> = contract.events.iter().map(|event| event.rust_name.as_str()).collect();
+        assert_eq!(event_names, ["Transfer_tuple_address", "Transfer_address"]);
+    }
+
+    #[test]
+    fn overloaded_names_clash_errors() {
+        let abi = json!([
+            { "type": "function", "name": "transfer", "inputs": [{ "type": "address" }] },
+            { "type": "function", "name": "transfer", "inputs": [{ "type": "bytes32" }] },
+            { "type": "function", "name": "transfer_address", "inputs": [] },
+        ]);
+        assert_eq!(
+            ContractAbi::parse(&abi).unwrap_err().to_string(),
+            "The name of an overloaded function or event clashes with another one: transfer_address"
+        );
+    }
+}
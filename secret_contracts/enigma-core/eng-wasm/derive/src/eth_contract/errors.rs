@@ -10,6 +10,10 @@ pub enum EngWasmError {
     JsonError { error: String },
     #[fail(display = "Token parse error: {}", error)]
     TokenParseError { error: String },
+    #[fail(display = "The type {} is not supported", type_)]
+    UnsupportedType { type_: String },
+    #[fail(display = "The name of an overloaded function or event clashes with another one: {}", name)]
+    Overloaded { name: String },
 }
 
 impl From<io::Error> for EngWasmError {
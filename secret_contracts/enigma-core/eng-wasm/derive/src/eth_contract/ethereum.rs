@@ -1,16 +1,24 @@
-use ethabi::param_type::{ParamType, Writer};
 use tiny_keccak::Keccak;
 
-pub fn short_signature(name: &str, params: &[ParamType]) -> u32 /*[u8; 4] */ {
+use super::abi::ParamType;
+
+pub fn short_signature(name: &str, params: &[ParamType]) -> [u8; 4] {
     let mut result = [0u8; 4];
     fill_signature(name, params, &mut result);
-    u32::from_be_bytes(result)
+    result
+}
+
+/// The first topic of the logs of an event which isn't anonymous.
+pub fn event_topic(name: &str, params: &[ParamType]) -> [u8; 32] {
+    let mut result = [0u8; 32];
+    fill_signature(name, params, &mut result);
+    result
 }
 
 fn fill_signature(name: &str, params: &[ParamType], result: &mut [u8]) {
     let types = params
         .iter()
-        .map(Writer::write)
+        .map(ParamType::canonical)
         .collect::<Vec<String>>()
         .join(",");
 
@@ -24,9 +24,11 @@ macro_rules! parse_macro_input2 {
 mod into_ident;
 mod reduce_mut;
 
+mod eng_abi;
 mod eth_contract;
 mod pub_interface;
 
+use eng_abi::impl_eng_abi;
 use eth_contract::impl_eth_contract;
 use pub_interface::impl_pub_interface;
 
@@ -37,12 +39,47 @@ use pub_interface::impl_pub_interface;
 /// trait will be considered exported by the contract. If it is placed on an impl block,
 /// all methods declared as pub will be exported by the contract. if placed on an impl block,
 /// the implementing struct can have any name you choose.
+///
+/// Exported methods annotated with `#[view]` are read-only: the runtime fails the task if they
+/// try to change the state, and their execution never produces a delta.
+///
+/// Exported methods can also be restricted with access control attributes, which are checked before
+/// the method is called. `#[requires_signature(signer = "addr", signature = "sig", message = "...")]`
+/// requires the `sig` argument to be a signature on the message by the `addr` argument, and
+/// `#[guard(Self::is_owner(id))]` requires the function to return `true` for references to the named
+/// arguments. Rejected calls revert the task with `eng_wasm::UNAUTHORIZED_ERROR`.
+///
+/// `construct` runs when the contract is deployed, and `migrate` runs instead when a deployed contract
+/// is upgraded to this code, after approving it with `eng_wasm::approve_upgrade`. Neither is exported.
+///
+/// The macro also generates a JSON ABI manifest describing the constructor and exported functions.
+/// If the contract has a build script, the manifest is written to `$OUT_DIR/abi.json`, and with the
+/// `abi-section` feature it is embedded in the `enigma_abi` custom section of the wasm bytecode.
 #[proc_macro_attribute]
 pub fn pub_interface(attr: TokenStream, item: TokenStream) -> TokenStream {
     impl_pub_interface(attr.into(), item.into()).into()
 }
 
+/// This macro generates bindings to an Ethereum contract from the path of its JSON ABI.
+///
+/// It is placed on a unit struct, which gets a `new` constructor taking the hex address of the contract.
+/// Each function of the contract becomes a method sending a call through the Ethereum bridge, with a
+/// `decode_<function>_output` function for its return values, and each event becomes an
+/// `encode_<event>_event` method building its log. Every ABI type except `fixed` and `ufixed` is
+/// supported, unsupported types are reported as compile errors. Overloads are named after the types of their inputs,
+/// e.g. `transfer_address_uint256`.
 #[proc_macro_attribute]
 pub fn eth_contract(attr: TokenStream, item: TokenStream) -> TokenStream {
     impl_eth_contract(attr.into(), item.into()).into()
 }
+
+/// Derive `eng_wasm::EngAbi` for a struct or enum, so it can be used in the signatures of exported
+/// functions and as a field of other derived types.
+///
+/// The value is encoded as ABI `bytes` holding the ABI encoding of its fields, and enums prefix
+/// their fields with the index of the variant. Fields can also be `Option`s and nested `Vec`s
+/// of any `EngAbi` type.
+#[proc_macro_derive(EngAbi)]
+pub fn eng_abi(input: TokenStream) -> TokenStream {
+    impl_eng_abi(input.into()).into()
+}
@@ -1,13 +1,19 @@
 use quote::{quote, quote_spanned, ToTokens};
 use syn::spanned::Spanned;
 
+mod abi_manifest;
+mod access_control;
 mod parse_signatures;
 
 use super::into_ident::IntoIdent;
-use parse_signatures::{ParseError, PubInterfaceItemType, PubInterfaceSignatures};
+use access_control::{generate_access_control_snippet, is_access_control_attribute};
+use abi_manifest::{generate_abi_manifest, generate_abi_section, write_abi_manifest};
+use parse_signatures::{is_view_attribute, ParseError, PubInterfaceItemType, PubInterfaceSignatures};
 
 const DEFAULT_IMPLEMENTOR_NAME: &str = "Contract";
 const CONSTRUCTOR_NAME: &str = "construct";
+const MIGRATION_NAME: &str = "migrate";
+const VIEW_ATTRIBUTE_NAME: &str = "view";
 
 const DEPLOY_FUNC_NAME: &str = "deploy";
 const DISPATCH_FUNC_NAME: &str = "dispatch";
@@ -20,6 +26,9 @@ pub(crate) fn impl_pub_interface(
     item: proc_macro2::TokenStream,
 ) -> proc_macro2::TokenStream {
     let cloned_item = item.clone();
+    // `#[view]` and the access control attributes are only meaningful to this macro,
+    // so they are removed from the generated code
+    let item = remove_method_attributes(item);
 
     // Make sure that even if we issue a compilation error, we still generate
     // the original code, just like a derive macro would.
@@ -49,15 +58,31 @@ pub(crate) fn impl_pub_interface(
 
     let aux_functions = generate_eng_wasm_aux_functions(&function_name_func_name, &args_func_name);
     let constructor_function =
-        generate_deploy_function(&deploy_func_name, &pub_interface_signatures);
+        generate_deploy_function(&deploy_func_name, &function_name_func_name, &pub_interface_signatures);
     let dispatch_function =
         generate_dispatch_function(&dispatch_func_name, &pub_interface_signatures);
 
+    let abi_manifest = generate_abi_manifest(&pub_interface_signatures);
+    if let Err(error) = write_abi_manifest(&abi_manifest) {
+        let compilation_error = error.to_compile_error();
+        return quote!(
+            #item
+            #compilation_error
+        );
+    }
+    // Embedding the manifest is opt-in because it makes the deployed bytecode larger.
+    let abi_section = if cfg!(feature = "abi-section") {
+        generate_abi_section(&abi_manifest)
+    } else {
+        quote!()
+    };
+
     quote! {
         #item
         #aux_functions
         #constructor_function
         #dispatch_function
+        #abi_section
 
         #[no_mangle]
         pub fn #call_func_name(){
@@ -66,6 +91,32 @@ pub(crate) fn impl_pub_interface(
     }
 }
 
+/// Remove the `#[view]` and access control attributes from the methods of the item.
+///
+/// If the item isn't a trait or an impl, it is returned as is, and the parsing reports the error.
+fn remove_method_attributes(item: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let is_kept = |attr: &syn::Attribute| !is_view_attribute(attr) && !is_access_control_attribute(attr);
+    match syn::parse2::<syn::Item>(item.clone()) {
+        Ok(syn::Item::Trait(mut item_trait)) => {
+            for trait_item in &mut item_trait.items {
+                if let syn::TraitItem::Method(method) = trait_item {
+                    method.attrs.retain(is_kept);
+                }
+            }
+            item_trait.into_token_stream()
+        }
+        Ok(syn::Item::Impl(mut item_impl)) => {
+            for impl_item in &mut item_impl.items {
+                if let syn::ImplItem::Method(method) = impl_item {
+                    method.attrs.retain(is_kept);
+                }
+            }
+            item_impl.into_token_stream()
+        }
+        _ => item,
+    }
+}
+
 /// Parse the arguments to the macro and apply them to the `PubInterfaceSignatures`
 ///
 /// The arguments to the macro are the parts written between parenthesis in the macro invocation.
@@ -176,45 +227,62 @@ fn generate_eng_wasm_aux_functions(
     }
 }
 
+/// Check if the method runs when the contract code is deployed, instead of being dispatched to.
+fn is_deploy_function(ident: &syn::Ident) -> bool {
+    ident == CONSTRUCTOR_NAME || ident == MIGRATION_NAME
+}
+
+/// Generate the `deploy` function, which runs the constructor when the contract is deployed,
+/// or the migration when the contract is upgraded to this code.
+///
+/// An upgrade of a contract without a migration only replaces its code.
 fn generate_deploy_function(
     deploy_func_name: &syn::Ident,
+    function_name_func_name: &syn::Ident,
     signatures: &PubInterfaceSignatures,
 ) -> proc_macro2::TokenStream {
-    if let Some(constructor_signature) = signatures
-        .signatures
-        .iter()
-        .find(|item| item.ident == CONSTRUCTOR_NAME)
-    {
-        let implementor = &signatures.implementor;
-        let constructor_name = &constructor_signature.ident;
-        let input_pats_and_types = get_signature_input_pats_and_types(&constructor_signature);
-        let expectations = get_contract_input_parsing_error_messages(&input_pats_and_types);
-
-        // Manually construct the code that implicitly checks properties of the input types
-        // so that the spans of type errors are correctly propagated
-        let parsed_inputs = input_pats_and_types
-            .iter()
-            .map(|(_pat, type_)| type_)
-            .zip(expectations)
-            .map(|(type_, expectation)|
-                quote_spanned!(type_.span()=> stream.pop::<#type_>().expect(#expectation))
-            );
-        let variables = generate_enumerated_idents("var_", input_pats_and_types.len());
+    let construction = generate_deploy_call(CONSTRUCTOR_NAME, signatures);
+    let migration = generate_deploy_call(MIGRATION_NAME, signatures);
 
-        return quote! {
-            #[no_mangle]
-            pub fn #deploy_func_name() {
-                let args_ = args();
-                let mut stream = eng_wasm::eng_pwasm_abi::eth::Stream::new(&args_);
-                #(let #variables = #parsed_inputs;)*
-                <#implementor>::#constructor_name(#(#variables),*);
+    quote! {
+        #[no_mangle]
+        pub fn #deploy_func_name() {
+            if #function_name_func_name() == #MIGRATION_NAME {
+                #migration
+            } else {
+                #construction
             }
-        };
-    } else {
-        return quote! {
-            #[no_mangle]
-            pub fn #deploy_func_name() {}
-        };
+        }
+    }
+}
+
+/// Generate the call of the constructor or migration with the decoded arguments, if the contract defines it.
+fn generate_deploy_call(name: &str, signatures: &PubInterfaceSignatures) -> proc_macro2::TokenStream {
+    let signature = match signatures.signatures.iter().find(|item| item.ident == name) {
+        Some(signature) => signature,
+        None => return quote!(),
+    };
+    let implementor = &signatures.implementor;
+    let method_name = &signature.ident;
+    let input_pats_and_types = get_signature_input_pats_and_types(&signature);
+    let expectations = get_contract_input_parsing_error_messages(&input_pats_and_types);
+
+    // Manually construct the code that implicitly checks properties of the input types
+    // so that the spans of type errors are correctly propagated
+    let parsed_inputs = input_pats_and_types
+        .iter()
+        .map(|(_pat, type_)| type_)
+        .zip(expectations)
+        .map(|(type_, expectation)|
+            quote_spanned!(type_.span()=> stream.pop::<#type_>().expect(#expectation))
+        );
+    let variables = generate_enumerated_idents("var_", input_pats_and_types.len());
+
+    quote! {
+        let args_ = args();
+        let mut stream = eng_wasm::eng_pwasm_abi::eth::Stream::new(&args_);
+        #(let #variables = #parsed_inputs;)*
+        <#implementor>::#method_name(#(#variables),*);
     }
 }
 
@@ -259,7 +327,7 @@ fn generate_dispatch_function(
         .iter()
         .filter_map(|signature| {
             let method_name = &signature.ident;
-            if method_name == CONSTRUCTOR_NAME {
+            if is_deploy_function(method_name) {
                 return None;
             }
 
@@ -270,75 +338,86 @@ fn generate_dispatch_function(
 
             // Manually construct the code that implicitly checks properties of the input types
             // so that the spans of type errors are correctly propagated
-            let parsed_inputs = input_pats_and_types
+            let parsed_inputs: Vec<proc_macro2::TokenStream> = input_pats_and_types
                 .iter()
                 .map(|(_pat, type_)| type_)
                 .zip(expectations)
                 .map(|(type_, expectation)|
                     quote_spanned!(type_.span()=> stream.pop::<#type_>().expect(#expectation))
-                );
+                )
+                .collect();
             let variables = generate_enumerated_idents("var_", input_pats_and_types.len());
 
-            let return_value_count = match output_type {
-                syn::ReturnType::Default => 0,
-                syn::ReturnType::Type(_, type_) => match type_.as_ref() {
-                    // If the return value is a tuple, we count it like multiple return values.
-                    // This is the same thing that pwasm_abi does under
-                    // pwasm_abi/derive/src/item.rs :: fn into_signature
-                    // which flows back to
-                    // pwasm_abi/derive/src/lib.rs :: fn generate_eth_endpoint
-                    // which dictates how return values are serialised into the Sink.
-                    // This can be 0 if the return type is () which is correct.
-                    syn::Type::Tuple(return_tuple) => return_tuple.elems.len(),
-                    // Any other type is a single return value. Arrays such as [u8; 4]
-                    // are not AbiType so Sink will reject them at compile time.
-                    _ => 1,
-                }
-            };
-
             // Make sure we only generate code for initializing the stream of inputs,
-            // if we expect inputs at all
+            // if we expect inputs at all.
+            // Views are started before anything else, so the runtime rejects any change to the state.
+            let view_snippet = if signatures.views.contains(method_name) {
+                quote!(eng_wasm::begin_view();)
+            } else {
+                quote!()
+            };
             let stream_initialization_snippet = match input_pats_and_types.len() {
-                0 => quote!(),
-                _ => quote!(let mut stream = eng_wasm::eng_pwasm_abi::eth::Stream::new(args);),
+                0 => view_snippet,
+                _ => quote!(#view_snippet let mut stream = eng_wasm::eng_pwasm_abi::eth::Stream::new(args);),
             };
-
-            match return_value_count {
-                0 => Some(quote! {
-                    #method_name_as_string => {
-                        #stream_initialization_snippet
-                        #(let #variables = #parsed_inputs;)*
-                        <#implementor>::#method_name(#(#variables),*);
+            // Calls that don't meet the access requirements are rejected before calling the method
+            let requirements: Vec<_> = signatures
+                .access_requirements
+                .iter()
+                .filter(|(name, _requirement)| name == method_name)
+                .map(|(_name, requirement)| requirement)
+                .collect();
+            let access_control_snippet =
+                generate_access_control_snippet(&requirements, signature, implementor, &variables);
+
+            let result_token = quote!(result);
+            match output_type {
+                syn::ReturnType::Type(_arrow, output_type) => {
+                    // Functions returning a `Result` revert the task on `Err`, so the state changes are
+                    // discarded, and the error is encoded like a return value into the output.
+                    if let Some((ok_type, err_type)) = get_result_types(output_type) {
+                        let ok_pattern = match count_return_values(ok_type) {
+                            0 => quote!(_),
+                            _ => result_token.clone(),
+                        };
+                        let return_snippet = generate_return_snippet(ok_type, &result_token);
+                        let revert_with_error = quote_spanned!(err_type.span()=> eng_wasm::revert_with(error));
+                        return Some(quote! {
+                            #method_name_as_string => {
+                                #stream_initialization_snippet
+                                #(let #variables = #parsed_inputs;)*
+                                #access_control_snippet
+                                match <#implementor>::#method_name(#(#variables),*) {
+                                    Ok(#ok_pattern) => { #return_snippet }
+                                    Err(error) => #revert_with_error,
+                                }
+                            }
+                        });
                     }
-                }),
-                _ => {
-                    // Manually construct the code that implicitly checks properties of the return type
-                    // so that the spans of type errors are correctly propagated
-                    let result_token = quote!(result);
-                    // disassociate the return type from the arrow.
-                    let output_type = match output_type {
-                        syn::ReturnType::Type(_arrow, type_) => type_,
-                        syn::ReturnType::Default => unreachable!("We know that there is an explicit return type")
-                    };
-                    let push_result_to_sink = quote_spanned!(output_type.span()=> sink.push(#result_token));
-                    Some(quote! {
-                        #method_name_as_string => {
-                            #stream_initialization_snippet
-                            #(let #variables = #parsed_inputs;)*
-                            let #result_token = <#implementor>::#method_name(#(#variables),*);
-                            // 32 is the size of each argument in the serialised form
-                            // The Sink.drain_to() method might resize this array if any
-                            // dynamically sized elements are returned, but if not, then only one
-                            // allocation (this one) will happen for the Vec.
-                            let mut result_bytes = eng_wasm::Vec::with_capacity(#return_value_count * 32);
-                            let mut sink = eng_wasm::eng_pwasm_abi::eth::Sink::new(#return_value_count);
-                            #push_result_to_sink;
-                            sink.drain_to(&mut result_bytes);
-                            unsafe { eng_wasm::external::ret(result_bytes.as_ptr(), result_bytes.len() as u32) }
-                        }
-                    })
-                },
+                    if count_return_values(output_type) > 0 {
+                        let return_snippet = generate_return_snippet(output_type, &result_token);
+                        return Some(quote! {
+                            #method_name_as_string => {
+                                #stream_initialization_snippet
+                                #(let #variables = #parsed_inputs;)*
+                                #access_control_snippet
+                                let #result_token = <#implementor>::#method_name(#(#variables),*);
+                                #return_snippet
+                            }
+                        });
+                    }
+                }
+                syn::ReturnType::Default => {}
             }
+
+            Some(quote! {
+                #method_name_as_string => {
+                    #stream_initialization_snippet
+                    #(let #variables = #parsed_inputs;)*
+                    #access_control_snippet
+                    <#implementor>::#method_name(#(#variables),*);
+                }
+            })
         })
         .collect();
 
@@ -352,6 +431,65 @@ fn generate_dispatch_function(
     }
 }
 
+/// If the type is `Result<T, E>`, return `T` and `E`.
+fn get_result_types(type_: &syn::Type) -> Option<(&syn::Type, &syn::Type)> {
+    let segment = match type_ {
+        syn::Type::Path(type_path) => type_path.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != "Result" {
+        return None;
+    }
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(arguments) if arguments.args.len() == 2 => {
+            match (&arguments.args[0], &arguments.args[1]) {
+                (syn::GenericArgument::Type(ok_type), syn::GenericArgument::Type(err_type)) => Some((ok_type, err_type)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Count the values returned by a function returning `type_`.
+fn count_return_values(type_: &syn::Type) -> usize {
+    match type_ {
+        // If the return value is a tuple, we count it like multiple return values.
+        // This is the same thing that pwasm_abi does under
+        // pwasm_abi/derive/src/item.rs :: fn into_signature
+        // which flows back to
+        // pwasm_abi/derive/src/lib.rs :: fn generate_eth_endpoint
+        // which dictates how return values are serialised into the Sink.
+        // This can be 0 if the return type is () which is correct.
+        syn::Type::Tuple(return_tuple) => return_tuple.elems.len(),
+        // Any other type is a single return value. Arrays such as [u8; 4]
+        // are not AbiType so Sink will reject them at compile time.
+        _ => 1,
+    }
+}
+
+/// Generate the code that serialises the value in `result_token` of type `output_type` and returns it.
+fn generate_return_snippet(output_type: &syn::Type, result_token: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let return_value_count = count_return_values(output_type);
+    if return_value_count == 0 {
+        return quote!();
+    }
+    // Manually construct the code that implicitly checks properties of the return type
+    // so that the spans of type errors are correctly propagated
+    let push_result_to_sink = quote_spanned!(output_type.span()=> sink.push(#result_token));
+    quote! {
+        // 32 is the size of each argument in the serialised form
+        // The Sink.drain_to() method might resize this array if any
+        // dynamically sized elements are returned, but if not, then only one
+        // allocation (this one) will happen for the Vec.
+        let mut result_bytes = eng_wasm::Vec::with_capacity(#return_value_count * 32);
+        let mut sink = eng_wasm::eng_pwasm_abi::eth::Sink::new(#return_value_count);
+        #push_result_to_sink;
+        sink.drain_to(&mut result_bytes);
+        unsafe { eng_wasm::external::ret(result_bytes.as_ptr(), result_bytes.len() as u32) }
+    }
+}
+
 /// Generate `count` identifiers with the specified prefix, and a decimal suffix in ascending order.
 ///
 /// for example, calling `generate_enumerated_idents("var_", 5)` will generate a vector with the
@@ -414,6 +552,8 @@ mod tests {
 
         let mut pub_interface_signatures = PubInterfaceSignatures {
             signatures: vec![],
+            views: vec![],
+            access_requirements: vec![],
             implementor: syn::parse2(quote!(Foo)).unwrap(),
             item_type: PubInterfaceItemType::ItemTrait,
         };
@@ -432,6 +572,8 @@ mod tests {
 
         let mut pub_interface_signatures = PubInterfaceSignatures {
             signatures: vec![],
+            views: vec![],
+            access_requirements: vec![],
             implementor: syn::parse2(quote!(Foo)).unwrap(),
             item_type: PubInterfaceItemType::ItemTrait,
         };
@@ -451,6 +593,8 @@ mod tests {
 
         let mut pub_interface_signatures = PubInterfaceSignatures {
             signatures: vec![],
+            views: vec![],
+            access_requirements: vec![],
             implementor: syn::parse2(quote!(Foo)).unwrap(),
             item_type: PubInterfaceItemType::ItemTrait,
         };
@@ -472,6 +616,8 @@ mod tests {
 
         let mut pub_interface_signatures = PubInterfaceSignatures {
             signatures: vec![],
+            views: vec![],
+            access_requirements: vec![],
             implementor: syn::parse2(quote!(Foo)).unwrap(),
             item_type: PubInterfaceItemType::ItemImpl,
         };
@@ -491,6 +637,8 @@ mod tests {
 
         let mut pub_interface_signatures = PubInterfaceSignatures {
             signatures: vec![],
+            views: vec![],
+            access_requirements: vec![],
             implementor: syn::parse2(quote!(Foo)).unwrap(),
             item_type: PubInterfaceItemType::ItemImpl,
         };
@@ -538,25 +686,81 @@ mod tests {
         let expected_output = quote!(
             #[no_mangle]
             pub fn deploy() {
-                let args_ = args();
-                let mut stream = eng_wasm::eng_pwasm_abi::eth::Stream::new(&args_);
-                let var_0 = stream
-                    .pop::<H256>()
-                    .expect("could not decode argument `contract_owner` as `H256`");
-                let var_1 = stream
-                    .pop::<U256>()
-                    .expect("could not decode argument `total_supply` as `U256`");
-                <Contract>::construct(var_0, var_1);
+                if function_name() == "migrate" {
+                } else {
+                    let args_ = args();
+                    let mut stream = eng_wasm::eng_pwasm_abi::eth::Stream::new(&args_);
+                    let var_0 = stream
+                        .pop::<H256>()
+                        .expect("could not decode argument `contract_owner` as `H256`");
+                    let var_1 = stream
+                        .pop::<U256>()
+                        .expect("could not decode argument `total_supply` as `U256`");
+                    <Contract>::construct(var_0, var_1);
+                }
+            }
+        );
+
+        let signatures = syn::parse2::<PubInterfaceSignatures>(input)?;
+        let output = generate_deploy_function(
+            &DEPLOY_FUNC_NAME.into_ident(),
+            &FUNCTION_NAME_FUNC_NAME.into_ident(),
+            &signatures,
+        );
+
+        assert_eq!(
+            syn::parse2::<syn::ItemFn>(output)?,
+            syn::parse2::<syn::ItemFn>(expected_output)?,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn deploy_generation_with_migration() -> syn::Result<()> {
+        let input = quote!(
+            pub trait Counter {
+                fn construct(admin: H160);
+                fn migrate(version: u32);
+                fn increment();
+            }
+        );
+
+        let expected_output = quote!(
+            #[no_mangle]
+            pub fn deploy() {
+                if function_name() == "migrate" {
+                    let args_ = args();
+                    let mut stream = eng_wasm::eng_pwasm_abi::eth::Stream::new(&args_);
+                    let var_0 = stream
+                        .pop::<u32>()
+                        .expect("could not decode argument `version` as `u32`");
+                    <Contract>::migrate(var_0);
+                } else {
+                    let args_ = args();
+                    let mut stream = eng_wasm::eng_pwasm_abi::eth::Stream::new(&args_);
+                    let var_0 = stream
+                        .pop::<H160>()
+                        .expect("could not decode argument `admin` as `H160`");
+                    <Contract>::construct(var_0);
+                }
             }
         );
 
         let signatures = syn::parse2::<PubInterfaceSignatures>(input)?;
-        let output = generate_deploy_function(&DEPLOY_FUNC_NAME.into_ident(), &signatures);
+        let output = generate_deploy_function(
+            &DEPLOY_FUNC_NAME.into_ident(),
+            &FUNCTION_NAME_FUNC_NAME.into_ident(),
+            &signatures,
+        );
 
         assert_eq!(
             syn::parse2::<syn::ItemFn>(output)?,
             syn::parse2::<syn::ItemFn>(expected_output)?,
         );
+
+        // The migration isn't exported as a function
+        let dispatch = generate_dispatch_function(&DISPATCH_FUNC_NAME.into_ident(), &signatures).to_string();
+        assert!(!dispatch.contains("migrate"));
         Ok(())
     }
 
@@ -721,4 +925,274 @@ mod tests {
         assert_eq!(output_ast, expected_output_ast);
         Ok(())
     }
+
+    #[test]
+    fn dispatch_generation_with_view() -> syn::Result<()> {
+        let input = quote!(
+            pub trait AccountInterface {
+                #[view]
+                fn balance(id: String) -> U256;
+                #[view]
+                fn ping();
+            }
+        );
+
+        #[rustfmt::skip]
+        let expected_output = quote!(
+            pub fn dispatch(name: &str, args: &[u8]) {
+                match name {
+                    "balance" => {
+                        eng_wasm::begin_view();
+                        let mut stream = eng_wasm::eng_pwasm_abi::eth::Stream::new(args);
+                        let var_0 = stream
+                            .pop::<String>()
+                            .expect("could not decode argument `id` as `String`");
+                        let result = <Contract>::balance(var_0);
+                        let mut result_bytes = eng_wasm::Vec::with_capacity(1usize * 32);
+                        let mut sink = eng_wasm::eng_pwasm_abi::eth::Sink::new(1usize);
+                        sink.push(result);
+                        sink.drain_to(&mut result_bytes);
+                        unsafe {
+                            eng_wasm::external::ret(result_bytes.as_ptr(), result_bytes.len() as u32)
+                        }
+                    }
+                    "ping" => {
+                        eng_wasm::begin_view();
+                        <Contract>::ping();
+                    }
+                    _ => panic!("Unknown method called:\"{}\"", name),
+                }
+            }
+        );
+
+        let expected_output_ast = syn::parse2::<syn::ItemFn>(expected_output)?;
+
+        let signatures = syn::parse2::<PubInterfaceSignatures>(input)?;
+        let output = generate_dispatch_function(&DISPATCH_FUNC_NAME.into_ident(), &signatures);
+        let output_ast = syn::parse2::<syn::ItemFn>(output)?;
+
+        assert_eq!(output_ast, expected_output_ast);
+        Ok(())
+    }
+
+    #[test]
+    fn dispatch_generation_with_access_control() -> syn::Result<()> {
+        let input = quote!(
+            pub trait AccountInterface {
+                #[requires_signature(signer = "owner", signature = "sig", message = "withdraw")]
+                #[guard(Self::is_open(owner))]
+                fn withdraw(owner: H160, sig: Vec<u8>);
+            }
+        );
+
+        #[rustfmt::skip]
+        let expected_output = quote!(
+            pub fn dispatch(name: &str, args: &[u8]) {
+                match name {
+                    "withdraw" => {
+                        let mut stream = eng_wasm::eng_pwasm_abi::eth::Stream::new(args);
+                        let var_0 = stream
+                            .pop::<H160>()
+                            .expect("could not decode argument `owner` as `H160`");
+                        let var_1 = stream
+                            .pop::<Vec<u8>>()
+                            .expect("could not decode argument `sig` as `Vec < u8 >`");
+                        if !eng_wasm::verify_signature(&var_0, "withdraw".as_bytes(), &var_1) {
+                            eng_wasm::revert_unauthorized()
+                        }
+                        if !<Contract>::is_open(&var_0) {
+                            eng_wasm::revert_unauthorized()
+                        }
+                        <Contract>::withdraw(var_0, var_1);
+                    }
+                    _ => panic!("Unknown method called:\"{}\"", name),
+                }
+            }
+        );
+
+        let expected_output_ast = syn::parse2::<syn::ItemFn>(expected_output)?;
+
+        let signatures = syn::parse2::<PubInterfaceSignatures>(input)?;
+        let output = generate_dispatch_function(&DISPATCH_FUNC_NAME.into_ident(), &signatures);
+        let output_ast = syn::parse2::<syn::ItemFn>(output)?;
+
+        assert_eq!(output_ast, expected_output_ast);
+        Ok(())
+    }
+
+    #[test]
+    fn method_attributes_are_removed() -> syn::Result<()> {
+        let input = quote!(
+            pub trait AccountInterface {
+                /// the balance
+                #[view]
+                #[guard(Self::is_owner(id))]
+                fn balance(id: String) -> U256;
+            }
+        );
+        let expected_output = quote!(
+            pub trait AccountInterface {
+                /// the balance
+                fn balance(id: String) -> U256;
+            }
+        );
+
+        let output_ast = syn::parse2::<syn::ItemTrait>(remove_method_attributes(input))?;
+        assert_eq!(output_ast, syn::parse2::<syn::ItemTrait>(expected_output)?);
+        Ok(())
+    }
+
+    #[test]
+    fn dispatch_generation_with_result() -> syn::Result<()> {
+        let input = quote!(
+            pub trait AccountInterface {
+                fn register(id: String) -> Result<(), String>;
+                fn show(id: String) -> Result<U256, AccountError>;
+            }
+        );
+
+        #[rustfmt::skip]
+        let expected_output = quote!(
+            pub fn dispatch(name: &str, args: &[u8]) {
+                match name {
+                    "register" => {
+                        let mut stream = eng_wasm::eng_pwasm_abi::eth::Stream::new(args);
+                        let var_0 = stream
+                            .pop::<String>()
+                            .expect("could not decode argument `id` as `String`");
+                        match <Contract>::register(var_0) {
+                            Ok(_) => {}
+                            Err(error) => eng_wasm::revert_with(error),
+                        }
+                    }
+                    "show" => {
+                        let mut stream = eng_wasm::eng_pwasm_abi::eth::Stream::new(args);
+                        let var_0 = stream
+                            .pop::<String>()
+                            .expect("could not decode argument `id` as `String`");
+                        match <Contract>::show(var_0) {
+                            Ok(result) => {
+                                let mut result_bytes = eng_wasm::Vec::with_capacity(1usize * 32);
+                                let mut sink = eng_wasm::eng_pwasm_abi::eth::Sink::new(1usize);
+                                sink.push(result);
+                                sink.drain_to(&mut result_bytes);
+                                unsafe {
+                                    eng_wasm::external::ret(
+                                        result_bytes.as_ptr(),
+                                        result_bytes.len() as u32
+                                    )
+                                }
+                            }
+                            Err(error) => eng_wasm::revert_with(error),
+                        }
+                    }
+                    _ => panic!("Unknown method called:\"{}\"", name),
+                }
+            }
+        );
+
+        let expected_output_ast = syn::parse2::<syn::ItemFn>(expected_output)?;
+
+        let signatures = syn::parse2::<PubInterfaceSignatures>(input)?;
+        let output = generate_dispatch_function(&DISPATCH_FUNC_NAME.into_ident(), &signatures);
+        let output_ast = syn::parse2::<syn::ItemFn>(output)?;
+
+        assert_eq!(output_ast, expected_output_ast);
+        Ok(())
+    }
+
+    #[test]
+    fn abi_manifest_generation() -> syn::Result<()> {
+        let input = quote!(
+            pub trait Erc20Interface {
+                fn construct(contract_owner: H256, total_supply: U256);
+                /// creates new tokens and sends to the specified address
+                #[guard(Self::is_minter(owner))]
+                fn mint(owner: H256, addr: H256, tokens: U256, sig: Vec<u8>);
+                /// get the total_supply
+                #[view]
+                fn total_supply() -> U256;
+                fn dynamic_types(bytes_arr: Vec<Vec<u8>>, string_arr: Vec<String>, eth_addr: H160);
+                fn pair() -> (bool, u64);
+                fn alias(amount: Balance) -> ();
+                fn withdraw(amount: U256) -> Result<(), String>;
+            }
+        );
+
+        let expected_manifest = serde_json::json!({
+            "constructor": {
+                "inputs": [
+                    { "name": "contract_owner", "type": "bytes32", "rustType": "H256" },
+                    { "name": "total_supply", "type": "uint256", "rustType": "U256" },
+                ]
+            },
+            "functions": [
+                {
+                    "name": "mint",
+                    "inputs": [
+                        { "name": "owner", "type": "bytes32", "rustType": "H256" },
+                        { "name": "addr", "type": "bytes32", "rustType": "H256" },
+                        { "name": "tokens", "type": "uint256", "rustType": "U256" },
+                        { "name": "sig", "type": "bytes", "rustType": "Vec < u8 >" },
+                    ],
+                    "outputs": [],
+                    "access": [{ "kind": "guard", "guard": "Self :: is_minter", "args": ["owner"] }],
+                },
+                {
+                    "name": "total_supply",
+                    "inputs": [],
+                    "outputs": [{ "type": "uint256", "rustType": "U256" }],
+                    "view": true,
+                },
+                {
+                    "name": "dynamic_types",
+                    "inputs": [
+                        { "name": "bytes_arr", "type": "bytes[]", "rustType": "Vec < Vec < u8 > >" },
+                        { "name": "string_arr", "type": "string[]", "rustType": "Vec < String >" },
+                        { "name": "eth_addr", "type": "address", "rustType": "H160" },
+                    ],
+                    "outputs": [],
+                },
+                {
+                    "name": "pair",
+                    "inputs": [],
+                    "outputs": [
+                        { "type": "bool", "rustType": "bool" },
+                        { "type": "uint64", "rustType": "u64" },
+                    ],
+                },
+                {
+                    "name": "alias",
+                    "inputs": [{ "name": "amount", "type": null, "rustType": "Balance" }],
+                    "outputs": [],
+                },
+                {
+                    "name": "withdraw",
+                    "inputs": [{ "name": "amount", "type": "uint256", "rustType": "U256" }],
+                    "outputs": [],
+                    "error": { "type": "string", "rustType": "String" },
+                },
+            ]
+        });
+
+        let signatures = syn::parse2::<PubInterfaceSignatures>(input)?;
+        assert_eq!(generate_abi_manifest(&signatures), expected_manifest);
+        Ok(())
+    }
+
+    #[test]
+    fn abi_manifest_without_constructor() -> syn::Result<()> {
+        let input = quote!(
+            impl Contract {
+                pub fn flip() -> bool {}
+            }
+        );
+
+        let signatures = syn::parse2::<PubInterfaceSignatures>(input)?;
+        let manifest = generate_abi_manifest(&signatures);
+
+        assert!(manifest["constructor"].is_null());
+        assert_eq!(manifest["functions"][0]["name"], "flip");
+        Ok(())
+    }
 }
@@ -0,0 +1,206 @@
+//! This module generates the JSON ABI manifest describing the interface exported by `#[pub_interface]`
+//!
+//! The manifest has the form:
+//! ```json
+//! {
+//!     "constructor": { "inputs": [{ "name": "total_supply", "type": "uint256", "rustType": "U256" }] },
+//!     "migration": { "inputs": [] },
+//!     "functions": [
+//!         {
+//!             "name": "balance_of",
+//!             "inputs": [{ "name": "token_owner", "type": "bytes32", "rustType": "H256" }],
+//!             "outputs": [{ "type": "uint256", "rustType": "U256" }],
+//!             "view": true
+//!         },
+//!         {
+//!             "name": "transfer",
+//!             "inputs": [..],
+//!             "outputs": [],
+//!             "error": { "type": "string", "rustType": "String" }
+//!         }
+//!     ]
+//! }
+//! ```
+//! The `migration` is only present if the contract defines `migrate`.
+//! Functions returning `Result<T, E>` have the outputs of `T`, and the `error` returned
+//! as the output of the reverted task when they return `Err`.
+//! Functions annotated with `#[view]` are marked with `"view": true`, and functions with access control
+//! attributes list their requirements under `access`, e.g.
+//! `[{ "kind": "signature", "signer": "address", "signature": "sig", "message": "..." }]` or
+//! `[{ "kind": "guard", "guard": "Self :: is_owner", "args": ["id", "pass"] }]`.
+//! The `type` of every parameter is its Ethereum ABI type, which is how arguments and return values
+//! are encoded. It is `null` if the rust type has no known ABI equivalent (e.g. a type alias).
+
+use std::path::Path;
+
+use ethabi::ParamType;
+use quote::{quote, ToTokens};
+use serde_json::{json, Value};
+
+use super::access_control::access_requirement_manifest;
+use super::parse_signatures::PubInterfaceSignatures;
+use super::{get_result_types, get_signature_input_pats_and_types, is_deploy_function, CONSTRUCTOR_NAME, MIGRATION_NAME};
+
+/// The name of the wasm custom section that holds the manifest.
+pub(crate) const ABI_SECTION_NAME: &str = "enigma_abi";
+/// The name of the manifest file written to `OUT_DIR`.
+pub(crate) const ABI_FILE_NAME: &str = "abi.json";
+
+/// Build the manifest for the exported signatures.
+pub(crate) fn generate_abi_manifest(signatures: &PubInterfaceSignatures) -> Value {
+    let constructor = signatures
+        .signatures
+        .iter()
+        .find(|signature| signature.ident == CONSTRUCTOR_NAME)
+        .map(|signature| json!({ "inputs": signature_inputs(signature) }));
+
+    let functions: Vec<Value> = signatures
+        .signatures
+        .iter()
+        .filter(|signature| !is_deploy_function(&signature.ident))
+        .map(|signature| {
+            let mut function = json!({
+                "name": signature.ident.to_string(),
+                "inputs": signature_inputs(signature),
+                "outputs": signature_outputs(signature),
+            });
+            if let syn::ReturnType::Type(_, type_) = &signature.output {
+                if let Some((_, err_type)) = get_result_types(type_) {
+                    function["error"] = abi_param(err_type);
+                }
+            }
+            if signatures.views.contains(&signature.ident) {
+                function["view"] = json!(true);
+            }
+            let access: Vec<Value> = signatures
+                .access_requirements
+                .iter()
+                .filter(|(name, _requirement)| *name == signature.ident)
+                .map(|(_name, requirement)| access_requirement_manifest(requirement))
+                .collect();
+            if !access.is_empty() {
+                function["access"] = json!(access);
+            }
+            function
+        })
+        .collect();
+
+    let mut manifest = json!({
+        "constructor": constructor,
+        "functions": functions,
+    });
+    if let Some(migration) = signatures.signatures.iter().find(|signature| signature.ident == MIGRATION_NAME) {
+        manifest["migration"] = json!({ "inputs": signature_inputs(migration) });
+    }
+    manifest
+}
+
+/// Generate the static that embeds the manifest in the wasm custom section `enigma_abi`.
+pub(crate) fn generate_abi_section(manifest: &Value) -> proc_macro2::TokenStream {
+    let manifest = manifest.to_string();
+    let manifest_len = manifest.len();
+    let manifest_bytes = syn::LitByteStr::new(manifest.as_bytes(), proc_macro2::Span::call_site());
+
+    quote! {
+        #[cfg_attr(target_arch = "wasm32", link_section = #ABI_SECTION_NAME)]
+        #[used]
+        #[doc(hidden)]
+        pub static __ENG_WASM_ABI_MANIFEST: [u8; #manifest_len] = *#manifest_bytes;
+    }
+}
+
+/// Write the manifest to `$OUT_DIR/abi.json`.
+///
+/// `OUT_DIR` is only defined for crates that have a build script, so if it's missing nothing is written.
+pub(crate) fn write_abi_manifest(manifest: &Value) -> syn::Result<()> {
+    let out_dir = match std::env::var_os("OUT_DIR") {
+        Some(out_dir) => out_dir,
+        None => return Ok(()),
+    };
+    let path = Path::new(&out_dir).join(ABI_FILE_NAME);
+    // `to_string_pretty` on a `Value` can't fail
+    let manifest = serde_json::to_string_pretty(manifest).unwrap();
+
+    std::fs::write(&path, manifest).map_err(|err| {
+        syn::Error::new(
+            proc_macro2::Span::call_site(),
+            format!("failed writing the ABI manifest to {}: {}", path.display(), err),
+        )
+    })
+}
+
+fn signature_inputs(signature: &syn::Signature) -> Vec<Value> {
+    get_signature_input_pats_and_types(signature)
+        .into_iter()
+        .map(|(pat, type_)| {
+            let mut param = abi_param(type_);
+            param["name"] = json!(pat.to_token_stream().to_string());
+            param
+        })
+        .collect()
+}
+
+fn signature_outputs(signature: &syn::Signature) -> Vec<Value> {
+    match &signature.output {
+        syn::ReturnType::Default => vec![],
+        syn::ReturnType::Type(_, type_) => match get_result_types(type_) {
+            Some((ok_type, _)) => type_outputs(ok_type),
+            None => type_outputs(type_),
+        },
+    }
+}
+
+fn type_outputs(type_: &syn::Type) -> Vec<Value> {
+    match type_ {
+        // Tuples are returned as multiple values, see `generate_dispatch_function`
+        syn::Type::Tuple(return_tuple) => return_tuple.elems.iter().map(abi_param).collect(),
+        type_ => vec![abi_param(type_)],
+    }
+}
+
+fn abi_param(type_: &syn::Type) -> Value {
+    json!({
+        "type": abi_param_type(type_).map(|param_type| param_type.to_string()),
+        "rustType": type_.to_token_stream().to_string(),
+    })
+}
+
+/// Map a rust type to the Ethereum ABI type used to encode it by `eng_pwasm_abi`.
+fn abi_param_type(type_: &syn::Type) -> Option<ParamType> {
+    let segment = match type_ {
+        syn::Type::Path(type_path) => type_path.path.segments.last()?,
+        _ => return None,
+    };
+
+    let param_type = match segment.ident.to_string().as_str() {
+        "bool" => ParamType::Bool,
+        "u32" => ParamType::Uint(32),
+        "u64" => ParamType::Uint(64),
+        "i32" => ParamType::Int(32),
+        "i64" => ParamType::Int(64),
+        "U256" => ParamType::Uint(256),
+        "H256" => ParamType::FixedBytes(32),
+        "H160" | "Address" => ParamType::Address,
+        "String" => ParamType::String,
+        "Vec" => {
+            let element = vec_element_type(segment)?;
+            if element.to_token_stream().to_string() == "u8" {
+                ParamType::Bytes
+            } else {
+                ParamType::Array(Box::new(abi_param_type(element)?))
+            }
+        }
+        _ => return None,
+    };
+    Some(param_type)
+}
+
+fn vec_element_type(segment: &syn::PathSegment) -> Option<&syn::Type> {
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(arguments) => match arguments.args.first()? {
+            syn::GenericArgument::Type(element) => Some(element),
+            _ => None,
+        },
+        _ => None,
+    }
+}
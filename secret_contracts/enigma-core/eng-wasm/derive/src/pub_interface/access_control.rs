@@ -0,0 +1,225 @@
+//! This module handles the access control attributes of the methods exported by `#[pub_interface]`
+//!
+//! * `#[requires_signature(signer = "address", signature = "sig", message = "Authentication by you")]`
+//!   accepts the call only if the `sig` argument is a signature by the `address` argument on the message,
+//!   see `eng_wasm::verify_signature`.
+//! * `#[guard(Self::is_owner(id, pass))]` accepts the call only if the function returns `true`
+//!   for references to the named arguments.
+//!
+//! The requirements are checked in order before the method is called, and a rejected call reverts
+//! the task with `eng_wasm::UNAUTHORIZED_ERROR` as its error.
+
+use quote::{quote, quote_spanned, ToTokens};
+use serde_json::{json, Value};
+use syn::spanned::Spanned;
+
+use super::parse_signatures::ParseError;
+use super::{get_signature_input_pats_and_types, is_deploy_function};
+
+const REQUIRES_SIGNATURE_ATTRIBUTE_NAME: &str = "requires_signature";
+const GUARD_ATTRIBUTE_NAME: &str = "guard";
+
+/// A requirement that a call has to meet before the method is called.
+#[derive(Clone)]
+pub(crate) enum AccessRequirement {
+    /// The `signature` argument is a signature by the `signer` argument on `message`.
+    Signature {
+        signer: syn::Ident,
+        signature: syn::Ident,
+        message: syn::LitStr,
+    },
+    /// `guard` returns `true` when called with references to `args`.
+    Guard {
+        guard: syn::Path,
+        args: Vec<syn::Ident>,
+    },
+}
+
+impl AccessRequirement {
+    /// The arguments of the method that the requirement refers to.
+    fn args(&self) -> Vec<&syn::Ident> {
+        match self {
+            AccessRequirement::Signature { signer, signature, .. } => vec![signer, signature],
+            AccessRequirement::Guard { args, .. } => args.iter().collect(),
+        }
+    }
+}
+
+/// Check if the attribute is one of the access control attributes
+pub(crate) fn is_access_control_attribute(attr: &syn::Attribute) -> bool {
+    attr.path.is_ident(REQUIRES_SIGNATURE_ATTRIBUTE_NAME) || attr.path.is_ident(GUARD_ATTRIBUTE_NAME)
+}
+
+/// Parse an access control attribute of the method with `signature`.
+pub(crate) fn parse_access_requirement(
+    attr: &syn::Attribute,
+    signature: &syn::Signature,
+) -> syn::Result<AccessRequirement> {
+    if is_deploy_function(&signature.ident) {
+        return Err(syn::Error::new_spanned(
+            attr,
+            ParseError::AccessControlOnConstructor,
+        ));
+    }
+
+    let requirement = if attr.path.is_ident(GUARD_ATTRIBUTE_NAME) {
+        parse_guard(attr)?
+    } else {
+        parse_requires_signature(attr)?
+    };
+
+    let arg_names = get_signature_arg_names(signature);
+    for arg in requirement.args() {
+        if !arg_names.contains(&arg.to_string()) {
+            return Err(syn::Error::new_spanned(
+                arg,
+                ParseError::UnknownAccessControlArgument,
+            ));
+        }
+    }
+    Ok(requirement)
+}
+
+fn parse_requires_signature(attr: &syn::Attribute) -> syn::Result<AccessRequirement> {
+    let bad_attribute = || syn::Error::new_spanned(attr, ParseError::BadRequiresSignature);
+
+    let meta_list = match attr.parse_meta() {
+        Ok(syn::Meta::List(meta_list)) => meta_list,
+        _ => return Err(bad_attribute()),
+    };
+
+    let (mut signer, mut signature, mut message) = (None, None, None);
+    for nested in &meta_list.nested {
+        let name_value = match nested {
+            syn::NestedMeta::Meta(syn::Meta::NameValue(name_value)) => name_value,
+            _ => return Err(bad_attribute()),
+        };
+        let value = match &name_value.lit {
+            syn::Lit::Str(value) => value,
+            _ => return Err(bad_attribute()),
+        };
+
+        if name_value.path.is_ident("signer") {
+            signer = Some(value.parse::<syn::Ident>()?);
+        } else if name_value.path.is_ident("signature") {
+            signature = Some(value.parse::<syn::Ident>()?);
+        } else if name_value.path.is_ident("message") {
+            message = Some(value.clone());
+        } else {
+            return Err(bad_attribute());
+        }
+    }
+
+    match (signer, signature, message) {
+        (Some(signer), Some(signature), Some(message)) => Ok(AccessRequirement::Signature {
+            signer,
+            signature,
+            message,
+        }),
+        _ => Err(bad_attribute()),
+    }
+}
+
+fn parse_guard(attr: &syn::Attribute) -> syn::Result<AccessRequirement> {
+    let bad_attribute = || syn::Error::new_spanned(attr, ParseError::BadGuard);
+
+    let call: syn::ExprCall = attr.parse_args().map_err(|_err| bad_attribute())?;
+    let guard = match *call.func {
+        syn::Expr::Path(expr_path) => expr_path.path,
+        _ => return Err(bad_attribute()),
+    };
+    let args = call
+        .args
+        .iter()
+        .map(|arg| match arg {
+            syn::Expr::Path(expr_path) => expr_path.path.get_ident().cloned().ok_or_else(bad_attribute),
+            _ => Err(bad_attribute()),
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    Ok(AccessRequirement::Guard { guard, args })
+}
+
+fn get_signature_arg_names(signature: &syn::Signature) -> Vec<String> {
+    get_signature_input_pats_and_types(signature)
+        .into_iter()
+        .map(|(pat, _type)| pat.to_token_stream().to_string())
+        .collect()
+}
+
+/// Generate the checks of the requirements of the method with `signature`,
+/// where `variables` hold its decoded arguments.
+pub(crate) fn generate_access_control_snippet(
+    requirements: &[&AccessRequirement],
+    signature: &syn::Signature,
+    implementor: &syn::Type,
+    variables: &[syn::Ident],
+) -> proc_macro2::TokenStream {
+    let arg_names = get_signature_arg_names(signature);
+    // The arguments were checked to exist while parsing
+    let variable_of = |arg: &syn::Ident| {
+        let index = arg_names
+            .iter()
+            .position(|name| *name == arg.to_string())
+            .unwrap();
+        &variables[index]
+    };
+
+    let checks = requirements.iter().map(|requirement| match requirement {
+        AccessRequirement::Signature {
+            signer,
+            signature,
+            message,
+        } => {
+            let signer = variable_of(signer);
+            let signature = variable_of(signature);
+            quote_spanned!(message.span()=> eng_wasm::verify_signature(&#signer, #message.as_bytes(), &#signature))
+        }
+        AccessRequirement::Guard { guard, args } => {
+            let guard_tokens = resolve_self(guard, implementor);
+            let args = args.iter().map(variable_of);
+            quote_spanned!(guard.span()=> #guard_tokens(#(&#args),*))
+        }
+    });
+
+    quote! {
+        #(
+            if !#checks {
+                eng_wasm::revert_unauthorized()
+            }
+        )*
+    }
+}
+
+/// The generated dispatcher isn't a method of the implementor, so `Self` in the guard is replaced with it.
+fn resolve_self(path: &syn::Path, implementor: &syn::Type) -> proc_macro2::TokenStream {
+    let mut segments = path.segments.iter();
+    match segments.next() {
+        Some(first) if path.leading_colon.is_none() && first.ident == "Self" => {
+            let rest = segments;
+            quote!(<#implementor> #(:: #rest)*)
+        }
+        _ => path.to_token_stream(),
+    }
+}
+
+/// Describe the requirement in the ABI manifest.
+pub(crate) fn access_requirement_manifest(requirement: &AccessRequirement) -> Value {
+    match requirement {
+        AccessRequirement::Signature {
+            signer,
+            signature,
+            message,
+        } => json!({
+            "kind": "signature",
+            "signer": signer.to_string(),
+            "signature": signature.to_string(),
+            "message": message.value(),
+        }),
+        AccessRequirement::Guard { guard, args } => json!({
+            "kind": "guard",
+            "guard": guard.to_token_stream().to_string(),
+            "args": args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>(),
+        }),
+    }
+}
@@ -8,7 +8,8 @@ use parse_display::Display;
 use crate::into_ident::IntoIdent;
 use crate::reduce_mut::ReduceMut;
 
-use crate::pub_interface::CONSTRUCTOR_NAME;
+use crate::pub_interface::access_control::{is_access_control_attribute, parse_access_requirement, AccessRequirement};
+use crate::pub_interface::{is_deploy_function, VIEW_ATTRIBUTE_NAME};
 
 /// Failures that can happen while parsing the macro input.
 ///
@@ -21,10 +22,10 @@ pub(crate) enum ParseError {
     #[display("pub_interface item can not be a trait impl")]
     TraitImpl,
 
-    #[display("The constructor function of a secret contract should be `pub`")]
+    #[display("The constructor and migration functions of a secret contract should be `pub`")]
     PrivateImplConstructor,
 
-    #[display("The constructor function of a secret contract should not have a return value")]
+    #[display("The constructor and migration functions of a secret contract should not have a return value")]
     ConstructorWithReturnValue,
 
     #[display("pub_interface item can not have methods that receive `self`")]
@@ -50,6 +51,24 @@ pub(crate) enum ParseError {
 
     #[display("custom implementors are not supported when pub_interface is applied to `impl`s")]
     CustomImplementorOnImpl,
+
+    #[display("the view attribute does not take arguments")]
+    ViewWithArguments,
+
+    #[display("The constructor and migration functions of a secret contract can not be views")]
+    ViewConstructor,
+
+    #[display(r#"requires_signature expects `signer = "arg", signature = "arg", message = "..."`"#)]
+    BadRequiresSignature,
+
+    #[display("guard expects a call of a function with arguments of the method, e.g. `#[guard(Self::is_owner(id, pass))]`")]
+    BadGuard,
+
+    #[display("access control attributes can only refer to the arguments of the method")]
+    UnknownAccessControlArgument,
+
+    #[display("The constructor and migration functions of a secret contract can not have access control attributes")]
+    AccessControlOnConstructor,
 }
 
 /// This enum is used to present the result of the speculative parsing inside
@@ -79,6 +98,12 @@ pub(crate) struct PubInterfaceSignatures {
     /// The list of exported signatures
     pub(crate) signatures: Vec<syn::Signature>,
 
+    /// The names of the exported methods annotated with `#[view]`
+    pub(crate) views: Vec<syn::Ident>,
+
+    /// The access requirements of the exported methods, in the order they are checked
+    pub(crate) access_requirements: Vec<(syn::Ident, AccessRequirement)>,
+
     /// This records what kind of item the macro was applied to
     pub(crate) item_type: PubInterfaceItemType,
 }
@@ -115,7 +140,7 @@ impl syn::parse::Parse for PubInterfaceSignatures {
 
         let item_type: PubInterfaceItemType;
         let implementor: syn::Type;
-        let signatures = match pub_interface_input {
+        let methods = match pub_interface_input {
             PubInterfaceInput::ItemTrait(item_trait) => {
                 item_type = PubInterfaceItemType::ItemTrait;
                 let default_implementor_name = super::DEFAULT_IMPLEMENTOR_NAME.into_ident();
@@ -133,20 +158,62 @@ impl syn::parse::Parse for PubInterfaceSignatures {
             }
         }?;
 
+        let views = methods
+            .iter()
+            .filter(|(_signature, attrs)| has_view_attribute(attrs))
+            .map(|(signature, _attrs)| signature.ident.clone())
+            .collect();
+        let access_requirements = get_access_requirements(&methods)?;
+        let signatures = methods
+            .into_iter()
+            .map(|(signature, _attrs)| signature)
+            .collect();
+
         Ok(Self {
             implementor,
             signatures,
+            views,
+            access_requirements,
             item_type,
         })
     }
 }
 
-/// Extract the signatures of methods defined in a trait.
+/// Parse the access control attributes of the methods.
+///
+/// If errors are found while parsing, they are all `.combine()`d into one error.
+fn get_access_requirements(
+    methods: &[(syn::Signature, Vec<syn::Attribute>)],
+) -> Result<Vec<(syn::Ident, AccessRequirement)>, syn::Error> {
+    let (requirements, errors) = methods
+        .iter()
+        .flat_map(|(signature, attrs)| {
+            attrs
+                .iter()
+                .filter(|attr| is_access_control_attribute(attr))
+                .map(move |attr| {
+                    parse_access_requirement(attr, signature)
+                        .map(|requirement| (signature.ident.clone(), requirement))
+                })
+        })
+        .partition::<Vec<_>, _>(|item| item.is_ok());
+
+    if errors.is_empty() {
+        Ok(requirements.into_iter().map(|res| res.unwrap()).collect())
+    } else {
+        let errors = errors.into_iter().map(|res| res.err().unwrap());
+        // We can unwrap here because we know that `errors` wasn't empty.`
+        let combined_errors = errors.reduce_mut(syn::Error::combine).unwrap();
+        Err(combined_errors)
+    }
+}
+
+/// Extract the signatures of methods defined in a trait, with their attributes.
 ///
 /// If errors are found while parsing, they are all `.combine()`d into one error.
 fn get_signatures_from_item_trait(
     item_trait: syn::ItemTrait,
-) -> Result<Vec<syn::Signature>, syn::Error> {
+) -> Result<Vec<(syn::Signature, Vec<syn::Attribute>)>, syn::Error> {
     let (trait_methods, errors) = item_trait
         .items
         .into_iter()
@@ -167,7 +234,7 @@ fn get_signatures_from_item_trait(
         Ok(trait_methods
             .into_iter()
             .map(|res| res.unwrap())
-            .map(|method| method.sig)
+            .map(|method| (method.sig, method.attrs))
             .collect())
     } else {
         let errors = errors.into_iter().map(|res| res.err().unwrap()).flatten();
@@ -189,10 +256,10 @@ fn get_trait_item_as_method(trait_item: syn::TraitItem) -> Option<syn::TraitItem
 /// Check if the trait method is defined the way we want it.
 ///
 /// Specifically we check that:
-/// * it has no additional attributes on it (except doc comments)
+/// * it has no additional attributes on it (except doc comments, `#[view]` and access control)
 /// * it has no `self` receiver
 /// * it has no default implementation
-/// * if it's the constructor function, we make sure it returns nothing.
+/// * if it's the constructor or migration function, we make sure it returns nothing.
 fn check_trait_method(
     trait_method: syn::TraitItemMethod,
 ) -> Result<syn::TraitItemMethod, Vec<syn::Error>> {
@@ -202,7 +269,7 @@ fn check_trait_method(
         if attr
             .path
             .get_ident()
-            .map(|ident| ident != "doc")
+            .map(|ident| ident != "doc" && ident != VIEW_ATTRIBUTE_NAME && !is_access_control_attribute(attr))
             // if it's not a simple ident, it's definitely not allowed
             .unwrap_or(true)
         {
@@ -222,7 +289,7 @@ fn check_trait_method(
         }
     });
 
-    if trait_method.sig.ident == CONSTRUCTOR_NAME && signature_has_return_value(&trait_method.sig) {
+    if is_deploy_function(&trait_method.sig.ident) && signature_has_return_value(&trait_method.sig) {
         errors.push(syn::Error::new_spanned(
             trait_method.sig.output.clone(),
             ParseError::ConstructorWithReturnValue,
@@ -236,6 +303,8 @@ fn check_trait_method(
         ))
     }
 
+    check_view_attributes(&trait_method.attrs, &trait_method.sig, &mut errors);
+
     if errors.is_empty() {
         Ok(trait_method)
     } else {
@@ -243,12 +312,12 @@ fn check_trait_method(
     }
 }
 
-/// Extract the signatures of methods defined in an impl.
+/// Extract the signatures of methods defined in an impl, with their attributes.
 ///
 /// If errors are found while parsing, they are all `.combine()`d into one error.
 fn get_signatures_from_item_impl(
     item_impl: syn::ItemImpl,
-) -> Result<Vec<syn::Signature>, syn::Error> {
+) -> Result<Vec<(syn::Signature, Vec<syn::Attribute>)>, syn::Error> {
     // Split the methods to private and non-private
     let (priv_methods, non_priv_methods) = item_impl
         .items
@@ -288,7 +357,7 @@ fn get_signatures_from_item_impl(
         Ok(impl_non_priv_methods
             .into_iter()
             .map(|res| res.unwrap())
-            .map(|method| method.sig)
+            .map(|method| (method.sig, method.attrs))
             .collect())
     } else {
         let errors = errors.into_iter().map(|res| res.err().unwrap()).flatten();
@@ -319,14 +388,14 @@ fn is_impl_item_method_private(method: &syn::ImplItemMethod) -> bool {
 /// Check if the private impl-method is defined the way we want it.
 ///
 /// Specifically we check that:
-/// * it is not named like the constructor.
-/// * if it's named like a constructor, check that it looks like a valid constructor.
+/// * it is not named like the constructor or the migration.
+/// * if it's named like one of them, check that it looks like a valid constructor.
 fn check_impl_priv_method(
     impl_method: syn::ImplItemMethod,
 ) -> Result<syn::ImplItemMethod, Vec<syn::Error>> {
     let mut errors = Vec::new();
 
-    if impl_method.sig.ident == CONSTRUCTOR_NAME {
+    if is_deploy_function(&impl_method.sig.ident) {
         errors.push(syn::Error::new_spanned(
             impl_method.sig.clone(),
             ParseError::PrivateImplConstructor,
@@ -386,13 +455,15 @@ fn check_impl_non_priv_method(
         }
     });
 
-    if impl_method.sig.ident == CONSTRUCTOR_NAME && signature_has_return_value(&impl_method.sig) {
+    if is_deploy_function(&impl_method.sig.ident) && signature_has_return_value(&impl_method.sig) {
         errors.push(syn::Error::new_spanned(
             impl_method.sig.output.clone(),
             ParseError::ConstructorWithReturnValue,
         ))
     }
 
+    check_view_attributes(&impl_method.attrs, &impl_method.sig, &mut errors);
+
     if errors.is_empty() {
         Ok(impl_method)
     } else {
@@ -400,6 +471,34 @@ fn check_impl_non_priv_method(
     }
 }
 
+/// Check if the attribute is `#[view]`
+pub(crate) fn is_view_attribute(attr: &syn::Attribute) -> bool {
+    attr.path.is_ident(VIEW_ATTRIBUTE_NAME)
+}
+
+/// Check if the attributes of a method contain `#[view]`
+fn has_view_attribute(attrs: &[syn::Attribute]) -> bool {
+    attrs.iter().any(is_view_attribute)
+}
+
+/// Check that `#[view]` is written without arguments, and isn't applied to the constructor or migration.
+fn check_view_attributes(attrs: &[syn::Attribute], signature: &syn::Signature, errors: &mut Vec<syn::Error>) {
+    for attr in attrs.iter().filter(|attr| is_view_attribute(attr)) {
+        if !attr.tokens.is_empty() {
+            errors.push(syn::Error::new_spanned(
+                attr.clone(),
+                ParseError::ViewWithArguments,
+            ))
+        }
+        if is_deploy_function(&signature.ident) {
+            errors.push(syn::Error::new_spanned(
+                attr.clone(),
+                ParseError::ViewConstructor,
+            ))
+        }
+    }
+}
+
 /// This function checks if the function has a non-unit return type.
 ///
 /// This has two syntactic forms, either the function specifies no return type at all,
@@ -420,6 +519,9 @@ fn signature_has_return_value(signature: &syn::Signature) -> bool {
 #[cfg(test)]
 mod tests {
     use quote::quote;
+    use serde_json::json;
+
+    use crate::pub_interface::access_control::access_requirement_manifest;
 
     use super::*;
 
@@ -584,6 +686,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn migration_in_trait_with_return_type_or_view_error() {
+        let tokens = quote!(
+            trait Foo {
+                #[view]
+                fn migrate() -> u32;
+            }
+        );
+
+        let parse_errors = syn::parse2::<PubInterfaceSignatures>(tokens)
+            .err()
+            .expect("This macro should not accept migrations with return types or views");
+
+        assert_eq!(
+            vec![
+                ParseError::ConstructorWithReturnValue.to_string(),
+                ParseError::ViewConstructor.to_string(),
+            ],
+            parse_errors
+                .into_iter()
+                .map(|parse_error| parse_error.to_string())
+                .collect::<Vec<_>>(),
+        );
+    }
+
     #[test]
     fn constructor_in_impl_with_no_return_type() -> syn::Result<()> {
         let tokens = quote!(
@@ -707,6 +834,137 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn view_methods_parsing() -> syn::Result<()> {
+        let trait_tokens = quote!(
+            trait Foo {
+                #[view]
+                fn foo() -> usize;
+                fn bar(_bar: i32);
+            }
+        );
+        let impl_tokens = quote!(
+            impl Foo {
+                #[view]
+                pub fn foo() -> usize {}
+                pub fn bar(_bar: i32) {}
+            }
+        );
+
+        for tokens in vec![trait_tokens, impl_tokens] {
+            let signatures = syn::parse2::<PubInterfaceSignatures>(tokens)?;
+            assert_eq!(
+                signatures.signatures,
+                vec![
+                    signature_of!(fn foo() -> usize;),
+                    signature_of!(fn bar(_bar: i32);)
+                ],
+            );
+            assert_eq!(signatures.views, vec!["foo".into_ident()]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bad_view_attributes_errors() {
+        let tokens = quote!(
+            trait Foo {
+                #[view]
+                fn construct();
+
+                #[view(true)]
+                fn foo();
+            }
+        );
+
+        let parse_errors = syn::parse2::<PubInterfaceSignatures>(tokens)
+            .err()
+            .expect("The macro should not accept a view constructor or arguments to view");
+
+        assert_eq!(
+            vec![
+                ParseError::ViewConstructor.to_string(),
+                ParseError::ViewWithArguments.to_string(),
+            ],
+            parse_errors
+                .into_iter()
+                .map(|parse_error| parse_error.to_string())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn access_control_methods_parsing() -> syn::Result<()> {
+        let tokens = quote!(
+            trait Foo {
+                #[requires_signature(signer = "addr", signature = "sig", message = "hello")]
+                fn foo(addr: H160, sig: Vec<u8>);
+                #[view]
+                #[guard(Self::is_owner(id, pass))]
+                fn bar(id: String, pass: String) -> u32;
+                fn baz();
+            }
+        );
+
+        let signatures = syn::parse2::<PubInterfaceSignatures>(tokens)?;
+
+        assert_eq!(signatures.views, vec!["bar".into_ident()]);
+        assert_eq!(
+            vec![
+                (
+                    "foo".to_string(),
+                    json!({"kind": "signature", "signer": "addr", "signature": "sig", "message": "hello"})
+                ),
+                (
+                    "bar".to_string(),
+                    json!({"kind": "guard", "guard": "Self :: is_owner", "args": ["id", "pass"]})
+                ),
+            ],
+            signatures
+                .access_requirements
+                .iter()
+                .map(|(ident, requirement)| (ident.to_string(), access_requirement_manifest(requirement)))
+                .collect::<Vec<_>>(),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bad_access_control_attributes_errors() {
+        let tokens = quote!(
+            trait Foo {
+                #[guard(Self::is_owner())]
+                fn construct();
+
+                #[requires_signature(signer = "addr", message = "hello")]
+                fn foo(addr: H160, sig: Vec<u8>);
+
+                #[guard(is_owner)]
+                fn bar(id: String);
+
+                #[guard(Self::is_owner(id, pass))]
+                fn baz(id: String);
+            }
+        );
+
+        let parse_errors = syn::parse2::<PubInterfaceSignatures>(tokens)
+            .err()
+            .expect("The macro should not accept malformed access control attributes");
+
+        assert_eq!(
+            vec![
+                ParseError::AccessControlOnConstructor.to_string(),
+                ParseError::BadRequiresSignature.to_string(),
+                ParseError::BadGuard.to_string(),
+                ParseError::UnknownAccessControlArgument.to_string(),
+            ],
+            parse_errors
+                .into_iter()
+                .map(|parse_error| parse_error.to_string())
+                .collect::<Vec<_>>(),
+        )
+    }
+
     #[test]
     fn trait_methods_with_self_receiver() {
         let tokens = quote!(
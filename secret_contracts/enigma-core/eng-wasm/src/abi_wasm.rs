@@ -0,0 +1,119 @@
+//! Encoding of user defined types in the arguments and return values of exported functions.
+//!
+//! Types annotated with `#[derive(EngAbi)]` are encoded as ABI `bytes`, which hold the ABI encoding
+//! of the tuple of their fields. Enums are encoded the same way, with the variant index as a `uint32`
+//! before the fields of the variant. Inside such types `Option<T>` is the tuple `(bool[, T])` and
+//! `Vec<T>` is the tuple `(uint32 length, T...)`, so they can hold any other `EngAbi` type.
+use super::*;
+use eng_pwasm_abi::eth::{AbiType, Error, Sink, Stream};
+
+/// A type that can be a field of a type deriving `EngAbi`.
+pub trait EngAbi: Sized {
+    /// Push the value into the sink as a single ABI value.
+    fn push(self, sink: &mut Sink);
+    /// Pop a single ABI value from the stream.
+    fn pop(stream: &mut Stream) -> Result<Self, Error>;
+}
+
+macro_rules! impl_eng_abi_for_abi_type {
+    ( $($type_: ty),+ ) => {
+        $(
+            impl EngAbi for $type_ {
+                fn push(self, sink: &mut Sink) { sink.push(self) }
+
+                fn pop(stream: &mut Stream) -> Result<Self, Error> { stream.pop() }
+            }
+        )+
+    }
+}
+
+impl_eng_abi_for_abi_type!(bool, u32, u64, i32, i64, U256, H256, Address, String, Vec<u8>);
+
+impl<T: EngAbi> EngAbi for Option<T> {
+    fn push(self, sink: &mut Sink) {
+        let bytes = match self {
+            Some(value) => encode_fields(2, |fields| {
+                fields.push(true);
+                value.push(fields);
+            }),
+            None => encode_fields(1, |fields| fields.push(false)),
+        };
+        sink.push(bytes)
+    }
+
+    fn pop(stream: &mut Stream) -> Result<Self, Error> {
+        let bytes: Vec<u8> = stream.pop()?;
+        decode_fields(&bytes, |fields| match fields.pop::<bool>()? {
+            true => Ok(Some(T::pop(fields)?)),
+            false => Ok(None),
+        })
+    }
+}
+
+impl<T: EngAbi> EngAbi for Vec<T> {
+    fn push(self, sink: &mut Sink) {
+        let len = self.len();
+        let bytes = encode_fields(len + 1, |fields| {
+            fields.push(len as u32);
+            for item in self {
+                item.push(fields);
+            }
+        });
+        sink.push(bytes)
+    }
+
+    fn pop(stream: &mut Stream) -> Result<Self, Error> {
+        let bytes: Vec<u8> = stream.pop()?;
+        decode_fields(&bytes, |fields| {
+            let len: u32 = fields.pop()?;
+            (0..len).map(|_| T::pop(fields)).collect()
+        })
+    }
+}
+
+/// Encode the `count` values pushed by `push_fields` as an ABI tuple.
+///
+/// This is used by `#[derive(EngAbi)]`.
+pub fn encode_fields<F: FnOnce(&mut Sink)>(count: usize, push_fields: F) -> Vec<u8> {
+    let mut fields = Sink::new(count);
+    push_fields(&mut fields);
+    let mut bytes = Vec::with_capacity(count * 32);
+    fields.drain_to(&mut bytes);
+    bytes
+}
+
+/// Decode an ABI tuple with `pop_fields`.
+///
+/// This is used by `#[derive(EngAbi)]`.
+pub fn decode_fields<T, F: FnOnce(&mut Stream) -> Result<T, Error>>(bytes: &[u8], pop_fields: F) -> Result<T, Error> {
+    let mut fields = Stream::new(bytes);
+    pop_fields(&mut fields)
+}
+
+/// Write the encoded fields of a derived type as its ABI value.
+pub fn encode_abi_bytes(bytes: Vec<u8>, sink: &mut Sink) { AbiType::encode(bytes, sink) }
+
+/// Read the encoded fields of a derived type from its ABI value.
+pub fn decode_abi_bytes(stream: &mut Stream) -> Result<Vec<u8>, Error> { <Vec<u8> as AbiType>::decode(stream) }
+
+/// Abort the task with `error` encoded like a return value.
+///
+/// This is used by `#[pub_interface]` when an exported function returns `Err`.
+pub fn revert_with<E: AbiType>(error: E) -> ! {
+    let mut payload = Vec::with_capacity(32);
+    let mut sink = Sink::new(1);
+    sink.push(error);
+    sink.drain_to(&mut payload);
+    revert(&payload)
+}
+
+/// The error of a task rejected by the access control attributes of `#[pub_interface]`.
+pub const UNAUTHORIZED_ERROR: &str = "unauthorized";
+
+/// Abort the task with `UNAUTHORIZED_ERROR` as the error.
+///
+/// This is used by `#[pub_interface]` when a call doesn't meet the access requirements of a function.
+pub fn revert_unauthorized() -> ! { revert_with(String::from(UNAUTHORIZED_ERROR)) }
+
+/// The error returned when decoding an enum with an unknown variant index.
+pub fn unknown_variant_error() -> Error { Error::Other }
@@ -0,0 +1,37 @@
+/// Wrapper for the Enigma runtime services exposing the context of the running task.
+///
+/// The block number and the task id are supplied by the worker alongside the task, the enclave binds them into the
+/// signed inputs hash so a result computed with other values than the ones of the task is rejected when it's verified.
+/// They are still chosen by the worker before that, so contracts must not rely on them for randomness.
+/// The contract address is taken from the state and the user public key from the key exchange, so they can be trusted.
+use super::*;
+
+pub struct TaskContext;
+
+impl TaskContext {
+    /// The Ethereum block number the task was submitted in, or 0 if the worker didn't give it.
+    pub fn block_number() -> u64 { unsafe { external::fetch_block_number() } }
+
+    /// The address of the running contract.
+    pub fn contract_address() -> H256 {
+        let mut address = [0u8; 32];
+        unsafe { external::fetch_contract_address(address.as_mut_ptr()) };
+        H256::from(address)
+    }
+
+    /// The id of the running task, or `None` if the task was sent without one.
+    pub fn task_id() -> Option<H256> {
+        let mut task_id = [0u8; 32];
+        match unsafe { external::fetch_task_id(task_id.as_mut_ptr()) } {
+            0 => None,
+            _ => Some(H256::from(task_id)),
+        }
+    }
+
+    /// The DH public key of the user who sent the task.
+    pub fn user_pubkey() -> [u8; 64] {
+        let mut pubkey = [0u8; 64];
+        unsafe { external::fetch_user_pubkey(pubkey.as_mut_ptr()) };
+        pubkey
+    }
+}
@@ -11,6 +11,17 @@ const SYMMETRIC_KEY_SIZE: usize = 256 / 8;
 pub type SymmetricKey = [u8; SYMMETRIC_KEY_SIZE];
 const AES_256_GCM_TAG_SIZE: usize = 16;
 const AES_256_GCM_IV_SIZE: usize = 96 / 8;
+pub type IV = [u8; AES_256_GCM_IV_SIZE];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CryptoError {
+    /// The message is too long to be encrypted.
+    EncryptionError,
+    /// The cipher text is too short to hold the IV and authentication tag.
+    ImproperEncryption,
+    /// The authentication tag doesn't match the cipher text, key and associated data.
+    DecryptionError,
+}
 
 
 /// The extra length (IV and authentication tag) of the encryption result
@@ -41,6 +52,107 @@ pub fn decrypt(cipheriv: &[u8], key: &SymmetricKey) -> Vec<u8> {
     // The buffer for the plaintext
     let mut payload = vec![0u8; length];
     // Call to the runtime service to decrypt
-    unsafe { external::decrypt(cipheriv.as_ptr(), cipheriv.len() as u32, key.as_ptr(), payload.as_mut_ptr()) };
+    let decrypted = unsafe { external::decrypt(cipheriv.as_ptr(), cipheriv.len() as u32, key.as_ptr(), payload.as_mut_ptr()) };
+    assert_eq!(decrypted, 1, "Failed decrypting, the authentication tag doesn't match");
     payload
 }
+
+/// Encrypt `message` while also authenticating `aad`, the same `aad` must be given to `decrypt_with_aad`.
+/// The IV is generated randomly by the runtime.
+pub fn encrypt_with_aad(message: &[u8], key: &SymmetricKey, aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let mut iv: IV = [0u8; AES_256_GCM_IV_SIZE];
+    Rand::gen_slice(&mut iv);
+    encrypt_with_aad_and_nonce(message, key, aad, &iv)
+}
+
+/// Same as `encrypt_with_aad` with a caller chosen IV, which must never be reused with the same key.
+pub fn encrypt_with_aad_and_nonce(message: &[u8], key: &SymmetricKey, aad: &[u8], iv: &IV) -> Result<Vec<u8>, CryptoError> {
+    let length = message.len().checked_add(extra_size_for_encrypted_text()).ok_or(CryptoError::EncryptionError)?;
+    let mut payload = vec![0u8; length];
+    unsafe {
+        external::encrypt_with_aad(message.as_ptr(), message.len() as u32, key.as_ptr(), aad.as_ptr(), aad.len() as u32,
+                                   iv.as_ptr(), payload.as_mut_ptr())
+    };
+    Ok(payload)
+}
+
+/// Decrypt a cipher text of `encrypt_with_aad`, failing with `DecryptionError` if it wasn't encrypted
+/// with `key` and `aad`. Cipher texts of `encrypt` can be decrypted with an empty `aad`.
+pub fn decrypt_with_aad(cipheriv: &[u8], key: &SymmetricKey, aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let length = cipheriv.len().checked_sub(extra_size_for_encrypted_text()).ok_or(CryptoError::ImproperEncryption)?;
+    let mut payload = vec![0u8; length];
+    let decrypted = unsafe {
+        external::decrypt_with_aad(cipheriv.as_ptr(), cipheriv.len() as u32, key.as_ptr(), aad.as_ptr(), aad.len() as u32,
+                                   payload.as_mut_ptr())
+    };
+    if decrypted == 1 {
+        Ok(payload)
+    } else {
+        Err(CryptoError::DecryptionError)
+    }
+}
+
+/// Derive a key from `key` with HKDF-SHA256, e.g. a per-record key by using the record id as `info`.
+pub fn derive_key(key: &SymmetricKey, salt: &[u8], info: &[u8]) -> SymmetricKey {
+    let mut derived: SymmetricKey = [0u8; SYMMETRIC_KEY_SIZE];
+    unsafe {
+        external::derive_key(key.as_ptr(), salt.as_ptr(), salt.len() as u32, info.as_ptr(), info.len() as u32, derived.as_mut_ptr())
+    };
+    derived
+}
+
+/// Keccak256 of `data`, computed natively by the runtime for a fixed amount of gas.
+pub fn keccak256(data: &[u8]) -> H256 {
+    let mut hash = [0u8; 32];
+    unsafe { external::keccak256(data.as_ptr(), data.len() as u32, hash.as_mut_ptr()) };
+    H256::from(hash)
+}
+
+/// Sha256 of `data`, computed natively by the runtime for a fixed amount of gas.
+pub fn sha256(data: &[u8]) -> H256 {
+    let mut hash = [0u8; 32];
+    unsafe { external::sha256(data.as_ptr(), data.len() as u32, hash.as_mut_ptr()) };
+    H256::from(hash)
+}
+
+/// Recover the public key that signed the Keccak256 of `message` (like `enigma_crypto::KeyPair::recover`).
+/// `sig` is (r, s, v) with an Ethereum style `v` (27/28), returns `None` if the signature is invalid.
+pub fn ecrecover(message: &[u8], sig: &[u8; 65]) -> Option<[u8; 64]> {
+    let mut pubkey = [0u8; 64];
+    let recovered = unsafe { external::ecrecover(message.as_ptr(), message.len() as u32, sig.as_ptr(), pubkey.as_mut_ptr()) };
+    if recovered == 1 {
+        Some(pubkey)
+    } else {
+        None
+    }
+}
+
+/// Concatenate `messages` with each one prefixed by its length as a big endian `u64`,
+/// the same as `enigma_crypto::hash::prepare_hash_multiple`.
+pub fn prepare_hash_multiple<B: AsRef<[u8]>>(messages: &[B]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(messages.iter().map(|msg| msg.as_ref().len() + 8).sum());
+    for msg in messages {
+        let msg = msg.as_ref();
+        res.extend_from_slice(&(msg.len() as u64).to_be_bytes());
+        res.extend_from_slice(msg);
+    }
+    res
+}
+
+/// Check that `sig` was signed by the Ethereum address `signer` on `prepare_hash_multiple(&[signer, message])`.
+///
+/// This is what `#[requires_signature]` checks before calling an exported function.
+pub fn verify_signature(signer: &Address, message: &[u8], sig: &[u8]) -> bool {
+    if sig.len() != 65 {
+        return false;
+    }
+    let mut sig_bytes = [0u8; 65];
+    sig_bytes.copy_from_slice(sig);
+
+    let signed_message = prepare_hash_multiple(&[&signer[..], message]);
+    match ecrecover(&signed_message, &sig_bytes) {
+        // The address is the last 20 bytes of the Keccak256 of the public key
+        Some(pubkey) => keccak256(&pubkey)[12..] == signer[..],
+        None => false,
+    }
+}
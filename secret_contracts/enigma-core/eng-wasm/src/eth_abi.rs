@@ -0,0 +1,380 @@
+//! Ethereum ABI encoding of the calls, events and return values generated by `#[eth_contract]`.
+//!
+//! Unlike `eng_pwasm_abi::eth` this covers every ABI type: all `intN`/`uintN` widths, `bytesN`,
+//! nested arrays and tuples. The generated code converts its typed arguments into `Token`s which are
+//! encoded as described in the Solidity ABI specification, and decodes return values with a `Decoder`.
+use super::*;
+
+/// A single ABI value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// A static value held in one word: integers, `bool`, `address` and `bytesN`.
+    Word([u8; 32]),
+    /// `bytes` or `string`.
+    Bytes(Vec<u8>),
+    /// `T[]`
+    Array(Vec<Token>),
+    /// `T[N]`
+    FixedArray(Vec<Token>),
+    /// `(T1, T2, ...)`
+    Tuple(Vec<Token>),
+}
+
+impl Token {
+    /// Dynamic values are encoded in the tail, with their offset in the head.
+    pub fn is_dynamic(&self) -> bool {
+        match self {
+            Token::Word(_) => false,
+            Token::Bytes(_) | Token::Array(_) => true,
+            Token::FixedArray(tokens) | Token::Tuple(tokens) => tokens.iter().any(Token::is_dynamic),
+        }
+    }
+
+    fn head_len(&self) -> usize {
+        match self {
+            Token::FixedArray(tokens) | Token::Tuple(tokens) if !self.is_dynamic() => tokens.iter().map(Token::head_len).sum(),
+            _ => 32,
+        }
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            Token::Word(word) => out.extend_from_slice(word),
+            Token::Bytes(bytes) => {
+                out.extend_from_slice(&(bytes.len() as u64).to_word());
+                extend_padded(out, bytes);
+            }
+            Token::Array(tokens) => {
+                out.extend_from_slice(&(tokens.len() as u64).to_word());
+                out.extend(encode(tokens));
+            }
+            Token::FixedArray(tokens) | Token::Tuple(tokens) => out.extend(encode(tokens)),
+        }
+    }
+
+    /// The topic of the value as an indexed event parameter, values that don't fit in a word are hashed.
+    pub fn topic(&self) -> H256 {
+        match self {
+            Token::Word(word) => H256::from(*word),
+            Token::Bytes(bytes) => keccak256(bytes),
+            _ => {
+                let mut in_place = Vec::new();
+                self.encode_in_place(&mut in_place);
+                keccak256(&in_place)
+            }
+        }
+    }
+
+    /// The encoding of indexed arrays and tuples: the padded elements without lengths or offsets.
+    fn encode_in_place(&self, out: &mut Vec<u8>) {
+        match self {
+            Token::Word(word) => out.extend_from_slice(word),
+            Token::Bytes(bytes) => extend_padded(out, bytes),
+            Token::Array(tokens) | Token::FixedArray(tokens) | Token::Tuple(tokens) => {
+                for token in tokens {
+                    token.encode_in_place(out);
+                }
+            }
+        }
+    }
+}
+
+fn extend_padded(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(bytes);
+    let padding = (32 - bytes.len() % 32) % 32;
+    out.extend(iter::repeat(0).take(padding));
+}
+
+/// Encode `tokens` like the arguments of a function.
+pub fn encode(tokens: &[Token]) -> Vec<u8> {
+    let heads_len: usize = tokens.iter().map(Token::head_len).sum();
+    let mut heads = Vec::with_capacity(heads_len);
+    let mut tails = Vec::new();
+    for token in tokens {
+        if token.is_dynamic() {
+            heads.extend_from_slice(&((heads_len + tails.len()) as u64).to_word());
+            token.encode_into(&mut tails);
+        } else {
+            token.encode_into(&mut heads);
+        }
+    }
+    heads.extend(tails);
+    heads
+}
+
+/// Encode a call to the function with the 4 bytes `selector` and `tokens` as its arguments.
+pub fn encode_call(selector: [u8; 4], tokens: &[Token]) -> Vec<u8> {
+    let mut payload = selector.to_vec();
+    payload.extend(encode(tokens));
+    payload
+}
+
+/// A Solidity event log, as emitted by the contract at `address`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventLog {
+    pub address: Address,
+    /// The event signature hash (unless the event is anonymous) followed by the indexed parameters.
+    pub topics: Vec<H256>,
+    /// The encoding of the parameters which aren't indexed.
+    pub data: Vec<u8>,
+}
+
+/// Errors when decoding ABI encoded data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecodeError {
+    /// The data ends before the value, or an offset points outside of it.
+    UnexpectedEnd,
+    /// A word holds a value out of the range of its type, or a `string` isn't UTF-8.
+    InvalidValue,
+}
+
+/// A value held in a single word.
+pub trait Word: Sized {
+    fn to_word(&self) -> [u8; 32];
+    fn from_word(word: &[u8; 32]) -> Result<Self, DecodeError>;
+}
+
+macro_rules! impl_word_for_uint {
+    ( $($type_: ty),+ ) => {
+        $(
+            impl Word for $type_ {
+                fn to_word(&self) -> [u8; 32] {
+                    let mut word = [0u8; 32];
+                    word[32 - mem::size_of::<$type_>()..].copy_from_slice(&self.to_be_bytes());
+                    word
+                }
+
+                fn from_word(word: &[u8; 32]) -> Result<Self, DecodeError> {
+                    const SIZE: usize = mem::size_of::<$type_>();
+                    if word[..32 - SIZE].iter().any(|byte| *byte != 0) {
+                        return Err(DecodeError::InvalidValue);
+                    }
+                    let mut bytes = [0u8; SIZE];
+                    bytes.copy_from_slice(&word[32 - SIZE..]);
+                    Ok(<$type_>::from_be_bytes(bytes))
+                }
+            }
+        )+
+    }
+}
+
+// Negative values are sign extended to the whole word
+macro_rules! impl_word_for_int {
+    ( $($type_: ty),+ ) => {
+        $(
+            impl Word for $type_ {
+                fn to_word(&self) -> [u8; 32] {
+                    let mut word = if *self < 0 { [0xff; 32] } else { [0u8; 32] };
+                    word[32 - mem::size_of::<$type_>()..].copy_from_slice(&self.to_be_bytes());
+                    word
+                }
+
+                fn from_word(word: &[u8; 32]) -> Result<Self, DecodeError> {
+                    const SIZE: usize = mem::size_of::<$type_>();
+                    let sign = if word[32 - SIZE] & 0x80 == 0 { 0 } else { 0xff };
+                    if word[..32 - SIZE].iter().any(|byte| *byte != sign) {
+                        return Err(DecodeError::InvalidValue);
+                    }
+                    let mut bytes = [0u8; SIZE];
+                    bytes.copy_from_slice(&word[32 - SIZE..]);
+                    Ok(<$type_>::from_be_bytes(bytes))
+                }
+            }
+        )+
+    }
+}
+
+// `bytesN` is left aligned, `bytes32` is `H256`
+macro_rules! impl_word_for_fixed_bytes {
+    ( $($len: expr),+ ) => {
+        $(
+            impl Word for [u8; $len] {
+                fn to_word(&self) -> [u8; 32] {
+                    let mut word = [0u8; 32];
+                    word[..$len].copy_from_slice(self);
+                    word
+                }
+
+                fn from_word(word: &[u8; 32]) -> Result<Self, DecodeError> {
+                    if word[$len..].iter().any(|byte| *byte != 0) {
+                        return Err(DecodeError::InvalidValue);
+                    }
+                    let mut bytes = [0u8; $len];
+                    bytes.copy_from_slice(&word[..$len]);
+                    Ok(bytes)
+                }
+            }
+        )+
+    }
+}
+
+impl_word_for_uint!(u8, u16, u32, u64, u128);
+impl_word_for_int!(i8, i16, i32, i64, i128);
+impl_word_for_fixed_bytes!(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31);
+
+impl Word for bool {
+    fn to_word(&self) -> [u8; 32] { (*self as u8).to_word() }
+
+    fn from_word(word: &[u8; 32]) -> Result<Self, DecodeError> {
+        match u8::from_word(word)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidValue),
+        }
+    }
+}
+
+/// `uint256`, and the two's complement of `int256` which has no Rust type.
+impl Word for U256 {
+    fn to_word(&self) -> [u8; 32] {
+        let mut word = [0u8; 32];
+        self.to_big_endian(&mut word);
+        word
+    }
+
+    fn from_word(word: &[u8; 32]) -> Result<Self, DecodeError> { Ok(U256::from_big_endian(word)) }
+}
+
+impl Word for H256 {
+    fn to_word(&self) -> [u8; 32] { self.0 }
+
+    fn from_word(word: &[u8; 32]) -> Result<Self, DecodeError> { Ok(H256::from(*word)) }
+}
+
+impl Word for Address {
+    fn to_word(&self) -> [u8; 32] {
+        let mut word = [0u8; 32];
+        word[12..].copy_from_slice(&self[..]);
+        word
+    }
+
+    fn from_word(word: &[u8; 32]) -> Result<Self, DecodeError> {
+        if word[..12].iter().any(|byte| *byte != 0) {
+            return Err(DecodeError::InvalidValue);
+        }
+        Ok(Address::from_slice(&word[12..]))
+    }
+}
+
+/// Reads the heads of a tuple, offsets of dynamic values are relative to the start of `data`.
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self { Decoder { data, position: 0 } }
+
+    fn read(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.position.checked_add(len).ok_or(DecodeError::UnexpectedEnd)?;
+        let bytes = self.data.get(self.position..end).ok_or(DecodeError::UnexpectedEnd)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_len(&mut self) -> Result<usize, DecodeError> {
+        let len: u64 = self.word()?;
+        if len > self.data.len() as u64 {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        Ok(len as usize)
+    }
+
+    /// Decode the next word.
+    pub fn word<T: Word>(&mut self) -> Result<T, DecodeError> {
+        let mut word = [0u8; 32];
+        word.copy_from_slice(self.read(32)?);
+        T::from_word(&word)
+    }
+
+    /// Follow the offset in the next word to the data of a dynamic value.
+    pub fn dynamic(&mut self) -> Result<Decoder<'a>, DecodeError> {
+        let offset = self.read_len()?;
+        Ok(Decoder::new(&self.data[offset..]))
+    }
+
+    /// Decode the next value as `bytes`.
+    pub fn bytes(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let mut tail = self.dynamic()?;
+        let len = tail.read_len()?;
+        Ok(tail.read(len)?.to_vec())
+    }
+
+    /// Decode the next value as a `string`.
+    pub fn string(&mut self) -> Result<String, DecodeError> {
+        String::from_utf8(self.bytes()?).map_err(|_| DecodeError::InvalidValue)
+    }
+
+    /// Decode the length of the next `T[]` value, and get the decoder of its elements.
+    pub fn array(&mut self) -> Result<(usize, Decoder<'a>), DecodeError> {
+        let mut tail = self.dynamic()?;
+        let len = tail.read_len()?;
+        Ok((len, Decoder::new(&tail.data[tail.position..])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(hex_words: &[&str]) -> Vec<u8> {
+        hex_words
+            .iter()
+            .flat_map(|word| (0..32).map(move |i| u8::from_str_radix(&word[i * 2..i * 2 + 2], 16).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_dynamic_arguments() {
+        // The `f(uint,uint32[],bytes10,bytes)` example of the Solidity ABI specification
+        let tokens = [
+            Token::Word(U256::from(0x123).to_word()),
+            Token::Array(vec![Token::Word(0x456u32.to_word()), Token::Word(0x789u32.to_word())]),
+            Token::Word(b"1234567890".to_word()),
+            Token::Bytes(b"Hello, world!".to_vec()),
+        ];
+        let expected = words(&[
+            "0000000000000000000000000000000000000000000000000000000000000123",
+            "0000000000000000000000000000000000000000000000000000000000000080",
+            "3132333435363738393000000000000000000000000000000000000000000000",
+            "00000000000000000000000000000000000000000000000000000000000000e0",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "0000000000000000000000000000000000000000000000000000000000000456",
+            "0000000000000000000000000000000000000000000000000000000000000789",
+            "000000000000000000000000000000000000000000000000000000000000000d",
+            "48656c6c6f2c20776f726c642100000000000000000000000000000000000000",
+        ]);
+        assert_eq!(encode(&tokens), expected);
+    }
+
+    #[test]
+    fn test_decode_dynamic_arguments() {
+        let tokens = [
+            Token::Tuple(vec![Token::Word((-2i16).to_word()), Token::Bytes(b"enigma".to_vec())]),
+            Token::FixedArray(vec![Token::Word(true.to_word()), Token::Word(false.to_word())]),
+            Token::Array(vec![Token::Array(vec![Token::Word(7u8.to_word())]), Token::Array(vec![])]),
+        ];
+        let data = encode(&tokens);
+
+        let mut decoder = Decoder::new(&data);
+        let mut tuple = decoder.dynamic().unwrap();
+        assert_eq!(tuple.word::<i16>(), Ok(-2));
+        assert_eq!(tuple.string(), Ok(String::from("enigma")));
+        assert_eq!(decoder.word::<bool>(), Ok(true));
+        assert_eq!(decoder.word::<bool>(), Ok(false));
+        let (len, mut items) = decoder.array().unwrap();
+        assert_eq!(len, 2);
+        let (first_len, mut first) = items.array().unwrap();
+        assert_eq!((first_len, first.word::<u8>()), (1, Ok(7)));
+        assert_eq!(items.array().unwrap().0, 0);
+    }
+
+    #[test]
+    fn test_decode_invalid_words() {
+        assert_eq!(u8::from_word(&256u16.to_word()), Err(DecodeError::InvalidValue));
+        assert_eq!(i8::from_word(&(-129i16).to_word()), Err(DecodeError::InvalidValue));
+        assert_eq!(bool::from_word(&2u8.to_word()), Err(DecodeError::InvalidValue));
+        assert_eq!(Decoder::new(&[0u8; 31]).word::<u8>(), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(Decoder::new(&0x40u8.to_word()).bytes(), Err(DecodeError::UnexpectedEnd));
+    }
+}
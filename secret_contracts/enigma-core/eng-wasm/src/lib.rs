@@ -10,12 +10,19 @@ extern crate std;
 #[macro_use]
 extern crate serde_json;
 extern crate serde;
+#[cfg(feature = "msgpack-state")]
+extern crate rmp_serde;
 #[macro_use]
 mod internal_std;
+mod abi_wasm;
+mod context_wasm;
 pub mod crypto_wasm;
+pub mod eth_abi;
 mod rand_wasm;
 pub extern crate eng_pwasm_abi;
 
+pub use abi_wasm::*;
+pub use context_wasm::*;
 pub use crypto_wasm::*;
 pub use eng_pwasm_abi::types::*;
 pub use internal_std::*;
@@ -27,6 +34,16 @@ pub mod external {
         pub fn write_state(key: *const u8, key_len: u32, value: *const u8, value_len: u32);
         pub fn read_state_len(key: *const u8, key_len: u32) -> i32;
         pub fn read_state(key: *const u8, key_len: u32, value_holder: *const u8);
+        pub fn write_state_msgpack(key: *const u8, key_len: u32, value: *const u8, value_len: u32);
+        pub fn read_state_len_msgpack(key: *const u8, key_len: u32) -> i32;
+        pub fn read_state_msgpack(key: *const u8, key_len: u32, value_holder: *const u8);
+        pub fn fetch_block_number() -> u64;
+        pub fn fetch_contract_address(address_holder: *mut u8);
+        pub fn fetch_task_id(task_id_holder: *mut u8) -> i32;
+        pub fn fetch_user_pubkey(pubkey_holder: *mut u8);
+        pub fn keccak256(data: *const u8, data_len: u32, hash_holder: *mut u8);
+        pub fn sha256(data: *const u8, data_len: u32, hash_holder: *mut u8);
+        pub fn ecrecover(message: *const u8, message_len: u32, sig: *const u8, pubkey_holder: *mut u8) -> i32;
         pub fn remove_from_state(key: *const u8, key_len: u32);
         pub fn eprint(str_ptr: *const u8, str_len: u32);
         pub fn fetch_function_name_length() -> i32;
@@ -38,7 +55,17 @@ pub mod external {
         pub fn ret(payload: *const u8, payload_len: u32);
         pub fn rand(payload: *const u8, payload_len: u32);
         pub fn encrypt(message: *const u8, message_len: u32, key: *const u8, payload: *const u8);
-        pub fn decrypt(cipheriv: *const u8, cipheriv_len: u32, key: *const u8, payload: *const u8);
+        pub fn decrypt(cipheriv: *const u8, cipheriv_len: u32, key: *const u8, payload: *const u8) -> i32;
+        pub fn encrypt_with_aad(message: *const u8, message_len: u32, key: *const u8, aad: *const u8, aad_len: u32, iv: *const u8, payload: *const u8);
+        pub fn decrypt_with_aad(cipheriv: *const u8, cipheriv_len: u32, key: *const u8, aad: *const u8, aad_len: u32, payload: *const u8) -> i32;
+        pub fn derive_key(key: *const u8, salt: *const u8, salt_len: u32, info: *const u8, info_len: u32, derived_key: *mut u8);
+        pub fn emit_event(topic: *const u8, topic_len: u32, data: *const u8, data_len: u32, encrypted: i32);
+        pub fn revert(payload: *const u8, payload_len: u32);
+        pub fn begin_view();
+        pub fn approve_upgrade(hash: *const u8);
+        pub fn set_state_inspectors(addresses: *const u8, count: u32);
+        pub fn call_contract(address: *const u8, function: *const u8, function_len: u32, args: *const u8, args_len: u32) -> i32;
+        pub fn fetch_call_result(result_holder: *mut u8);
     }
 }
 
@@ -57,31 +84,69 @@ macro_rules! eprint {
     );
 }
 
+#[cfg(not(feature = "msgpack-state"))]
+mod state_encoding {
+    use super::*;
+
+    pub fn encode<T: serde::Serialize>(value: &T) -> Vec<u8> { serde_json::to_vec(value).unwrap() }
+
+    pub fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> T {
+        serde_json::from_slice(bytes)
+            .map_err(|_| print("failed unwrapping from_slice in read_state"))
+            .expect("read_state failed")
+    }
+
+    pub unsafe fn write_state(key: &str, value: &[u8]) {
+        external::write_state(key.as_ptr(), key.len() as u32, value.as_ptr(), value.len() as u32)
+    }
+
+    pub unsafe fn read_state_len(key: &str) -> i32 { external::read_state_len(key.as_ptr(), key.len() as u32) }
+
+    pub unsafe fn read_state(key: &str, value_holder: &[u8]) {
+        external::read_state(key.as_ptr(), key.len() as u32, value_holder.as_ptr())
+    }
+}
+
+/// With the `msgpack-state` feature values cross the runtime boundary as MessagePack,
+/// the runtime rejects accessing a state that was created with the other encoding.
+#[cfg(feature = "msgpack-state")]
+mod state_encoding {
+    use super::*;
+
+    pub fn encode<T: serde::Serialize>(value: &T) -> Vec<u8> { rmp_serde::to_vec(value).unwrap() }
+
+    pub fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> T {
+        rmp_serde::from_slice(bytes)
+            .map_err(|_| print("failed unwrapping from_slice in read_state"))
+            .expect("read_state failed")
+    }
+
+    pub unsafe fn write_state(key: &str, value: &[u8]) {
+        external::write_state_msgpack(key.as_ptr(), key.len() as u32, value.as_ptr(), value.len() as u32)
+    }
+
+    pub unsafe fn read_state_len(key: &str) -> i32 { external::read_state_len_msgpack(key.as_ptr(), key.len() as u32) }
+
+    pub unsafe fn read_state(key: &str, value_holder: &[u8]) {
+        external::read_state_msgpack(key.as_ptr(), key.len() as u32, value_holder.as_ptr())
+    }
+}
+
 /// Write to state
-pub fn write<T>(key: &str, _value: T)
+pub fn write<T>(key: &str, value: T)
 where T: serde::Serialize {
-    let value = json!(_value);
-    let value_vec = serde_json::to_vec(&value).unwrap();
-    unsafe { external::write_state(key.as_ptr(), key.len() as u32, value_vec.as_ptr(), value_vec.len() as u32) }
+    let value_vec = state_encoding::encode(&value);
+    unsafe { state_encoding::write_state(key, &value_vec) }
 }
 
 /// Read from state
 pub fn read<T>(key: &str) -> Option<T>
 where for<'de> T: serde::Deserialize<'de> {
-    let val_len = unsafe { external::read_state_len(key.as_ptr(), key.len() as u32) };
+    let val_len = unsafe { state_encoding::read_state_len(key) };
     let value_holder: Vec<u8> = iter::repeat(0).take(val_len as usize).collect();
-    unsafe { external::read_state(key.as_ptr(), key.len() as u32, value_holder.as_ptr()) };
-    let value: Value = serde_json::from_slice(&value_holder)
-        .map_err(|_| print("failed unwrapping from_slice in read_state"))
-        .expect("read_state failed");
-    if value.is_null() {
-        return None;
-    }
-    Some(
-        serde_json::from_value(value.clone())
-            .map_err(|_| print("failed unwrapping from_value in read_state"))
-            .expect("read_state failed"),
-    )
+    unsafe { state_encoding::read_state(key, &value_holder) };
+    // A missing key is read as null, which is decoded as `None`.
+    state_encoding::decode(&value_holder)
 }
 
 /// Remove key and value from state
@@ -96,6 +161,58 @@ pub fn write_ethereum_bridge(payload: &[u8], address: &Address) {
     unsafe { external::write_eth_bridge(payload.as_ptr(), payload.len() as u32, address.as_ptr()) };
 }
 
+/// Abort the task, the state changes are discarded and `payload` is returned to the user
+/// as the output of the failed task.
+pub fn revert(payload: &[u8]) -> ! {
+    unsafe { external::revert(payload.as_ptr(), payload.len() as u32) };
+    unreachable!("the runtime does not return from revert")
+}
+
+/// Make the rest of the execution a view, the runtime fails the task on any following change to the state.
+///
+/// `#[pub_interface]` calls this before running functions annotated with `#[view]`.
+pub fn begin_view() {
+    unsafe { external::begin_view() };
+}
+
+/// Allow the contract to be upgraded to the code whose pre-deployed bytecode hashes to `new_code_hash`.
+///
+/// The upgrade runs `migrate` of the new code in place of its constructor, on the current state.
+/// An approval replaces the previous one and is consumed by the upgrade.
+pub fn approve_upgrade(new_code_hash: &H256) {
+    unsafe { external::approve_upgrade(new_code_hash.as_ptr()) };
+}
+
+/// Allow the owners of the Ethereum `addresses` to list the keys and read the values of the decrypted state
+/// with a signed state inspection request to the worker, replacing the previously allowed addresses.
+/// Up to 32 addresses are allowed, an empty slice disallows inspecting the state.
+pub fn set_state_inspectors(addresses: &[Address]) {
+    let flat: Vec<u8> = addresses.iter().flat_map(|address| address.to_vec()).collect();
+    unsafe { external::set_state_inspectors(flat.as_ptr(), addresses.len() as u32) };
+}
+
+/// Call `function` of the secret contract at `address` with the ABI encoded `args`, and return its output.
+///
+/// The called contract runs in the same task with the gas left, and its state changes are committed
+/// with the ones of the caller. If the called function fails, the whole task fails.
+/// A contract can't be called while it's already running, and up to 8 contracts can run at once.
+pub fn call_contract(address: &H256, function: &str, args: &[u8]) -> Vec<u8> {
+    unsafe {
+        let len = external::call_contract(address.as_ptr(), function.as_ptr(), function.len() as u32, args.as_ptr(), args.len() as u32);
+        let mut result = vec![0u8; len as usize];
+        external::fetch_call_result(result.as_mut_ptr());
+        result
+    }
+}
+
+/// Emit an event that is returned to the caller alongside the task result,
+/// if `encrypted` is set the data is encrypted with the user's key.
+pub fn emit_event<T>(topic: &str, data: T, encrypted: bool)
+where T: serde::Serialize {
+    let value = serde_json::to_vec(&json!(data)).unwrap();
+    unsafe { external::emit_event(topic.as_ptr(), topic.len() as u32, value.as_ptr(), value.len() as u32, encrypted as i32) };
+}
+
 #[macro_export]
 macro_rules! write_state {
      ( $($key: expr => $val: expr),+ ) => {
@@ -121,6 +238,20 @@ macro_rules! remove_from_state {
     }};
 }
 
+#[macro_export]
+macro_rules! emit_event {
+    ( $topic: expr, $data: expr ) => {{
+        $crate::emit_event($topic, $data, false)
+    }};
+}
+
+#[macro_export]
+macro_rules! emit_encrypted_event {
+    ( $topic: expr, $data: expr ) => {{
+        $crate::emit_event($topic, $data, true)
+    }};
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
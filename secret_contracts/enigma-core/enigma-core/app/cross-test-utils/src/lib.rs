@@ -11,7 +11,7 @@ extern crate futures;
 use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Command, Stdio};
 pub use enigma_types::{ContractAddress, StateKey, Hash256};
 use enigma_crypto::{KeyPair, symmetric, rand};
 use enigma_crypto::hash::{Sha256, Keccak256};
@@ -35,21 +35,30 @@ pub fn sign_message(key: KeyPair, address: ERC20UserAddress, amount: u64) -> [u8
     key.sign_multiple(&to_sign).unwrap()
 }
 
+/// Builds the contract in `contract_path` and returns its wasm code.
+/// The examples share a workspace, so the path of the wasm file is taken from the artifacts cargo reports.
 pub fn get_bytecode_from_path(contract_path: &str) -> Vec<u8> {
     let mut dir = PathBuf::new();
     dir.push(contract_path);
-    let mut output = Command::new("cargo")
+    let output = Command::new("cargo")
         .current_dir(&dir)
-        .args(&["build", "--release"]) // In real contract we should use --release
-//        .args(&["build"])
-        .spawn()
+        .args(&["build", "--release", "--message-format=json"]) // In real contract we should use --release
+        .stderr(Stdio::inherit())
+        .output()
         .unwrap_or_else(|_| panic!("Failed compiling wasm contract: {:?}", &dir));
-
-    assert!(output.wait().unwrap().success());
-//    dir.push("target/wasm32-unknown-unknown/debug/contract.wasm");
-    dir.push("target/wasm32-unknown-unknown/release/contract.wasm");
-
-    let mut f = File::open(&dir).unwrap_or_else(|_| panic!("Can't open the contract.wasm file: {:?}", &dir));
+    assert!(output.status.success(), "Failed compiling wasm contract: {:?}", &dir);
+
+    let wasm_path = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| from_str::<Value>(line).ok())
+        .filter(|message| message["reason"] == "compiler-artifact")
+        .filter_map(|message| message["filenames"].as_array().cloned())
+        .flatten()
+        .filter_map(|filename| filename.as_str().map(PathBuf::from))
+        .find(|filename| filename.extension().map_or(false, |ext| ext == "wasm"))
+        .unwrap_or_else(|| panic!("Can't find the wasm file of the contract: {:?}", &dir));
+
+    let mut f = File::open(&wasm_path).unwrap_or_else(|_| panic!("Can't open the wasm file: {:?}", &wasm_path));
     let mut wasm_code = Vec::new();
     f.read_to_end(&mut wasm_code).expect("Failed reading the wasm file");
     wasm_code
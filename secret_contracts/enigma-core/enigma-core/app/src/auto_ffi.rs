@@ -28,6 +28,27 @@ extern "C" {
         address: *const ContractAddress,
         user_key: *mut [u8; 64usize],
         gas_limit: *const u64,
+        context: *const TaskContext,
+        db_ptr: *const RawPointer,
+        result: *mut ExecuteResult,
+    ) -> sgx_status_t;
+}
+extern "C" {
+    pub fn ecall_upgrade(
+        eid: sgx_enclave_id_t,
+        retval: *mut EnclaveReturn,
+        bytecode: *const u8,
+        bytecode_len: usize,
+        prev_code: *const u8,
+        prev_code_len: usize,
+        migration: *const u8,
+        migration_len: usize,
+        args: *const u8,
+        args_len: usize,
+        address: *const ContractAddress,
+        user_key: *mut [u8; 64usize],
+        gas_limit: *const u64,
+        context: *const TaskContext,
         db_ptr: *const RawPointer,
         result: *mut ExecuteResult,
     ) -> sgx_status_t;
@@ -45,6 +66,7 @@ extern "C" {
         pubkey: *mut [u8; 64usize],
         address: *const ContractAddress,
         gas_limit: *const u64,
+        context: *const TaskContext,
         db_ptr: *const RawPointer,
         result: *mut ExecuteResult,
     ) -> sgx_status_t;
@@ -71,6 +93,26 @@ extern "C" {
         failed_ptr: *mut u64,
     ) -> sgx_status_t;
 }
+extern "C" {
+    pub fn ecall_snapshot_state(
+        eid: sgx_enclave_id_t,
+        retval: *mut EnclaveReturn,
+        db_ptr: *const RawPointer,
+        address: *const ContractAddress,
+        delta_index: *mut u32,
+    ) -> sgx_status_t;
+}
+extern "C" {
+    pub fn ecall_verify_snapshot(
+        eid: sgx_enclave_id_t,
+        retval: *mut EnclaveReturn,
+        db_ptr: *const RawPointer,
+        address: *const ContractAddress,
+        enc_state: *const u8,
+        state_len: usize,
+        delta_index: u32,
+    ) -> sgx_status_t;
+}
 extern "C" {
     pub fn ecall_get_user_key(
         eid: sgx_enclave_id_t,
@@ -80,3 +122,20 @@ extern "C" {
         serialized_ptr: *mut u64,
     ) -> sgx_status_t;
 }
+extern "C" {
+    pub fn ecall_inspect_state(
+        eid: sgx_enclave_id_t,
+        retval: *mut EnclaveReturn,
+        db_ptr: *const RawPointer,
+        address: *const ContractAddress,
+        prefix: *const u8,
+        prefix_len: usize,
+        from: *const u8,
+        from_len: usize,
+        limit: u32,
+        values: u8,
+        user_sig: *mut [u8; 65usize],
+        sig: *mut [u8; 65usize],
+        serialized_ptr: *mut u64,
+    ) -> sgx_status_t;
+}
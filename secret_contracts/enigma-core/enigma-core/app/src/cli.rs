@@ -5,9 +5,18 @@
 //! it is used by running `let opt: Opt = Opt::from_args();` and then it will fill up the struct from the user inputs.
 //! (and of course fail if needed)
 
-use std::path::PathBuf;
+use crate::db::{Backend, ContractArchive, P2PCalls, DB};
+use crate::networking::messages::{IpcMessageRequest, IpcMessageResponse, IpcRequest, IpcResponse};
+use enigma_types::ContractAddress;
+use failure::Error;
+use hex::ToHex;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
+/// How long to wait for the node to create a backup, in milliseconds.
+const BACKUP_TIMEOUT: i32 = 10 * 60 * 1000;
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "Enigma Core", about = "Enigma Core CLI commands.")]
 pub struct Opt {
@@ -29,4 +38,148 @@ pub struct Opt {
     /// Specify the number of Attestation call retries when failing
     #[structopt(long = "retries", short = "r", default_value = "10")]
     pub retries: u32,
+    /// The number of requests handled at the same time, up to 4
+    #[structopt(long = "concurrency", default_value = "4")]
+    pub concurrency: usize,
+    /// The directory the backups are created in, by default `backups` in the data directory
+    #[structopt(parse(from_os_str), long = "backup-dir")]
+    pub backup_dir: Option<PathBuf>,
+    /// Snapshot the state of a contract every N deltas, if not set it's only snapshotted on request
+    #[structopt(long = "snapshot-interval")]
+    pub snapshot_interval: Option<u32>,
+    /// The number of deltas included in a snapshot to keep for syncing peers, older deltas are pruned
+    #[structopt(long = "delta-retention", default_value = "1000")]
+    pub delta_retention: u32,
+    /// The storage engine of the DB: rocksdb, sled (if built with the `sled` feature) or memory, which isn't persisted
+    #[structopt(long = "db-backend", default_value = "rocksdb")]
+    pub db_backend: Backend,
+    /// Serve JSON-RPC over HTTP on this address, e.g. 127.0.0.1:3030
+    #[structopt(long = "rpc-http")]
+    pub rpc_http: Option<SocketAddr>,
+    /// Serve JSON-RPC over WebSocket on this address, with subscriptions to the results of the tasks
+    #[structopt(long = "rpc-ws")]
+    pub rpc_ws: Option<SocketAddr>,
+    /// An origin browsers can send JSON-RPC requests over HTTP and WebSocket from, "*" allows any origin
+    #[structopt(long = "rpc-cors")]
+    pub rpc_cors: Vec<String>,
+    /// Serve the JSON-RPC methods that change the DB or run the key management, they must be called with the token
+    /// in this file as `authToken`
+    #[structopt(parse(from_os_str), long = "rpc-admin-token-file")]
+    pub rpc_admin_token_file: Option<PathBuf>,
+    /// Serve Prometheus metrics on /metrics and the health checks on /healthz and /readyz over HTTP on this address
+    #[structopt(long = "metrics")]
+    pub metrics: Option<SocketAddr>,
+    #[structopt(subcommand)]
+    pub cmd: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Create a backup of the DB of the running node in a new directory, with a checksum of every contract
+    #[structopt(name = "backup")]
+    Backup {
+        /// The name of the backup, it's created in the backup directory of the node
+        name: String,
+    },
+    /// Restore a backup into the data directory, the node must be stopped and the directory can't contain a DB
+    #[structopt(name = "restore")]
+    Restore {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Export the bytecode, state and deltas of a contract into an archive, the node must be stopped
+    #[structopt(name = "export")]
+    Export {
+        /// The contract address in hex
+        address: String,
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Import a contract from an archive exported by another worker, the node must be stopped
+    #[structopt(name = "import")]
+    Import {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Verify the deltas of the contracts against the hashes recorded when they were stored, the node must be stopped
+    #[structopt(name = "verify")]
+    Verify {
+        /// The contract addresses in hex, if none are given all the contracts are verified
+        addresses: Vec<String>,
+        /// Record the hashes of the legacy deltas that were stored before hashes were recorded, if the deltas pass the verification
+        #[structopt(long = "hash-legacy")]
+        hash_legacy: bool,
+    },
+}
+
+impl Command {
+    /// Runs the command on the DB in `datadir` that is stored in `backend`.
+    /// The running node keeps the DB open, so `backup` is sent to the node listening on `port`.
+    pub fn run(self, datadir: &Path, backend: Backend, port: u16) -> Result<(), Error> {
+        match self {
+            Command::Backup { name } => {
+                let request = IpcMessageRequest { id: "backup".to_string(), request: IpcRequest::BackupDB { path: name } };
+                match send_request(port, &request)?.response {
+                    IpcResponse::Error { msg } => bail!("The node failed creating the backup: {}", msg),
+                    response => println!("{}", serde_json::to_string_pretty(&response)?),
+                }
+            }
+            Command::Restore { path } => {
+                let db = DB::restore(&path, datadir)?;
+                println!("Restored {} contracts into {:?}", db.get_all_addresses().unwrap_or_default().len(), datadir);
+            }
+            Command::Export { address, path } => {
+                let db = DB::open(datadir, backend, false)?;
+                db.export_contract(&ContractAddress::from_hex(&address)?)?.save(&path)?;
+                println!("Exported {} into {:?}", address, path);
+            }
+            Command::Import { path } => {
+                let mut db = DB::open(datadir, backend, false)?;
+                let address = db.import_contract(&ContractArchive::load(&path)?)?;
+                println!("Imported {} from {:?}", address.to_hex(), path);
+            }
+            Command::Verify { addresses, hash_legacy } => {
+                let mut db = DB::open(datadir, backend, false)?;
+                let verifications = if addresses.is_empty() {
+                    db.verify_all_deltas()?
+                } else {
+                    let mut verifications = Vec::with_capacity(addresses.len());
+                    for address in &addresses {
+                        verifications.push(db.verify_deltas(&ContractAddress::from_hex(address)?)?);
+                    }
+                    verifications
+                };
+                let mut failed = 0;
+                for verification in &verifications {
+                    match verification.first_bad {
+                        None if verification.unhashed > 0 && hash_legacy => {
+                            let hashed = db.hash_legacy_deltas(&verification.address)?;
+                            println!("{}: {} deltas verified, hashed {} legacy deltas", verification.address.to_hex(), verification.verified, hashed);
+                        }
+                        None if verification.unhashed > 0 => println!("{}: {} deltas verified, {} legacy deltas have no hash",
+                                                                      verification.address.to_hex(), verification.verified, verification.unhashed),
+                        None => println!("{}: {} deltas verified", verification.address.to_hex(), verification.verified),
+                        Some((index, ref fault)) => {
+                            failed += 1;
+                            println!("{}: delta {} is bad: {}", verification.address.to_hex(), index, fault);
+                        }
+                    }
+                }
+                if failed > 0 {
+                    bail!("The deltas of {} out of {} contracts failed the verification", failed, verifications.len());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn send_request(port: u16, request: &IpcMessageRequest) -> Result<IpcMessageResponse, Error> {
+    let context = zmq::Context::new();
+    let socket = context.socket(zmq::REQ)?;
+    socket.set_rcvtimeo(BACKUP_TIMEOUT)?;
+    socket.connect(&format!("tcp://localhost:{}", port))?;
+    socket.send(&serde_json::to_vec(request)?, 0)?;
+    let response = socket.recv_bytes(0)?;
+    Ok(serde_json::from_slice(&response)?)
 }
\ No newline at end of file
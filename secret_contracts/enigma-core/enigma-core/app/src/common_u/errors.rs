@@ -61,6 +61,9 @@ pub enum DBErrKind {
     FetchError,
     MissingKey,
     UpdateError,
+    /// The deltas before this index were pruned, they are only included in a state snapshot.
+    Pruned(u32),
+    ChecksumMismatch,
 }
 
 impl fmt::Display for DBErrKind {
@@ -71,6 +74,8 @@ impl fmt::Display for DBErrKind {
             DBErrKind::FetchError => "Failed to fetch the data",
             DBErrKind::MissingKey => "The Key doesn't exist",
             DBErrKind::UpdateError => "Failed to update the key",
+            DBErrKind::ChecksumMismatch => "The data doesn't match its checksum",
+            DBErrKind::Pruned(index) => return write!(f, "The deltas before {} were pruned after a state snapshot", index),
         };
         write!(f, "{}", printable)
     }
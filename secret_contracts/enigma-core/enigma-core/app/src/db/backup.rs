@@ -0,0 +1,342 @@
+use common_u::errors::{DBErr, DBErrKind};
+use db::dal::DB;
+use db::iterator::P2PCalls;
+use db::storage::{Backend, StorageBatch};
+use enigma_crypto::hash::Sha256;
+use enigma_types::ContractAddress;
+use failure::Error;
+use hex::ToHex;
+use std::fs::{self, File};
+use std::path::{Component, Path, PathBuf};
+
+/// The version of the backup and archive formats, bumped on incompatible changes.
+pub const BACKUP_VERSION: u32 = 1;
+const BACKUP_DB_DIR: &str = "db";
+const BACKUP_MANIFEST: &str = "manifest.json";
+
+/// Describes a backup, so it can be verified before it's restored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackupManifest {
+    pub version: u32,
+    /// The storage backend the backup is in, a backup can only be restored into the same backend.
+    #[serde(default)]
+    pub backend: Backend,
+    pub contracts: Vec<ContractChecksum>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContractChecksum {
+    pub address: String,
+    /// The number of keys of the contract, the bytecode, the state, the deltas and the snapshots.
+    pub keys: u64,
+    pub checksum: String,
+}
+
+/// All the keys of a single contract in a portable format, so it can be moved between workers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContractArchive {
+    pub version: u32,
+    pub address: String,
+    pub entries: Vec<ArchiveEntry>,
+    pub checksum: String,
+}
+
+/// A key inside the column family of the contract as it's stored in the DB and its value.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl ContractArchive {
+    /// Serializes the archive with MessagePack into a new file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut file = File::create(path)?;
+        rmp_serde::encode::write(&mut file, self)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ContractArchive, Error> {
+        let file = File::open(path)?;
+        Ok(rmp_serde::from_read(file)?)
+    }
+
+    pub fn contract_address(&self) -> Result<ContractAddress, Error> {
+        Ok(ContractAddress::from_hex(&self.address)?)
+    }
+
+    /// Checks the version of the archive and that the entries match the checksum.
+    pub fn verify(&self) -> Result<(), Error> {
+        if self.version != BACKUP_VERSION {
+            bail!("Unsupported archive version: {}, expected: {}", self.version, BACKUP_VERSION);
+        }
+        if checksum(&self.entries).to_hex() != self.checksum {
+            return Err(DBErr { command: "verify_archive".to_string(), kind: DBErrKind::ChecksumMismatch }.into());
+        }
+        Ok(())
+    }
+}
+
+/// Resolves the name of a backup under `dir`, it must be a relative path that can't leave it.
+fn resolve_backup_name(dir: &Path, name: &str) -> Result<PathBuf, Error> {
+    let name = Path::new(name);
+    if name.as_os_str().is_empty() || !name.components().all(|c| match c { Component::Normal(_) => true, _ => false }) {
+        bail!("Invalid backup name: {:?}, it must be a relative path inside the backup directory", name);
+    }
+    Ok(dir.join(name))
+}
+
+/// Chains the hashes of the entries in the order they're stored in, so any change to a key, a value or the order changes it.
+fn checksum(entries: &[ArchiveEntry]) -> [u8; 32] {
+    entries.iter().fold([0u8; 32], |acc, entry| {
+        [&acc[..], &entry.key.len().to_be_bytes(), &entry.key, &entry.value].concat().sha256().into()
+    })
+}
+
+/// Copies the files in `from` into `to` recursively, and adds the paths that were created in `to` to `copied`.
+fn copy_dir(from: &Path, to: &Path, copied: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            fs::create_dir(&target)?;
+            copied.push(target.clone());
+            copy_dir(&entry.path(), &target, &mut Vec::new())?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+            copied.push(target);
+        }
+    }
+    Ok(())
+}
+
+impl DB {
+    /// returns all the keys and values of the contract in the order they're stored in.
+    pub fn contract_entries(&self, address: &ContractAddress) -> Result<Vec<ArchiveEntry>, Error> {
+        let str_addr: String = address.to_hex();
+        if !self.storage.has_namespace(&str_addr) {
+            return Err(DBErr { command: "contract_entries".to_string(), kind: DBErrKind::MissingKey }.into());
+        }
+        Ok(self.storage.all(&str_addr)?.into_iter().map(|(key, value)| ArchiveEntry { key, value }).collect())
+    }
+
+    /// returns the checksums of all the contracts in the DB.
+    pub fn manifest(&self) -> Result<BackupManifest, Error> {
+        let mut contracts = Vec::new();
+        for address in self.get_all_addresses().unwrap_or_default() {
+            let entries = self.contract_entries(&address)?;
+            contracts.push(ContractChecksum {
+                address: address.to_hex(),
+                keys: entries.len() as u64,
+                checksum: checksum(&entries).to_hex(),
+            });
+        }
+        Ok(BackupManifest { version: BACKUP_VERSION, backend: self.backend(), contracts })
+    }
+
+    /// Creates a consistent backup of the whole DB in a new directory while it's still in use.
+    ///
+    /// The backup is a checkpoint of the storage, with RocksDB the files are hard linked if the directory is on the same filesystem,
+    /// and a manifest of the checksums of every contract, that is checked on restore.
+    #[logfn(INFO)]
+    pub fn backup<P: AsRef<Path>>(&self, path: P) -> Result<BackupManifest, Error> {
+        let path = path.as_ref();
+        fs::create_dir(path)?;
+        let db_path = path.join(BACKUP_DB_DIR);
+        if let Err(e) = self.storage.checkpoint(&db_path) {
+            fs::remove_dir_all(path)?;
+            return Err(e);
+        }
+        // the checksums are of the checkpoint itself, and not of the DB that might have changed since.
+        let manifest = DB::open(&db_path, self.backend(), false)?.manifest()?;
+        serde_json::to_writer_pretty(File::create(path.join(BACKUP_MANIFEST))?, &manifest)?;
+        Ok(manifest)
+    }
+
+    /// Creates a backup named `name` in the backup directory, this is how backups are requested over IPC
+    /// so they can't be written anywhere else. returns the path of the backup and its manifest.
+    pub fn backup_named(&self, name: &str) -> Result<(PathBuf, BackupManifest), Error> {
+        let dir = self.get_backup_dir().ok_or_else(|| format_err!("Backups aren't enabled, the node has no backup directory"))?;
+        let path = resolve_backup_name(dir, name)?;
+        fs::create_dir_all(dir)?;
+        let manifest = self.backup(&path)?;
+        Ok((path, manifest))
+    }
+
+    /// Restores a backup into `location` with the backend it was created with, `location` can't already contain a DB.
+    /// The restored DB is checked against the manifest of the backup, and removed if it doesn't match.
+    pub fn restore<P: AsRef<Path>, L: AsRef<Path>>(backup: P, location: L) -> Result<DB, Error> {
+        let (backup, location) = (backup.as_ref(), location.as_ref());
+        let manifest: BackupManifest = serde_json::from_reader(File::open(backup.join(BACKUP_MANIFEST))?)?;
+        if manifest.version != BACKUP_VERSION {
+            bail!("Unsupported backup version: {}, expected: {}", manifest.version, BACKUP_VERSION);
+        }
+        let db_dir = backup.join(BACKUP_DB_DIR);
+        for file in fs::read_dir(&db_dir)? {
+            if location.join(file?.file_name()).exists() {
+                bail!("There's already a DB in {:?}, move it before restoring", location);
+            }
+        }
+        fs::create_dir_all(location)?;
+        let mut copied = Vec::new();
+        copy_dir(&db_dir, location, &mut copied)?;
+
+        let db = DB::open(location, manifest.backend, false)?;
+        if db.manifest()? != manifest {
+            drop(db);
+            for path in copied {
+                if path.is_dir() { fs::remove_dir_all(path)? } else { fs::remove_file(path)? }
+            }
+            return Err(DBErr { command: "restore".to_string(), kind: DBErrKind::ChecksumMismatch }.into());
+        }
+        Ok(db)
+    }
+
+    /// Exports all the data of the contract, the bytecode, the state, the deltas and the snapshots.
+    pub fn export_contract(&self, address: &ContractAddress) -> Result<ContractArchive, Error> {
+        let entries = self.contract_entries(address)?;
+        let checksum = checksum(&entries).to_hex();
+        Ok(ContractArchive { version: BACKUP_VERSION, address: address.to_hex(), entries, checksum })
+    }
+
+    /// Imports a contract that was exported from another DB, it fails if the contract already exists.
+    #[logfn(INFO)]
+    pub fn import_contract(&mut self, archive: &ContractArchive) -> Result<ContractAddress, Error> {
+        archive.verify()?;
+        let address = archive.contract_address()?;
+        let str_addr: String = address.to_hex();
+        if self.storage.has_namespace(&str_addr) {
+            return Err(DBErr { command: "import_contract".to_string(), kind: DBErrKind::KeyExists }.into());
+        }
+        let mut batch = StorageBatch::default();
+        for entry in &archive.entries {
+            batch.put(&str_addr, &entry.key, &entry.value);
+        }
+        self.storage.create_namespace(&str_addr)?;
+        if let Err(e) = self.write_synced(batch) {
+            self.storage.drop_namespace(&str_addr)?;
+            return Err(e);
+        }
+        // the imported deltas weren't applied to the state yet.
+        self.update_state_status(false);
+        Ok(address)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempfile;
+    use common_u::errors::{DBErr, DBErrKind};
+    use db::{tests::{create_disk_test_db, create_test_db, test_backend}, CRUDInterface, ContractArchive, DeltaKey, P2PCalls, Stype, DB};
+    use enigma_types::ContractAddress;
+    use std::fs::File;
+
+    fn fill_contract(db: &mut DB, address: ContractAddress) {
+        let mut tuples = vec![(DeltaKey::new(address, Stype::ByteCode), b"code".to_vec()),
+                              (DeltaKey::new(address, Stype::State), b"state".to_vec())];
+        tuples.extend((0..5).map(|i| (DeltaKey::new(address, Stype::Delta(i)), vec![i as u8; 8])));
+        for res in db.insert_tuples(&tuples) {
+            res.unwrap();
+        }
+    }
+
+    fn is_checksum_mismatch(err: failure::Error) -> bool {
+        match err.downcast::<DBErr>() {
+            Ok(DBErr { kind: DBErrKind::ChecksumMismatch, .. }) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_backup_restore() {
+        let (mut db, dir) = create_disk_test_db(test_backend());
+        let addresses: Vec<ContractAddress> = vec![[1u8; 32].into(), [2u8; 32].into()];
+        for address in &addresses {
+            fill_contract(&mut db, *address);
+        }
+        let backup = dir.path().join("backup");
+        let manifest = db.backup(&backup).unwrap();
+        assert_eq!(manifest.contracts.len(), 2);
+        // the bytecode, the state, and 5 deltas with their hashes
+        assert_eq!(manifest.contracts[0].keys, 12);
+        // changes after the backup aren't included in it
+        db.force_update(&DeltaKey::new(addresses[0], Stype::State), b"new state").unwrap();
+
+        let target = tempfile::tempdir().unwrap();
+        let restored = DB::restore(&backup, target.path()).unwrap();
+        assert_eq!(restored.get_all_addresses().unwrap(), addresses);
+        assert_eq!(restored.read(&DeltaKey::new(addresses[0], Stype::State)).unwrap(), b"state".to_vec());
+        assert_eq!(restored.get_delta(DeltaKey::new(addresses[1], Stype::Delta(4))).unwrap(), vec![4u8; 8]);
+
+        // a DB can't be restored over another one
+        drop(restored);
+        assert!(DB::restore(&backup, target.path()).is_err());
+    }
+
+    #[test]
+    fn test_backup_named() {
+        let (mut db, dir) = create_disk_test_db(test_backend());
+        fill_contract(&mut db, [1u8; 32].into());
+        // backups by name are disabled until there's a backup directory
+        assert!(db.backup_named("backup").is_err());
+
+        let backups = dir.path().join("backups");
+        db.set_backup_dir(&backups);
+        for name in &["", "..", "../backup", "nested/../../backup", "/tmp/backup"] {
+            assert!(db.backup_named(name).is_err(), "{:?} was accepted", name);
+        }
+        let (path, manifest) = db.backup_named("nested/backup").unwrap();
+        assert_eq!(path, backups.join("nested/backup"));
+        assert_eq!(manifest.contracts.len(), 1);
+    }
+
+    #[test]
+    fn test_backup_in_memory() {
+        let mut db = DB::new_in_memory();
+        fill_contract(&mut db, [1u8; 32].into());
+        let dir = tempfile::tempdir().unwrap();
+        assert!(db.backup(dir.path().join("backup")).is_err());
+        assert!(!dir.path().join("backup").exists());
+    }
+
+    #[test]
+    fn test_restore_checksum_mismatch() {
+        let (mut db, dir) = create_disk_test_db(test_backend());
+        fill_contract(&mut db, [1u8; 32].into());
+        let backup = dir.path().join("backup");
+        let mut manifest = db.backup(&backup).unwrap();
+        manifest.contracts[0].keys -= 1;
+        serde_json::to_writer(File::create(backup.join("manifest.json")).unwrap(), &manifest).unwrap();
+
+        let target = tempfile::tempdir().unwrap();
+        assert!(is_checksum_mismatch(DB::restore(&backup, target.path()).err().unwrap()));
+        assert_eq!(target.path().read_dir().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_export_import() {
+        let (mut db, _dir) = create_test_db();
+        let dir = tempfile::tempdir().unwrap();
+        let address: ContractAddress = [7u8; 32].into();
+        fill_contract(&mut db, address);
+        db.save_snapshot(&address, 3, b"snapshot").unwrap();
+
+        let path = dir.path().join("contract.archive");
+        db.export_contract(&address).unwrap().save(&path).unwrap();
+        let archive = ContractArchive::load(&path).unwrap();
+        assert_eq!(archive.entries.len(), 13);
+
+        let (mut other, _other_dir) = create_test_db();
+        assert_eq!(other.import_contract(&archive).unwrap(), address);
+        assert_eq!(other.get_contract(address).unwrap(), b"code".to_vec());
+        assert_eq!(other.get_latest_snapshot(&address).unwrap(), (3, b"snapshot".to_vec()));
+        assert!(!other.get_state_status());
+        // the contract already exists
+        assert!(other.import_contract(&archive).is_err());
+
+        let mut tampered = archive.clone();
+        tampered.entries[0].value = b"other code".to_vec();
+        let (mut other, _other_dir) = create_test_db();
+        assert!(is_checksum_mismatch(other.import_contract(&tampered).unwrap_err()));
+    }
+}
@@ -1,26 +1,31 @@
 use failure::Error;
-use rocksdb::DB as rocks_db;
-use rocksdb::{Options, SliceTransform, WriteOptions};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use common_u::errors::{DBErr, DBErrKind};
-use db::primitives::SplitKey;
+use db::primitives::{delta_hash_key, SplitKey};
+use db::snapshot::SnapshotConfig;
+use db::storage::{open_storage, Backend, Storage, StorageBatch};
+use enigma_crypto::hash::Keccak256;
+use enigma_types::ContractAddress;
+use hex::ToHex;
 
 // These are global variables for Reade/Write/Create Options
 const SYNC: bool = true;
 
 pub struct DB {
     pub location: PathBuf,
-    pub database: rocks_db,
-    // the DB needs to store the options for creating new
-    // cf's that would be able to imitate the DB behaviour
-    pub options: Options,
+    pub storage: Box<dyn Storage>,
     // keeps track if the state needs to be rebuilt
     state_updated: bool,
+    // when to snapshot the states and how many deltas to keep behind a snapshot
+    snapshot_config: SnapshotConfig,
+    // the directory the backups requested over IPC are created in, if `None` they're disabled
+    backup_dir: Option<PathBuf>,
 }
 
 impl DB {
-    /// Constructs a new `DB`. with a db file accordingly.
+    /// Constructs a new `DB`. with a RocksDB db file accordingly.
     ///
     /// You need to pass a path for the location of the db file
     /// and as a default, it adds options with a flag which creates the file if missing
@@ -37,28 +42,25 @@ impl DB {
     /// let mut db = DB::new(path, true).unwrap();
     /// ```
     pub fn new<P: AsRef<Path>>(location: P, create_if_missing: bool) -> Result<DB, Error> {
-        // number of bytes to take into consideration when looking for a similar prefix
-        // would be helpful when querying the DB using iterators.
-        let prefix_extractor = SliceTransform::create_fixed_prefix(1);
-        let mut options = Options::default();
-        options.create_if_missing(create_if_missing);
-        options.set_prefix_extractor(prefix_extractor);
-        // cf_list gets a list of all column families (addresses) from the location where the DB
-        // is stored and when opening it, it adds the list as an argument to the DB. this is done
-        // in case the DB existed, otherwise, an empty list will be added and the call will
-        // be similar to a fresh start
-        let cf_list = match rocks_db::list_cf(&options, &location) {
-            Ok(list) => list,
-            Err(_) => Vec::new(),
-        };
-        // converts the Strings to slices (str)
-        let cf_list_burrowed = cf_list.iter().map(String::as_str).collect::<Vec<&str>>();
-        let database = rocks_db::open_cf(&options, &location, &cf_list_burrowed[..])?;
+        DB::open(location, Backend::RocksDB, create_if_missing)
+    }
+
+    /// Constructs a new `DB` stored in the `backend`, the location is ignored by the in-memory backend.
+    pub fn open<P: AsRef<Path>>(location: P, backend: Backend, create_if_missing: bool) -> Result<DB, Error> {
         let location = location.as_ref().to_path_buf();
+        let storage = open_storage(&location, backend, create_if_missing)?;
         // the state_updated is initialized to true since it won't be necessary to build
         // the state when the DB is empty.
-        let db_par = DB { location, database, options, state_updated: true};
-        Ok(db_par)
+        Ok(DB { location, storage, state_updated: true, snapshot_config: SnapshotConfig::default(), backup_dir: None })
+    }
+
+    /// Constructs a new empty `DB` that is only kept in memory.
+    pub fn new_in_memory() -> DB {
+        DB::open(PathBuf::new(), Backend::Memory, true).expect("Opening an in-memory DB can't fail")
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.storage.backend()
     }
 
     /// updates the state_updated field according to the status of the state.
@@ -69,9 +71,68 @@ impl DB {
     }
 
     /// get the current status of the state
-    pub fn get_state_status(& mut self) -> bool {
+    pub fn get_state_status(&self) -> bool {
         self.state_updated
     }
+
+    /// sets when the states are snapshotted and which deltas are pruned after a snapshot.
+    pub fn set_snapshot_config(&mut self, config: SnapshotConfig) {
+        self.snapshot_config = config;
+    }
+
+    pub fn get_snapshot_config(&self) -> SnapshotConfig {
+        self.snapshot_config
+    }
+
+    /// sets the directory the backups requested over IPC are created in.
+    pub fn set_backup_dir<P: AsRef<Path>>(&mut self, dir: P) {
+        self.backup_dir = Some(dir.as_ref().to_path_buf());
+    }
+
+    pub fn get_backup_dir(&self) -> Option<&Path> {
+        self.backup_dir.as_ref().map(PathBuf::as_path)
+    }
+
+    /// returns an estimate of the number of bytes the contract takes, without reading its keys,
+    /// or `None` if the storage doesn't keep one (sled only knows the size of the whole DB).
+    pub fn estimated_contract_size(&self, address: &ContractAddress) -> Result<Option<u64>, Error> {
+        let hash = address.to_hex();
+        if !self.storage.has_namespace(&hash) {
+            return Err(DBErr { command: "estimated_contract_size".to_string(), kind: DBErrKind::MissingKey }.into());
+        }
+        self.storage.estimated_size(&hash)
+    }
+
+    /// writes the batch and waits for it to reach the disk.
+    pub(crate) fn write_synced(&mut self, batch: StorageBatch) -> Result<(), Error> {
+        self.storage.write(batch, SYNC)
+    }
+
+    /// writes the value, and if it's a delta also its keccak in the same batch so the delta can be verified later.
+    fn put_synced(&mut self, hash: &str, index_key: &[u8], value: &[u8]) -> Result<(), Error> {
+        if !self.storage.has_namespace(hash) {
+            return Err(DBErr { command: "put".to_string(), kind: DBErrKind::MissingKey }.into());
+        }
+        let mut batch = StorageBatch::default();
+        batch.put(hash, index_key, value);
+        if let Some(hash_key) = delta_hash_key(index_key) {
+            batch.put(hash, &hash_key, &value.keccak256()[..]);
+        }
+        self.write_synced(batch)
+    }
+}
+
+/// A single write in a batch, see `CRUDInterface::batch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOp<K, V> {
+    /// Creates a new key, the batch fails if it already exists.
+    Create(K, V),
+    /// Updates an existing key, the batch fails if it doesn't exist.
+    Update(K, V),
+    /// Writes the key whether it exists or not.
+    ForceUpdate(K, V),
+    /// Deletes an existing key, the batch fails if it doesn't exist.
+    Delete(K),
 }
 
 pub trait CRUDInterface<E, K, T, V> {
@@ -185,6 +246,25 @@ pub trait CRUDInterface<E, K, T, V> {
     /// db.force_update(&key, &updated_val[..]).unwrap();
     /// assert_eq!(b"EnigmaMPC".to_vec(), db.read(&key).unwrap());
     fn force_update(&mut self, key: K, value: V) -> Result<(), E>;
+    /// Writes all the operations atomically, either all of them are written or none of them are.
+    /// The operations are checked in order, so a key created in the batch can be updated or deleted later in it.
+    ///
+    /// # Examples
+    /// ```
+    /// # extern crate tempfile;
+    /// # extern crate enigma_core_app;
+    /// # use enigma_core_app::db::dal::{DB, BatchOp, CRUDInterface};
+    /// # use enigma_core_app::db::primitives::Array32u8;
+    ///
+    /// # let tempdir = tempfile::tempdir().unwrap();
+    /// # let mut db = DB::new(tempdir.path(), true).unwrap();
+    /// # let (key, other_key) = (Array32u8([7u8; 32]), Array32u8([8u8; 32]));
+    /// db.batch(vec![BatchOp::Create(&key, &b"Enigma"[..]), BatchOp::Create(&other_key, &b"MPC"[..])]).unwrap();
+    /// // the key already exists, so nothing is written
+    /// assert!(db.batch(vec![BatchOp::Update(&other_key, &b"protocol"[..]), BatchOp::Create(&key, &b"MPC"[..])]).is_err());
+    /// assert_eq!(b"MPC".to_vec(), db.read(&other_key).unwrap());
+    /// ```
+    fn batch(&mut self, ops: Vec<BatchOp<K, V>>) -> Result<(), E>;
 }
 
 impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
@@ -193,21 +273,13 @@ impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
     fn create(&mut self, key: &'a K, value: &'a [u8]) -> Result<(), Error> {
         key.as_split(|hash, index_key| {
             debug!("DB: Create: contract_address: {}, key: {:?}, value: {:?}", hash, index_key, value);
-            // creates the ColumnFamily and verifies that it doesn't already exist
-            let cf_key = match self.database.cf_handle(hash) {
-                Some(cf) => cf,
-                None => self.database.create_cf(hash, &self.options)?,
-            };
+            // creates the namespace of the contract if it doesn't already exist
+            self.storage.create_namespace(hash)?;
 
-            // verifies that the key inside the CF doesn't already exist
-            match self.database.get_cf(cf_key, &index_key)? {
+            // verifies that the key inside the namespace doesn't already exist
+            match self.storage.get(hash, index_key)? {
                 Some(_) => Err(DBErr { command: "create".to_string(), kind: DBErrKind::KeyExists }.into()),
-                None => {
-                    let mut write_options = WriteOptions::default();
-                    write_options.set_sync(SYNC);
-                    self.database.put_cf_opt(cf_key, &index_key, &value, &write_options)?;
-                    Ok(())
-                }
+                None => self.put_synced(hash, index_key, value),
             }
         })
     }
@@ -216,9 +288,11 @@ impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
     fn read(&self, key: &'a K) -> Result<Vec<u8>, Error> {
         key.as_split(|hash, index_key| {
             debug!("DB: Read: contract_address: {}, key: {:?}", hash, index_key);
-            let cf_key = self.database.cf_handle(&hash).ok_or(DBErr { command: "read".to_string(), kind: DBErrKind::MissingKey })?;
-            let value = self.database.get_cf(cf_key, &index_key)?.ok_or(DBErr { command: "read".to_string(), kind: DBErrKind::MissingKey })?;
-            Ok(value.to_vec())
+            if !self.storage.has_namespace(hash) {
+                return Err(DBErr { command: "read".to_string(), kind: DBErrKind::MissingKey }.into());
+            }
+            let value = self.storage.get(hash, index_key)?.ok_or(DBErr { command: "read".to_string(), kind: DBErrKind::MissingKey })?;
+            Ok(value)
         })
     }
 
@@ -226,16 +300,11 @@ impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
     fn update(&mut self, key: &'a K, value: &'a [u8]) -> Result<(), Error> {
         key.as_split(|hash, index_key| {
             debug!("Updating DB: contract_address: {}, key: {:?}, value: {:?}", hash, index_key, value);
-            let cf_key = self.database.cf_handle(&hash).ok_or(DBErr { command: "update".to_string(), kind: DBErrKind::MissingKey })?;
-
-            if self.database.get_cf(cf_key, &index_key)?.is_none() {
+            if !self.storage.has_namespace(hash) || self.storage.get(hash, index_key)?.is_none() {
                 return Err(DBErr { command: "update".to_string(), kind: DBErrKind::MissingKey }.into());
             }
 
-            let mut write_options = WriteOptions::default();
-            write_options.set_sync(SYNC);
-            self.database.put_cf_opt(cf_key, &index_key, value, &write_options)?;
-            Ok(())
+            self.put_synced(hash, index_key, value)
         })
     }
 
@@ -243,13 +312,15 @@ impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
     fn delete(&mut self, key: &'a K) -> Result<(), Error> {
         key.as_split(|hash, index_key| {
             debug!("DB: Delete: contract_address: {}, key: {:?}", hash, index_key);
-            let cf_key = self.database.cf_handle(&hash).ok_or(DBErr { command: "delete".to_string(), kind: DBErrKind::MissingKey })?;
-
-            if self.database.get_cf(cf_key, &index_key)?.is_none() {
+            if !self.storage.has_namespace(hash) || self.storage.get(hash, index_key)?.is_none() {
                 return Err(DBErr { command: "delete".to_string(), kind: DBErrKind::MissingKey }.into());
             }
-            self.database.delete_cf(cf_key, &index_key)?;
-            Ok(())
+            let mut batch = StorageBatch::default();
+            batch.delete(hash, index_key);
+            if let Some(hash_key) = delta_hash_key(index_key) {
+                batch.delete(hash, &hash_key);
+            }
+            self.storage.write(batch, false)
         })
     }
 
@@ -257,7 +328,7 @@ impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
     fn delete_contract(&mut self, key: &'a K) -> Result<(), Error> {
         key.as_split(|hash, _| {
             debug!("DB: Delete Contract: contract_address: {}", hash);
-            self.database.drop_cf(&hash).
+            self.storage.drop_namespace(hash).
                 map_err(|_| DBErr { command: "delete_contract".to_string(), kind: DBErrKind::MissingKey }.into())
         })
     }
@@ -267,22 +338,86 @@ impl<'a, K: SplitKey> CRUDInterface<Error, &'a K, Vec<u8>, &'a [u8]> for DB {
         key.as_split(|hash, index_key| {
             debug!("DB: Force Update: contract_address: {}, key: {:?}, value: {:?}", hash, index_key, value);
             // if the address does not exist, in force update, we would like to write it anyways.
-            let cf_key = match self.database.cf_handle(hash) {
-                Some(cf) => cf,
-                None => self.database.create_cf(hash, &self.options)?,
-            };
-            let mut write_options = WriteOptions::default();
-            write_options.set_sync(SYNC);
-            self.database.put_cf_opt(cf_key, &index_key, value, &write_options)?;
-            Ok(())
+            self.storage.create_namespace(hash)?;
+            self.put_synced(hash, index_key, value)
         })
     }
+
+    #[logfn(DEBUG)]
+    fn batch(&mut self, ops: Vec<BatchOp<&'a K, &'a [u8]>>) -> Result<(), Error> {
+        debug!("DB: Batch: {} operations", ops.len());
+        // whether each key exists after the operations that came before it in the batch.
+        let mut exists: HashMap<(String, Vec<u8>), bool> = HashMap::new();
+        let mut writes = Vec::with_capacity(ops.len());
+        for op in ops {
+            let (key, value, should_exist, command) = match op {
+                BatchOp::Create(key, value) => (key, Some(value), Some(false), "create"),
+                BatchOp::Update(key, value) => (key, Some(value), Some(true), "update"),
+                BatchOp::ForceUpdate(key, value) => (key, Some(value), None, "force_update"),
+                BatchOp::Delete(key) => (key, None, Some(true), "delete"),
+            };
+            key.as_split(|hash, index_key| -> Result<(), Error> {
+                let id = (hash.to_string(), index_key.to_vec());
+                let existed = match exists.get(&id) {
+                    Some(existed) => *existed,
+                    None => self.storage.has_namespace(hash) && self.storage.get(hash, index_key)?.is_some(),
+                };
+                match should_exist {
+                    Some(true) if !existed => return Err(DBErr { command: command.to_string(), kind: DBErrKind::MissingKey }.into()),
+                    Some(false) if existed => return Err(DBErr { command: command.to_string(), kind: DBErrKind::KeyExists }.into()),
+                    _ => (),
+                }
+                exists.insert(id.clone(), value.is_some());
+                writes.push((id, value));
+                Ok(())
+            })?;
+        }
+
+        // namespaces can't be created in a batch, so the new ones are dropped if the batch fails.
+        let mut created = Vec::new();
+        let mut batch = StorageBatch::default();
+        for ((hash, index_key), value) in &writes {
+            if !self.storage.has_namespace(hash) && !created.contains(&hash) {
+                match value {
+                    Some(_) => created.push(hash),
+                    // a key that was created and deleted in the same batch.
+                    None => continue,
+                }
+            }
+            let hash_key = delta_hash_key(index_key);
+            match value {
+                Some(value) => {
+                    batch.put(hash, index_key, value);
+                    if let Some(hash_key) = hash_key {
+                        batch.put(hash, &hash_key, &value.keccak256()[..]);
+                    }
+                }
+                None => {
+                    batch.delete(hash, index_key);
+                    if let Some(hash_key) = hash_key {
+                        batch.delete(hash, &hash_key);
+                    }
+                }
+            }
+        }
+        let mut res = created.iter().map(|hash| self.storage.create_namespace(hash)).collect::<Result<(), Error>>();
+        if res.is_ok() {
+            res = self.write_synced(batch);
+        }
+        if res.is_err() {
+            for hash in created {
+                // the namespace might have failed being created.
+                let _ = self.storage.drop_namespace(hash);
+            }
+        }
+        res
+    }
 }
 
 #[cfg(test)]
 mod test {
 
-    use crate::db::{tests::create_test_db, dal::CRUDInterface, primitives::{Array32u8, DeltaKey, Stype}};
+    use crate::db::{tests::create_test_db, dal::{BatchOp, CRUDInterface, DB}, primitives::{Array32u8, DeltaKey, Stype}, P2PCalls};
     use hex::ToHex;
     use enigma_types::ContractAddress;
 
@@ -335,7 +470,7 @@ mod test {
 
         let arr = [3u8; 32];
         //created an empty cf in the DB
-        db.database.create_cf(&arr.to_hex(), &db.options).unwrap();
+        db.storage.create_namespace(&arr.to_hex()).unwrap();
         let v = b"Enigma";
         db.create(&Array32u8(arr), v).unwrap();
         assert_eq!(db.read(&Array32u8(arr)).unwrap(), v);
@@ -462,7 +597,7 @@ mod test {
         let (db, _dir) = create_test_db();
 
         let arr = [3u8; 32];
-        let _cf = db.database.create_cf(&arr.to_hex(), &db.options).unwrap();
+        let _cf = db.storage.create_namespace(&arr.to_hex()).unwrap();
         db.read(&Array32u8(arr)).unwrap();
     }
 
@@ -481,7 +616,7 @@ mod test {
         let (mut db, _dir) = create_test_db();
 
         let arr = [4u8; 32];
-        db.database.create_cf(&arr.to_hex(), &db.options).unwrap();
+        db.storage.create_namespace(&arr.to_hex()).unwrap();
         db.update(&Array32u8(arr), b"Enigma").unwrap();
     }
 
@@ -500,7 +635,7 @@ mod test {
         let (mut db, _dir) = create_test_db();
 
         let arr = [5u8; 32];
-        db.database.create_cf(&arr.to_hex(), &db.options).unwrap();
+        db.storage.create_namespace(&arr.to_hex()).unwrap();
         db.delete(&Array32u8(arr)).unwrap();
     }
 
@@ -515,4 +650,57 @@ mod test {
         assert_eq!(db.read(&Array32u8(arr)).unwrap(), v);
         db.create(&Array32u8(arr), v).unwrap();
     }
+
+    #[test]
+    fn test_batch() {
+        let (mut db, _dir) = create_test_db();
+        let addr: ContractAddress = [2u8; 32].into();
+        let dk_code = DeltaKey::new(addr, Stype::ByteCode);
+        let dk_delta = DeltaKey::new(addr, Stype::Delta(0));
+        let dk_state = DeltaKey::new(addr, Stype::State);
+        db.batch(vec![BatchOp::Create(&dk_code, &b"code"[..]), BatchOp::Create(&dk_delta, &b"delta"[..])]).unwrap();
+        assert_eq!(db.read(&dk_code).unwrap(), b"code");
+        assert!(db.verify_deltas(&addr).unwrap().is_valid());
+
+        // a key created earlier in the batch can be updated and deleted later in it
+        db.batch(vec![BatchOp::Create(&dk_state, &b"state"[..]), BatchOp::Update(&dk_state, &b"new state"[..]), BatchOp::Delete(&dk_delta)]).unwrap();
+        assert_eq!(db.read(&dk_state).unwrap(), b"new state");
+        assert!(db.read(&dk_delta).is_err());
+        assert_eq!(db.verify_deltas(&addr).unwrap().verified, 0);
+    }
+
+    #[test]
+    fn test_batch_all_or_nothing() {
+        let (mut db, _dir) = create_test_db();
+        let addr: ContractAddress = [2u8; 32].into();
+        let dk_code = DeltaKey::new(addr, Stype::ByteCode);
+        db.create(&dk_code, &b"code"[..]).unwrap();
+
+        let dk_delta = DeltaKey::new(addr, Stype::Delta(0));
+        let other_addr: ContractAddress = [3u8; 32].into();
+        let dk_other = DeltaKey::new(other_addr, Stype::ByteCode);
+        let ops = vec![BatchOp::ForceUpdate(&dk_delta, &b"delta"[..]), BatchOp::ForceUpdate(&dk_other, &b"other code"[..]), BatchOp::Create(&dk_code, &b"new code"[..])];
+        assert!(db.batch(ops).is_err());
+        assert!(db.read(&dk_delta).is_err());
+        assert!(db.read(&dk_other).is_err());
+        assert_eq!(db.read(&dk_code).unwrap(), b"code");
+        assert_eq!(db.get_all_addresses().unwrap(), vec![addr]);
+
+        assert!(db.batch(vec![BatchOp::Delete(&dk_code), BatchOp::Update(&dk_code, &b"new code"[..])]).is_err());
+        assert_eq!(db.read(&dk_code).unwrap(), b"code");
+    }
+
+    #[test]
+    fn test_estimated_contract_size() {
+        let mut db = DB::new_in_memory();
+        let addr: ContractAddress = [5u8; 32].into();
+        assert!(db.estimated_contract_size(&addr).is_err());
+
+        db.create(&DeltaKey::new(addr, Stype::ByteCode), &b"code"[..]).unwrap();
+        let code_size = db.estimated_contract_size(&addr).unwrap().unwrap();
+        assert!(code_size > 4);
+        // the hash of a delta is stored with it
+        db.create(&DeltaKey::new(addr, Stype::Delta(0)), &b"delta"[..]).unwrap();
+        assert!(db.estimated_contract_size(&addr).unwrap().unwrap() >= code_size + 5 + 32);
+    }
 }
@@ -1,11 +1,11 @@
 use common_u::errors::{DBErr, DBErrKind};
 use db::dal::{CRUDInterface, DB};
-use db::primitives::{DeltaKey, SplitKey, Stype};
+use db::primitives::{delta_hash_key, DeltaKey, SplitKey, Stype};
+use db::storage::StorageBatch;
+use enigma_crypto::hash::Keccak256;
 use enigma_types::ContractAddress;
 use failure::Error;
 use hex::{FromHex, ToHex};
-use rocksdb::DB as rocks_db;
-use rocksdb::{Direction, IteratorMode, ReadOptions, WriteBatch};
 
 const DELTA_PREFIX: &[u8] = &[1];
 
@@ -226,6 +226,11 @@ pub trait P2PCalls<V> {
     /// let deltas = db.get_deltas(dk1, dk3).unwrap().unwrap();
     /// assert_eq!(deltas.len(), 2);
     /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the range starts with a delta that was pruned after a state snapshot
+    /// it returns a `DBErr` of kind `DBErrKind::Pruned` with the index of the first delta that was kept.
     fn get_deltas<K: SplitKey>(&self, from: K, to: K) -> ResultTypeVec<(K, V)>;
 
     /// Inserts a list of Key-Values into the DB in one atomic operation
@@ -265,13 +270,13 @@ impl P2PCalls<Vec<u8>> for DB {
         // to_hex converts the [u8] to str
         let str_addr = address.to_hex();
         debug!("DB: Get Tip: cf: {}, ", str_addr);
-        let cf_key =
-            self.database.cf_handle(&str_addr).ok_or(DBErr { command: "get_tip".to_string(), kind: DBErrKind::MissingKey })?;
+        if !self.storage.has_namespace(&str_addr) {
+            return Err(DBErr { command: "get_tip".to_string(), kind: DBErrKind::MissingKey }.into());
+        }
 
-        let iter = self.database.prefix_iterator_cf(cf_key, DELTA_PREFIX)?;
-        let last = iter.last().ok_or(DBErr { command: "get_tip".to_string(), kind: DBErrKind::MissingKey })?;
-        let k_key = K::from_split(&str_addr, &*last.0)?;
-        let value =  (&*last.1).to_vec();
+        let tip = self.storage.last_in_prefix(&str_addr, DELTA_PREFIX)?;
+        let (key, value) = tip.ok_or(DBErr { command: "get_tip".to_string(), kind: DBErrKind::MissingKey })?;
+        let k_key = K::from_split(&str_addr, &key)?;
         debug!("DB: Continue Get Tip, key: {:?} value: {:?}", k_key, value);
         Ok((k_key, value))
     }
@@ -292,14 +297,11 @@ impl P2PCalls<Vec<u8>> for DB {
     #[logfn(DEBUG)]
     fn get_all_addresses(&self) -> Result<Vec<ContractAddress>, Error> {
         debug!("DB: Get all addresses");
-        // get a list of all CF's (addresses) in our DB
-        let mut cf_list = rocks_db::list_cf(&self.options, &self.location)?;
-        match cf_list.len() {
-            // list_cf returns "Default" as the first CF,
-            // so we remove it if we have elements other than that in the DB.
-            l if l > 1 => cf_list.remove(0),
-            _ => return Err(DBErr { command: "get_all_addresses".to_string(), kind: DBErrKind::MissingKey }.into()),
-        };
+        // get a list of all the namespaces (addresses) in our DB
+        let cf_list = self.storage.namespaces()?;
+        if cf_list.is_empty() {
+            return Err(DBErr { command: "get_all_addresses".to_string(), kind: DBErrKind::MissingKey }.into());
+        }
         // convert all addresses from strings to slices.
         // filter_map filters all None types from the iterator,
         // therefore we return Option type for each item in the closure
@@ -323,7 +325,7 @@ impl P2PCalls<Vec<u8>> for DB {
 
     #[logfn(DEBUG)]
     fn get_delta<K: SplitKey>(&self, key: K) -> ResultVec<u8> {
-
+        key.as_split(|hash, index_key| check_pruned(self, "get_delta", hash, index_key))?;
         Ok(self.read(&key)?)
     }
 
@@ -344,31 +346,31 @@ impl P2PCalls<Vec<u8>> for DB {
     #[logfn(DEBUG)]
     fn get_deltas<K: SplitKey>(&self, from: K, to: K) -> ResultTypeVec<(K, Vec<u8>)> {
         // a vector for the output values which will consist of tuples: (key: K, value/delta: D)
-        // convert the key to the rocksdb representation
+        // convert the key to the storage representation
         from.as_split(|from_hash, from_key| {
-            // make sure the address exists as a CF in the DB
-            let cf_key =
-                self.database.cf_handle(&from_hash).ok_or(DBErr { command: "read".to_string(), kind: DBErrKind::MissingKey })?;
+            // make sure the address exists as a namespace in the DB
+            if !self.storage.has_namespace(from_hash) {
+                return Err(DBErr { command: "read".to_string(), kind: DBErrKind::MissingKey }.into());
+            }
+
+            // a range that starts with pruned deltas can't be returned.
+            check_pruned(self, "get_deltas", from_hash, from_key)?;
 
             // if exists, extract the second key for the range.
             to.as_split(|hash_to, to_key| {
                 if hash_to != from_hash {
                     bail!("addresses of values are not equal {:?},{:?}", hash_to, from_hash);
                 }
-                let mut read_opts = ReadOptions::default();
-                // add the key as an upper bound
-                // (all elements up to this key, not included!!)
-                read_opts.set_iterate_upper_bound(&to_key);
-                // build an iterator which will iterate from the first key
-                let db_iter =
-                    self.database.iterator_cf_opt(cf_key, &read_opts, IteratorMode::From(&from_key, Direction::Forward))?;
-                let key_val: Vec<(K, Vec<u8>)> = db_iter
+                // all elements from the first key up to the second key (not included!!)
+                let range = self.storage.range(hash_to, from_key, Some(to_key))?;
+                let key_val: Vec<(K, Vec<u8>)> = range
+                    .into_iter()
                     .map(|(key, val)| {
                         // creating from the string of the address and the
                         // key of each result in the iterator a K type.
                         // from_split returns a result and therefore will return
                         // an error in case that it wasn't able to create the key.
-                        (K::from_split(hash_to, &*key).unwrap(), (&*val).to_vec()) // TODO: Handle this error
+                        (K::from_split(hash_to, &key).unwrap(), val) // TODO: Handle this error
                     })
                     .collect();
                 // add the values received from this loop to the output vector.
@@ -392,23 +394,35 @@ impl P2PCalls<Vec<u8>> for DB {
     #[logfn(DEBUG)]
     fn insert_tuples<K: SplitKey>(&mut self, key_vals: &[(K, Vec<u8>)]) -> Vec<Result<(), Error>> {
         let mut res = Vec::with_capacity(key_vals.len());
-        let mut batch = WriteBatch::default();
+        let mut batch = StorageBatch::default();
         for (key, val) in key_vals {
             let tmp_res = key.as_split(|cf_str, key_slice| -> Result<(), Error> {
-                let cf = match self.database.cf_handle(cf_str) {
-                    Some(cf) => cf,
-                    None => self.database.create_cf(cf_str, &self.options)?,
-                };
-                batch.put_cf(cf, key_slice, val)?;
+                self.storage.create_namespace(cf_str)?;
+                batch.put(cf_str, key_slice, val);
+                if let Some(hash_key) = delta_hash_key(key_slice) {
+                    batch.put(cf_str, &hash_key, &val.keccak256()[..]);
+                }
                 Ok(())
             });
             res.push(tmp_res);
         }
-        match self.database.write(batch) {
+        match self.storage.write(batch, false) {
             Ok(_) => res,
-            Err(e) => vec![Err(e.into())],
+            Err(e) => vec![Err(e)],
+        }
+    }
+}
+
+/// the deltas before the first one that was kept are only included in a snapshot,
+/// so instead of a missing key this returns an error with the index of the first delta that was kept.
+fn check_pruned(db: &DB, command: &str, hash: &str, key: &[u8]) -> Result<(), Error> {
+    if let Ok(DeltaKey { contract_address, key_type: Stype::Delta(index) }) = DeltaKey::from_split(hash, key) {
+        let first_delta = db.get_first_delta(&contract_address)?;
+        if index < first_delta {
+            return Err(DBErr { command: command.to_string(), kind: DBErrKind::Pruned(first_delta) }.into());
         }
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -636,7 +650,7 @@ mod test {
 
         let expected_addresses = vec![contract_address_a, contract_address_b, contract_address_c];
 
-        let _cf = db.database.create_cf(&cf_str, &db.options).unwrap();
+        db.storage.create_namespace(&cf_str).unwrap();
 
         let accepted_addresses: Vec<ContractAddress> = db.get_all_addresses().unwrap();
 
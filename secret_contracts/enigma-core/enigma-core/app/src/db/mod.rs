@@ -1,22 +1,56 @@
+pub mod backup;
 pub mod dal;
 pub mod iterator;
 pub mod primitives;
+pub mod shared;
+pub mod snapshot;
+pub mod storage;
+pub mod verify;
 
+pub use crate::db::backup::*;
 pub use crate::db::dal::*;
 pub use crate::db::iterator::*;
 pub use crate::db::primitives::*;
+pub use crate::db::shared::*;
+pub use crate::db::snapshot::*;
+pub use crate::db::storage::{Backend, Storage, StorageBatch};
+pub use crate::db::verify::*;
 
 
 #[cfg(test)]
 pub mod tests {
     extern crate tempfile;
     use self::tempfile::TempDir;
-    use crate::db::DB;
+    use crate::db::{Backend, DB};
+    use std::env;
 
-    /// It's important to save TempDir too, because when it gets dropped the directory will be removed.
-    pub fn create_test_db() -> (DB, TempDir) {
+    /// The backend of the test DBs, it can be set with `ENIGMA_TEST_DB_BACKEND` to run the tests against any backend,
+    /// by default the test DBs are kept in memory.
+    pub fn test_backend() -> Backend {
+        match env::var("ENIGMA_TEST_DB_BACKEND") {
+            Ok(backend) => backend.parse().unwrap(),
+            Err(_) => Backend::Memory,
+        }
+    }
+
+    /// returns an empty DB with the test backend.
+    /// It's important to save the TempDir of the backends stored on disk too, because when it gets dropped the directory will be removed.
+    pub fn create_test_db() -> (DB, Option<TempDir>) {
+        match test_backend() {
+            Backend::Memory => (DB::new_in_memory(), None),
+            backend => {
+                let (db, tempdir) = create_disk_test_db(backend);
+                (db, Some(tempdir))
+            }
+        }
+    }
+
+    /// returns an empty DB stored on disk, for the tests that need the files of the DB.
+    /// It's the test backend, or RocksDB if the test backend is in memory.
+    pub fn create_disk_test_db(backend: Backend) -> (DB, TempDir) {
+        let backend = if backend == Backend::Memory { Backend::RocksDB } else { backend };
         let tempdir = tempfile::tempdir().unwrap();
-        let db = DB::new(tempdir.path(), true).unwrap();
+        let db = DB::open(tempdir.path(), backend, true).unwrap();
         (db, tempdir)
     }
-}
\ No newline at end of file
+}
@@ -1,4 +1,4 @@
-use enigma_types::ContractAddress;
+use enigma_types::{ContractAddress, Hash256};
 use failure::Error;
 use hex::{FromHex, ToHex};
 use std::str;
@@ -25,6 +25,14 @@ pub enum Stype {
     Delta(u32),
     State,
     ByteCode,
+    /// An encrypted state that includes all the deltas up to this index.
+    Snapshot(u32),
+    /// The index of the first delta that wasn't pruned.
+    PruneIndex,
+    /// The keccak of the delta with this index, recorded when the delta is written.
+    DeltaHash(u32),
+    /// The result of the task with this id, stored with its delta so a retry of the task doesn't run it again.
+    Task(Hash256),
 }
 
 impl Stype {
@@ -34,6 +42,21 @@ impl Stype {
             _ => panic!("called `Stype::unwrap()` on a non `Delta` value"),
         }
     }
+
+    pub fn unwrap_snapshot(self) -> u32 {
+        match self {
+            Stype::Snapshot(val) => val,
+            _ => panic!("called `Stype::unwrap_snapshot()` on a non `Snapshot` value"),
+        }
+    }
+}
+
+/// returns the key of the hash of the delta if the key is of a delta.
+pub(crate) fn delta_hash_key(index_key: &[u8]) -> Option<Vec<u8>> {
+    match index_key.first() {
+        Some(1) => Some([&[6u8][..], &index_key[1..]].concat()),
+        _ => None,
+    }
 }
 
 use std::fmt::Debug;
@@ -72,6 +95,19 @@ impl SplitKey for DeltaKey {
             }
             Stype::State => key.push(2),    //type
             Stype::ByteCode => key.push(3), //type
+            Stype::Snapshot(num) => {
+                key.push(4); //type
+                key.extend_from_slice(&num.to_be_bytes());
+            }
+            Stype::PruneIndex => key.push(5), //type
+            Stype::DeltaHash(num) => {
+                key.push(6); //type
+                key.extend_from_slice(&num.to_be_bytes());
+            }
+            Stype::Task(id) => {
+                key.push(7); //type
+                key.extend_from_slice(&id[..]);
+            }
         }
         f(&cf, &key)
     }
@@ -85,6 +121,22 @@ impl SplitKey for DeltaKey {
             },
             2 => Stype::State,
             3 => Stype::ByteCode,
+            4 => {
+                let mut be_bytes = [0u8; 4];
+                be_bytes.copy_from_slice(&_key_type[1..]);
+                Stype::Snapshot(u32::from_be_bytes(be_bytes))
+            },
+            5 => Stype::PruneIndex,
+            6 => {
+                let mut be_bytes = [0u8; 4];
+                be_bytes.copy_from_slice(&_key_type[1..]);
+                Stype::DeltaHash(u32::from_be_bytes(be_bytes))
+            },
+            7 => {
+                let mut id = Hash256::default();
+                id.copy_from_slice(&_key_type[1..]);
+                Stype::Task(id)
+            },
             _ => bail!("Failed parsing the Key, key does not contain a correct index"),
         };
         // if the address is not a correct hex then it not a correct address.
@@ -133,4 +185,15 @@ mod tests {
             assert_eq!(key, expected_key);
         });
     }
+
+    #[test]
+    fn test_task_key_split() {
+        let key = DeltaKey::new([7u8; 32].into(), Stype::Task([9u8; 32].into()));
+        let from = key.as_split(|address, key_type| {
+            assert_eq!(key_type[0], 7);
+            assert_eq!(key_type.len(), 33);
+            DeltaKey::from_split(address, key_type).unwrap()
+        });
+        assert_eq!(from, key);
+    }
 }
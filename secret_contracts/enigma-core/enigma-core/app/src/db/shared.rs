@@ -0,0 +1,256 @@
+//! Sharing the DB between the requests that run concurrently.
+//!
+//! Queries and the ocalls of the tasks read the DB together, so the enclave can run tasks of different contracts
+//! at the same time. The DB is never locked for writing during an ecall, since the ecalls of the other tasks would wait for it
+//! in their ocalls. <br>
+//! The writes of a task are kept in its `TaskWrites` and stored in one batch after the ecall, so a task that conflicts
+//! with another one on a contract they both called fails without storing anything. <br>
+//! The requests that change the DB outside of a task get it exclusively, after the running tasks ended.
+use db::dal::{BatchOp, CRUDInterface, DB};
+use db::primitives::DeltaKey;
+use failure::Error;
+use std::ops::{Deref, DerefMut};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockResult};
+
+pub struct SharedDB {
+    db: RwLock<DB>,
+    // held for reading by every running task, and for writing by the requests that need the DB exclusively.
+    tasks: RwLock<()>,
+}
+
+impl SharedDB {
+    pub fn new(db: DB) -> SharedDB { SharedDB { db: RwLock::new(db), tasks: RwLock::new(()) } }
+
+    /// Reads the DB, concurrently with the other readers.
+    pub fn read(&self) -> RwLockReadGuard<DB> { self.db.read().expect("The DB lock is poisoned") }
+
+    /// Reads the DB only if it isn't locked, so monitoring doesn't wait for a request that holds it.
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<DB>> { self.db.try_read() }
+
+    /// Locks the DB for writing, a task only takes it to commit its `TaskWrites`.
+    pub fn lock(&self) -> RwLockWriteGuard<DB> { self.db.write().expect("The DB lock is poisoned") }
+
+    /// Marks a task as running until the guard is dropped.
+    pub fn task(&self) -> RwLockReadGuard<()> { self.tasks.read().expect("The tasks lock is poisoned") }
+
+    /// Gets the DB exclusively, after the running tasks ended.
+    pub fn exclusive(&self) -> ExclusiveDB {
+        let tasks = self.tasks.write().expect("The tasks lock is poisoned");
+        ExclusiveDB { db: self.lock(), _tasks: tasks }
+    }
+
+    pub fn into_inner(self) -> DB { self.db.into_inner().expect("The DB lock is poisoned") }
+}
+
+/// The DB while no task is running, see `SharedDB::exclusive`.
+pub struct ExclusiveDB<'a> {
+    db: RwLockWriteGuard<'a, DB>,
+    _tasks: RwLockWriteGuard<'a, ()>,
+}
+
+impl<'a> Deref for ExclusiveDB<'a> {
+    type Target = DB;
+    fn deref(&self) -> &DB { &self.db }
+}
+
+impl<'a> DerefMut for ExclusiveDB<'a> {
+    fn deref_mut(&mut self) -> &mut DB { &mut self.db }
+}
+
+/// What the ocalls of an ecall get the DB through.
+pub enum DbAccess<'a> {
+    /// The DB is borrowed for the whole ecall.
+    Borrowed(&'a mut DB),
+    /// The DB is shared with the tasks running concurrently, so every ocall locks it for reading,
+    /// and the writes are kept until the ecall ended.
+    Shared(&'a SharedDB, &'a mut TaskWrites),
+}
+
+impl<'a> DbAccess<'a> {
+    /// The DB for an ocall that reads it, a shared DB is locked for reading so the running tasks read it together.
+    pub fn read(&self) -> DbRef {
+        match self {
+            DbAccess::Borrowed(db) => DbRef::Borrowed(db),
+            DbAccess::Shared(db, _) => DbRef::Read(db.read()),
+        }
+    }
+
+    /// The DB for an ocall that writes to it outside of a task, a task keeps its writes in `writes()` instead.
+    pub fn lock(&mut self) -> DbGuard {
+        match self {
+            DbAccess::Borrowed(db) => DbGuard::Borrowed(db),
+            DbAccess::Shared(db, _) => DbGuard::Locked(db.lock()),
+        }
+    }
+
+    /// The writes of the task if the DB is shared, they must be stored with `TaskWrites::commit`.
+    pub fn writes(&mut self) -> Option<&mut TaskWrites> {
+        match self {
+            DbAccess::Borrowed(_) => None,
+            DbAccess::Shared(_, writes) => Some(&mut **writes),
+        }
+    }
+}
+
+impl<'a> From<&'a mut DB> for DbAccess<'a> {
+    fn from(db: &'a mut DB) -> Self { DbAccess::Borrowed(db) }
+}
+
+impl<'a> From<(&'a SharedDB, &'a mut TaskWrites)> for DbAccess<'a> {
+    fn from((db, writes): (&'a SharedDB, &'a mut TaskWrites)) -> Self { DbAccess::Shared(db, writes) }
+}
+
+/// The writes of a task that shares the DB, in the order they were made.
+#[derive(Debug, Default)]
+pub struct TaskWrites {
+    ops: Vec<BatchOp<DeltaKey, Vec<u8>>>,
+}
+
+impl TaskWrites {
+    pub fn push(&mut self, op: BatchOp<DeltaKey, Vec<u8>>) { self.ops.push(op) }
+
+    /// Drops the delta the task created under `key`, returns false if it didn't create one.
+    pub fn remove_created(&mut self, key: &DeltaKey) -> bool {
+        match self.ops.iter().rposition(|op| match op { BatchOp::Create(k, _) => k == key, _ => false }) {
+            Some(pos) => {
+                self.ops.remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stores all the writes or none of them,
+    /// it fails if another task already created one of the deltas the task created.
+    pub fn commit(self, db: &SharedDB) -> Result<(), Error> {
+        if self.ops.is_empty() {
+            return Ok(());
+        }
+        let ops: Vec<BatchOp<&DeltaKey, &[u8]>> = self.ops.iter().map(|op| match op {
+            BatchOp::Create(key, value) => BatchOp::Create(key, &value[..]),
+            BatchOp::Update(key, value) => BatchOp::Update(key, &value[..]),
+            BatchOp::ForceUpdate(key, value) => BatchOp::ForceUpdate(key, &value[..]),
+            BatchOp::Delete(key) => BatchOp::Delete(key),
+        }).collect();
+        db.lock().batch(ops)
+    }
+}
+
+pub enum DbRef<'a> {
+    Borrowed(&'a DB),
+    Read(RwLockReadGuard<'a, DB>),
+}
+
+impl<'a> Deref for DbRef<'a> {
+    type Target = DB;
+    fn deref(&self) -> &DB {
+        match self {
+            DbRef::Borrowed(db) => db,
+            DbRef::Read(db) => db,
+        }
+    }
+}
+
+pub enum DbGuard<'a> {
+    Borrowed(&'a mut DB),
+    Locked(RwLockWriteGuard<'a, DB>),
+}
+
+impl<'a> Deref for DbGuard<'a> {
+    type Target = DB;
+    fn deref(&self) -> &DB {
+        match self {
+            DbGuard::Borrowed(db) => db,
+            DbGuard::Locked(db) => db,
+        }
+    }
+}
+
+impl<'a> DerefMut for DbGuard<'a> {
+    fn deref_mut(&mut self) -> &mut DB {
+        match self {
+            DbGuard::Borrowed(db) => db,
+            DbGuard::Locked(db) => db,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use db::{BatchOp, CRUDInterface, DeltaKey, Stype};
+    use enigma_types::ContractAddress;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_access_writes_through() {
+        let mut db = DB::new_in_memory();
+        let key = DeltaKey::new(ContractAddress::from([1u8; 32]), Stype::Delta(1));
+        let mut access = DbAccess::from(&mut db);
+        assert!(access.writes().is_none());
+        access.lock().force_update(&key, &b"borrowed"[..]).unwrap();
+        assert_eq!(db.read(&key).unwrap(), b"borrowed");
+    }
+
+    #[test]
+    fn test_task_writes() {
+        let shared = SharedDB::new(DB::new_in_memory());
+        let address = ContractAddress::from([1u8; 32]);
+        let (delta, other, state) = (DeltaKey::new(address, Stype::Delta(1)), DeltaKey::new(address, Stype::Delta(2)), DeltaKey::new(address, Stype::State));
+
+        let mut writes = TaskWrites::default();
+        DbAccess::from((&shared, &mut writes)).writes().unwrap().push(BatchOp::Create(delta, b"delta".to_vec()));
+        writes.push(BatchOp::Create(other, b"other".to_vec()));
+        writes.push(BatchOp::ForceUpdate(state, b"state".to_vec()));
+        assert!(writes.remove_created(&other));
+        assert!(!writes.remove_created(&other));
+        // nothing is stored until the task commits
+        assert!(shared.read().read(&delta).is_err());
+        writes.commit(&shared).unwrap();
+        assert_eq!(shared.read().read(&delta).unwrap(), b"delta");
+        assert_eq!(shared.read().read(&state).unwrap(), b"state");
+        assert!(shared.read().read(&other).is_err());
+
+        // another task that created the same delta stores none of its writes
+        let mut writes = TaskWrites::default();
+        writes.push(BatchOp::ForceUpdate(state, b"new state".to_vec()));
+        writes.push(BatchOp::Create(delta, b"conflict".to_vec()));
+        assert!(writes.commit(&shared).is_err());
+        assert_eq!(shared.read().read(&delta).unwrap(), b"delta");
+        assert_eq!(shared.read().read(&state).unwrap(), b"state");
+    }
+
+    #[test]
+    fn test_tasks_read_together() {
+        let shared = SharedDB::new(DB::new_in_memory());
+        let key = DeltaKey::new(ContractAddress::from([1u8; 32]), Stype::State);
+        shared.lock().force_update(&key, &b"state"[..]).unwrap();
+        let (mut first_writes, mut second_writes) = (TaskWrites::default(), TaskWrites::default());
+        let first = DbAccess::from((&shared, &mut first_writes));
+        let second = DbAccess::from((&shared, &mut second_writes));
+        let reading = first.read();
+        assert_eq!(second.read().read(&key).unwrap(), b"state");
+        assert!(shared.db.try_write().is_err());
+        drop(reading);
+    }
+
+    #[test]
+    fn test_exclusive_waits_for_tasks() {
+        let shared = Arc::new(SharedDB::new(DB::new_in_memory()));
+        let task = shared.task();
+        let (sender, receiver) = mpsc::channel();
+        let other = Arc::clone(&shared);
+        let handle = thread::spawn(move || {
+            let _db = other.exclusive();
+            sender.send(()).unwrap();
+        });
+        // the DB itself can still be used by the running task.
+        drop(shared.lock());
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(task);
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        handle.join().unwrap();
+    }
+}
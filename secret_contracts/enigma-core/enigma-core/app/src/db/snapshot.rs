@@ -0,0 +1,207 @@
+use common_u::errors::{self, DBErr, DBErrKind};
+use db::dal::{CRUDInterface, DB};
+use db::primitives::{DeltaKey, SplitKey, Stype};
+use db::storage::StorageBatch;
+use enigma_types::ContractAddress;
+use failure::Error;
+use hex::ToHex;
+
+const SNAPSHOT_PREFIX: &[u8] = &[4];
+
+/// Configures when the states of the contracts are snapshotted and which deltas are pruned after a snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct SnapshotConfig {
+    /// Snapshot the state of a contract every `interval` deltas, if `None` it's only snapshotted on demand.
+    pub interval: Option<u32>,
+    /// The number of deltas included in the latest snapshot that are kept, so peers can still sync them.
+    pub retention: u32,
+}
+
+impl DB {
+    /// Stores an encrypted state that includes all the deltas up to `index`.
+    pub fn save_snapshot(&mut self, address: &ContractAddress, index: u32, enc_state: &[u8]) -> Result<(), Error> {
+        self.force_update(&DeltaKey::new(*address, Stype::Snapshot(index)), enc_state)
+    }
+
+    /// returns the latest snapshot of the contract and the index of the last delta it includes.
+    #[logfn(DEBUG)]
+    pub fn get_latest_snapshot(&self, address: &ContractAddress) -> Result<(u32, Vec<u8>), Error> {
+        let str_addr = address.to_hex();
+        if !self.storage.has_namespace(&str_addr) {
+            return Err(DBErr { command: "get_latest_snapshot".to_string(), kind: DBErrKind::MissingKey }.into());
+        }
+        let snapshot = self.storage.last_in_prefix(&str_addr, SNAPSHOT_PREFIX)?;
+        let (key, value) = snapshot.ok_or(DBErr { command: "get_latest_snapshot".to_string(), kind: DBErrKind::MissingKey })?;
+        let key = DeltaKey::from_split(&str_addr, &key)?;
+        Ok((key.key_type.unwrap_snapshot(), value))
+    }
+
+    /// returns the index of the first delta of the contract that wasn't pruned.
+    pub fn get_first_delta(&self, address: &ContractAddress) -> Result<u32, Error> {
+        match self.read(&DeltaKey::new(*address, Stype::PruneIndex)) {
+            Ok(value) => {
+                if value.len() != 4 {
+                    return Err(DBErr { command: "get_first_delta".to_string(), kind: DBErrKind::FetchError }.into());
+                }
+                let mut be_bytes = [0u8; 4];
+                be_bytes.copy_from_slice(&value);
+                Ok(u32::from_be_bytes(be_bytes))
+            }
+            // nothing was pruned yet.
+            Err(e) => errors::is_db_err_type(e).map(|_| 0),
+        }
+    }
+
+    /// checks if the state of the contract should be snapshotted now that it has the delta with `index`.
+    pub fn is_snapshot_due(&self, address: &ContractAddress, index: u32) -> bool {
+        let interval = match self.get_snapshot_config().interval {
+            Some(interval) if interval > 0 => interval,
+            _ => return false,
+        };
+        let last_snapshot = self.get_latest_snapshot(address).map(|(index, _)| index).unwrap_or(0);
+        index >= last_snapshot.saturating_add(interval)
+    }
+
+    /// Prunes the deltas that are included in the latest snapshot except for the retention window,
+    /// and the older snapshots that can't be built upon anymore.
+    /// returns the index of the first delta that was kept.
+    #[logfn(DEBUG)]
+    pub fn prune_deltas(&mut self, address: &ContractAddress) -> Result<u32, Error> {
+        let (snapshot_index, _) = self.get_latest_snapshot(address)?;
+        let first_delta = self.get_first_delta(address)?;
+        let prune_until = snapshot_index.saturating_add(1).saturating_sub(self.get_snapshot_config().retention);
+        if prune_until <= first_delta {
+            return Ok(first_delta);
+        }
+        debug!("DB: Prune Deltas: contract_address: {:?}, deltas: {}..{}", address, first_delta, prune_until);
+
+        let str_addr = address.to_hex();
+        if !self.storage.has_namespace(&str_addr) {
+            return Err(DBErr { command: "prune_deltas".to_string(), kind: DBErrKind::MissingKey }.into());
+        }
+        let mut batch = StorageBatch::default();
+        for index in first_delta..prune_until {
+            DeltaKey::new(*address, Stype::Delta(index)).as_split(|hash, key| batch.delete(hash, key));
+            DeltaKey::new(*address, Stype::DeltaHash(index)).as_split(|hash, key| batch.delete(hash, key));
+        }
+        // a snapshot can only be built upon if the delta right after it was kept.
+        for (key, _) in self.storage.prefix(&str_addr, SNAPSHOT_PREFIX)? {
+            if DeltaKey::from_split(&str_addr, &key)?.key_type.unwrap_snapshot() + 1 < prune_until {
+                batch.delete(&str_addr, &key);
+            }
+        }
+        DeltaKey::new(*address, Stype::PruneIndex).as_split(|hash, key| batch.put(hash, key, &prune_until.to_be_bytes()));
+        self.storage.write(batch, false)?;
+        Ok(prune_until)
+    }
+
+    /// returns the keys of the snapshots that include the delta with `index`,
+    /// they must be removed with the delta so the state won't be built from a snapshot that includes it.
+    pub fn snapshots_from(&self, address: &ContractAddress, index: u32) -> Result<Vec<DeltaKey>, Error> {
+        let str_addr = address.to_hex();
+        if !self.storage.has_namespace(&str_addr) {
+            return Ok(Vec::new());
+        }
+        let mut keys = Vec::new();
+        for (key, _) in self.storage.prefix(&str_addr, SNAPSHOT_PREFIX)? {
+            let key = DeltaKey::from_split(&str_addr, &key)?;
+            if key.key_type.unwrap_snapshot() >= index {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use common_u::errors::{DBErr, DBErrKind};
+    use db::{tests::create_test_db, BatchOp, CRUDInterface, DeltaKey, P2PCalls, SnapshotConfig, Stype, DB};
+    use enigma_types::ContractAddress;
+
+    fn fill_deltas(db: &mut DB, address: ContractAddress, count: u32) {
+        let tuples: Vec<_> = (0..count).map(|i| (DeltaKey::new(address, Stype::Delta(i)), vec![i as u8; 8])).collect();
+        for res in db.insert_tuples(&tuples) {
+            res.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_latest_snapshot() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [3u8; 32].into();
+        fill_deltas(&mut db, address, 10);
+        assert!(db.get_latest_snapshot(&address).is_err());
+
+        db.save_snapshot(&address, 4, b"state at 4").unwrap();
+        db.save_snapshot(&address, 8, b"state at 8").unwrap();
+        assert_eq!(db.get_latest_snapshot(&address).unwrap(), (8, b"state at 8".to_vec()));
+        // the snapshots aren't deltas
+        let (tip, _): (DeltaKey, Vec<u8>) = db.get_tip(&address).unwrap();
+        assert_eq!(tip.key_type, Stype::Delta(9));
+
+        let snapshots = db.snapshots_from(&address, 5).unwrap();
+        assert_eq!(snapshots, vec![DeltaKey::new(address, Stype::Snapshot(8))]);
+        db.batch(snapshots.iter().map(BatchOp::Delete).collect()).unwrap();
+        assert_eq!(db.get_latest_snapshot(&address).unwrap(), (4, b"state at 4".to_vec()));
+    }
+
+    #[test]
+    fn test_snapshot_due() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [3u8; 32].into();
+        assert!(!db.is_snapshot_due(&address, 100));
+
+        db.set_snapshot_config(SnapshotConfig { interval: Some(10), retention: 0 });
+        assert!(!db.is_snapshot_due(&address, 9));
+        assert!(db.is_snapshot_due(&address, 10));
+        db.save_snapshot(&address, 10, b"state").unwrap();
+        assert!(!db.is_snapshot_due(&address, 19));
+        assert!(db.is_snapshot_due(&address, 20));
+    }
+
+    #[test]
+    fn test_prune_deltas() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [3u8; 32].into();
+        fill_deltas(&mut db, address, 10);
+        db.set_snapshot_config(SnapshotConfig { interval: None, retention: 3 });
+        // nothing can be pruned before there's a snapshot
+        assert!(db.prune_deltas(&address).is_err());
+
+        db.save_snapshot(&address, 2, b"state at 2").unwrap();
+        db.save_snapshot(&address, 7, b"state at 7").unwrap();
+        assert_eq!(db.prune_deltas(&address).unwrap(), 5);
+        assert_eq!(db.get_first_delta(&address).unwrap(), 5);
+        // pruning again doesn't change anything
+        assert_eq!(db.prune_deltas(&address).unwrap(), 5);
+
+        assert!(db.read(&DeltaKey::new(address, Stype::Delta(4))).is_err());
+        assert!(db.get_delta(DeltaKey::new(address, Stype::Delta(4))).unwrap_err().to_string().contains("pruned"));
+        assert!(db.read(&DeltaKey::new(address, Stype::Delta(5))).is_ok());
+        // the deltas after the first snapshot were pruned, so it was removed
+        assert!(db.read(&DeltaKey::new(address, Stype::Snapshot(2))).is_err());
+        assert_eq!(db.get_latest_snapshot(&address).unwrap().0, 7);
+
+        let res = db.get_deltas(DeltaKey::new(address, Stype::Delta(5)), DeltaKey::new(address, Stype::Delta(10))).unwrap();
+        assert_eq!(res.unwrap().len(), 5);
+        let err = db.get_deltas(DeltaKey::new(address, Stype::Delta(3)), DeltaKey::new(address, Stype::Delta(10))).unwrap_err();
+        match err.downcast::<DBErr>().unwrap().kind {
+            DBErrKind::Pruned(index) => assert_eq!(index, 5),
+            kind => panic!("Expected a pruned error, got: {}", kind),
+        }
+    }
+
+    #[test]
+    fn test_corrupted_prune_index() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [3u8; 32].into();
+        fill_deltas(&mut db, address, 2);
+        db.force_update(&DeltaKey::new(address, Stype::PruneIndex), &[1, 2, 3]).unwrap();
+        let err = db.get_first_delta(&address).unwrap_err();
+        match err.downcast::<DBErr>().unwrap().kind {
+            DBErrKind::FetchError => (),
+            kind => panic!("Expected a fetch error, got: {}", kind),
+        }
+    }
+}
@@ -0,0 +1,610 @@
+//! The storage engines the DB can be stored in.
+//!
+//! The DB keeps every contract in its own namespace, named by the hex of the address,
+//! so an engine only needs to provide ordered keys inside namespaces and atomic batches of writes.
+use common_u::errors::{DBErr, DBErrKind};
+use failure::Error;
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::DB as rocks_db;
+use rocksdb::{Direction, IteratorMode, Options, ReadOptions, SliceTransform, WriteBatch, WriteOptions};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The storage engines that can be selected, RocksDB is the default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    RocksDB,
+    /// Only available if enigma-core was built with the `sled` feature.
+    Sled,
+    /// Nothing is written to disk, so everything is lost when the DB is dropped.
+    Memory,
+}
+
+impl Default for Backend {
+    fn default() -> Self { Backend::RocksDB }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Backend::RocksDB => "rocksdb",
+            Backend::Sled => "sled",
+            Backend::Memory => "memory",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "rocksdb" => Ok(Backend::RocksDB),
+            "sled" => Ok(Backend::Sled),
+            "memory" => Ok(Backend::Memory),
+            _ => bail!("Unknown DB backend: {}, expected one of: rocksdb, sled, memory", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StorageOp {
+    Put { namespace: String, key: Vec<u8>, value: Vec<u8> },
+    Delete { namespace: String, key: Vec<u8> },
+}
+
+/// Writes that are applied atomically by `Storage::write`, the namespaces must already exist.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StorageBatch {
+    pub ops: Vec<StorageOp>,
+}
+
+impl StorageBatch {
+    pub fn put(&mut self, namespace: &str, key: &[u8], value: &[u8]) {
+        self.ops.push(StorageOp::Put { namespace: namespace.to_string(), key: key.to_vec(), value: value.to_vec() });
+    }
+
+    pub fn delete(&mut self, namespace: &str, key: &[u8]) {
+        self.ops.push(StorageOp::Delete { namespace: namespace.to_string(), key: key.to_vec() });
+    }
+
+    pub fn is_empty(&self) -> bool { self.ops.is_empty() }
+
+    fn namespaces<'a>(&'a self) -> impl Iterator<Item = &'a str> + 'a {
+        self.ops.iter().map(|op| match op {
+            StorageOp::Put { namespace, .. } | StorageOp::Delete { namespace, .. } => namespace.as_str(),
+        })
+    }
+}
+
+pub type KeyValue = (Vec<u8>, Vec<u8>);
+pub type KeyValues = Vec<KeyValue>;
+
+/// A key-value storage engine with namespaces, the keys in a namespace are ordered bytewise.
+pub trait Storage: Send + Sync {
+    fn backend(&self) -> Backend;
+
+    /// returns the names of all the namespaces, in the order they were created.
+    fn namespaces(&self) -> Result<Vec<String>, Error>;
+
+    fn has_namespace(&self, namespace: &str) -> bool;
+
+    /// Creates the namespace, it does nothing if it already exists.
+    fn create_namespace(&mut self, namespace: &str) -> Result<(), Error>;
+
+    /// Removes the namespace with all of its keys.
+    fn drop_namespace(&mut self, namespace: &str) -> Result<(), Error>;
+
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+    /// returns the keys in the namespace from `from` (included) up to `to` (not included) with their values, in order.
+    fn range(&self, namespace: &str, from: &[u8], to: Option<&[u8]>) -> Result<KeyValues, Error>;
+
+    /// returns the first key from `from` (included) up to `to` (not included) with its value, without reading the rest of them.
+    fn first_in_range(&self, namespace: &str, from: &[u8], to: Option<&[u8]>) -> Result<Option<KeyValue>, Error>;
+
+    /// returns the last key from `from` (included) up to `to` (not included) with its value, without reading the rest of them.
+    fn last_in_range(&self, namespace: &str, from: &[u8], to: Option<&[u8]>) -> Result<Option<KeyValue>, Error>;
+
+    /// Applies all the writes of the batch or none of them,
+    /// if `sync` is set it only returns after the writes reached the disk.
+    fn write(&mut self, batch: StorageBatch, sync: bool) -> Result<(), Error>;
+
+    /// Creates a consistent copy of the whole storage in a new directory at `path`.
+    fn checkpoint(&self, path: &Path) -> Result<(), Error>;
+
+    /// returns an estimate of the bytes the namespace takes without reading its keys, if the backend keeps one.
+    fn estimated_size(&self, _namespace: &str) -> Result<Option<u64>, Error> { Ok(None) }
+
+    /// returns the keys in the namespace that start with `prefix` with their values, in order.
+    fn prefix(&self, namespace: &str, prefix: &[u8]) -> Result<KeyValues, Error> {
+        self.range(namespace, prefix, prefix_end(prefix).as_ref().map(|end| &end[..]))
+    }
+
+    /// returns the last key in the namespace that starts with `prefix` with its value.
+    fn last_in_prefix(&self, namespace: &str, prefix: &[u8]) -> Result<Option<KeyValue>, Error> {
+        self.last_in_range(namespace, prefix, prefix_end(prefix).as_ref().map(|end| &end[..]))
+    }
+
+    /// returns the first key in the namespace from `from` (included) with its value.
+    fn first_from(&self, namespace: &str, from: &[u8]) -> Result<Option<KeyValue>, Error> { self.first_in_range(namespace, from, None) }
+
+    /// returns all the keys in the namespace with their values, in order.
+    fn all(&self, namespace: &str) -> Result<KeyValues, Error> { self.range(namespace, &[], None) }
+}
+
+/// returns the first key that is after all the keys that start with `prefix`, `None` if there's no such key.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::max_value() {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+fn missing_namespace(command: &str) -> Error { DBErr { command: command.to_string(), kind: DBErrKind::MissingKey }.into() }
+
+/// Opens the storage of the `backend` in `location`, the location is ignored by the in-memory backend.
+pub fn open_storage(location: &Path, backend: Backend, create_if_missing: bool) -> Result<Box<dyn Storage>, Error> {
+    match backend {
+        Backend::RocksDB => Ok(Box::new(RocksStorage::open(location, create_if_missing)?)),
+        Backend::Sled => open_sled(location, create_if_missing),
+        Backend::Memory => Ok(Box::new(MemoryStorage::default())),
+    }
+}
+
+#[cfg(feature = "sled")]
+fn open_sled(location: &Path, create_if_missing: bool) -> Result<Box<dyn Storage>, Error> {
+    Ok(Box::new(SledStorage::open(location, create_if_missing)?))
+}
+
+#[cfg(not(feature = "sled"))]
+fn open_sled(_location: &Path, _create_if_missing: bool) -> Result<Box<dyn Storage>, Error> {
+    bail!("enigma-core was built without the sled backend, build it with `--features sled`")
+}
+
+/// The namespaces are column families.
+pub struct RocksStorage {
+    database: rocks_db,
+    // the options are needed for creating new cf's that would be able to imitate the DB behaviour
+    options: Options,
+}
+
+impl RocksStorage {
+    pub fn open(location: &Path, create_if_missing: bool) -> Result<RocksStorage, Error> {
+        // number of bytes to take into consideration when looking for a similar prefix
+        // would be helpful when querying the DB using iterators.
+        let prefix_extractor = SliceTransform::create_fixed_prefix(1);
+        let mut options = Options::default();
+        options.create_if_missing(create_if_missing);
+        options.set_prefix_extractor(prefix_extractor);
+        // cf_list gets a list of all column families (addresses) from the location where the DB
+        // is stored and when opening it, it adds the list as an argument to the DB. this is done
+        // in case the DB existed, otherwise, an empty list will be added and the call will
+        // be similar to a fresh start
+        let cf_list = rocks_db::list_cf(&options, location).unwrap_or_default();
+        // converts the Strings to slices (str)
+        let cf_list_burrowed = cf_list.iter().map(String::as_str).collect::<Vec<&str>>();
+        let database = rocks_db::open_cf(&options, location, &cf_list_burrowed[..])?;
+        Ok(RocksStorage { database, options })
+    }
+}
+
+impl Storage for RocksStorage {
+    fn backend(&self) -> Backend { Backend::RocksDB }
+
+    fn namespaces(&self) -> Result<Vec<String>, Error> {
+        let mut cf_list = rocks_db::list_cf(&self.options, self.database.path())?;
+        // list_cf returns "default" too, which isn't a namespace.
+        cf_list.retain(|cf| cf != "default");
+        Ok(cf_list)
+    }
+
+    fn has_namespace(&self, namespace: &str) -> bool { self.database.cf_handle(namespace).is_some() }
+
+    fn create_namespace(&mut self, namespace: &str) -> Result<(), Error> {
+        if !self.has_namespace(namespace) {
+            self.database.create_cf(namespace, &self.options)?;
+        }
+        Ok(())
+    }
+
+    fn drop_namespace(&mut self, namespace: &str) -> Result<(), Error> {
+        self.database.drop_cf(namespace).map_err(|_| missing_namespace("drop_namespace"))
+    }
+
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let cf_key = self.database.cf_handle(namespace).ok_or_else(|| missing_namespace("get"))?;
+        Ok(self.database.get_cf(cf_key, key)?.map(|value| value.to_vec()))
+    }
+
+    fn range(&self, namespace: &str, from: &[u8], to: Option<&[u8]>) -> Result<KeyValues, Error> {
+        let cf_key = self.database.cf_handle(namespace).ok_or_else(|| missing_namespace("range"))?;
+        let mut read_opts = ReadOptions::default();
+        if let Some(to) = to {
+            read_opts.set_iterate_upper_bound(to);
+        }
+        let iter = self.database.iterator_cf_opt(cf_key, &read_opts, IteratorMode::From(from, Direction::Forward))?;
+        Ok(iter.map(|(key, value)| (key.to_vec(), value.to_vec())).collect())
+    }
+
+    fn first_in_range(&self, namespace: &str, from: &[u8], to: Option<&[u8]>) -> Result<Option<KeyValue>, Error> {
+        let cf_key = self.database.cf_handle(namespace).ok_or_else(|| missing_namespace("first_in_range"))?;
+        let mut read_opts = ReadOptions::default();
+        if let Some(to) = to {
+            read_opts.set_iterate_upper_bound(to);
+        }
+        let mut iter = self.database.iterator_cf_opt(cf_key, &read_opts, IteratorMode::From(from, Direction::Forward))?;
+        Ok(iter.next().map(|(key, value)| (key.to_vec(), value.to_vec())))
+    }
+
+    fn last_in_range(&self, namespace: &str, from: &[u8], to: Option<&[u8]>) -> Result<Option<KeyValue>, Error> {
+        let cf_key = self.database.cf_handle(namespace).ok_or_else(|| missing_namespace("last_in_range"))?;
+        let mode = match to {
+            Some(to) => IteratorMode::From(to, Direction::Reverse),
+            None => IteratorMode::End,
+        };
+        // seeking backwards starts at `to` itself if it exists, which isn't in the range.
+        let last = self.database.iterator_cf(cf_key, mode)?.find(|(key, _)| to.map_or(true, |to| &key[..] < to));
+        Ok(last.filter(|(key, _)| &key[..] >= from).map(|(key, value)| (key.to_vec(), value.to_vec())))
+    }
+
+    fn write(&mut self, batch: StorageBatch, sync: bool) -> Result<(), Error> {
+        let mut write_batch = WriteBatch::default();
+        for op in batch.ops {
+            match op {
+                StorageOp::Put { namespace, key, value } => {
+                    let cf_key = self.database.cf_handle(&namespace).ok_or_else(|| missing_namespace("write"))?;
+                    write_batch.put_cf(cf_key, &key, &value)?;
+                }
+                StorageOp::Delete { namespace, key } => {
+                    let cf_key = self.database.cf_handle(&namespace).ok_or_else(|| missing_namespace("write"))?;
+                    write_batch.delete_cf(cf_key, &key)?;
+                }
+            }
+        }
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(sync);
+        Ok(self.database.write_opt(write_batch, &write_options)?)
+    }
+
+    /// The files are hard linked if the directory is on the same filesystem.
+    fn checkpoint(&self, path: &Path) -> Result<(), Error> { Ok(Checkpoint::new(&self.database)?.create_checkpoint(path)?) }
+
+    /// The live data in the SST files of the column family and the data in its memtables that wasn't flushed yet.
+    fn estimated_size(&self, namespace: &str) -> Result<Option<u64>, Error> {
+        let cf_key = self.database.cf_handle(namespace).ok_or_else(|| missing_namespace("estimated_size"))?;
+        let mut size = 0;
+        for property in &["rocksdb.estimate-live-data-size", "rocksdb.cur-size-all-mem-tables"] {
+            size += self.database.property_int_value_cf(cf_key, property)?.unwrap_or(0);
+        }
+        Ok(Some(size))
+    }
+}
+
+/// Keeps everything in memory, used for tests and for running a node that doesn't need to persist anything.
+#[derive(Default)]
+pub struct MemoryStorage {
+    namespaces: BTreeMap<String, BTreeMap<Vec<u8>, Vec<u8>>>,
+    created: Vec<String>,
+}
+
+impl Storage for MemoryStorage {
+    fn backend(&self) -> Backend { Backend::Memory }
+
+    fn namespaces(&self) -> Result<Vec<String>, Error> { Ok(self.created.clone()) }
+
+    fn has_namespace(&self, namespace: &str) -> bool { self.namespaces.contains_key(namespace) }
+
+    fn create_namespace(&mut self, namespace: &str) -> Result<(), Error> {
+        if !self.has_namespace(namespace) {
+            self.namespaces.insert(namespace.to_string(), BTreeMap::new());
+            self.created.push(namespace.to_string());
+        }
+        Ok(())
+    }
+
+    fn drop_namespace(&mut self, namespace: &str) -> Result<(), Error> {
+        self.namespaces.remove(namespace).ok_or_else(|| missing_namespace("drop_namespace"))?;
+        self.created.retain(|created| created != namespace);
+        Ok(())
+    }
+
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let keys = self.namespaces.get(namespace).ok_or_else(|| missing_namespace("get"))?;
+        Ok(keys.get(key).cloned())
+    }
+
+    fn range(&self, namespace: &str, from: &[u8], to: Option<&[u8]>) -> Result<KeyValues, Error> {
+        let keys = self.namespaces.get(namespace).ok_or_else(|| missing_namespace("range"))?;
+        Ok(keys
+            .range(from.to_vec()..)
+            .take_while(|(key, _)| to.map_or(true, |to| &key[..] < to))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn first_in_range(&self, namespace: &str, from: &[u8], to: Option<&[u8]>) -> Result<Option<KeyValue>, Error> {
+        let keys = self.namespaces.get(namespace).ok_or_else(|| missing_namespace("first_in_range"))?;
+        let first = keys.range(from.to_vec()..).next();
+        Ok(first.filter(|(key, _)| to.map_or(true, |to| &key[..] < to)).map(|(key, value)| (key.clone(), value.clone())))
+    }
+
+    fn last_in_range(&self, namespace: &str, from: &[u8], to: Option<&[u8]>) -> Result<Option<KeyValue>, Error> {
+        let keys = self.namespaces.get(namespace).ok_or_else(|| missing_namespace("last_in_range"))?;
+        let last = match to {
+            Some(to) => keys.range(from.to_vec()..to.to_vec()).next_back(),
+            None => keys.range(from.to_vec()..).next_back(),
+        };
+        Ok(last.map(|(key, value)| (key.clone(), value.clone())))
+    }
+
+    fn write(&mut self, batch: StorageBatch, _sync: bool) -> Result<(), Error> {
+        // all the namespaces are checked first so a failing batch doesn't write anything.
+        if batch.namespaces().any(|namespace| !self.has_namespace(namespace)) {
+            return Err(missing_namespace("write"));
+        }
+        for op in batch.ops {
+            match op {
+                StorageOp::Put { namespace, key, value } => {
+                    self.namespaces.get_mut(&namespace).unwrap().insert(key, value);
+                }
+                StorageOp::Delete { namespace, key } => {
+                    self.namespaces.get_mut(&namespace).unwrap().remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&self, _path: &Path) -> Result<(), Error> { bail!("An in-memory DB can't be backed up") }
+
+    fn estimated_size(&self, namespace: &str) -> Result<Option<u64>, Error> {
+        let keys = self.namespaces.get(namespace).ok_or_else(|| missing_namespace("estimated_size"))?;
+        Ok(Some(keys.iter().map(|(key, value)| (key.len() + value.len()) as u64).sum()))
+    }
+}
+
+/// sled can only apply a batch to a single tree atomically, so all the namespaces are in the default tree:
+/// a namespace is registered under `[0, name]` with a sequence number to keep the order they were created in,
+/// and its keys are stored under `[1, len(name), name, key]`.
+#[cfg(feature = "sled")]
+pub struct SledStorage {
+    db: sled::Db,
+}
+
+#[cfg(feature = "sled")]
+impl SledStorage {
+    const NAMESPACE_PREFIX: u8 = 0;
+    const DATA_PREFIX: u8 = 1;
+
+    pub fn open(location: &Path, create_if_missing: bool) -> Result<SledStorage, Error> {
+        if !create_if_missing && !location.join("conf").exists() {
+            bail!("There's no sled DB in {:?}", location);
+        }
+        Ok(SledStorage { db: sled::open(location)? })
+    }
+
+    fn namespace_key(namespace: &str) -> Vec<u8> { [&[Self::NAMESPACE_PREFIX][..], namespace.as_bytes()].concat() }
+
+    fn data_key(namespace: &str, key: &[u8]) -> Vec<u8> {
+        [&[Self::DATA_PREFIX, namespace.len() as u8][..], namespace.as_bytes(), key].concat()
+    }
+
+    fn check_namespace(&self, namespace: &str, command: &str) -> Result<(), Error> {
+        if self.has_namespace(namespace) { Ok(()) } else { Err(missing_namespace(command)) }
+    }
+
+    /// returns the range of the keys of the namespace in the tree.
+    fn data_range(namespace: &str, from: &[u8], to: Option<&[u8]>) -> std::ops::Range<Vec<u8>> {
+        let start = Self::data_key(namespace, from);
+        let end = match to {
+            Some(to) => Self::data_key(namespace, to),
+            // the namespace header can't be all 0xff since the name is hex.
+            None => prefix_end(&Self::data_key(namespace, &[])).unwrap(),
+        };
+        start..end
+    }
+
+    fn strip_entry(namespace: &str, entry: sled::Result<(sled::IVec, sled::IVec)>) -> Result<KeyValue, Error> {
+        let (key, value) = entry?;
+        Ok((key[2 + namespace.len()..].to_vec(), value.to_vec()))
+    }
+}
+
+#[cfg(feature = "sled")]
+impl Storage for SledStorage {
+    fn backend(&self) -> Backend { Backend::Sled }
+
+    fn namespaces(&self) -> Result<Vec<String>, Error> {
+        let mut namespaces = Vec::new();
+        for entry in self.db.scan_prefix([Self::NAMESPACE_PREFIX]) {
+            let (key, sequence) = entry?;
+            let mut be_bytes = [0u8; 8];
+            be_bytes.copy_from_slice(&sequence);
+            namespaces.push((u64::from_be_bytes(be_bytes), String::from_utf8(key[1..].to_vec())?));
+        }
+        namespaces.sort();
+        Ok(namespaces.into_iter().map(|(_, namespace)| namespace).collect())
+    }
+
+    fn has_namespace(&self, namespace: &str) -> bool {
+        self.db.contains_key(Self::namespace_key(namespace)).unwrap_or(false)
+    }
+
+    fn create_namespace(&mut self, namespace: &str) -> Result<(), Error> {
+        if namespace.len() > u8::max_value() as usize {
+            bail!("The namespace {} is too long", namespace);
+        }
+        if !self.has_namespace(namespace) {
+            let sequence = self.db.generate_id()?;
+            self.db.insert(Self::namespace_key(namespace), &sequence.to_be_bytes()[..])?;
+        }
+        Ok(())
+    }
+
+    fn drop_namespace(&mut self, namespace: &str) -> Result<(), Error> {
+        self.check_namespace(namespace, "drop_namespace")?;
+        let mut batch = sled::Batch::default();
+        for entry in self.db.scan_prefix(Self::data_key(namespace, &[])) {
+            batch.remove(entry?.0);
+        }
+        batch.remove(Self::namespace_key(namespace));
+        self.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.check_namespace(namespace, "get")?;
+        Ok(self.db.get(Self::data_key(namespace, key))?.map(|value| value.to_vec()))
+    }
+
+    fn range(&self, namespace: &str, from: &[u8], to: Option<&[u8]>) -> Result<KeyValues, Error> {
+        self.check_namespace(namespace, "range")?;
+        self.db.range(Self::data_range(namespace, from, to)).map(|entry| Self::strip_entry(namespace, entry)).collect()
+    }
+
+    fn first_in_range(&self, namespace: &str, from: &[u8], to: Option<&[u8]>) -> Result<Option<KeyValue>, Error> {
+        self.check_namespace(namespace, "first_in_range")?;
+        self.db.range(Self::data_range(namespace, from, to)).next().map(|entry| Self::strip_entry(namespace, entry)).transpose()
+    }
+
+    fn last_in_range(&self, namespace: &str, from: &[u8], to: Option<&[u8]>) -> Result<Option<KeyValue>, Error> {
+        self.check_namespace(namespace, "last_in_range")?;
+        self.db.range(Self::data_range(namespace, from, to)).next_back().map(|entry| Self::strip_entry(namespace, entry)).transpose()
+    }
+
+    fn write(&mut self, batch: StorageBatch, sync: bool) -> Result<(), Error> {
+        if batch.namespaces().any(|namespace| !self.has_namespace(namespace)) {
+            return Err(missing_namespace("write"));
+        }
+        let mut sled_batch = sled::Batch::default();
+        for op in batch.ops {
+            match op {
+                StorageOp::Put { namespace, key, value } => sled_batch.insert(Self::data_key(&namespace, &key), value),
+                StorageOp::Delete { namespace, key } => sled_batch.remove(Self::data_key(&namespace, &key)),
+            }
+        }
+        self.db.apply_batch(sled_batch)?;
+        if sync {
+            self.db.flush()?;
+        }
+        Ok(())
+    }
+
+    /// sled has no checkpoints and its iterators see concurrent writes, so the tree is copied into a new DB
+    /// and it must not be written to until the copy is done, the node backs it up while no task is running.
+    fn checkpoint(&self, path: &Path) -> Result<(), Error> {
+        if path.exists() {
+            bail!("The checkpoint directory {:?} already exists", path);
+        }
+        self.db.flush()?;
+        let target = sled::open(path)?;
+        let mut batch = sled::Batch::default();
+        for entry in self.db.iter() {
+            let (key, value) = entry?;
+            batch.insert(key, value);
+        }
+        target.apply_batch(batch)?;
+        target.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{prefix_end, Backend, MemoryStorage, Storage, StorageBatch};
+    use db::tests::create_test_db;
+
+    #[test]
+    fn test_prefix_end() {
+        assert_eq!(prefix_end(&[1]), Some(vec![2]));
+        assert_eq!(prefix_end(&[1, 255]), Some(vec![2]));
+        assert_eq!(prefix_end(&[255, 255]), None);
+    }
+
+    #[test]
+    fn test_backend_from_str() {
+        for backend in &[Backend::RocksDB, Backend::Sled, Backend::Memory] {
+            assert_eq!(backend.to_string().parse::<Backend>().unwrap(), *backend);
+        }
+        assert!("lmdb".parse::<Backend>().is_err());
+    }
+
+    #[test]
+    fn test_memory_batch_is_atomic() {
+        let mut storage = MemoryStorage::default();
+        storage.create_namespace("a").unwrap();
+        let mut batch = StorageBatch::default();
+        batch.put("a", &[1, 0], b"value");
+        batch.put("b", &[1, 0], b"value");
+        assert!(storage.write(batch, true).is_err());
+        assert!(storage.all("a").unwrap().is_empty());
+
+        let mut batch = StorageBatch::default();
+        batch.put("a", &[1, 0], b"first");
+        batch.put("a", &[1, 1], b"second");
+        batch.put("a", &[2], b"other");
+        storage.write(batch, true).unwrap();
+        assert_eq!(storage.prefix("a", &[1]).unwrap().len(), 2);
+        assert_eq!(storage.range("a", &[1, 1], None).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_first_and_last() {
+        let (mut db, _dir) = create_test_db();
+        let storage = &mut db.storage;
+        storage.create_namespace("a").unwrap();
+        let mut batch = StorageBatch::default();
+        for key in &[&[1, 0][..], &[1, 5], &[2, 0], &[2, 3], &[255]] {
+            batch.put("a", key, key);
+        }
+        storage.write(batch, true).unwrap();
+
+        assert_eq!(storage.last_in_prefix("a", &[1]).unwrap().unwrap().0, vec![1, 5]);
+        assert_eq!(storage.last_in_prefix("a", &[2]).unwrap().unwrap().0, vec![2, 3]);
+        assert_eq!(storage.last_in_prefix("a", &[255]).unwrap().unwrap().0, vec![255]);
+        assert!(storage.last_in_prefix("a", &[3]).unwrap().is_none());
+        // the end of the range isn't included
+        assert_eq!(storage.last_in_range("a", &[1], Some(&[2, 3])).unwrap().unwrap().0, vec![2, 0]);
+
+        assert_eq!(storage.first_from("a", &[1, 1]).unwrap().unwrap().0, vec![1, 5]);
+        assert_eq!(storage.first_from("a", &[3]).unwrap().unwrap().0, vec![255]);
+        assert!(storage.first_in_range("a", &[3], Some(&[255])).unwrap().is_none());
+        assert!(storage.first_from("b", &[]).is_err());
+    }
+
+    #[test]
+    fn test_estimated_size() {
+        let (mut db, _dir) = create_test_db();
+        let storage = &mut db.storage;
+        storage.create_namespace("a").unwrap();
+        let mut batch = StorageBatch::default();
+        batch.put("a", b"key", &[7u8; 1024]);
+        storage.write(batch, true).unwrap();
+
+        match storage.estimated_size("a").unwrap() {
+            Some(size) => assert!(size > 0),
+            None => assert_eq!(storage.backend(), Backend::Sled),
+        }
+        assert!(storage.estimated_size("b").is_err() || storage.backend() == Backend::Sled);
+    }
+
+    #[test]
+    fn test_memory_namespaces_order() {
+        let mut storage = MemoryStorage::default();
+        for namespace in &["b", "c", "a", "c"] {
+            storage.create_namespace(namespace).unwrap();
+        }
+        assert_eq!(storage.namespaces().unwrap(), vec!["b", "c", "a"]);
+        storage.drop_namespace("c").unwrap();
+        assert_eq!(storage.namespaces().unwrap(), vec!["b", "a"]);
+        assert!(storage.drop_namespace("c").is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use common_u::errors::{DBErr, DBErrKind};
+use db::dal::DB;
+use db::iterator::P2PCalls;
+use db::primitives::{DeltaKey, SplitKey, Stype};
+use db::storage::StorageBatch;
+use enigma_crypto::hash::Keccak256;
+use enigma_types::{ContractAddress, Hash256};
+use failure::Error;
+use hex::ToHex;
+use std::collections::HashMap;
+use std::fmt;
+
+const DELTA_PREFIX: &[u8] = &[1];
+const DELTA_HASH_PREFIX: &[u8] = &[6];
+
+/// The reason a delta failed the verification.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeltaFault {
+    /// The delta with this index is missing, while there are deltas or hashes after it.
+    Missing,
+    /// The key of the delta isn't a valid delta key.
+    MalformedKey,
+    /// The delta has the same data as the delta with the index `of`.
+    Duplicate { of: u32 },
+    /// No hash was recorded when the delta was written, while hashes were recorded for the deltas before it.
+    MissingHash,
+    /// The keccak of the delta doesn't match the hash recorded when it was written.
+    HashMismatch,
+}
+
+impl fmt::Display for DeltaFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeltaFault::Missing => write!(f, "The delta is missing"),
+            DeltaFault::MalformedKey => write!(f, "The key of the delta is malformed"),
+            DeltaFault::Duplicate { of } => write!(f, "The delta is a duplicate of delta {}", of),
+            DeltaFault::MissingHash => write!(f, "No hash was recorded for the delta"),
+            DeltaFault::HashMismatch => write!(f, "The delta doesn't match its recorded hash"),
+        }
+    }
+}
+
+/// The result of verifying the deltas of a contract, the verification stops at the first bad delta.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeltasVerification {
+    pub address: ContractAddress,
+    /// The number of deltas that passed the verification.
+    pub verified: u32,
+    /// The number of legacy deltas that were written before their hashes were recorded,
+    /// they're checked for gaps and duplicates but can't be checked against a hash.
+    pub unhashed: u32,
+    /// The index of the first bad delta and what's wrong with it.
+    pub first_bad: Option<(u32, DeltaFault)>,
+}
+
+impl DeltasVerification {
+    pub fn is_valid(&self) -> bool { self.first_bad.is_none() }
+}
+
+impl DB {
+    /// Verifies that the deltas of the contract are continuous from the first delta that wasn't pruned,
+    /// that every delta matches the keccak recorded when it was written and that no delta was stored twice.
+    /// The deltas before the first one with a hash are legacy deltas, written before the hashes were recorded,
+    /// they're counted in `unhashed` instead of failing the verification.
+    #[logfn(DEBUG)]
+    pub fn verify_deltas(&self, address: &ContractAddress) -> Result<DeltasVerification, Error> {
+        let str_addr: String = address.to_hex();
+        if !self.storage.has_namespace(&str_addr) {
+            return Err(DBErr { command: "verify_deltas".to_string(), kind: DBErrKind::MissingKey }.into());
+        }
+        let mut expected = self.get_first_delta(address)?;
+        let mut verification = DeltasVerification { address: *address, verified: 0, unhashed: 0, first_bad: None };
+        let mut hashed = false;
+        let mut seen: HashMap<Hash256, u32> = HashMap::new();
+
+        for (key, value) in self.storage.prefix(&str_addr, DELTA_PREFIX)? {
+            if key.len() != 5 {
+                verification.first_bad = Some((expected, DeltaFault::MalformedKey));
+                return Ok(verification);
+            }
+            let index = DeltaKey::from_split(&str_addr, &key)?.key_type.unwrap_delta();
+            if index != expected {
+                verification.first_bad = Some((expected, DeltaFault::Missing));
+                return Ok(verification);
+            }
+            let hash = value.keccak256();
+            let recorded = DeltaKey::new(*address, Stype::DeltaHash(index)).as_split(|_, hash_key| self.storage.get(&str_addr, hash_key))?;
+            let fault = match recorded {
+                None if !hashed => {
+                    if let Some(of) = seen.insert(hash, index) {
+                        verification.first_bad = Some((index, DeltaFault::Duplicate { of }));
+                        return Ok(verification);
+                    }
+                    verification.unhashed += 1;
+                    expected += 1;
+                    continue;
+                }
+                None => Some(DeltaFault::MissingHash),
+                Some(ref recorded) if recorded[..] != hash[..] => Some(DeltaFault::HashMismatch),
+                Some(_) => seen.insert(hash, index).map(|of| DeltaFault::Duplicate { of }),
+            };
+            if let Some(fault) = fault {
+                verification.first_bad = Some((index, fault));
+                return Ok(verification);
+            }
+            hashed = true;
+            verification.verified += 1;
+            expected += 1;
+        }
+
+        // a hash after the last delta means the latest deltas were lost.
+        let next = DeltaKey::new(*address, Stype::DeltaHash(expected)).as_split(|_, from| self.storage.first_from(&str_addr, from))?;
+        if next.map_or(false, |(key, _)| key.starts_with(DELTA_HASH_PREFIX)) {
+            verification.first_bad = Some((expected, DeltaFault::Missing));
+        }
+        Ok(verification)
+    }
+
+    /// Records the hashes of the legacy deltas of the contract, so they're verified from now on.
+    /// The deltas are verified first and nothing is recorded if they fail, returns the number of deltas that were hashed.
+    pub fn hash_legacy_deltas(&mut self, address: &ContractAddress) -> Result<u32, Error> {
+        let verification = self.verify_deltas(address)?;
+        if let Some((index, fault)) = verification.first_bad {
+            bail!("The deltas of {} failed the verification at delta {}: {}", address.to_hex(), index, fault);
+        }
+        let str_addr: String = address.to_hex();
+        let first = self.get_first_delta(address)?;
+        let mut batch = StorageBatch::default();
+        for index in first..first + verification.unhashed {
+            let delta = DeltaKey::new(*address, Stype::Delta(index)).as_split(|_, key| self.storage.get(&str_addr, key))?
+                .ok_or(DBErr { command: "hash_legacy_deltas".to_string(), kind: DBErrKind::MissingKey })?;
+            DeltaKey::new(*address, Stype::DeltaHash(index)).as_split(|hash, key| batch.put(hash, key, &delta.keccak256()[..]));
+        }
+        self.storage.write(batch, true)?;
+        Ok(verification.unhashed)
+    }
+
+    /// Verifies the deltas of all the contracts in the DB.
+    pub fn verify_all_deltas(&self) -> Result<Vec<DeltasVerification>, Error> {
+        self.get_all_addresses().unwrap_or_default().iter().map(|address| self.verify_deltas(address)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use db::{tests::create_test_db, CRUDInterface, DeltaFault, DeltaKey, P2PCalls, StorageBatch, Stype, DB};
+    use enigma_types::ContractAddress;
+    use hex::ToHex;
+
+    /// deletes a key without going through the DB, so its delta hash is kept.
+    fn delete_raw(db: &mut DB, address: ContractAddress, key: &[u8]) {
+        let mut batch = StorageBatch::default();
+        batch.delete(&address.to_hex(), key);
+        db.storage.write(batch, false).unwrap();
+    }
+
+    fn fill_deltas(db: &mut DB, address: ContractAddress, count: u32) {
+        let tuples: Vec<_> = (0..count).map(|i| (DeltaKey::new(address, Stype::Delta(i)), vec![i as u8; 8])).collect();
+        for res in db.insert_tuples(&tuples) {
+            res.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_verify_valid_deltas() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [1u8; 32].into();
+        fill_deltas(&mut db, address, 5);
+        db.force_update(&DeltaKey::new(address, Stype::Delta(5)), &[5u8; 8][..]).unwrap();
+
+        let verification = db.verify_deltas(&address).unwrap();
+        assert!(verification.is_valid());
+        assert_eq!(verification.verified, 6);
+
+        // removing a delta removes its hash, so the tip can be rolled back
+        db.delete(&DeltaKey::new(address, Stype::Delta(5))).unwrap();
+        assert_eq!(db.verify_deltas(&address).unwrap().verified, 5);
+        assert_eq!(db.verify_all_deltas().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_verify_gap() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [1u8; 32].into();
+        fill_deltas(&mut db, address, 5);
+        delete_raw(&mut db, address, &[1, 0, 0, 0, 2]);
+
+        let verification = db.verify_deltas(&address).unwrap();
+        assert_eq!(verification.verified, 2);
+        assert_eq!(verification.first_bad, Some((2, DeltaFault::Missing)));
+
+        // the tip is missing too
+        let (mut db, _dir) = create_test_db();
+        fill_deltas(&mut db, address, 5);
+        delete_raw(&mut db, address, &[1, 0, 0, 0, 4]);
+        assert_eq!(db.verify_deltas(&address).unwrap().first_bad, Some((4, DeltaFault::Missing)));
+    }
+
+    #[test]
+    fn test_verify_hash_mismatch() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [1u8; 32].into();
+        fill_deltas(&mut db, address, 5);
+        // written without going through the DB so the hash isn't updated
+        let mut batch = StorageBatch::default();
+        batch.put(&address.to_hex(), &[1, 0, 0, 0, 3], b"corrupted");
+        db.storage.write(batch, false).unwrap();
+        assert_eq!(db.verify_deltas(&address).unwrap().first_bad, Some((3, DeltaFault::HashMismatch)));
+
+        delete_raw(&mut db, address, &[6, 0, 0, 0, 1]);
+        assert_eq!(db.verify_deltas(&address).unwrap().first_bad, Some((1, DeltaFault::MissingHash)));
+    }
+
+    #[test]
+    fn test_verify_legacy_deltas() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [1u8; 32].into();
+        fill_deltas(&mut db, address, 5);
+        // deltas written before the hashes were recorded
+        delete_raw(&mut db, address, &[6, 0, 0, 0, 0]);
+        delete_raw(&mut db, address, &[6, 0, 0, 0, 1]);
+
+        let verification = db.verify_deltas(&address).unwrap();
+        assert!(verification.is_valid());
+        assert_eq!((verification.unhashed, verification.verified), (2, 3));
+
+        assert_eq!(db.hash_legacy_deltas(&address).unwrap(), 2);
+        let verification = db.verify_deltas(&address).unwrap();
+        assert_eq!((verification.unhashed, verification.verified), (0, 5));
+        assert_eq!(db.hash_legacy_deltas(&address).unwrap(), 0);
+
+        // a legacy delta can't be hashed if it's corrupted
+        delete_raw(&mut db, address, &[6, 0, 0, 0, 0]);
+        delete_raw(&mut db, address, &[1, 0, 0, 0, 3]);
+        assert!(db.hash_legacy_deltas(&address).is_err());
+        assert_eq!(db.verify_deltas(&address).unwrap().unhashed, 1);
+    }
+
+    #[test]
+    fn test_verify_duplicate() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [1u8; 32].into();
+        fill_deltas(&mut db, address, 3);
+        db.force_update(&DeltaKey::new(address, Stype::Delta(3)), &[1u8; 8][..]).unwrap();
+        assert_eq!(db.verify_deltas(&address).unwrap().first_bad, Some((3, DeltaFault::Duplicate { of: 1 })));
+    }
+
+    #[test]
+    fn test_verify_after_prune() {
+        let (mut db, _dir) = create_test_db();
+        let address: ContractAddress = [1u8; 32].into();
+        fill_deltas(&mut db, address, 5);
+        db.save_snapshot(&address, 2, b"state").unwrap();
+        assert_eq!(db.prune_deltas(&address).unwrap(), 3);
+
+        let verification = db.verify_deltas(&address).unwrap();
+        assert!(verification.is_valid());
+        assert_eq!(verification.verified, 2);
+    }
+}
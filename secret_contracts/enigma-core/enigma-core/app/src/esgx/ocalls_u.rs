@@ -1,5 +1,5 @@
 #![allow(unused_attributes)]
-use crate::db::{CRUDInterface, DeltaKey, P2PCalls, ResultType, ResultTypeVec, Stype, DB};
+use crate::db::{BatchOp, CRUDInterface, DeltaKey, P2PCalls, ResultType, ResultTypeVec, Stype, DbAccess, DB};
 use enigma_tools_m::utils::LockExpectMutex;
 use enigma_crypto::hash::Sha256;
 use enigma_types::{ContractAddress, EnclaveReturn, Hash256, RawPointer};
@@ -7,6 +7,8 @@ use lru_cache::LruCache;
 use std::sync::Mutex;
 use std::{ptr, slice};
 use common_u::errors::{DBErr, self};
+use failure::Error;
+use hex::ToHex;
 
 lazy_static! { static ref DELTAS_CACHE: Mutex<LruCache<Hash256, Vec<Vec<u8>>>> = Mutex::new(LruCache::new(500)); }
 
@@ -16,13 +18,18 @@ pub unsafe extern "C" fn ocall_update_state(db_ptr: *const RawPointer, id: &Cont
     let encrypted_state = slice::from_raw_parts(enc_state, state_len);
     let key = DeltaKey::new(*id, Stype::State);
 
-    let db: &mut DB = match (*db_ptr).get_mut_ref() {
-        Ok(db) => db,
+    let access: &mut DbAccess = match (*db_ptr).get_mut_ref() {
+        Ok(access) => access,
         Err(e) => {
             error!("{}", e);
             return EnclaveReturn::OcallDBError
         }
     };
+    if let Some(writes) = access.writes() {
+        writes.push(BatchOp::ForceUpdate(key, encrypted_state.to_vec()));
+        return EnclaveReturn::Success;
+    }
+    let mut db = access.lock();
     match db.force_update(&key, encrypted_state) {
         Ok(_) => EnclaveReturn::Success,
         Err(e) => {
@@ -33,6 +40,41 @@ pub unsafe extern "C" fn ocall_update_state(db_ptr: *const RawPointer, id: &Cont
 }
 
 
+#[no_mangle]
+pub unsafe extern "C" fn ocall_save_snapshot(db_ptr: *const RawPointer, id: &ContractAddress, delta_index_: *const u32,
+                                             enc_state: *const u8, state_len: usize) -> EnclaveReturn {
+    let delta_index = ptr::read(delta_index_);
+    let encrypted_state = slice::from_raw_parts(enc_state, state_len);
+
+    let access: &mut DbAccess = match (*db_ptr).get_mut_ref() {
+        Ok(access) => access,
+        Err(e) => {
+            error!("{}", e);
+            return EnclaveReturn::OcallDBError
+        }
+    };
+    if let Some(writes) = access.writes() {
+        writes.push(BatchOp::ForceUpdate(DeltaKey::new(*id, Stype::Snapshot(delta_index)), encrypted_state.to_vec()));
+        return EnclaveReturn::Success;
+    }
+    let mut db = access.lock();
+    match db.save_snapshot(id, delta_index, encrypted_state) {
+        Ok(_) => EnclaveReturn::Success,
+        Err(e) => {
+            error!("Failed saving the snapshot of {:?} at delta {} with: \"{}\" ", id, delta_index, &e);
+            EnclaveReturn::OcallDBError
+        }
+    }
+}
+
+/// Reads the encrypted state of the contract,
+/// if it was removed the state is built from the latest snapshot instead of from the deltas that might have been pruned.
+fn read_state(db: &DB, addr: &ContractAddress) -> Result<Vec<u8>, Error> {
+    db.read(&DeltaKey::new(*addr, Stype::State))
+        .or_else(|_| db.get_latest_snapshot(addr).map(|(_, state)| state))
+}
+
+
 #[no_mangle]
 pub unsafe extern "C" fn ocall_new_delta(db_ptr: *const RawPointer,
                                          enc_delta: *const u8, delta_len: usize,
@@ -40,14 +82,20 @@ pub unsafe extern "C" fn ocall_new_delta(db_ptr: *const RawPointer,
     let delta_index = ptr::read(delta_index_);
     let encrypted_delta = slice::from_raw_parts(enc_delta, delta_len);
     let key = DeltaKey::new(*contract_address, Stype::Delta(delta_index));
-    let db: &mut DB = match (*db_ptr).get_mut_ref() {
-        Ok(db) => db,
+    let access: &mut DbAccess = match (*db_ptr).get_mut_ref() {
+        Ok(access) => access,
         Err(e) => {
             error!("{}", e);
             return EnclaveReturn::OcallDBError
         }
     };
-    match db.force_update(&key, encrypted_delta) {
+    // tasks running concurrently can call the same contract, so the delta is only created when the task commits,
+    // which fails without storing any of its writes if another task already stored this delta.
+    if let Some(writes) = access.writes() {
+        writes.push(BatchOp::Create(key, encrypted_delta.to_vec()));
+        return EnclaveReturn::Success;
+    }
+    match access.lock().force_update(&key, encrypted_delta) {
         Ok(_) => EnclaveReturn::Success,
         Err(e) => {
             error!("Failed creating key in db: {:?} with: \"{}\" ", &key, &e);
@@ -60,15 +108,15 @@ pub unsafe extern "C" fn ocall_new_delta(db_ptr: *const RawPointer,
 #[no_mangle]
 pub unsafe extern "C" fn ocall_get_state_size(db_ptr: *const RawPointer, addr: &ContractAddress, state_size: *mut usize) -> EnclaveReturn {
     let mut cache_id = addr.to_vec();
-    let _state_key = DeltaKey::new(*addr, Stype::State);
-    let db: &mut DB = match (*db_ptr).get_mut_ref() {
-        Ok(db) => db,
+    let access: &mut DbAccess = match (*db_ptr).get_mut_ref() {
+        Ok(access) => access,
         Err(e) => {
             error!("{}", e);
             return EnclaveReturn::OcallDBError
         }
     };
-    match db.read(&_state_key) {
+    let db = access.read();
+    match read_state(&db, addr) {
         Ok(state) => {
             let state_len = state.len();
             *state_size = state_len;
@@ -86,13 +134,14 @@ pub unsafe extern "C" fn ocall_get_state(db_ptr: *const RawPointer, addr: &Contr
     let mut cache_id = addr.to_vec();
     cache_id.extend_from_slice(&state_size.to_be_bytes());
 
-    let db: &mut DB = match (*db_ptr).get_mut_ref() {
-        Ok(db) => db,
+    let access: &mut DbAccess = match (*db_ptr).get_mut_ref() {
+        Ok(access) => access,
         Err(e) => {
             error!("{}", e);
             return EnclaveReturn::OcallDBError
         }
     };
+    let db = access.read();
 
 
     match DELTAS_CACHE.lock_expect("DeltaCache").remove(&cache_id.sha256()) {
@@ -101,8 +150,7 @@ pub unsafe extern "C" fn ocall_get_state(db_ptr: *const RawPointer, addr: &Contr
             EnclaveReturn::Success
         }
         None => {
-            let _state_key = DeltaKey::new(*addr, Stype::State);
-            match db.read(&_state_key) {
+            match read_state(&db, addr) {
                 Ok(state) => {
                     enigma_types::write_ptr(&state, state_ptr, state_size);
                     EnclaveReturn::Success
@@ -114,18 +162,64 @@ pub unsafe extern "C" fn ocall_get_state(db_ptr: *const RawPointer, addr: &Contr
 }
 
 
+#[no_mangle]
+pub unsafe extern "C" fn ocall_get_contract_size(db_ptr: *const RawPointer, addr: &ContractAddress, code_size: *mut usize) -> EnclaveReturn {
+    let access: &mut DbAccess = match (*db_ptr).get_mut_ref() {
+        Ok(access) => access,
+        Err(e) => {
+            error!("{}", e);
+            return EnclaveReturn::OcallDBError
+        }
+    };
+    let db = access.read();
+    match db.get_contract(*addr) {
+        Ok(code) => {
+            *code_size = code.len();
+            EnclaveReturn::Success
+        }
+        Err(_) => EnclaveReturn::OcallDBError,
+    }
+}
+
+
+#[no_mangle]
+pub unsafe extern "C" fn ocall_get_contract(db_ptr: *const RawPointer, addr: &ContractAddress, code_ptr: *mut u8, code_size: usize) -> EnclaveReturn {
+    let access: &mut DbAccess = match (*db_ptr).get_mut_ref() {
+        Ok(access) => access,
+        Err(e) => {
+            error!("{}", e);
+            return EnclaveReturn::OcallDBError
+        }
+    };
+    let db = access.read();
+    match db.get_contract(*addr) {
+        // The contract could have been replaced since the enclave got its size
+        Ok(code) if code.len() != code_size => {
+            error!("The size of the contract {} changed from {} to {}", addr.to_hex(), code_size, code.len());
+            EnclaveReturn::OcallDBError
+        }
+        Ok(code) => {
+            enigma_types::write_ptr(&code, code_ptr, code_size);
+            EnclaveReturn::Success
+        }
+        Err(_) => EnclaveReturn::OcallDBError,
+    }
+}
+
+
 #[no_mangle]
 pub unsafe extern "C" fn ocall_get_deltas_sizes(db_ptr: *const RawPointer, addr: &ContractAddress,
                                                 start: *const u32, end: *const u32,
                                                 res_ptr: *mut usize, res_len: usize) -> EnclaveReturn {
 
-    let db: &mut DB = match (*db_ptr).get_mut_ref() {
-        Ok(db) => db,
+    let access: &mut DbAccess = match (*db_ptr).get_mut_ref() {
+        Ok(access) => access,
         Err(e) => {
             error!("{}", e);
             return EnclaveReturn::OcallDBError
         }
     };
+    let db = access.read();
 
     let len = (*end - *start) as usize;
     if len != res_len {
@@ -137,7 +231,7 @@ pub unsafe extern "C" fn ocall_get_deltas_sizes(db_ptr: *const RawPointer, addr:
 
     let mut deltas_vec = Vec::with_capacity(len);
     let mut sizes = Vec::with_capacity(len);
-    match get_deltas(db, *addr, *start, *end) {
+    match get_deltas(&db, *addr, *start, *end) {
         Ok(deltas_type) => match deltas_type {
             ResultType::None => return EnclaveReturn::OcallDBError,
             ResultType::Full(deltas) | ResultType::Partial(deltas) => {
@@ -163,13 +257,14 @@ pub unsafe extern "C" fn ocall_get_deltas(db_ptr: *const RawPointer, addr: &Cont
     cache_id.extend_from_slice(&(*start).to_be_bytes());
     cache_id.extend_from_slice(&(*end).to_be_bytes());
 
-    let db: &mut DB = match (*db_ptr).get_mut_ref() {
-        Ok(db) => db,
+    let access: &mut DbAccess = match (*db_ptr).get_mut_ref() {
+        Ok(access) => access,
         Err(e) => {
             error!("{}", e);
             return EnclaveReturn::OcallDBError
         }
     };
+    let db = access.read();
 
 
     match DELTAS_CACHE.lock_expect("DeltaCache").remove(&cache_id.sha256()) {
@@ -182,7 +277,7 @@ pub unsafe extern "C" fn ocall_get_deltas(db_ptr: *const RawPointer, addr: &Cont
         }
         None => {
             // If the data doesn't exist in the cache I need to pull it from the DB
-            match get_deltas(db, *addr, *start, *end) {
+            match get_deltas(&db, *addr, *start, *end) {
                 Ok(deltas_type) => match deltas_type {
                     ResultType::None => EnclaveReturn::OcallDBError,
                     ResultType::Full(deltas) | ResultType::Partial(deltas) => {
@@ -203,13 +298,19 @@ pub unsafe extern "C" fn ocall_remove_delta(db_ptr: *const RawPointer,
                                             contract_address: &ContractAddress, delta_index_: *const u32) -> EnclaveReturn {
     let delta_index = ptr::read(delta_index_);
     let key = DeltaKey::new(*contract_address, Stype::Delta(delta_index));
-    let db: &mut DB = match (*db_ptr).get_mut_ref() {
-        Ok(db) => db,
+    let access: &mut DbAccess = match (*db_ptr).get_mut_ref() {
+        Ok(access) => access,
         Err(e) => {
             error!("{}", e);
             return EnclaveReturn::OcallDBError
         }
     };
+    // a task only removes the deltas it created, which weren't stored yet.
+    if let Some(writes) = access.writes() {
+        writes.remove_created(&key);
+        return EnclaveReturn::Success;
+    }
+    let mut db = access.lock();
     match db.delete(&key) {
         Ok(_) => EnclaveReturn::Success,
         Err(e) => {
@@ -224,7 +325,7 @@ pub unsafe extern "C" fn ocall_remove_delta(db_ptr: *const RawPointer,
     }
 }
 
-fn get_deltas(db: &mut DB, addr: ContractAddress, start: u32, end: u32) -> ResultTypeVec<(DeltaKey, Vec<u8>)> {
+fn get_deltas(db: &DB, addr: ContractAddress, start: u32, end: u32) -> ResultTypeVec<(DeltaKey, Vec<u8>)> {
     let key_start = DeltaKey::new(addr, Stype::Delta(start));
     let key_end = DeltaKey::new(addr, Stype::Delta(end));
 
@@ -1,12 +1,13 @@
 #![allow(dead_code)] // TODO: Remove later
 
 use crate::common_u::errors::EnclaveFailError;
-use crate::db::DB;
+use crate::db::{DbAccess, DB};
 use enigma_types::traits::SliceCPtr;
 use enigma_types::{EnclaveReturn, ContractAddress, PubKey, RawPointer};
 use failure::Error;
 use sgx_types::{sgx_enclave_id_t, sgx_status_t};
-use crate::auto_ffi::{ecall_ptt_req, ecall_ptt_res, ecall_build_state, ecall_get_user_key};
+use crate::auto_ffi::{ecall_ptt_req, ecall_ptt_res, ecall_build_state, ecall_get_user_key, ecall_snapshot_state, ecall_inspect_state,
+                      ecall_verify_snapshot};
 
 /// This function builds the states that it received in ptt_req and ptt_res
 /// It returns a Vec of the failed contract addresses
@@ -15,7 +16,8 @@ pub fn ptt_build_state(db: &mut DB, eid: sgx_enclave_id_t) -> Result<Vec<Contrac
     let mut ret = EnclaveReturn::Success;
     let mut failed_ptr = 0u64;
 
-    let db_ptr = unsafe { RawPointer::new_mut(db) };
+    let mut access = DbAccess::from(db);
+    let db_ptr = unsafe { RawPointer::new_mut(&mut access) };
 
     let status = unsafe {
         ecall_build_state(eid,
@@ -39,6 +41,53 @@ pub fn ptt_build_state(db: &mut DB, eid: sgx_enclave_id_t) -> Result<Vec<Contrac
     Ok(part)
 }
 
+/// This function makes the enclave snapshot the state of the contract, so the deltas it includes can be pruned.
+/// It returns the index of the last delta included in the snapshot
+#[logfn(INFO)]
+pub fn snapshot_state<'a>(db: impl Into<DbAccess<'a>>, eid: sgx_enclave_id_t, address: ContractAddress) -> Result<u32, Error> {
+    let mut ret = EnclaveReturn::Success;
+    let mut delta_index = 0u32;
+
+    let mut access: DbAccess = db.into();
+    let db_ptr = unsafe { RawPointer::new_mut(&mut access) };
+
+    let status = unsafe {
+        ecall_snapshot_state(eid,
+                             &mut ret as *mut EnclaveReturn,
+                             &db_ptr as *const RawPointer,
+                             &address as *const ContractAddress,
+                             &mut delta_index as *mut u32) };
+
+    if ret != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        return Err(EnclaveFailError { err: ret, status }.into());
+    }
+    Ok(delta_index)
+}
+
+/// This function makes the enclave verify a snapshot received from a peer against the local deltas of the contract,
+/// it must be verified before it's stored since the deltas it includes can be pruned after that.
+#[logfn(INFO)]
+pub fn verify_snapshot(db: &mut DB, eid: sgx_enclave_id_t, address: ContractAddress, enc_state: &[u8], delta_index: u32) -> Result<(), Error> {
+    let mut ret = EnclaveReturn::Success;
+
+    let mut access = DbAccess::from(db);
+    let db_ptr = unsafe { RawPointer::new_mut(&mut access) };
+
+    let status = unsafe {
+        ecall_verify_snapshot(eid,
+                              &mut ret as *mut EnclaveReturn,
+                              &db_ptr as *const RawPointer,
+                              &address as *const ContractAddress,
+                              enc_state.as_c_ptr(),
+                              enc_state.len(),
+                              delta_index) };
+
+    if ret != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        return Err(EnclaveFailError { err: ret, status }.into());
+    }
+    Ok(())
+}
+
 pub fn ptt_res(eid: sgx_enclave_id_t, msg: &[u8]) -> Result<(), Error> {
     let mut ret = EnclaveReturn::Success;
     let status = unsafe { ecall_ptt_res(eid, &mut ret as *mut EnclaveReturn, msg.as_c_ptr(), msg.len()) };
@@ -84,6 +133,55 @@ pub fn get_user_key(eid: sgx_enclave_id_t, user_pubkey: &PubKey) -> Result<(Box<
     Ok((*part, sig))
 }
 
+/// A scan of the decrypted state of a contract, in the order of the keys.
+/// The user requests it by signing S(address, prefix, from, limit, values).
+#[derive(Debug, Clone, Default)]
+pub struct StateQuery {
+    /// Only the keys starting with `prefix` are returned.
+    pub prefix: String,
+    /// The first key to return, so a scan can continue from the `next` key of the previous one.
+    pub from: String,
+    /// The maximum number of keys to return, 0 returns all of them.
+    pub limit: u32,
+    /// Return the values under the keys and not only the keys.
+    pub values: bool,
+}
+
+/// This function runs `query` on the latest state of the contract for the user who signed it.
+/// The user must be allowed to inspect the state by the contract and must have a DH key with the enclave.
+/// It returns the result encrypted with the DH key and the signature of the enclave on S(address, encrypted result)
+pub fn inspect_state<'a>(db: impl Into<DbAccess<'a>>, eid: sgx_enclave_id_t, address: ContractAddress, query: &StateQuery, user_sig: &[u8; 65]) -> Result<(Box<[u8]>, [u8; 65]), Error> {
+    let mut sig = [0u8; 65];
+    let mut user_sig = *user_sig;
+    let mut ret = EnclaveReturn::Success;
+    let mut serialized_ptr = 0u64;
+
+    let mut access: DbAccess = db.into();
+    let db_ptr = unsafe { RawPointer::new_mut(&mut access) };
+
+    let status = unsafe {
+        ecall_inspect_state(eid,
+                            &mut ret as *mut EnclaveReturn,
+                            &db_ptr as *const RawPointer,
+                            &address as *const ContractAddress,
+                            query.prefix.as_bytes().as_c_ptr(),
+                            query.prefix.len(),
+                            query.from.as_bytes().as_c_ptr(),
+                            query.from.len(),
+                            query.limit,
+                            query.values as u8,
+                            &mut user_sig,
+                            &mut sig,
+                            &mut serialized_ptr as *mut u64) };
+
+    if ret != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        return Err(EnclaveFailError { err: ret, status }.into());
+    }
+    let box_ptr = serialized_ptr as *mut Box<[u8]>;
+    let part = unsafe { Box::from_raw(box_ptr) };
+    Ok((*part, sig))
+}
+
 #[cfg(test)]
 pub mod tests {
     extern crate ethabi;
@@ -3,11 +3,14 @@
 
 extern crate dirs;
 pub extern crate rocksdb;
+#[cfg(feature = "sled")]
+pub extern crate sled;
 pub extern crate sgx_types;
 extern crate sgx_urts;
 #[macro_use]
 extern crate lazy_static;
 pub extern crate futures;
+extern crate futures_cpupool;
 extern crate rmp_serde;
 pub extern crate serde_json;
 extern crate tokio_zmq;
@@ -20,6 +23,14 @@ extern crate enigma_crypto;
 extern crate enigma_types;
 extern crate rustc_hex as hex;
 extern crate lru_cache;
+extern crate jsonrpc_core;
+extern crate jsonrpc_http_server;
+extern crate jsonrpc_pubsub;
+extern crate jsonrpc_ws_server;
+extern crate hyper;
+extern crate tokio_timer;
+#[macro_use]
+extern crate prometheus;
 #[macro_use]
 extern crate serde;
 extern crate serde_repr;
@@ -47,24 +58,15 @@ pub mod cross_test_utils {
 
 #[cfg(test)]
 mod tests {
-    extern crate tempfile;
     use crate::esgx::general::init_enclave_wrapper;
     use sgx_types::*;
-    use crate::db::DB;
+    use crate::db::{DbAccess, DB};
     use enigma_types::{RawPointer, ResultStatus};
     use simplelog::TermLogger;
     use log::LevelFilter;
-    use self::tempfile::TempDir;
     use crate::auto_ffi::ecall_run_tests;
 
 
-    /// It's important to save TempDir too, because when it gets dropped the directory will be removed.
-    fn create_test_db() -> (DB, TempDir) {
-        let tempdir = tempfile::tempdir().unwrap();
-        let db = DB::new(tempdir.path(), true).unwrap();
-        (db, tempdir)
-    }
-
     #[allow(dead_code)]
     pub fn log_to_stdout(level: Option<LevelFilter>) {
         let level = level.unwrap_or_else(|| LevelFilter::max());
@@ -73,9 +75,10 @@ mod tests {
 
     #[test]
     pub fn test_enclave_internal() {
-        let (mut db, _dir) = create_test_db();
+        let mut db = DB::new_in_memory();
         let enclave = init_enclave_wrapper().unwrap();
-        let db_ptr = unsafe { RawPointer::new_mut(&mut db) };
+        let mut access = DbAccess::from(&mut db);
+        let db_ptr = unsafe { RawPointer::new_mut(&mut access) };
         let mut result: ResultStatus = ResultStatus::Ok;
         let ret = unsafe { ecall_run_tests(enclave.geteid(), &db_ptr as *const RawPointer, &mut result) };
 
@@ -5,34 +5,57 @@ extern crate log;
 extern crate log_derive;
 
 pub use enigma_core_app::*;
-pub use esgx::ocalls_u::{ocall_get_deltas, ocall_get_deltas_sizes, ocall_get_state, ocall_get_state_size,
-                                ocall_new_delta, ocall_update_state, ocall_remove_delta};
+pub use esgx::ocalls_u::{ocall_get_contract, ocall_get_contract_size, ocall_get_deltas, ocall_get_deltas_sizes, ocall_get_state,
+                                ocall_get_state_size, ocall_new_delta, ocall_update_state, ocall_remove_delta,
+                                ocall_save_snapshot};
 pub use enigma_tools_u::esgx::ocalls_u::{ocall_get_home, ocall_save_to_memory};
 use enigma_tools_u::common_u::logging;
-use networking::{ipc_listener, IpcListener};
-use db::DB;
+use networking::{ipc_listener, IpcListener, MetricsServer, RpcConfig, RpcServer, Worker};
+use db::{SnapshotConfig, DB};
 use cli::Opt;
 use structopt::StructOpt;
 use futures::Future;
 use simplelog::CombinedLogger;
+use std::fs;
 
 fn main() {
-    let opt: Opt = Opt::from_args();
+    let mut opt: Opt = Opt::from_args();
     debug!("CLI params: {:?}", opt);
 
     let datadir = opt.data_dir.clone().unwrap_or_else(|| dirs::home_dir().unwrap().join(".enigma"));
     let loggers = logging::get_logger(opt.debug_stdout, datadir.clone(), opt.verbose).expect("Failed Creating the loggers");
     CombinedLogger::init(loggers).expect("Failed initializing the logger");
 
+    if let Some(cmd) = opt.cmd.take() {
+        if let Err(e) = cmd.run(&datadir, opt.db_backend, opt.port) {
+            error!("{}", e);
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let enclave = esgx::general::init_enclave_wrapper().expect("[-] Init Enclave Failed");
     let eid = enclave.geteid();
     info!("[+] Init Enclave Successful {}!", eid);
 
-    let mut db = DB::new(datadir, true).expect("Failed initializing the DB");
-    let server = IpcListener::new(&format!("tcp://*:{}", opt.port));
+    let backup_dir = opt.backup_dir.clone().unwrap_or_else(|| datadir.join("backups"));
+    let mut db = DB::open(datadir, opt.db_backend, true).expect("Failed initializing the DB");
+    db.set_snapshot_config(SnapshotConfig { interval: opt.snapshot_interval, retention: opt.delta_retention });
+    db.set_backup_dir(backup_dir);
+    let worker = Worker::new(db, eid, &opt.spid, opt.retries, opt.concurrency).expect("Failed creating the worker");
 
+    let admin_token = opt.rpc_admin_token_file.as_ref().map(|path| {
+        let token = fs::read_to_string(path).expect("Failed reading the JSON-RPC admin token");
+        token.trim().to_string()
+    });
+    let rpc_config = RpcConfig { http: opt.rpc_http, ws: opt.rpc_ws, cors: opt.rpc_cors.clone(), admin_token };
+    let _rpc = RpcServer::start(&rpc_config, &worker).expect("Failed starting the JSON-RPC server");
+    let _metrics = opt.metrics.map(|addr| MetricsServer::start(&addr, &worker).expect("Failed starting the metrics server"));
+
+    let server = IpcListener::new(&format!("tcp://*:{}", opt.port));
     server
-        .run(move |multi| ipc_listener::handle_message(&mut db, multi, &opt.spid, eid, opt.retries))
+        .run(opt.concurrency, move |multi| ipc_listener::handle_message(&worker, multi))
         .wait()
         .unwrap();
 }
\ No newline at end of file
@@ -1,62 +1,131 @@
 use crate::networking::messages::*;
-use crate::db::DB;
-use futures::{Future, Stream};
-use sgx_types::sgx_enclave_id_t;
+use crate::networking::metrics::{self, TaskTimer};
+use crate::networking::task_cache::CachedTask;
+use crate::networking::worker::Worker;
+use futures::{future, Future, IntoFuture, Stream};
 use std::sync::Arc;
 use tokio_zmq::prelude::*;
-use tokio_zmq::{Error, Multipart, Rep};
+use tokio_zmq::{Error, Multipart, Router};
 
+/// Listens on a ROUTER socket, so the requests of a peer can be handled while other requests are still running.
 pub struct IpcListener {
     _context: Arc<zmq::Context>,
-    rep_future: Box<Future<Item = Rep, Error = Error>>,
+    router_future: Box<Future<Item = Router, Error = Error>>,
 }
 
 impl IpcListener {
     pub fn new(conn_str: &str) -> Self {
         let _context = Arc::new(zmq::Context::new());
-        let rep_future = Rep::builder(_context.clone()).bind(conn_str).build();
+        let router_future = Router::builder(_context.clone()).bind(conn_str).build();
         debug!("Binded to socket: {}", conn_str);
-        IpcListener { _context, rep_future }
+        IpcListener { _context, router_future }
     }
 
-    pub fn run<F>(self, f: F) -> impl Future<Item = (), Error = Error>
-    where F: FnMut(Multipart) -> Multipart {
-        self.rep_future.and_then(|rep| {
-            let (sink, stream) = rep.sink_stream(25).split();
-            stream.map(f).forward(sink).map(|(_stream, _sink)| ())
+    /// Runs `f` on every message, up to `concurrency` messages are handled at the same time
+    /// and their replies are sent in the order they're ready.
+    pub fn run<F, R>(self, concurrency: usize, f: F) -> impl Future<Item = (), Error = Error>
+    where F: FnMut(Multipart) -> R, R: IntoFuture<Item = Multipart, Error = Error> {
+        self.router_future.and_then(move |router| {
+            let (sink, stream) = router.sink_stream(25).split();
+            stream.map(f).buffer_unordered(concurrency).forward(sink).map(|(_stream, _sink)| ())
         })
     }
 }
 
-pub fn handle_message(db: &mut DB, request: Multipart, spid: &str, eid: sgx_enclave_id_t, retries: u32) -> Multipart {
-    let mut responses = Multipart::new();
-    for msg in request {
-        let msg: IpcMessageRequest = msg.into();
-        let id = msg.id.clone();
-        let response_msg = match msg.request {
-            IpcRequest::GetRegistrationParams => handling::get_registration_params(eid, spid, retries),
-            IpcRequest::GetTip { input } => handling::get_tip(db, &input),
-            IpcRequest::GetTips { input } => handling::get_tips(db, &input),
-            IpcRequest::GetAllTips => handling::get_all_tips(db),
-            IpcRequest::GetAllAddrs => handling::get_all_addrs(db),
-            IpcRequest::GetDelta { input } => handling::get_delta(db, input),
-            IpcRequest::GetDeltas { input } => handling::get_deltas(db, &input),
-            IpcRequest::GetContract { input } => handling::get_contract(db, &input),
-            IpcRequest::UpdateNewContract { address, bytecode } => handling::update_new_contract(db, address, &bytecode),
-            IpcRequest::UpdateNewContractOnDeployment { address, bytecode, delta } => handling::update_new_contract_on_deployment(db, address, &bytecode, delta),
-            IpcRequest::RemoveContract {address } => handling::remove_contract(db, address),
-            IpcRequest::UpdateDeltas { deltas } => handling::update_deltas(db, deltas),
-            IpcRequest::RemoveDeltas { input } => handling::remove_deltas(db, input),
-            IpcRequest::NewTaskEncryptionKey { user_pubkey } => handling::get_dh_user_key( &user_pubkey, eid),
-            IpcRequest::DeploySecretContract { input } => handling::deploy_contract(db, input, eid),
-            IpcRequest::ComputeTask { input } => handling::compute_task(db, input, eid),
-            IpcRequest::GetPTTRequest => handling::get_ptt_req(eid),
-            IpcRequest::PTTResponse { input } => handling::ptt_response(db, &input, eid),
-        };
-        let msg = IpcMessageResponse::from_response(response_msg.unwrap_or_error(), id);
-        responses.push_back(msg.into());
+/// Splits the envelope the ROUTER socket added to the message, the identity of the peer up to the empty delimiter,
+/// the reply has to start with it.
+fn split_envelope(message: &mut Multipart) -> Multipart {
+    let mut envelope = Multipart::new();
+    while let Some(frame) = message.pop_front() {
+        let delimiter = frame.is_empty();
+        envelope.push_back(frame);
+        if delimiter {
+            break;
+        }
+    }
+    envelope
+}
+
+pub fn handle_message(worker: &Worker, mut request: Multipart) -> impl Future<Item = Multipart, Error = Error> {
+    let mut reply = split_envelope(&mut request);
+    let responses: Vec<_> = request
+        .into_iter()
+        .map(|msg| {
+            let msg: IpcMessageRequest = msg.into();
+            let id = msg.id;
+            worker.submit::<Error>(msg.request).map(move |response| -> zmq::Message { IpcMessageResponse::from_response(response, id).into() })
+        })
+        .collect();
+    future::join_all(responses).map(move |responses: Vec<zmq::Message>| {
+        for response in responses {
+            reply.push_back(response);
+        }
+        reply
+    })
+}
+
+/// Handle a single request, this is shared by the IPC listener and the JSON-RPC server.
+/// The subscribers to the results of tasks are notified when the request is a task,
+/// and a retry of a task that already completed gets its cached result without running it again.
+pub fn handle_request(worker: &Worker, request: IpcRequest) -> IpcResponse {
+    let (db, eid) = (&*worker.db, worker.eid);
+    let cached_task = CachedTask::new(&request);
+    if let Some(task) = &cached_task {
+        match worker.results.get(&db.read(), task) {
+            Ok(Some(response)) => {
+                info!("Returning the cached result of the task {}", request.task_id().unwrap_or_default());
+                return response;
+            }
+            Ok(None) => (),
+            Err(e) => return Err::<IpcResponse, _>(e).unwrap_or_error(),
+        }
+    }
+    let task_id = request.task_id();
+    let timer = TaskTimer::start(&request);
+    let result = match request {
+        IpcRequest::GetRegistrationParams => handling::get_registration_params(eid, &worker.spid, worker.retries),
+        IpcRequest::NewTaskEncryptionKey { user_pubkey } => handling::get_dh_user_key( &user_pubkey, eid),
+        IpcRequest::GetPTTRequest => handling::get_ptt_req(eid),
+        // queries only read the DB, so they run concurrently.
+        IpcRequest::GetTip { input } => handling::get_tip(&db.read(), &input),
+        IpcRequest::GetTips { input } => handling::get_tips(&db.read(), &input),
+        IpcRequest::GetAllTips => handling::get_all_tips(&db.read()),
+        IpcRequest::GetAllAddrs => handling::get_all_addrs(&db.read()),
+        IpcRequest::GetDelta { input } => handling::get_delta(&db.read(), input),
+        IpcRequest::GetDeltas { input } => handling::get_deltas(&db.read(), &input),
+        IpcRequest::GetContract { input } => handling::get_contract(&db.read(), &input),
+        IpcRequest::BackupDB { path } => handling::backup_db(db, &path),
+        IpcRequest::VerifyDeltas { input } => handling::verify_deltas(&db.read(), &input),
+        IpcRequest::GetSnapshot { input } => handling::get_snapshot(&db.read(), &input),
+        // tasks only lock the DB while they access it, so tasks of different contracts run in parallel.
+        IpcRequest::DeploySecretContract { input } => handling::deploy_contract(db, input, eid, cached_task.as_ref()),
+        IpcRequest::UpgradeSecretContract { input } => handling::upgrade_contract(db, input, eid, cached_task.as_ref()),
+        IpcRequest::ComputeTask { input } => handling::compute_task(db, input, eid, cached_task.as_ref()),
+        // the rest change the DB outside of a task, so they wait for the running tasks.
+        IpcRequest::UpdateNewContract { address, bytecode } => handling::update_new_contract(&mut db.exclusive(), address, &bytecode),
+        IpcRequest::UpdateNewContractOnDeployment { address, bytecode, delta } => handling::update_new_contract_on_deployment(&mut db.exclusive(), address, &bytecode, delta),
+        IpcRequest::RemoveContract {address } => handling::remove_contract(&mut db.exclusive(), address),
+        IpcRequest::UpdateDeltas { deltas } => handling::update_deltas(&mut db.exclusive(), deltas),
+        IpcRequest::RemoveDeltas { input } => handling::remove_deltas(&mut db.exclusive(), input),
+        IpcRequest::PTTResponse { input } => handling::ptt_response(&mut db.exclusive(), &input, eid),
+        IpcRequest::SnapshotState { input } => handling::snapshot_state(&mut db.exclusive(), &input, eid),
+        IpcRequest::UpdateSnapshot { input } => handling::update_snapshot(&mut db.exclusive(), &input, eid),
+        IpcRequest::InspectState { input } => handling::inspect_state(db, &input, eid),
+    };
+    if let Err(e) = &result {
+        metrics::record_error(e);
+    }
+    let response = result.unwrap_or_error();
+    if let Some(timer) = timer {
+        timer.observe(&response);
+    }
+    if let Some(task) = cached_task {
+        worker.results.insert(task, &response);
     }
-    responses
+    if let Some(task_id) = task_id {
+        worker.tasks.notify(&task_id, &response);
+    }
+    response
 }
 
 
@@ -64,9 +133,10 @@ pub fn handle_message(db: &mut DB, request: Multipart, spid: &str, eid: sgx_encl
 pub(self) mod handling {
     #![allow(clippy::needless_pass_by_value)]
     use crate::common_u::errors::P2PErr;
-    use crate::db::{CRUDInterface, DeltaKey, P2PCalls, Stype, DB};
+    use crate::db::{Backend, BatchOp, CRUDInterface, DeltaKey, P2PCalls, SharedDB, Stype, TaskWrites, DB};
     use crate::km_u;
     use crate::networking::messages::*;
+    use crate::networking::task_cache::CachedTask;
     use crate::esgx::equote;
     use crate::wasm_u::*;
     use enigma_crypto::hash::Keccak256;
@@ -79,12 +149,12 @@ pub(self) mod handling {
     use serde::Deserialize;
     use serde_json::Value;
     use sgx_types::sgx_enclave_id_t;
+    use std::sync::RwLockReadGuard;
     use std::str;
     use common_u::errors::{DBErr, self};
 
     type ResponseResult = Result<IpcResponse, Error>;
 
-    static DEPLOYMENT_VALS_LEN: usize = 2;
     static FAILED_STATE: i64 = -1;
 
     impl Into<IpcResponse> for WasmTaskFailure{
@@ -92,6 +162,7 @@ pub(self) mod handling {
             let result = IpcResults::FailedTask {
                 used_gas: self.used_gas,
                 output: self.output.to_hex(),
+                reverted: self.reverted,
                 signature: self.signature.to_hex(),
             };
             IpcResponse::FailedTask { result }
@@ -99,13 +170,26 @@ pub(self) mod handling {
     }
 
     impl WasmTaskResult {
+        /// The keys of the deltas the task stored, a view stores none.
+        fn stored_deltas(&self) -> Vec<DeltaKey> {
+            if self.view {
+                return Vec::new();
+            }
+            std::iter::once(&self.delta).chain(self.callee_deltas.iter()).map(|delta| delta.key).collect()
+        }
+
         pub fn into_execute_response(self) -> IpcResponse {
+            let delta = if self.view { None } else { Some(self.delta.into()) };
             let result = IpcResults::ComputeResult {
                 used_gas: self.used_gas,
                 output: self.output.to_hex(),
-                delta: self.delta.into(),
+                delta,
                 ethereum_address: self.eth_contract_addr.to_hex(),
                 ethereum_payload: self.eth_payload.to_hex(),
+                events: self.events.into_iter().map(IpcEvent::from).collect(),
+                profile: self.profile.map(|profile| profile.to_hex()),
+                callee_deltas: self.callee_deltas.into_iter().map(IpcDelta::from).collect(),
+                view: self.view,
                 signature: self.signature.to_hex(),
             };
             IpcResponse::ComputeTask { result }
@@ -119,10 +203,28 @@ pub(self) mod handling {
                 delta: self.delta.into(),
                 ethereum_address: self.eth_contract_addr.to_hex(),
                 ethereum_payload: self.eth_payload.to_hex(),
+                events: self.events.into_iter().map(IpcEvent::from).collect(),
+                profile: self.profile.map(|profile| profile.to_hex()),
                 signature: self.signature.to_hex(),
             };
             IpcResponse::DeploySecretContract { result }
         }
+
+        pub fn into_upgrade_response(self, bytecode: &[u8], prev_code: &[u8]) -> IpcResponse {
+            let result = IpcResults::UpgradeResult {
+                pre_code_hash: bytecode.keccak256().to_hex(),
+                previous_code_hash: prev_code.keccak256().to_hex(),
+                used_gas: self.used_gas,
+                output: self.output.to_hex(),
+                delta: self.delta.into(),
+                ethereum_address: self.eth_contract_addr.to_hex(),
+                ethereum_payload: self.eth_payload.to_hex(),
+                events: self.events.into_iter().map(IpcEvent::from).collect(),
+                profile: self.profile.map(|profile| profile.to_hex()),
+                signature: self.signature.to_hex(),
+            };
+            IpcResponse::UpgradeSecretContract { result }
+        }
     }
 
     #[logfn(INFO)]
@@ -238,24 +340,26 @@ pub(self) mod handling {
 
     #[logfn(INFO)]
     pub fn update_new_contract_on_deployment(db: &mut DB, address: String, bytecode: &str, delta: IpcDelta) -> ResponseResult {
-        let mut tuples = Vec::with_capacity(DEPLOYMENT_VALS_LEN);
         let address_arr = ContractAddress::from_hex(&address)?;
-
         let bytecode = bytecode.from_hex()?;
         let bytecode_delta_key = DeltaKey::new(address_arr, Stype::ByteCode);
-        tuples.push((bytecode_delta_key, bytecode));
 
         let data = delta.data.ok_or(P2PErr { cmd: "UpdateNewContractOnDeployment".to_string(), msg: "Delta Data Missing".to_string() })?;
         let delta_key = DeltaKey::new(address_arr, Stype::Delta(delta.key));
-        tuples.push((delta_key, data));
 
-        let results = db.insert_tuples(&tuples);
-        let mut status = Status::Passed;
-        if results.into_iter().any(| result | result.is_err()) {
-            status = Status::Failed;
-        }
-        // since a new delta and bytecode were added, the state is no longer updated
-        db.update_state_status(false);
+        // the bytecode and the initial delta are written together, so a contract never exists without its initial state.
+        let ops = vec![BatchOp::ForceUpdate(&bytecode_delta_key, &bytecode[..]), BatchOp::ForceUpdate(&delta_key, &data[..])];
+        let status = match db.batch(ops) {
+            Ok(()) => {
+                // since a new delta and bytecode were added, the state is no longer updated
+                db.update_state_status(false);
+                Status::Passed
+            }
+            Err(e) => {
+                error!("Failed storing the contract {}: {}", address, e);
+                Status::Failed
+            }
+        };
         let result = IpcResults::Status(status);
         Ok(IpcResponse::UpdateNewContractOnDeployment { address, result })
     }
@@ -290,61 +394,64 @@ pub(self) mod handling {
             let delta_key = DeltaKey::new(address, Stype::Delta(delta.key));
             tuples.push((delta_key, data));
         }
-        let results = db.insert_tuples(&tuples);
-        let mut errors = Vec::with_capacity(tuples.len());
-        let mut overall_status = Status::Passed;
-        for ((deltakey, _), res) in tuples.into_iter().zip(results.into_iter()) {
-            let status = if res.is_err() {
-                overall_status = Status::Failed;
-                Status::Failed
-            } else {
+        // either all the deltas are stored or none of them are.
+        let overall_status = match db.batch(tuples.iter().map(|(key, data)| BatchOp::ForceUpdate(key, &data[..])).collect()) {
+            Ok(()) => {
+                // since a new delta was added the state is no longer updated
+                db.update_state_status(false);
                 Status::Passed
-            };
-            let key = Some(deltakey.key_type.unwrap_delta() as i64);
-            let address = deltakey.contract_address.to_hex();
-            let delta = IpcStatusResult { address, key, status };
-            errors.push(delta);
-        }
-        // since a new delta was added the state is no longer updated
-        db.update_state_status(false);
+            }
+            Err(e) => {
+                error!("Failed storing the deltas: {}", e);
+                Status::Failed
+            }
+        };
+        let errors = tuples
+            .into_iter()
+            .map(|(deltakey, _)| IpcStatusResult {
+                address: deltakey.contract_address.to_hex(),
+                key: Some(deltakey.key_type.unwrap_delta() as i64),
+                status: overall_status.clone(),
+            })
+            .collect();
         let result = IpcResults::DeltasResult { status: overall_status, errors };
         Ok(IpcResponse::UpdateDeltas {result})
     }
 
-    fn delete_data_from_db(db: &mut DB, addr: &str, key_type: Stype) -> Result<IpcResults, Error> {
-        let addr_arr = ContractAddress::from_hex(addr)?;
-        let dk = DeltaKey::new(addr_arr, key_type);
-        match db.delete(&dk) {
-            Ok(_) => Ok(IpcResults::Status(Status::Passed)),
-            Err(e) => {
-                match errors::is_db_err_type(e) {
-                    Ok(_) =>  Ok(IpcResults::Status(Status::Passed)),
-                    Err(_) => Ok(IpcResults::Status(Status::Failed)),
-                }
-            },
-        }
-    }
-
     #[logfn(INFO)]
     pub fn remove_deltas(db: &mut DB, input: Vec<IpcDeltasRange>) -> ResponseResult {
-        let mut errors = Vec::new();
-        let mut overall_status = Status::Passed;
-        for addr_deltas in input {
-            for key in addr_deltas.from..addr_deltas.to {
-                let delta_res = delete_data_from_db(db,&addr_deltas.address.clone(), Stype::Delta(key))?;
-                if let IpcResults::Status(Status::Failed) = delta_res {
-                    let failed_delta = IpcStatusResult { address: addr_deltas.address.clone() , key: Some(key as i64), status: Status::Failed };
-                    errors.push(failed_delta);
-                    overall_status = Status::Failed;
+        let mut keys = Vec::new();
+        for addr_deltas in &input {
+            let addr_arr = ContractAddress::from_hex(&addr_deltas.address)?;
+            // keys that don't exist are already removed
+            let range = (addr_deltas.from..addr_deltas.to).map(Stype::Delta).chain(std::iter::once(Stype::State));
+            keys.extend(range.map(|key_type| DeltaKey::new(addr_arr, key_type)).filter(|dk| db.read(dk).is_ok()));
+            // the snapshots that include the removed deltas can't be used to build the state anymore.
+            for snapshot in db.snapshots_from(&addr_arr, addr_deltas.from)? {
+                if !keys.contains(&snapshot) {
+                    keys.push(snapshot);
                 }
             }
-            let status_res = delete_data_from_db(db,&addr_deltas.address, Stype::State)?;
-            if let IpcResults::Status(Status::Failed) = status_res {
-                let failed_delta = IpcStatusResult { address: addr_deltas.address.clone() , key: Some(FAILED_STATE), status: Status::Failed };
-                errors.push(failed_delta);
-                overall_status = Status::Failed;
-            }
         }
+        // either all the deltas are removed or none of them are.
+        let (overall_status, errors) = match db.batch(keys.iter().map(BatchOp::Delete).collect()) {
+            Ok(()) => (Status::Passed, Vec::new()),
+            Err(e) => {
+                error!("Failed removing the deltas: {}", e);
+                let errors = keys
+                    .iter()
+                    .filter(|dk| match dk.key_type { Stype::Snapshot(_) => false, _ => true })
+                    .map(|dk| {
+                        let key = match dk.key_type {
+                            Stype::Delta(index) => index as i64,
+                            _ => FAILED_STATE,
+                        };
+                        IpcStatusResult { address: dk.contract_address.to_hex(), key: Some(key), status: Status::Failed }
+                    })
+                    .collect();
+                (Status::Failed, errors)
+            }
+        };
         db.update_state_status(false);
         let result = IpcResults::DeltasResult { status: overall_status, errors };
         Ok(IpcResponse::RemoveDeltas {result})
@@ -389,69 +496,283 @@ pub(self) mod handling {
         Ok(IpcResponse::PTTResponse {result})
     }
 
-    pub fn deploy_contract(db: &mut DB, input: IpcTask, eid: sgx_enclave_id_t) -> ResponseResult {
+    #[logfn(INFO)]
+    pub fn snapshot_state(db: &mut DB, input: &[String], eid: sgx_enclave_id_t) -> ResponseResult {
+        if !db.get_state_status() {
+            km_u::ptt_build_state(db, eid)?;
+            db.update_state_status(true);
+        }
+        let mut result = Vec::with_capacity(input.len());
+        for address in input {
+            let snapshot = match snapshot_and_prune(db, eid, ContractAddress::from_hex(address)?) {
+                Ok((delta_index, first_delta)) => {
+                    IpcSnapshot { address: address.clone(), delta_index: Some(delta_index), first_delta: Some(first_delta), status: Status::Passed }
+                }
+                Err(e) => {
+                    error!("Failed snapshotting the state of {}: {}", address, e);
+                    IpcSnapshot { address: address.clone(), delta_index: None, first_delta: None, status: Status::Failed }
+                }
+            };
+            result.push(snapshot);
+        }
+        Ok(IpcResponse::SnapshotState { result: IpcResults::Snapshots(result) })
+    }
+
+    #[logfn(INFO)]
+    pub fn get_snapshot(db: &DB, input: &str) -> ResponseResult {
+        let address = ContractAddress::from_hex(input)?;
+        let (delta_index, state) = db.get_latest_snapshot(&address)?;
+        let first_delta = db.get_first_delta(&address)?;
+        Ok(IpcResponse::GetSnapshot { result: IpcStateSnapshot::new(&address, delta_index, first_delta, &state) })
+    }
+
+    #[logfn(INFO)]
+    pub fn update_snapshot(db: &mut DB, input: &IpcStateSnapshot, eid: sgx_enclave_id_t) -> ResponseResult {
+        let address = ContractAddress::from_hex(&input.address)?;
+        let state = input.state.from_hex()?;
+        if state.keccak256().to_hex() != input.hash.trim_start_matches("0x") {
+            return Err(P2PErr { cmd: "UpdateSnapshot".to_string(), msg: "The state doesn't match its hash".to_string() }.into());
+        }
+        // the hash comes from the same peer, so the enclave checks the snapshot against the local deltas before it's stored.
+        if let Err(e) = km_u::verify_snapshot(db, eid, address, &state, input.delta_index) {
+            return Err(P2PErr { cmd: "UpdateSnapshot".to_string(), msg: format!("The snapshot failed the verification: {}", e) }.into());
+        }
+        db.save_snapshot(&address, input.delta_index, &state)?;
+        // the state is built again from the snapshot and the deltas after it.
+        db.update_state_status(false);
+        Ok(IpcResponse::UpdateSnapshot { result: IpcResults::Status(Status::Passed) })
+    }
+
+    #[logfn(INFO)]
+    pub fn inspect_state(db: &SharedDB, input: &IpcStateQuery, eid: sgx_enclave_id_t) -> ResponseResult {
+        let address = ContractAddress::from_hex(&input.address)?;
+        let user_sig = input.user_sig.from_hex()?;
+        if user_sig.len() != 65 {
+            return Err(P2PErr { cmd: "InspectState".to_string(), msg: format!("Invalid signature length: {}", user_sig.len()) }.into());
+        }
+        let mut sig = [0u8; 65];
+        sig.copy_from_slice(&user_sig);
+        let query = km_u::StateQuery { prefix: input.prefix.clone(), from: input.from.clone(), limit: input.limit, values: input.values };
+
+        // the inspection only reads the state, so it runs along with the tasks.
+        let _task = task_on_built_states(db, eid)?;
+        let mut writes = TaskWrites::default();
+        let (output, sig) = km_u::inspect_state((db, &mut writes), eid, address, &query, &sig)?;
+        let result = IpcResults::Inspection { output: output.to_hex(), sig: sig.to_hex() };
+        Ok(IpcResponse::InspectState { result })
+    }
+
+    #[logfn(INFO)]
+    pub fn backup_db(db: &SharedDB, path: &str) -> ResponseResult {
+        let backend = db.read().backend();
+        // a sled backup copies the tree, so it waits for the running tasks and blocks new ones until it's done.
+        let (path, manifest) = match backend {
+            Backend::Sled => db.exclusive().backup_named(path)?,
+            _ => db.read().backup_named(path)?,
+        };
+        Ok(IpcResponse::BackupDB { result: IpcResults::Backup { path: path.to_string_lossy().into_owned(), manifest } })
+    }
+
+    #[logfn(INFO)]
+    pub fn verify_deltas(db: &DB, input: &[String]) -> ResponseResult {
+        let verifications = if input.is_empty() {
+            db.verify_all_deltas()?
+        } else {
+            let mut verifications = Vec::with_capacity(input.len());
+            for address in input {
+                verifications.push(db.verify_deltas(&ContractAddress::from_hex(address)?)?);
+            }
+            verifications
+        };
+        for verification in verifications.iter().filter(|v| !v.is_valid()) {
+            error!("The deltas of {:?} failed the verification: {:?}", verification.address, verification.first_bad);
+        }
+        let result = verifications.into_iter().map(IpcDeltasVerification::from).collect();
+        Ok(IpcResponse::VerifyDeltas { result: IpcResults::Verifications(result) })
+    }
+
+    /// Snapshots the state of the contract and prunes the deltas included in it,
+    /// returns the index of the last delta in the snapshot and of the first delta that was kept.
+    fn snapshot_and_prune(db: &mut DB, eid: sgx_enclave_id_t, address: ContractAddress) -> Result<(u32, u32), Error> {
+        let delta_index = km_u::snapshot_state(&mut *db, eid, address)?;
+        let first_delta = db.prune_deltas(&address)?;
+        Ok((delta_index, first_delta))
+    }
+
+    /// Like `snapshot_and_prune`, but along with the running tasks,
+    /// the DB is only locked to store the snapshot and prune the deltas, and not during the ecall.
+    fn snapshot_and_prune_shared(db: &SharedDB, eid: sgx_enclave_id_t, address: ContractAddress) -> Result<(u32, u32), Error> {
+        let mut writes = TaskWrites::default();
+        let delta_index = km_u::snapshot_state((db, &mut writes), eid, address)?;
+        writes.commit(db)?;
+        let first_delta = db.lock().prune_deltas(&address)?;
+        Ok((delta_index, first_delta))
+    }
+
+    /// Snapshots the states of the contracts that got new deltas in the task if their interval passed,
+    /// failing to do so doesn't fail the task since the deltas are already stored.
+    fn snapshot_if_due(db: &SharedDB, eid: sgx_enclave_id_t, deltas: &[DeltaKey]) {
+        for delta in deltas {
+            let address = delta.contract_address;
+            let due = db.read().is_snapshot_due(&address, delta.key_type.unwrap_delta());
+            if due {
+                if let Err(e) = snapshot_and_prune_shared(db, eid, address) {
+                    warn!("Failed snapshotting the state of {:?}: {}", address, e);
+                }
+            }
+        }
+    }
+
+    /// Stores the writes of the task with its result, so a retry of the task gets the result even after the node restarted.
+    fn commit_task(db: &SharedDB, mut writes: TaskWrites, task: Option<&CachedTask>, response: &IpcResponse) -> Result<(), Error> {
+        if let Some(task) = task {
+            writes.push(task.result_write(response));
+        }
+        writes.commit(db)
+    }
+
+    pub fn deploy_contract(db: &SharedDB, input: IpcTask, eid: sgx_enclave_id_t, task: Option<&CachedTask>) -> ResponseResult {
+        // the context borrows the whole input, so it's taken before the bytecode is moved out of it.
+        let context = input.task_context()?;
         let bytecode = input.pre_code.expect("Bytecode Missing");
         let contract_address = ContractAddress::from_hex(&input.address)?;
         let enc_args = input.encrypted_args.from_hex()?;
         let constructor = input.encrypted_fn.from_hex()?;
         let mut user_pubkey = [0u8; 64];
         user_pubkey.clone_from_slice(&input.user_dhkey.from_hex()?);
+        let _task = db.task();
+        let mut writes = TaskWrites::default();
         let result = wasm::deploy(
-            db,
+            (db, &mut writes),
             eid,
             &bytecode,
             &constructor,
             &enc_args,
             &contract_address,
             &user_pubkey,
-            input.gas_limit)?;
+            input.gas_limit,
+            &context)?;
 
         match result {
             WasmResult::WasmTaskResult(v) => {
-                // Save the ExeCode into the DB.
-                let key = DeltaKey::new(contract_address, Stype::ByteCode);
-                db.create(&key, &v.output)?;
+                // Save the ExeCode into the DB with the state and the delta of the constructor.
+                writes.push(BatchOp::Create(DeltaKey::new(contract_address, Stype::ByteCode), v.output.to_vec()));
                 let ipc_response = v.into_deploy_response(&bytecode);
+                commit_task(db, writes, task, &ipc_response)?;
                 info!("deploy_contract() => Ok({})", ipc_response.display_without_bytecode());
                 debug!("deployed bytecode => {}", ipc_response.display_bytecode());
                 Ok(ipc_response)
             },
             WasmResult::WasmTaskFailure(v) => {
-                let response = Ok(v.into());
+                let response: IpcResponse = v.into();
+                commit_task(db, TaskWrites::default(), task, &response)?;
                 info!("{:?}", response);
-                response
+                Ok(response)
             }
         }
     }
 
     #[logfn(INFO)]
-    pub fn compute_task(db: &mut DB, input: IpcTask, eid: sgx_enclave_id_t) -> ResponseResult {
+    pub fn upgrade_contract(db: &SharedDB, input: IpcTask, eid: sgx_enclave_id_t, task: Option<&CachedTask>) -> ResponseResult {
+        // the context borrows the whole input, so it's taken before the bytecode is moved out of it.
+        let context = input.task_context()?;
+        let bytecode = input.pre_code.expect("Bytecode Missing");
+        let contract_address = ContractAddress::from_hex(&input.address)?;
         let enc_args = input.encrypted_args.from_hex()?;
-        let address = ContractAddress::from_hex(&input.address)?;
-        let callable = input.encrypted_fn.from_hex()?;
+        let migration = input.encrypted_fn.from_hex()?;
         let mut user_pubkey = [0u8; 64];
         user_pubkey.clone_from_slice(&input.user_dhkey.from_hex()?);
 
-        if !db.get_state_status() {
-            let res = km_u::ptt_build_state(db, eid)?;
-            db.update_state_status(true);
+        let _task = task_on_built_states(db, eid)?;
+        let prev_code = db.read().get_contract(contract_address)?;
+
+        let mut writes = TaskWrites::default();
+        let result = wasm::upgrade(
+            (db, &mut writes),
+            eid,
+            &bytecode,
+            &prev_code,
+            &migration,
+            &enc_args,
+            &contract_address,
+            &user_pubkey,
+            input.gas_limit,
+            &context)?;
+
+        match result {
+            WasmResult::WasmTaskResult(v) => {
+                // Replace the ExeCode, the state and the deltas of the contract are kept.
+                writes.push(BatchOp::ForceUpdate(DeltaKey::new(contract_address, Stype::ByteCode), v.output.to_vec()));
+                let deltas = v.stored_deltas();
+                let response = v.into_upgrade_response(&bytecode, &prev_code);
+                commit_task(db, writes, task, &response)?;
+                snapshot_if_due(db, eid, &deltas);
+                Ok(response)
+            },
+            WasmResult::WasmTaskFailure(v) => {
+                let response = v.into();
+                commit_task(db, TaskWrites::default(), task, &response)?;
+                Ok(response)
+            }
         }
-        let bytecode = db.get_contract(address)?;
+    }
 
+    #[logfn(INFO)]
+    pub fn compute_task(db: &SharedDB, input: IpcTask, eid: sgx_enclave_id_t, task: Option<&CachedTask>) -> ResponseResult {
+        let enc_args = input.encrypted_args.from_hex()?;
+        let address = ContractAddress::from_hex(&input.address)?;
+        let callable = input.encrypted_fn.from_hex()?;
+        let mut user_pubkey = [0u8; 64];
+        user_pubkey.clone_from_slice(&input.user_dhkey.from_hex()?);
+        let context = input.task_context()?;
 
+        let _task = task_on_built_states(db, eid)?;
+        let bytecode = db.read().get_contract(address)?;
+
+        let mut writes = TaskWrites::default();
         let result = wasm::execute(
-            db,
+            (db, &mut writes),
             eid,
             &bytecode,
             &callable,
             &enc_args,
             &user_pubkey,
             &address,
-            input.gas_limit)?;
+            input.gas_limit,
+            &context)?;
 
         match result {
-            WasmResult::WasmTaskResult(v) => Ok(v.into_execute_response()),
-            WasmResult::WasmTaskFailure(v) => Ok(v.into())
+            WasmResult::WasmTaskResult(v) => {
+                let deltas = v.stored_deltas();
+                let response = v.into_execute_response();
+                commit_task(db, writes, task, &response)?;
+                snapshot_if_due(db, eid, &deltas);
+                Ok(response)
+            },
+            WasmResult::WasmTaskFailure(v) => {
+                let response = v.into();
+                commit_task(db, TaskWrites::default(), task, &response)?;
+                Ok(response)
+            }
+        }
+    }
+
+    /// Builds the states if new deltas were stored since they were built, and marks a task as running on them.
+    /// Building the states writes all of them, so it's done with the DB exclusively and not during a task.
+    fn task_on_built_states(db: &SharedDB, eid: sgx_enclave_id_t) -> Result<RwLockReadGuard<()>, Error> {
+        loop {
+            {
+                let task = db.task();
+                if db.read().get_state_status() {
+                    return Ok(task);
+                }
+            }
+            let mut exclusive = db.exclusive();
+            // another request could have built them while this one waited.
+            if !exclusive.get_state_status() {
+                km_u::ptt_build_state(&mut exclusive, eid)?;
+                exclusive.update_state_status(true);
+            }
         }
     }
 
@@ -472,9 +793,9 @@ mod test {
         let conn = "tcp://*:5556";
         let server = IpcListener::new(conn);
         server
-            .run(|mul| {
+            .run(1, |mul| {
                 println!("{:?}", mul);
-                mul
+                Ok(mul)
             })
             .wait()
             .unwrap();
@@ -511,7 +832,8 @@ mod test {
 
         let conn = "tcp://*:2456";
         let server = IpcListener::new(conn);
-        server.run(|multi| handle_message(&mut db, multi,  SPID, enclave.geteid(), RETRIES)).wait().unwrap();
+        let worker = Worker::new(db, enclave.geteid(), SPID, RETRIES, 4).unwrap();
+        server.run(4, |multi| handle_message(&worker, multi)).wait().unwrap();
     }
 
 }
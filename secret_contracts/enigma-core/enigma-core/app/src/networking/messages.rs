@@ -1,7 +1,10 @@
 use serde_json;
 use serde_repr::{Serialize_repr, Deserialize_repr};
 use zmq::Message;
-use crate::db::{Delta, Stype, DeltaKey};
+use crate::db::{BackupManifest, Delta, DeltasVerification, Stype, DeltaKey};
+use crate::wasm_u::ContractEvent;
+use enigma_crypto::hash::Keccak256;
+use enigma_types::{ContractAddress, Hash256, TaskContext};
 use hex::ToHex;
 use failure::Error;
 
@@ -45,10 +48,17 @@ pub enum IpcResponse {
     RemoveDeltas { #[serde(flatten)] result: IpcResults},
     NewTaskEncryptionKey { #[serde(flatten)] result: IpcResults },
     DeploySecretContract { #[serde(flatten)] result: IpcResults},
+    UpgradeSecretContract { #[serde(flatten)] result: IpcResults},
     ComputeTask { #[serde(flatten)] result: IpcResults },
     FailedTask { #[serde(flatten)] result: IpcResults },
     GetPTTRequest { #[serde(flatten)] result: IpcResults },
     PTTResponse { result: IpcResults },
+    SnapshotState { result: IpcResults },
+    GetSnapshot { result: IpcStateSnapshot },
+    UpdateSnapshot { result: IpcResults },
+    BackupDB { #[serde(flatten)] result: IpcResults },
+    VerifyDeltas { result: IpcResults },
+    InspectState { result: IpcResults },
     Error { msg: String },
 }
 
@@ -83,12 +93,33 @@ impl IpcResponse {
             _ => "".to_string(),
         }
     }
+
+    /// The gas a task used, including a failed task.
+    pub fn used_gas(&self) -> Option<u64> {
+        match self {
+            IpcResponse::DeploySecretContract { result }
+            | IpcResponse::UpgradeSecretContract { result }
+            | IpcResponse::ComputeTask { result }
+            | IpcResponse::FailedTask { result } => match result {
+                IpcResults::DeployResult { used_gas, .. }
+                | IpcResults::UpgradeResult { used_gas, .. }
+                | IpcResults::ComputeResult { used_gas, .. }
+                | IpcResults::FailedTask { used_gas, .. } => Some(*used_gas),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase", rename = "result")]
 pub enum IpcResults {
     Errors(Vec<IpcStatusResult>),
+    Snapshots(Vec<IpcSnapshot>),
+    Verifications(Vec<IpcDeltasVerification>),
+    #[serde(rename = "result")]
+    Backup { path: String, manifest: BackupManifest },
     #[serde(rename = "result")]
     Request { request: String, #[serde(rename = "workerSig")] sig: String },
     Addresses(Vec<String>),
@@ -106,17 +137,31 @@ pub enum IpcResults {
     #[serde(rename = "result")]
     DHKey { #[serde(rename = "workerEncryptionKey")] dh_key: String, #[serde(rename = "workerSig")] sig: String },
     #[serde(rename = "result")]
+    Inspection { output: String, #[serde(rename = "workerSig")] sig: String },
+    #[serde(rename = "result")]
     RegistrationParams { #[serde(rename = "signingKey")] signing_key: String, report: String, signature: String },
     #[serde(rename = "result")]
     ComputeResult {
         #[serde(rename = "usedGas")]
         used_gas: u64,
         output: String,
-        delta: IpcDelta,
+        /// A view doesn't produce a delta, so there's nothing to store and the tip of the contract stays the same.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        delta: Option<IpcDelta>,
         #[serde(rename = "ethereumAddress")]
         ethereum_address: String,
         #[serde(rename = "ethereumPayload")]
         ethereum_payload: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        events: Vec<IpcEvent>,
+        /// Only returned if the task asked for `profile`, it's encrypted with the user key like the output.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+        /// The deltas of the other secret contracts called during the task, they're signed with the result.
+        #[serde(rename = "calleeDeltas", default, skip_serializing_if = "Vec::is_empty")]
+        callee_deltas: Vec<IpcDelta>,
+        #[serde(default)]
+        view: bool,
         signature: String,
     },
     #[serde(rename = "result")]
@@ -131,11 +176,39 @@ pub enum IpcResults {
         ethereum_address: String,
         #[serde(rename = "ethereumPayload")]
         ethereum_payload: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        events: Vec<IpcEvent>,
+        /// Only returned if the task asked for `profile`, it's encrypted with the user key like the output.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+        signature: String,
+    },
+    #[serde(rename = "result")]
+    UpgradeResult {
+        #[serde(rename = "preCodeHash")]
+        pre_code_hash: String,
+        #[serde(rename = "previousCodeHash")]
+        previous_code_hash: String,
+        #[serde(rename = "usedGas")]
+        used_gas: u64,
+        output: String,
+        delta: IpcDelta,
+        #[serde(rename = "ethereumAddress")]
+        ethereum_address: String,
+        #[serde(rename = "ethereumPayload")]
+        ethereum_payload: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        events: Vec<IpcEvent>,
+        /// Only returned if the task asked for `profile`, it's encrypted with the user key like the output.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
         signature: String,
     },
     #[serde(rename = "result")]
     FailedTask {
         output: String,
+        #[serde(default)]
+        reverted: bool,
         #[serde(rename = "usedGas")]
         used_gas: u64,
         signature: String,
@@ -160,9 +233,48 @@ pub enum IpcRequest {
     RemoveDeltas { input: Vec<IpcDeltasRange> },
     NewTaskEncryptionKey { #[serde(rename = "userPubKey")] user_pubkey: String },
     DeploySecretContract { input: IpcTask},
+    /// Replace the code of a deployed contract with `preCode`, `encryptedFn` must call its `migrate` function.
+    UpgradeSecretContract { input: IpcTask},
     ComputeTask { input: IpcTask },
     GetPTTRequest,
     PTTResponse {  input: PrincipalResponse },
+    /// Snapshot the states of the contracts in `input` and prune the deltas they include.
+    SnapshotState { input: Vec<String> },
+    /// Get the latest snapshot of the state of a contract, a node can bootstrap from it
+    /// and the deltas after it when its peers already pruned the deltas included in it.
+    GetSnapshot { input: String },
+    /// Store a snapshot that was fetched from a peer with `GetSnapshot`, it's rejected if it doesn't match its hash.
+    UpdateSnapshot { input: IpcStateSnapshot },
+    /// Create a backup of the DB while the node keeps running, `path` is relative to the backup directory of the node
+    /// and must not exist.
+    BackupDB { path: String },
+    /// Verify the deltas of the contracts in `input` against the hashes recorded when they were stored,
+    /// if `input` is empty all the contracts are verified.
+    VerifyDeltas { #[serde(default)] input: Vec<String> },
+    /// List the keys, or read the values, of the decrypted state of a contract for a user it allows to inspect it.
+    InspectState { input: IpcStateQuery },
+}
+
+impl IpcRequest {
+    /// The id of the task the request runs, `None` if it isn't a task or the task was sent without an id.
+    pub fn task_id(&self) -> Option<String> {
+        match self {
+            IpcRequest::DeploySecretContract { input } | IpcRequest::UpgradeSecretContract { input } | IpcRequest::ComputeTask { input } => {
+                input.task_id.clone()
+            }
+            _ => None,
+        }
+    }
+
+    /// The contract a task runs on, the tasks of a contract run one after the other.
+    pub fn task_address(&self) -> Option<ContractAddress> {
+        match self {
+            IpcRequest::DeploySecretContract { input } | IpcRequest::UpgradeSecretContract { input } | IpcRequest::ComputeTask { input } => {
+                ContractAddress::from_hex(&input.address).ok()
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -180,6 +292,15 @@ pub struct IpcTask {
     pub gas_limit: u64,
     #[serde(rename = "contractAddress")]
     pub address: String,
+    #[serde(rename = "blockNumber")]
+    #[serde(default)]
+    pub block_number: u64,
+    #[serde(rename = "taskID")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<String>,
+    /// Return an execution profile with the result, to see where the gas of the contract goes.
+    #[serde(default)]
+    pub profile: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -190,6 +311,93 @@ pub struct IpcStatusResult {
     pub status: Status,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IpcSnapshot {
+    pub address: String,
+    /// The index of the last delta included in the snapshot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta_index: Option<u32>,
+    /// The index of the first delta that wasn't pruned, deltas before it can't be requested anymore.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_delta: Option<u32>,
+    pub status: Status,
+}
+
+/// A snapshot of the encrypted state of a contract, only an enclave with the state key of the contract can decrypt it
+/// and the decryption fails if it was tampered with, the hash lets the node reject a corrupted snapshot before storing it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IpcStateSnapshot {
+    pub address: String,
+    /// The index of the last delta included in the snapshot.
+    pub delta_index: u32,
+    /// The index of the first delta the peer didn't prune.
+    #[serde(default)]
+    pub first_delta: u32,
+    pub state: String,
+    /// Keccak256(state)
+    pub hash: String,
+}
+
+impl IpcStateSnapshot {
+    pub fn new(address: &ContractAddress, delta_index: u32, first_delta: u32, state: &[u8]) -> Self {
+        IpcStateSnapshot { address: address.to_hex(), delta_index, first_delta, state: state.to_hex(), hash: state.keccak256().to_hex() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IpcDeltasVerification {
+    pub address: String,
+    /// The number of deltas that passed the verification.
+    pub verified: u32,
+    /// The number of legacy deltas that have no recorded hash, they aren't counted as verified.
+    #[serde(default)]
+    pub unhashed: u32,
+    /// The index of the first bad delta, the deltas after it weren't verified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bad_delta: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub status: Status,
+}
+
+impl From<DeltasVerification> for IpcDeltasVerification {
+    fn from(verification: DeltasVerification) -> Self {
+        let status = if verification.is_valid() { Status::Passed } else { Status::Failed };
+        let (bad_delta, error) = match verification.first_bad {
+            Some((index, fault)) => (Some(index), Some(fault.to_string())),
+            None => (None, None),
+        };
+        IpcDeltasVerification {
+            address: verification.address.to_hex(),
+            verified: verification.verified,
+            unhashed: verification.unhashed,
+            bad_delta,
+            error,
+            status,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IpcStateQuery {
+    pub address: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub from: String,
+    /// The maximum number of keys to return, 0 returns all of them.
+    #[serde(default)]
+    pub limit: u32,
+    #[serde(default)]
+    pub values: bool,
+    /// S(address, prefix, from, limit, values) signed by the user, who must have a DH key with the enclave.
+    pub user_sig: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct IpcDelta {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -200,6 +408,13 @@ pub struct IpcDelta {
     pub data: Option<Vec<u8>>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IpcEvent {
+    pub topic: String,
+    pub data: String,
+    pub encrypted: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IpcDeltasRange {
     pub address: String,
@@ -247,6 +462,33 @@ impl IpcDelta {
     }
 }
 
+impl IpcTask {
+    /// The context of the task that is exposed to the secret contract, the contract gets no task id if it's missing.
+    pub fn task_context(&self) -> Result<TaskContext, Error> {
+        let task_id = match &self.task_id {
+            Some(id) => Hash256::from_hex(id)?,
+            None => Hash256::default(),
+        };
+        Ok(TaskContext { block_number: self.block_number, task_id, has_task_id: self.task_id.is_some(), profile: self.profile })
+    }
+
+    /// The hash of the inputs the user gave the task: the code, the function, the arguments, the key, the contract and the gas limit.
+    /// The task id, the block it was sent at and the profile are left out, so a retry of the task has the same hash.
+    pub fn input_hash(&self) -> Hash256 {
+        let mut input = self.clone();
+        input.task_id = None;
+        input.block_number = 0;
+        input.profile = false;
+        serde_json::to_vec(&input).expect("Failed serializing the task").keccak256()
+    }
+}
+
+impl From<ContractEvent> for IpcEvent {
+    fn from(event: ContractEvent) -> Self {
+        IpcEvent { topic: event.topic.to_hex(), data: event.data.to_hex(), encrypted: event.encrypted }
+    }
+}
+
 impl From<Delta> for IpcDelta {
     fn from(delta: Delta) -> Self {
         let data = if delta.value.len() == 0 { None } else { Some ( delta.value ) };
@@ -0,0 +1,265 @@
+//! # Metrics and health checks.
+//!
+//! An optional HTTP server for monitoring the worker, next to the IPC listener. <br>
+//! `/metrics` exports the counters and histograms of the tasks and of the ecalls in the Prometheus text format,
+//! with the estimated size of every contract in the DB and the status of the states, which are read when the metrics are scraped. <br>
+//! `/healthz` answers 200 while the enclave answers an ecall and the DB lock isn't poisoned,
+//! `/readyz` also reads the DB, so it waits for a request that holds it. Otherwise they answer 503,
+//! the body has the result of every check. <br>
+//! The checks run on their own threads, and if they don't end in `CHECK_TIMEOUT` the server answers 503 without waiting for them.
+
+use crate::common_u::errors::EnclaveFailError;
+use crate::db::{P2PCalls, SharedDB};
+use crate::esgx::equote;
+use crate::networking::messages::{IpcRequest, IpcResponse};
+use crate::networking::worker::Worker;
+use enigma_tools_m::utils::LockExpectMutex;
+use failure::Error;
+use futures::future;
+use futures::sync::oneshot;
+use futures::Future;
+use futures_cpupool::{Builder, CpuPool};
+use hex::ToHex;
+use hyper::service::service_fn;
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder};
+use serde_json::{Map, Value};
+use std::net::SocketAddr;
+use std::sync::{Mutex, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio_timer::Timeout;
+
+/// How long the health checks can take before the server answers that they failed.
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref TASKS: IntCounterVec =
+        register_int_counter_vec!("enigma_tasks_total", "The tasks executed, by type", &["type"]).unwrap();
+    static ref FAILED_TASKS: IntCounterVec =
+        register_int_counter_vec!("enigma_tasks_failed_total", "The tasks that failed or returned an error, by type", &["type"]).unwrap();
+    static ref TASK_GAS: HistogramVec = register_histogram_vec!(
+        "enigma_task_used_gas",
+        "The gas used by the tasks, by type",
+        &["type"],
+        prometheus::exponential_buckets(1_000.0, 4.0, 12).unwrap()
+    )
+    .unwrap();
+    static ref TASK_SECONDS: HistogramVec = register_histogram_vec!(
+        "enigma_task_duration_seconds",
+        "The time it took to run the tasks, by type",
+        &["type"],
+        prometheus::exponential_buckets(0.005, 2.0, 14).unwrap()
+    )
+    .unwrap();
+    static ref ECALL_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "enigma_ecall_errors_total",
+        "The failed ecalls of the requests, by EnclaveReturn and sgx status",
+        &["error", "status"]
+    )
+    .unwrap();
+    static ref CONTRACT_BYTES: IntGaugeVec =
+        register_int_gauge_vec!("enigma_db_contract_bytes", "The estimated size of every contract in the DB", &["address"]).unwrap();
+    static ref STATES_BUILT: IntGauge =
+        register_int_gauge!("enigma_ptt_states_built", "1 if the states were built since the last PTT or new deltas").unwrap();
+    // the health checks take turns on the enclave, so together they only need one thread of it.
+    static ref ENCLAVE_CHECK: Mutex<()> = Mutex::new(());
+}
+
+/// Measures a task, from when the worker started handling its request.
+pub struct TaskTimer {
+    task_type: &'static str,
+    start: Instant,
+}
+
+impl TaskTimer {
+    /// Starts measuring the request if it's a task.
+    pub fn start(request: &IpcRequest) -> Option<TaskTimer> {
+        let task_type = match request {
+            IpcRequest::DeploySecretContract { .. } => "DeploySecretContract",
+            IpcRequest::UpgradeSecretContract { .. } => "UpgradeSecretContract",
+            IpcRequest::ComputeTask { .. } => "ComputeTask",
+            _ => return None,
+        };
+        Some(TaskTimer { task_type, start: Instant::now() })
+    }
+
+    /// Records the task with its response.
+    pub fn observe(self, response: &IpcResponse) {
+        let elapsed = self.start.elapsed();
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+        TASK_SECONDS.with_label_values(&[self.task_type]).observe(seconds);
+        TASKS.with_label_values(&[self.task_type]).inc();
+        if let IpcResponse::FailedTask { .. } | IpcResponse::Error { .. } = response {
+            FAILED_TASKS.with_label_values(&[self.task_type]).inc();
+        }
+        if let Some(used_gas) = response.used_gas() {
+            TASK_GAS.with_label_values(&[self.task_type]).observe(used_gas as f64);
+        }
+    }
+}
+
+/// Counts the error of a request if it came from an ecall.
+pub fn record_error(error: &Error) {
+    if let Some(e) = error.downcast_ref::<EnclaveFailError>() {
+        let (err, status) = (format!("{:?}", e.err), format!("{:?}", e.status));
+        ECALL_ERRORS.with_label_values(&[err.as_str(), status.as_str()]).inc();
+    }
+}
+
+/// Refreshes the gauges that are read from the DB, they keep their values if a request holds the DB.
+/// The sizes are the estimates of the storage, so the contracts aren't read, and they're missing if it has none.
+fn refresh_db_gauges(db: &SharedDB) {
+    let db = match db.try_read() {
+        Ok(db) => db,
+        Err(_) => return,
+    };
+    STATES_BUILT.set(db.get_state_status() as i64);
+    CONTRACT_BYTES.reset();
+    // an empty DB has no addresses.
+    for address in db.get_all_addresses().unwrap_or_default() {
+        if let Ok(Some(size)) = db.estimated_contract_size(&address) {
+            CONTRACT_BYTES.with_label_values(&[address.to_hex().as_str()]).set(size as i64);
+        }
+    }
+}
+
+/// Returns all the metrics in the Prometheus text format.
+pub fn gather(worker: &Worker) -> Vec<u8> {
+    refresh_db_gauges(&worker.db);
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer).expect("Failed encoding the metrics");
+    buffer
+}
+
+/// Runs the health checks, or the readiness checks if `ready` is set.
+/// Returns whether all of them passed and the result of every check.
+pub fn check(worker: &Worker, ready: bool) -> (bool, Value) {
+    let enclave = {
+        let _turn = ENCLAVE_CHECK.lock_expect("Enclave check");
+        equote::get_register_signing_address(worker.eid).map(|_| ()).map_err(|e| e.to_string())
+    };
+    let db = match worker.db.try_read() {
+        Ok(db) => db.storage.namespaces().map(|_| ()).map_err(|e| e.to_string()),
+        Err(TryLockError::WouldBlock) if !ready => Ok(()),
+        Err(TryLockError::WouldBlock) => worker.db.read().storage.namespaces().map(|_| ()).map_err(|e| e.to_string()),
+        Err(TryLockError::Poisoned(_)) => Err("The DB lock is poisoned".to_string()),
+    };
+
+    let mut checks = Map::new();
+    let mut passed = true;
+    for (name, result) in vec![("enclave", enclave), ("db", db)] {
+        passed &= result.is_ok();
+        checks.insert(name.to_string(), Value::String(result.err().unwrap_or_else(|| "ok".to_string())));
+    }
+    (passed, Value::Object(checks))
+}
+
+/// The running metrics server, it's shut down when it's dropped.
+pub struct MetricsServer {
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl MetricsServer {
+    pub fn start(addr: &SocketAddr, worker: &Worker) -> Result<Self, Error> {
+        let worker = worker.clone();
+        // the checks can block on the enclave or the DB, so they don't run on the thread of the server.
+        let checks = Builder::new().pool_size(2).name_prefix("metrics-check-").create();
+        let server = Server::try_bind(addr)
+            .map_err(|e| format_err!("Failed starting the metrics server on {}: {}", addr, e))?
+            .serve(move || {
+                let (worker, checks) = (worker.clone(), checks.clone());
+                service_fn(move |request| respond(&worker, &checks, &request))
+            });
+        let (shutdown, signal) = oneshot::channel();
+        let server = server.with_graceful_shutdown(signal).map_err(|e| error!("The metrics server failed: {}", e));
+        thread::Builder::new().name("metrics".to_string()).spawn(move || hyper::rt::run(server))?;
+        info!("Metrics and health checks are served over HTTP on {}", addr);
+        Ok(MetricsServer { _shutdown: shutdown })
+    }
+}
+
+type ResponseFuture = Box<dyn Future<Item = Response<Body>, Error = hyper::Error> + Send>;
+
+fn respond(worker: &Worker, checks: &CpuPool, request: &Request<Body>) -> ResponseFuture {
+    if request.method() != Method::GET {
+        return Box::new(future::ok(empty_response(StatusCode::METHOD_NOT_ALLOWED)));
+    }
+    match request.uri().path() {
+        "/metrics" => Box::new(future::ok(response(StatusCode::OK, TextEncoder::new().format_type(), gather(worker)))),
+        path @ "/healthz" | path @ "/readyz" => {
+            let (worker, ready) = (worker.clone(), path == "/readyz");
+            let checked = checks.spawn_fn(move || Ok::<_, ()>(check(&worker, ready)));
+            // a check that didn't end yet is dropped, so it won't start if it's still waiting for a thread.
+            Box::new(Timeout::new(checked, CHECK_TIMEOUT).then(|result| {
+                let (status, checks) = match result {
+                    Ok((true, checks)) => (StatusCode::OK, checks),
+                    Ok((false, checks)) => (StatusCode::SERVICE_UNAVAILABLE, checks),
+                    Err(_) => {
+                        let timeout = ("timeout".to_string(), Value::String(format!("The checks didn't end in {:?}", CHECK_TIMEOUT)));
+                        (StatusCode::SERVICE_UNAVAILABLE, Value::Object(std::iter::once(timeout).collect()))
+                    }
+                };
+                Ok::<_, hyper::Error>(response(status, "application/json", checks.to_string().into_bytes()))
+            }))
+        }
+        _ => Box::new(future::ok(empty_response(StatusCode::NOT_FOUND))),
+    }
+}
+
+fn response(status: StatusCode, content_type: &str, body: Vec<u8>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .expect("Failed building the response")
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    Response::builder().status(status).body(Body::empty()).expect("Failed building the response")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::DB;
+
+    fn test_worker() -> Worker { Worker::new(DB::new_in_memory(), 0, "", 0, 1).unwrap() }
+
+    #[test]
+    fn test_task_metrics() {
+        let request: IpcRequest = serde_json::from_str(
+            r#"{"type": "ComputeTask", "input": {"encryptedArgs": "", "encryptedFn": "", "userDHKey": "", "gasLimit": 100, "contractAddress": "00"}}"#,
+        )
+        .unwrap();
+        assert!(TaskTimer::start(&IpcRequest::GetAllAddrs).is_none());
+        TaskTimer::start(&request).unwrap().observe(&IpcResponse::Error { msg: "failed".to_string() });
+
+        let metrics = String::from_utf8(gather(&test_worker())).unwrap();
+        assert!(metrics.contains(r#"enigma_tasks_total{type="ComputeTask"}"#));
+        assert!(metrics.contains(r#"enigma_tasks_failed_total{type="ComputeTask"}"#));
+        assert!(metrics.contains("enigma_ptt_states_built 1"));
+    }
+
+    #[test]
+    fn test_checks() {
+        // there's no enclave with the id 0.
+        let (passed, checks) = check(&test_worker(), true);
+        assert!(!passed);
+        assert_eq!(checks["db"], "ok");
+        assert_ne!(checks["enclave"], "ok");
+    }
+
+    #[test]
+    fn test_respond() {
+        let (worker, checks) = (test_worker(), CpuPool::new(1));
+        let status = |method: Method, path: &str| {
+            let request = Request::builder().method(method).uri(path).body(Body::empty()).unwrap();
+            respond(&worker, &checks, &request).wait().unwrap().status()
+        };
+        assert_eq!(status(Method::GET, "/metrics"), StatusCode::OK);
+        assert_eq!(status(Method::GET, "/healthz"), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(status(Method::POST, "/metrics"), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(status(Method::GET, "/other"), StatusCode::NOT_FOUND);
+    }
+}
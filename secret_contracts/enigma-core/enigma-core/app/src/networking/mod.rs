@@ -1,4 +1,11 @@
 pub mod ipc_listener;
 pub mod messages;
+pub mod metrics;
+pub mod rpc;
+pub mod task_cache;
+pub mod worker;
 
 pub use self::ipc_listener::IpcListener;
+pub use self::metrics::MetricsServer;
+pub use self::rpc::{RpcConfig, RpcServer, TaskSubscriptions};
+pub use self::worker::Worker;
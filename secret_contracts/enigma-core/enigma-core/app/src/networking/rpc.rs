@@ -0,0 +1,363 @@
+//! # JSON-RPC server.
+//!
+//! An optional JSON-RPC 2.0 front-end for the worker, over HTTP and WebSocket, next to the IPC listener. <br>
+//! Every `IpcRequest` is a method named after its `type` (e.g. `GetTip`, `ComputeTask`), the params are the other
+//! fields of the request by name, and the result is the `IpcResponse` the IPC listener would send, without an `id`. <br>
+//! Only the queries and the tasks are served by default, the methods that change the DB outside of a task or run
+//! the key management are only served if the server has an admin token, and they must be called with it in `authToken`. <br>
+//! A request whose handler failed (an `Error` response) is returned as a JSON-RPC error with the code -32000. <br>
+//! Over WebSocket, `subscribeTaskResults` with a `taskId` subscribes to the response of that task, including the tasks
+//! received over IPC, and `unsubscribeTaskResults` cancels the subscription. Subscribing to the responses of all the
+//! tasks requires the admin token in `authToken`. <br>
+//! Browsers can only connect from the origins in `RpcConfig::cors`, over both transports.
+
+use crate::networking::messages::{IpcRequest, IpcResponse};
+use crate::networking::worker::Worker;
+use failure::Error;
+use futures::{future, Future};
+use futures_cpupool::{Builder, CpuPool};
+use jsonrpc_core::{self as rpc, MetaIoHandler, Metadata, Params, Value};
+use jsonrpc_http_server::{self as http, AccessControlAllowOrigin, DomainsValidation};
+use jsonrpc_pubsub::{self as pubsub, typed::{Sink, Subscriber}, PubSubHandler, Session, SubscriptionId};
+use jsonrpc_ws_server::{self as ws, RequestContext};
+use serde_json::Map;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc, RwLock};
+
+/// The methods anyone can call, the queries and the tasks.
+pub const METHODS: &[&str] = &[
+    "GetRegistrationParams", "GetTip", "GetTips", "GetAllTips", "GetAllAddrs", "GetDelta", "GetDeltas", "GetContract",
+    "GetSnapshot", "NewTaskEncryptionKey", "DeploySecretContract", "UpgradeSecretContract", "ComputeTask", "InspectState",
+];
+
+/// The methods that are only served with an admin token, together with `METHODS` there's one for every type of `IpcRequest`.
+pub const ADMIN_METHODS: &[&str] = &[
+    "UpdateNewContract", "UpdateNewContractOnDeployment", "RemoveContract", "UpdateDeltas", "RemoveDeltas", "GetPTTRequest",
+    "PTTResponse", "SnapshotState", "UpdateSnapshot", "BackupDB", "VerifyDeltas",
+];
+
+/// The param the admin methods get the admin token in.
+const AUTH_TOKEN: &str = "authToken";
+
+/// The JSON-RPC error code of a request whose handler failed.
+const HANDLER_ERROR: i64 = -32000;
+/// The JSON-RPC error code of an admin request without the admin token.
+const UNAUTHORIZED: i64 = -32001;
+
+/// Where the JSON-RPC server listens, a transport is only started if it has an address.
+#[derive(Debug, Clone, Default)]
+pub struct RpcConfig {
+    pub http: Option<SocketAddr>,
+    pub ws: Option<SocketAddr>,
+    /// The origins browsers can send requests over HTTP and WebSocket from, `*` allows any origin.
+    pub cors: Vec<String>,
+    /// The token the admin methods must be called with, if `None` they aren't served.
+    pub admin_token: Option<String>,
+}
+
+/// The WebSocket subscriptions to the responses of tasks, a subscription can be limited to a single task.
+#[derive(Clone)]
+pub struct TaskSubscriptions {
+    next_id: Arc<AtomicU64>,
+    sinks: Arc<RwLock<HashMap<SubscriptionId, (Option<String>, Sink<Value>)>>>,
+    // sends the notifications, so a slow subscriber doesn't hold the task that finished.
+    sender: CpuPool,
+}
+
+impl Default for TaskSubscriptions {
+    fn default() -> Self {
+        TaskSubscriptions {
+            next_id: Arc::default(),
+            sinks: Arc::default(),
+            sender: Builder::new().pool_size(1).name_prefix("rpc-notify-").create(),
+        }
+    }
+}
+
+impl TaskSubscriptions {
+    fn subscribe(&self, subscriber: Subscriber<Value>, task_id: Option<String>) {
+        let id = SubscriptionId::Number(self.next_id.fetch_add(1, Ordering::SeqCst));
+        if let Ok(sink) = subscriber.assign_id(id.clone()) {
+            self.sinks.write().expect("The task subscriptions lock is poisoned").insert(id, (task_id, sink));
+        }
+    }
+
+    fn unsubscribe(&self, id: &SubscriptionId) -> bool {
+        self.sinks.write().expect("The task subscriptions lock is poisoned").remove(id).is_some()
+    }
+
+    /// Sends the response of a task to its subscribers in the background, it doesn't wait for them.
+    /// The subscriptions of closed connections are dropped when sending to them fails.
+    pub fn notify(&self, task_id: &str, response: &IpcResponse) {
+        let subscribers: Vec<_> = self
+            .sinks
+            .read()
+            .expect("The task subscriptions lock is poisoned")
+            .iter()
+            .filter(|(_, (filter, _))| filter.as_ref().map_or(true, |filter| filter == task_id))
+            .map(|(id, (_, sink))| (id.clone(), sink.clone()))
+            .collect();
+        if subscribers.is_empty() {
+            return;
+        }
+        let mut notification = Map::new();
+        notification.insert("taskId".to_string(), Value::String(task_id.to_string()));
+        notification.insert("response".to_string(), serde_json::to_value(response).expect("Failed serializing the response"));
+        let notification = Value::Object(notification);
+
+        for (id, sink) in subscribers {
+            let sinks = Arc::clone(&self.sinks);
+            let sent = sink.notify(Ok(notification.clone())).then(move |res| {
+                if res.is_err() {
+                    sinks.write().expect("The task subscriptions lock is poisoned").remove(&id);
+                }
+                Ok::<(), ()>(())
+            });
+            self.sender.spawn(sent).forget();
+        }
+    }
+}
+
+/// Runs `method` with `params` on the worker like the IPC listener runs the request of that type.
+pub fn call(worker: &Worker, method: &str, params: Params) -> rpc::BoxFuture<Value> {
+    let mut fields = match params {
+        Params::Map(fields) => fields,
+        Params::None => Map::new(),
+        Params::Array(ref values) if values.is_empty() => Map::new(),
+        Params::Array(_) => return Box::new(future::err(rpc::Error::invalid_params("The params must be an object with the fields of the request"))),
+    };
+    fields.remove(AUTH_TOKEN);
+    fields.insert("type".to_string(), Value::String(method.to_string()));
+    let request: IpcRequest = match serde_json::from_value(Value::Object(fields)) {
+        Ok(request) => request,
+        Err(e) => return Box::new(future::err(rpc::Error::invalid_params(e.to_string()))),
+    };
+
+    Box::new(worker.submit(request).and_then(|response| match response {
+        IpcResponse::Error { msg } => Err(rpc::Error { code: rpc::ErrorCode::ServerError(HANDLER_ERROR), message: msg, data: None }),
+        response => Ok(serde_json::to_value(response).expect("Failed serializing the response")),
+    }))
+}
+
+/// The running JSON-RPC servers, they're closed when it's dropped.
+pub struct RpcServer {
+    _http: Option<http::Server>,
+    _ws: Option<ws::Server>,
+}
+
+impl RpcServer {
+    pub fn start(config: &RpcConfig, worker: &Worker) -> Result<Self, Error> {
+        let http = match config.http {
+            Some(addr) => {
+                let mut io = MetaIoHandler::<()>::default();
+                add_methods(&mut io, worker, &config.admin_token);
+                let cors = config.cors.iter().map(|origin| AccessControlAllowOrigin::from(origin.as_str())).collect();
+                let server = http::ServerBuilder::new(io)
+                    .cors(DomainsValidation::AllowOnly(cors))
+                    .start_http(&addr)
+                    .map_err(|e| format_err!("Failed starting the JSON-RPC HTTP server on {}: {}", addr, e))?;
+                info!("JSON-RPC over HTTP is listening on {}", addr);
+                Some(server)
+            }
+            None => None,
+        };
+        let ws = match config.ws {
+            Some(addr) => {
+                let server = ws::ServerBuilder::with_meta_extractor(pubsub_handler(worker, &config.admin_token), |context: &RequestContext| {
+                    Arc::new(Session::new(context.sender()))
+                })
+                .allowed_origins(ws_origins(&config.cors))
+                .start(&addr)
+                .map_err(|e| format_err!("Failed starting the JSON-RPC WebSocket server on {}: {}", addr, e))?;
+                info!("JSON-RPC over WebSocket is listening on {}", addr);
+                Some(server)
+            }
+            None => None,
+        };
+        Ok(RpcServer { _http: http, _ws: ws })
+    }
+}
+
+/// The origins WebSocket connections are accepted from, the same as the CORS origins of HTTP.
+/// Only browsers send an origin, so other clients can always connect.
+fn ws_origins(cors: &[String]) -> DomainsValidation<ws::Origin> {
+    if cors.iter().any(|origin| origin == "*") {
+        DomainsValidation::Disabled
+    } else {
+        DomainsValidation::AllowOnly(cors.iter().map(|origin| ws::Origin::from(origin.as_str())).collect())
+    }
+}
+
+/// Runs an admin method if `params` has the admin token.
+fn call_admin(worker: &Worker, admin_token: &str, method: &str, params: Params) -> rpc::BoxFuture<Value> {
+    if !is_authorized(&params, admin_token) {
+        return Box::new(future::err(unauthorized()));
+    }
+    call(worker, method, params)
+}
+
+/// Checks that `params` has the admin token.
+fn is_authorized(params: &Params, admin_token: &str) -> bool {
+    match params {
+        Params::Map(fields) => match fields.get(AUTH_TOKEN) {
+            Some(Value::String(token)) => constant_time_eq(token.as_bytes(), admin_token.as_bytes()),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn unauthorized() -> rpc::Error {
+    rpc::Error { code: rpc::ErrorCode::ServerError(UNAUTHORIZED), message: "Unauthorized".to_string(), data: None }
+}
+
+/// Compares the tokens without leaking how much of them matched through the time it takes.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn add_methods<M: Metadata>(io: &mut MetaIoHandler<M>, worker: &Worker, admin_token: &Option<String>) {
+    for method in METHODS {
+        let worker = worker.clone();
+        io.add_method(method, move |params: Params| call(&worker, method, params));
+    }
+    if let Some(admin_token) = admin_token {
+        for method in ADMIN_METHODS {
+            let (worker, admin_token) = (worker.clone(), admin_token.clone());
+            io.add_method(method, move |params: Params| call_admin(&worker, &admin_token, method, params));
+        }
+    }
+}
+
+fn pubsub_handler(worker: &Worker, admin_token: &Option<String>) -> PubSubHandler<Arc<Session>> {
+    let mut io = PubSubHandler::new(MetaIoHandler::default());
+    add_methods(&mut io, worker, admin_token);
+    let (subscriptions, unsubscriptions) = (worker.tasks.clone(), worker.tasks.clone());
+    let admin_token = admin_token.clone();
+    io.add_subscription(
+        "taskResult",
+        ("subscribeTaskResults", move |params: Params, _: Arc<Session>, subscriber: pubsub::Subscriber| {
+            let subscriber = Subscriber::new(subscriber);
+            match task_filter(&params, &admin_token) {
+                Ok(task_id) => subscriptions.subscribe(subscriber, task_id),
+                Err(e) => {
+                    let _ = subscriber.reject(e);
+                }
+            }
+        }),
+        ("unsubscribeTaskResults", move |id: SubscriptionId, _: Option<Arc<Session>>| -> rpc::Result<Value> {
+            Ok(Value::Bool(unsubscriptions.unsubscribe(&id)))
+        }),
+    );
+    io
+}
+
+/// The `taskId` a subscription is limited to, a subscription to all the tasks (`None`) needs the admin token
+/// since the responses of the tasks of other users would be sent to it.
+fn task_filter(params: &Params, admin_token: &Option<String>) -> rpc::Result<Option<String>> {
+    let task_id = match params {
+        Params::Map(fields) => match fields.get("taskId") {
+            None => None,
+            Some(Value::String(task_id)) => Some(task_id.clone()),
+            Some(_) => return Err(rpc::Error::invalid_params("taskId must be a string")),
+        },
+        Params::None => None,
+        Params::Array(values) if values.is_empty() => None,
+        Params::Array(_) => return Err(rpc::Error::invalid_params("The params must be an object")),
+    };
+    match (task_id, admin_token) {
+        (Some(task_id), _) => Ok(Some(task_id)),
+        (None, Some(admin_token)) if is_authorized(params, admin_token) => Ok(None),
+        (None, _) => Err(unauthorized()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempfile;
+    use self::tempfile::TempDir;
+    use super::*;
+    use crate::db::tests::create_test_db;
+
+    fn test_worker() -> (Worker, Option<TempDir>) {
+        let (db, dir) = create_test_db();
+        (Worker::new(db, 0, "", 0, 2).unwrap(), dir)
+    }
+
+    #[test]
+    fn test_call_method() {
+        let (worker, _dir) = test_worker();
+        let result = call(&worker, "GetAllAddrs", Params::None).wait().unwrap();
+        assert_eq!(result["type"], "GetAllAddrs");
+        assert_eq!(result["result"]["addresses"], Value::Array(vec![]));
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let (worker, _dir) = test_worker();
+        let err = call(&worker, "GetTip", Params::Array(vec![Value::from("00")])).wait().unwrap_err();
+        assert_eq!(err.code, rpc::ErrorCode::InvalidParams);
+        let err = call(&worker, "GetTip", Params::None).wait().unwrap_err();
+        assert_eq!(err.code, rpc::ErrorCode::InvalidParams);
+
+        let mut params = Map::new();
+        params.insert("input".to_string(), Value::from("not hex"));
+        let err = call(&worker, "GetTip", Params::Map(params)).wait().unwrap_err();
+        assert_eq!(err.code, rpc::ErrorCode::ServerError(HANDLER_ERROR));
+    }
+
+    #[test]
+    fn test_admin_methods() {
+        let (worker, _dir) = test_worker();
+        let err = call_admin(&worker, "secret", "VerifyDeltas", Params::None).wait().unwrap_err();
+        assert_eq!(err.code, rpc::ErrorCode::ServerError(UNAUTHORIZED));
+
+        let mut params = Map::new();
+        params.insert(AUTH_TOKEN.to_string(), Value::from("wrong"));
+        let err = call_admin(&worker, "secret", "VerifyDeltas", Params::Map(params.clone())).wait().unwrap_err();
+        assert_eq!(err.code, rpc::ErrorCode::ServerError(UNAUTHORIZED));
+
+        params.insert(AUTH_TOKEN.to_string(), Value::from("secret"));
+        let result = call_admin(&worker, "secret", "VerifyDeltas", Params::Map(params)).wait().unwrap();
+        assert_eq!(result["type"], "VerifyDeltas");
+
+        // the admin methods aren't served without an admin token
+        let mut io = MetaIoHandler::<()>::default();
+        add_methods(&mut io, &worker, &None);
+        let request = r#"{"jsonrpc": "2.0", "method": "RemoveContract", "params": {"address": "00"}, "id": 1}"#;
+        let response: Value = serde_json::from_str(&io.handle_request_sync(request, ()).unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], rpc::ErrorCode::MethodNotFound.code());
+    }
+
+    #[test]
+    fn test_task_filter() {
+        let admin_token = Some("secret".to_string());
+        let mut params = Map::new();
+        params.insert("taskId".to_string(), Value::from("0x1234"));
+        assert_eq!(task_filter(&Params::Map(params.clone()), &None).unwrap(), Some("0x1234".to_string()));
+        params.insert("taskId".to_string(), Value::from(5));
+        assert!(task_filter(&Params::Map(params), &admin_token).is_err());
+
+        // only the admin can subscribe to the responses of all the tasks
+        let err = task_filter(&Params::None, &None).unwrap_err();
+        assert_eq!(err.code, rpc::ErrorCode::ServerError(UNAUTHORIZED));
+        let mut params = Map::new();
+        params.insert(AUTH_TOKEN.to_string(), Value::from("wrong"));
+        assert!(task_filter(&Params::Map(params.clone()), &admin_token).is_err());
+        params.insert(AUTH_TOKEN.to_string(), Value::from("secret"));
+        assert_eq!(task_filter(&Params::Map(params), &admin_token).unwrap(), None);
+    }
+
+    #[test]
+    fn test_ws_origins() {
+        match ws_origins(&["*".to_string()]) {
+            DomainsValidation::Disabled => (),
+            _ => panic!("\"*\" should allow any origin"),
+        }
+        match ws_origins(&["https://enigma.co".to_string()]) {
+            DomainsValidation::AllowOnly(origins) => assert_eq!(origins, vec![ws::Origin::from("https://enigma.co")]),
+            _ => panic!("The origins should be limited"),
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! # Results of the completed tasks.
+//!
+//! The p2p layer retries a task when its response timed out, and running it again would store a second delta. <br>
+//! So the result of a task is stored by its contract and task id with the hash of its input, in the same batch as its delta,
+//! a retry of the task gets the same signed result, and a task id that is reused with another input is rejected. <br>
+//! The results of the last tasks are also kept in memory, so a retry doesn't read the DB. <br>
+//! Only the tasks that carry a task id are stored, and an `Error` response isn't, so the task can still be retried.
+
+use crate::common_u::errors;
+use crate::db::{BatchOp, CRUDInterface, DeltaKey, Stype, DB};
+use crate::networking::messages::{IpcRequest, IpcResponse, IpcTask};
+use enigma_tools_m::utils::LockExpectMutex;
+use enigma_types::{ContractAddress, Hash256};
+use failure::Error;
+use hex::ToHex;
+use lru_cache::LruCache;
+use serde_json;
+use std::sync::{Arc, Mutex};
+
+/// The number of task results that are kept in memory, the oldest ones are dropped first.
+pub const TASK_CACHE_SIZE: usize = 1024;
+
+/// A task as it's kept in the cache.
+pub struct CachedTask {
+    address: ContractAddress,
+    task_id: Hash256,
+    input_hash: Hash256,
+}
+
+impl CachedTask {
+    /// Returns `None` if the request isn't a task with a task id,
+    /// or if its address or task id are invalid, then the handler of the request returns the error.
+    pub fn new(request: &IpcRequest) -> Option<CachedTask> {
+        match request {
+            IpcRequest::DeploySecretContract { input } | IpcRequest::UpgradeSecretContract { input } | IpcRequest::ComputeTask { input } => {
+                CachedTask::from_task(input)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn from_task(input: &IpcTask) -> Option<CachedTask> {
+        let address = ContractAddress::from_hex(&input.address).ok()?;
+        let task_id = Hash256::from_hex(input.task_id.as_ref()?).ok()?;
+        Some(CachedTask { address, task_id, input_hash: input.input_hash() })
+    }
+
+    fn key(&self) -> DeltaKey { DeltaKey::new(self.address, Stype::Task(self.task_id)) }
+
+    /// The write that stores the result of the task, it's committed with the writes of the task.
+    /// It fails the commit if the task id was already used, so the task doesn't store a second delta.
+    pub fn result_write(&self, response: &IpcResponse) -> BatchOp<DeltaKey, Vec<u8>> {
+        let value = serde_json::to_vec(&(self.input_hash, response)).expect("Failed serializing the result of the task");
+        BatchOp::Create(self.key(), value)
+    }
+}
+
+#[derive(Clone)]
+pub struct TaskCache(Arc<Mutex<LruCache<(ContractAddress, Hash256), (Hash256, IpcResponse)>>>);
+
+impl Default for TaskCache {
+    fn default() -> Self { TaskCache(Arc::new(Mutex::new(LruCache::new(TASK_CACHE_SIZE)))) }
+}
+
+impl TaskCache {
+    /// Returns the result of the task if it already completed, or an error if its task id was used with another input.
+    pub fn get(&self, db: &DB, task: &CachedTask) -> Result<Option<IpcResponse>, Error> {
+        let mut cache = self.0.lock_expect("TaskCache");
+        let cached = cache.get_mut(&(task.address, task.task_id)).cloned();
+        let (input_hash, response) = match cached {
+            Some(cached) => cached,
+            None => match db.read(&task.key()) {
+                Ok(value) => {
+                    let stored: (Hash256, IpcResponse) = serde_json::from_slice(&value)?;
+                    cache.insert((task.address, task.task_id), stored.clone());
+                    stored
+                }
+                Err(e) => return errors::is_db_err_type(e).map(|_| None),
+            },
+        };
+        if input_hash != task.input_hash {
+            bail!("The task id {} of the contract {} was already used by a task with another input", task.task_id.to_hex(), task.address.to_hex())
+        }
+        Ok(Some(response))
+    }
+
+    pub fn insert(&self, task: CachedTask, response: &IpcResponse) {
+        if let IpcResponse::Error { .. } = response {
+            return;
+        }
+        self.0.lock_expect("TaskCache").insert((task.address, task.task_id), (task.input_hash, response.clone()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{SharedDB, TaskWrites};
+    use crate::networking::messages::IpcResults;
+
+    fn compute_request(task_id: Option<&str>, args: &str) -> IpcRequest {
+        compute_request_at(task_id, args, 0, false)
+    }
+
+    fn compute_request_at(task_id: Option<&str>, args: &str, block_number: u64, profile: bool) -> IpcRequest {
+        let task_id = task_id.map(|id| format!(r#", "taskID": "{}""#, id)).unwrap_or_default();
+        let request = format!(
+            r#"{{"type": "ComputeTask", "input": {{"encryptedArgs": "{}", "encryptedFn": "", "userDHKey": "", "gasLimit": 100, "contractAddress": "{}", "blockNumber": {}, "profile": {}{}}}}}"#,
+            args,
+            "11".repeat(32),
+            block_number,
+            profile,
+            task_id
+        );
+        serde_json::from_str(&request).unwrap()
+    }
+
+    fn failed_task(used_gas: u64) -> IpcResponse {
+        let result = IpcResults::FailedTask { output: "".to_string(), reverted: true, used_gas, signature: "".to_string() };
+        IpcResponse::FailedTask { result }
+    }
+
+    #[test]
+    fn test_retry_gets_the_result() {
+        let db = DB::new_in_memory();
+        let cache = TaskCache::default();
+        let task_id = "22".repeat(32);
+        let task = CachedTask::new(&compute_request(Some(&task_id), "aa")).unwrap();
+        assert!(cache.get(&db, &task).unwrap().is_none());
+        cache.insert(task, &failed_task(42));
+
+        let retry = CachedTask::new(&compute_request(Some(&task_id), "aa")).unwrap();
+        assert_eq!(cache.get(&db, &retry).unwrap().unwrap().used_gas(), Some(42));
+
+        // the task is retried at a later block, and asks for a profile this time.
+        let retry = CachedTask::new(&compute_request_at(Some(&task_id), "aa", 7, true)).unwrap();
+        assert_eq!(cache.get(&db, &retry).unwrap().unwrap().used_gas(), Some(42));
+
+        let conflict = CachedTask::new(&compute_request(Some(&task_id), "bb")).unwrap();
+        assert!(cache.get(&db, &conflict).is_err());
+    }
+
+    #[test]
+    fn test_retry_after_restart() {
+        let db = SharedDB::new(DB::new_in_memory());
+        let task_id = "44".repeat(32);
+        let task = CachedTask::new(&compute_request(Some(&task_id), "aa")).unwrap();
+        let mut writes = TaskWrites::default();
+        writes.push(task.result_write(&failed_task(42)));
+        writes.commit(&db).unwrap();
+
+        // a new cache is empty, like after the node restarted.
+        let cache = TaskCache::default();
+        let retry = CachedTask::new(&compute_request(Some(&task_id), "aa")).unwrap();
+        assert_eq!(cache.get(&db.read(), &retry).unwrap().unwrap().used_gas(), Some(42));
+        let conflict = CachedTask::new(&compute_request(Some(&task_id), "bb")).unwrap();
+        assert!(TaskCache::default().get(&db.read(), &conflict).is_err());
+
+        // a task can't store its result again under the same id.
+        let mut writes = TaskWrites::default();
+        writes.push(conflict.result_write(&failed_task(7)));
+        assert!(writes.commit(&db).is_err());
+    }
+
+    #[test]
+    fn test_uncached_tasks() {
+        assert!(CachedTask::new(&compute_request(None, "aa")).is_none());
+        assert!(CachedTask::new(&IpcRequest::GetAllAddrs).is_none());
+
+        let db = DB::new_in_memory();
+        let cache = TaskCache::default();
+        let task_id = "33".repeat(32);
+        cache.insert(CachedTask::new(&compute_request(Some(&task_id), "aa")).unwrap(), &IpcResponse::Error { msg: "failed".to_string() });
+        assert!(cache.get(&db, &CachedTask::new(&compute_request(Some(&task_id), "aa")).unwrap()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,131 @@
+//! # Worker pool.
+//!
+//! The requests of every transport run on a pool of threads, so a slow task doesn't block the queries
+//! or the tasks of other contracts. <br>
+//! The tasks of a contract run one after the other in the order they were submitted, so their deltas are stored in order,
+//! and the DB is shared between the requests as described in `db::shared`. <br>
+//! A task can still call a contract that another running task calls, then the task that stores its writes second
+//! fails without storing any of them, since the delta it created was already stored.
+
+use crate::db::{SharedDB, DB};
+use crate::networking::ipc_listener::handle_request;
+use crate::networking::messages::{IpcRequest, IpcResponse};
+use crate::networking::rpc::TaskSubscriptions;
+use crate::networking::task_cache::TaskCache;
+use enigma_tools_m::utils::LockExpectMutex;
+use enigma_types::ContractAddress;
+use failure::Error;
+use futures::future::{self, Shared};
+use futures::sync::oneshot;
+use futures::Future;
+use futures_cpupool::CpuPool;
+use sgx_types::sgx_enclave_id_t;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Every request running on the pool needs its own thread in the enclave, and the health checks need one more,
+/// so this is one less than the `TCSNum` of `Enclave.config.xml`. <br>
+/// The threads share the `HeapMaxSize` of 64MB, which keeps the enclave in the EPC.
+/// The memory of a contract grows by the pages it uses, up to 128 pages (8MB),
+/// so a task that only calls a few contracts uses a few MB of the heap with the states and deltas,
+/// but one that calls `MAX_CALL_DEPTH` (8) contracts deep, each with all of its memory, can take most of it.
+pub const MAX_CONCURRENCY: usize = 4;
+
+type TaskEnd = Shared<oneshot::Receiver<()>>;
+
+/// The end of the last task submitted for each contract.
+#[derive(Clone, Default)]
+struct ContractQueues(Arc<Mutex<HashMap<ContractAddress, TaskEnd>>>);
+
+impl ContractQueues {
+    /// Queues a task of the contract, returns the end of the previous task and the sender of the end of this one.
+    fn enqueue(&self, address: ContractAddress) -> (Option<TaskEnd>, oneshot::Sender<()>) {
+        let (end, receiver) = oneshot::channel();
+        let previous = self.0.lock_expect("ContractQueues").insert(address, receiver.shared());
+        (previous, end)
+    }
+}
+
+/// What the handlers of the requests need, shared by the IPC listener and the JSON-RPC server.
+#[derive(Clone)]
+pub struct Worker {
+    pub db: Arc<SharedDB>,
+    pub eid: sgx_enclave_id_t,
+    pub spid: String,
+    pub retries: u32,
+    pub tasks: TaskSubscriptions,
+    pub results: TaskCache,
+    queues: ContractQueues,
+    pool: CpuPool,
+}
+
+impl Worker {
+    /// Creates a worker running up to `concurrency` requests at the same time.
+    pub fn new(db: DB, eid: sgx_enclave_id_t, spid: &str, retries: u32, concurrency: usize) -> Result<Worker, Error> {
+        if concurrency == 0 || concurrency > MAX_CONCURRENCY {
+            bail!("The concurrency must be between 1 and {}, got {}", MAX_CONCURRENCY, concurrency);
+        }
+        Ok(Worker {
+            db: Arc::new(SharedDB::new(db)),
+            eid,
+            spid: spid.to_string(),
+            retries,
+            tasks: TaskSubscriptions::default(),
+            results: TaskCache::default(),
+            queues: ContractQueues::default(),
+            pool: CpuPool::new(concurrency),
+        })
+    }
+
+    /// Runs the request on the pool, a task starts only after the tasks submitted before it for the same contract ended.
+    pub fn submit<E: Send + 'static>(&self, request: IpcRequest) -> Box<dyn Future<Item = IpcResponse, Error = E> + Send> {
+        let worker = self.clone();
+        let address = request.task_address();
+        let job = move || Ok(handle_request(&worker, request));
+        match address {
+            Some(address) => {
+                let (previous, end) = self.queues.enqueue(address);
+                // the previous task ended when it sent its end or when it was dropped.
+                let previous: Box<dyn Future<Item = (), Error = ()> + Send> = match previous {
+                    Some(previous) => Box::new(previous.then(|_| Ok(()))),
+                    None => Box::new(future::ok(())),
+                };
+                let pool = self.pool.clone();
+                Box::new(previous.then(move |_| pool.spawn_fn(job)).then(move |response| {
+                    let _ = end.send(());
+                    response
+                }))
+            }
+            None => Box::new(self.pool.spawn_fn(job)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_concurrency_bounds() {
+        assert!(Worker::new(DB::new_in_memory(), 0, "", 0, 0).is_err());
+        assert!(Worker::new(DB::new_in_memory(), 0, "", 0, MAX_CONCURRENCY + 1).is_err());
+        assert!(Worker::new(DB::new_in_memory(), 0, "", 0, MAX_CONCURRENCY).is_ok());
+    }
+
+    #[test]
+    fn test_contract_queues_order() {
+        let queues = ContractQueues::default();
+        let (first, first_end) = queues.enqueue(ContractAddress::from([1u8; 32]));
+        let (second, second_end) = queues.enqueue(ContractAddress::from([1u8; 32]));
+        let (other, _other_end) = queues.enqueue(ContractAddress::from([2u8; 32]));
+        assert!(first.is_none() && other.is_none());
+
+        first_end.send(()).unwrap();
+        assert!(second.unwrap().wait().is_ok());
+
+        // a task that was dropped without sending its end doesn't block the next one.
+        let (third, _third_end) = queues.enqueue(ContractAddress::from([1u8; 32]));
+        drop(second_end);
+        assert!(third.unwrap().wait().is_err());
+    }
+}
@@ -3,23 +3,90 @@ pub mod wasm;
 use crate::common_u::errors::EnclaveFailError;
 use crate::db::{Delta, DeltaKey, Stype};
 use std::{fmt, convert::TryFrom};
-use enigma_types::{EnclaveReturn, ExecuteResult, ContractAddress};
+use enigma_crypto::hash::{prepare_hash_multiple, Keccak256};
+use enigma_types::{EnclaveReturn, ExecuteResult, ContractAddress, Hash256};
 use failure::Error;
+use rmp_serde;
 use sgx_types::*;
 
+/// An event emitted by the contract, the `data` is encrypted with the user's DH key if `encrypted` is set.
+/// This mirrors `ContractEvent` in the runtime, which is how the enclave serializes the events.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ContractEvent {
+    pub topic: Vec<u8>,
+    pub data: Vec<u8>,
+    pub encrypted: bool,
+}
+
+impl ContractEvent {
+    /// The hash of the events that the enclave signs with the result of the task, `H(H(topic, data, encrypted) for every event)`.
+    pub fn keccak256_all(events: &[ContractEvent]) -> Hash256 {
+        let hashes: Vec<Hash256> = events
+            .iter()
+            .map(|event| prepare_hash_multiple(&[&event.topic[..], &event.data[..], &[event.encrypted as u8][..]]).keccak256())
+            .collect();
+        prepare_hash_multiple(&hashes).keccak256()
+    }
+}
+
+/// The number of calls to a host function and the gas they were charged.
+/// This mirrors `HostFunctionProfile` in the runtime.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct HostFunctionProfile {
+    pub name: String,
+    pub calls: u64,
+    pub gas: u64,
+}
+
+/// The gas and resources used by the execution, returned if the task asked for profiling.
+/// This mirrors `ExecutionProfile` in the runtime, it's what the sealed profile decrypts to with the user's key.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ExecutionProfile {
+    pub instructions_gas: u64,
+    pub host_functions: Vec<HostFunctionProfile>,
+    pub host_calls: u64,
+    pub state_bytes_read: u64,
+    pub state_bytes_written: u64,
+    pub peak_memory_pages: u32,
+}
+
+/// A delta of another contract called by the executed contract.
+/// This mirrors `EncryptedPatch` in the runtime, which is how the enclave serializes the deltas.
+#[derive(Deserialize, Debug)]
+struct CalleeDelta {
+    data: Vec<u8>,
+    contract_address: ContractAddress,
+    index: u32,
+}
+
+impl From<CalleeDelta> for Delta {
+    fn from(callee: CalleeDelta) -> Delta {
+        Delta { key: DeltaKey::new(callee.contract_address, Stype::Delta(callee.index)), value: callee.data }
+    }
+}
+
 #[derive(Clone)]
 pub struct WasmTaskResult {
     pub bytecode: Box<[u8]>,
-    pub output: Box<[u8]>, // On Deploy this will be the exeCode
+    pub output: Box<[u8]>, // On Deploy and Upgrade this will be the exeCode
     pub delta: Delta,
     pub eth_payload: Box<[u8]>,
     pub eth_contract_addr: [u8; 20],
+    pub events: Vec<ContractEvent>,
+    /// The deltas of the other contracts called by the executed contract, which were already stored by the enclave.
+    pub callee_deltas: Vec<Delta>,
+    /// The `ExecutionProfile` serialized and encrypted with the user's key, if the task asked for it.
+    pub profile: Option<Box<[u8]>>,
+    /// The executed function is a view, so there is no delta to persist.
+    pub view: bool,
     pub signature: [u8; 65],
     pub used_gas: u64,
 }
 
 pub struct WasmTaskFailure {
     pub output: Box<[u8]>,
+    /// The contract reverted the task, so `output` is its encrypted error payload and not an error message.
+    pub reverted: bool,
     pub signature: [u8; 65],
     pub used_gas: u64,
 }
@@ -38,6 +105,10 @@ impl Default for WasmTaskResult {
             delta: Default::default(),
             eth_payload: Default::default(),
             eth_contract_addr: Default::default(),
+            events: Default::default(),
+            callee_deltas: Default::default(),
+            profile: None,
+            view: false,
             signature: [0u8; 65],
             used_gas: Default::default()
         }
@@ -48,6 +119,7 @@ impl Default for  WasmTaskFailure {
     fn default() -> WasmTaskFailure {
         WasmTaskFailure {
             output: Default::default(),
+            reverted: false,
             signature: [0u8; 65],
             used_gas: Default::default()
         }
@@ -62,6 +134,10 @@ impl fmt::Debug for WasmTaskResult {
         debug_builder.field("delta", &self.delta);
         debug_builder.field("eth_payload", &self.eth_payload);
         debug_builder.field("eth_contract_addr", &self.eth_contract_addr);
+        debug_builder.field("events", &self.events);
+        debug_builder.field("callee_deltas", &self.callee_deltas);
+        debug_builder.field("profile", &self.profile);
+        debug_builder.field("view", &self.view);
         debug_builder.field("signature", &(&self.signature[..]));
         debug_builder.field("used_gas", &self.used_gas);
         debug_builder.finish()
@@ -72,6 +148,7 @@ impl fmt::Debug for WasmTaskFailure{
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut debug_builder = f.debug_struct("WasmTaskFailure");
         debug_builder.field("output", &self.output);
+        debug_builder.field("reverted", &self.reverted);
         debug_builder.field("signature", &(&self.signature[..]));
         debug_builder.field("used_gas", &self.used_gas);
         debug_builder.finish()
@@ -92,6 +169,7 @@ impl TryFrom<(ExecuteResult, ContractAddress, EnclaveReturn, sgx_status_t)> for
         if exec.2 == EnclaveReturn::TaskFailure {
             let mut result: WasmTaskFailure = Default::default();
             result.output = get_output(exec.0)?;
+            result.reverted = exec.0.reverted;
             result.signature = exec.0.signature;
             result.used_gas = exec.0.used_gas;
             Ok(WasmResult::WasmTaskFailure(result))
@@ -100,7 +178,8 @@ impl TryFrom<(ExecuteResult, ContractAddress, EnclaveReturn, sgx_status_t)> for
             Err(EnclaveFailError { err: exec.2, status: exec.3 }.into())
         }
         else {
-            if exec.0.ethereum_payload_ptr.is_null() || exec.0.delta_ptr.is_null() {
+            if exec.0.ethereum_payload_ptr.is_null() || exec.0.delta_ptr.is_null() || exec.0.events_ptr.is_null()
+                || exec.0.callee_deltas_ptr.is_null() || exec.0.profile_ptr.is_null() {
                 bail!("One of the pointers in ExecuteResult is null: {:?}", exec.0);
             }
 
@@ -109,6 +188,7 @@ impl TryFrom<(ExecuteResult, ContractAddress, EnclaveReturn, sgx_status_t)> for
             result.output = get_output(exec.0)?;
             result.signature = exec.0.signature;
             result.used_gas = exec.0.used_gas;
+            result.view = exec.0.view;
 
             // If there is no call to any ethereum contract in the execution, then
             // `eth_contract_addr` is all zeros
@@ -120,6 +200,22 @@ impl TryFrom<(ExecuteResult, ContractAddress, EnclaveReturn, sgx_status_t)> for
             let payload = unsafe { Box::from_raw(box_payload_ptr) };
             result.eth_payload = *payload;
 
+            // If no events were emitted, then `events_ptr` points to an empty serialized list
+            let box_events_ptr = exec.0.events_ptr as *mut Box<[u8]>;
+            let events = unsafe { Box::from_raw(box_events_ptr) };
+            result.events = rmp_serde::from_slice(&events)?;
+
+            // If no other contract was changed, then `callee_deltas_ptr` points to an empty serialized list
+            let box_callee_deltas_ptr = exec.0.callee_deltas_ptr as *mut Box<[u8]>;
+            let callee_deltas = unsafe { Box::from_raw(box_callee_deltas_ptr) };
+            let callee_deltas: Vec<CalleeDelta> = rmp_serde::from_slice(&callee_deltas)?;
+            result.callee_deltas = callee_deltas.into_iter().map(Delta::from).collect();
+
+            // If the task didn't ask for profiling, then `profile_ptr` points to empty array []
+            let box_profile_ptr = exec.0.profile_ptr as *mut Box<[u8]>;
+            let profile = unsafe { Box::from_raw(box_profile_ptr) };
+            result.profile = if profile.is_empty() { None } else { Some(*profile) };
+
             // If state was not changed by the execution (which means that delta is empty),
             // then `delta_ptr` points to empty array []
             let box_ptr = exec.0.delta_ptr as *mut Box<[u8]>;
@@ -1,17 +1,18 @@
-use enigma_types::{ContractAddress, EnclaveReturn, ExecuteResult, PubKey, RawPointer, traits::SliceCPtr};
+use enigma_types::{ContractAddress, EnclaveReturn, ExecuteResult, PubKey, RawPointer, TaskContext, traits::SliceCPtr};
 use super::WasmResult;
-use crate::db::DB;
+use crate::db::DbAccess;
 use std::convert::TryInto;
 use failure::Error;
 use sgx_types::*;
-use crate::auto_ffi::{ecall_deploy, ecall_execute};
+use crate::auto_ffi::{ecall_deploy, ecall_execute, ecall_upgrade};
 
 #[logfn(DEBUG)]
-pub fn deploy(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], constructor: &[u8], args: &[u8],
-              contract_address: &ContractAddress, user_pubkey: &PubKey, gas_limit: u64)-> Result<WasmResult, Error> {
+pub fn deploy<'a>(db: impl Into<DbAccess<'a>>, eid: sgx_enclave_id_t,  bytecode: &[u8], constructor: &[u8], args: &[u8],
+              contract_address: &ContractAddress, user_pubkey: &PubKey, gas_limit: u64, context: &TaskContext)-> Result<WasmResult, Error> {
     let mut retval = EnclaveReturn::Success;
     let mut result = ExecuteResult::default();
-    let db_ptr = unsafe { RawPointer::new_mut(db) };
+    let mut access: DbAccess = db.into();
+    let db_ptr = unsafe { RawPointer::new_mut(&mut access) };
 
     let status = unsafe {
         ecall_deploy(eid,
@@ -25,6 +26,7 @@ pub fn deploy(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], constructor:
                      contract_address,
                      user_pubkey.as_ptr() as _,
                      &gas_limit as *const u64,
+                     context,
                      &db_ptr as *const RawPointer,
                      &mut result)
     };
@@ -32,11 +34,12 @@ pub fn deploy(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], constructor:
 }
 
 #[logfn(DEBUG)]
-pub fn execute(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], callable: &[u8], args: &[u8],
-               user_pubkey: &PubKey, contract_address: &ContractAddress, gas_limit: u64)-> Result<WasmResult,Error> {
+pub fn execute<'a>(db: impl Into<DbAccess<'a>>, eid: sgx_enclave_id_t,  bytecode: &[u8], callable: &[u8], args: &[u8],
+               user_pubkey: &PubKey, contract_address: &ContractAddress, gas_limit: u64, context: &TaskContext)-> Result<WasmResult,Error> {
     let mut retval = EnclaveReturn::Success;
     let mut result = ExecuteResult::default();
-    let db_ptr = unsafe { RawPointer::new_mut(db) };
+    let mut access: DbAccess = db.into();
+    let db_ptr = unsafe { RawPointer::new_mut(&mut access) };
 
     let status = unsafe {
         ecall_execute(eid,
@@ -50,6 +53,7 @@ pub fn execute(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], callable: &
                       user_pubkey.as_ptr() as _,
                       contract_address,
                       &gas_limit as *const u64,
+                      context,
                       &db_ptr as *const RawPointer,
                       &mut result)
     };
@@ -57,24 +61,56 @@ pub fn execute(db: &mut DB, eid: sgx_enclave_id_t,  bytecode: &[u8], callable: &
     (result, *contract_address, retval, status).try_into()
 }
 
+/// Upgrade the contract from `prev_code` to the pre-deployed `bytecode`, running the `migration` of the new code.
+/// On success the output is the new bytecode of the contract.
+#[logfn(DEBUG)]
+pub fn upgrade<'a>(db: impl Into<DbAccess<'a>>, eid: sgx_enclave_id_t, bytecode: &[u8], prev_code: &[u8], migration: &[u8], args: &[u8],
+               contract_address: &ContractAddress, user_pubkey: &PubKey, gas_limit: u64, context: &TaskContext)-> Result<WasmResult, Error> {
+    let mut retval = EnclaveReturn::Success;
+    let mut result = ExecuteResult::default();
+    let mut access: DbAccess = db.into();
+    let db_ptr = unsafe { RawPointer::new_mut(&mut access) };
+
+    let status = unsafe {
+        ecall_upgrade(eid,
+                      &mut retval,
+                      bytecode.as_c_ptr(),
+                      bytecode.len(),
+                      prev_code.as_c_ptr(),
+                      prev_code.len(),
+                      migration.as_c_ptr() as *const u8,
+                      migration.len(),
+                      args.as_c_ptr(),
+                      args.len(),
+                      contract_address,
+                      user_pubkey.as_ptr() as _,
+                      &gas_limit as *const u64,
+                      context,
+                      &db_ptr as *const RawPointer,
+                      &mut result)
+    };
+    (result, *contract_address, retval, status).try_into()
+}
+
 #[cfg(test)]
 mod tests {
     extern crate ethabi;
     extern crate cross_test_utils;
 
     use self::cross_test_utils::{generate_contract_address, sign_message, generate_user_address, get_bytecode_from_path};
-    use crate::esgx::general::init_enclave_wrapper;
+    use crate::esgx::{equote, general::init_enclave_wrapper};
     use crate::km_u::tests::exchange_keys;
     use crate::km_u::tests::instantiate_encryption_key;
-    use crate::db::{DB, tests::create_test_db};
+    use crate::db::{CRUDInterface, DB, DeltaKey, P2PCalls, SnapshotConfig, Stype, tests::create_test_db};
+    use crate::km_u;
     use crate::wasm_u::wasm;
     use self::ethabi::{Contract, Token, token::{LenientTokenizer, Tokenizer}};
-    use enigma_types::{ContractAddress, DhKey, PubKey};
-    use enigma_crypto::symmetric;
+    use enigma_types::{ContractAddress, DhKey, PubKey, ResultStatus, TaskContext};
+    use enigma_crypto::{KeyPair, hash::{prepare_hash_multiple, Keccak256}, symmetric};
     use hex::FromHex;
     use sgx_types::*;
     use std::fs::File;
-    use wasm_u::{WasmResult, WasmTaskResult};
+    use wasm_u::{ContractEvent, ExecutionProfile, WasmResult, WasmTaskResult};
     use self::ethabi::Uint;
 
     pub const GAS_LIMIT: u64 = 100_000_000;
@@ -92,7 +128,7 @@ mod tests {
         let wasm_code = get_bytecode_from_path(test_path);
         println!("Bytecode size: {}KB\n", wasm_code.len() / 1024);
 
-        wasm::deploy(db, eid, &wasm_code, constructor, args, &contract_address, &user_pubkey, GAS_LIMIT).expect("Deploy Failed")
+        wasm::deploy(db, eid, &wasm_code, constructor, args, &contract_address, &user_pubkey, GAS_LIMIT, &TaskContext::default()).expect("Deploy Failed")
     }
 
     fn compile_deploy_execute(db: &mut DB,
@@ -132,7 +168,8 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &contract_address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed").unwrap_result();
 
         (enclave, exe_code, result, shared_key)
@@ -187,13 +224,467 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &contract_address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed").unwrap_result();
 
         assert!(result.used_gas > 10_000);
 
     }
 
+    #[test]
+    fn test_task_context() {
+        let (mut db, _dir) = create_test_db();
+        let address = generate_contract_address();
+        let (enclave, deploy_res) = compile_deploy_contract_execute(
+            &mut db,
+            "../../examples/eng_wasm_contracts/task_context",
+            address,
+            "construct()",
+            &[],
+        );
+        let context = TaskContext { block_number: 4_242_424, task_id: [7u8; 32].into(), has_task_id: true, ..Default::default() };
+        let (_, pre_delta): (DeltaKey, Vec<u8>) = db.get_tip(&address).unwrap();
+
+        let mut execute = |func: &str, context: &TaskContext| {
+            let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+            let encrypted_callable = symmetric::encrypt(func.as_bytes(), &shared_key).unwrap();
+            let encrypted_args = symmetric::encrypt(&ethabi::encode(&[]), &shared_key).unwrap();
+            let result = wasm::execute(
+                &mut db,
+                enclave.geteid(),
+                &deploy_res.output,
+                &encrypted_callable,
+                &encrypted_args,
+                &keys.get_pubkey(),
+                &address,
+                GAS_LIMIT,
+                context
+            ).expect("Execution failed");
+            // The context the contract read is signed as part of the inputs
+            let block_number = context.block_number.to_be_bytes();
+            let inputs_hash = prepare_hash_multiple(&[&encrypted_callable[..], &encrypted_args[..], &address[..], &keys.get_pubkey()[..],
+                                                      &block_number[..], &context.task_id[..]]).keccak256();
+            (result, shared_key, keys.get_pubkey(), inputs_hash)
+        };
+        let mut call = |func: &str, output_type: ethabi::ParamType| {
+            let (result, shared_key, pubkey, _) = execute(func, &context);
+            let output = symmetric::decrypt(&result.unwrap_result().output, &shared_key).unwrap();
+            (ethabi::decode(&[output_type], &output).unwrap()[0].clone(), pubkey)
+        };
+
+        let (block_number, _) = call("block_number()", ethabi::ParamType::Uint(256));
+        assert_eq!(block_number.to_uint().unwrap(), context.block_number.into());
+
+        let (contract_address, _) = call("contract_address()", ethabi::ParamType::FixedBytes(32));
+        assert_eq!(contract_address.to_fixed_bytes().unwrap(), address.to_vec());
+
+        let (task_id, _) = call("task_id()", ethabi::ParamType::FixedBytes(32));
+        assert_eq!(task_id.to_fixed_bytes().unwrap(), context.task_id.to_vec());
+
+        let (user_pubkey, pubkey) = call("user_pubkey()", ethabi::ParamType::Bytes);
+        assert_eq!(user_pubkey.to_bytes().unwrap(), pubkey.to_vec());
+
+        // A worker can't give the contract another block number than the one the result is verified with
+        let (result, _, _, inputs_hash) = execute("block_number()", &context);
+        let result = result.unwrap_result();
+        let to_sign: &[&[u8]] = &[
+            &*deploy_res.output.keccak256(),
+            &*inputs_hash,
+            &*pre_delta.keccak256(),
+            &[0u8; 32],
+            &*result.output.keccak256(),
+            &GAS_LIMIT.to_be_bytes(),
+            &result.used_gas.to_be_bytes(),
+            &result.eth_payload,
+            &result.eth_contract_addr,
+            &[ResultStatus::Ok as u8],
+        ];
+        let signer = KeyPair::recover(&prepare_hash_multiple(to_sign), result.signature).unwrap().keccak256();
+        assert_eq!(signer[12..], equote::get_register_signing_address(enclave.geteid()).unwrap()[..]);
+
+        // A task without an id doesn't get an all zeros id
+        match execute("task_id()", &TaskContext { has_task_id: false, ..context }) {
+            (WasmResult::WasmTaskFailure(failure), _, _, _) => assert!(failure.reverted),
+            (WasmResult::WasmTaskResult(_), _, _, _) => panic!("The task wasn't reverted"),
+        }
+    }
+
+    #[test]
+    fn test_execution_profile() {
+        let (mut db, _dir) = create_test_db();
+        let address = generate_contract_address();
+        let (enclave, deploy_res) = compile_deploy_contract_execute(
+            &mut db,
+            "../../examples/eng_wasm_contracts/simplest",
+            address,
+            "construct(uint)",
+            &[Token::Uint(1.into())],
+        );
+        assert!(deploy_res.profile.is_none());
+
+        let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+        let encrypted_callable = symmetric::encrypt(b"addition(uint256,uint256)", &shared_key).unwrap();
+        let encrypted_args = symmetric::encrypt(&ethabi::encode(&[Token::Uint(1.into()), Token::Uint(2.into())]), &shared_key).unwrap();
+        let context = TaskContext { profile: true, ..Default::default() };
+        let result = wasm::execute(
+            &mut db,
+            enclave.geteid(),
+            &deploy_res.output,
+            &encrypted_callable,
+            &encrypted_args,
+            &keys.get_pubkey(),
+            &address,
+            GAS_LIMIT,
+            &context
+        ).expect("Execution failed").unwrap_result();
+
+        let sealed_profile = result.profile.expect("The task asked for a profile");
+        let profile: ExecutionProfile = rmp_serde::from_slice(&symmetric::decrypt(&sealed_profile, &shared_key).unwrap()).unwrap();
+        assert!(profile.instructions_gas > 0);
+        assert!(profile.peak_memory_pages > 0);
+        assert_eq!(profile.state_bytes_written, 1);
+        let write_state = profile.host_functions.iter().find(|function| function.name == "write_state").unwrap();
+        assert_eq!(write_state.calls, 1);
+        let host_gas: u64 = profile.host_functions.iter().map(|function| function.gas).sum();
+        assert!(profile.instructions_gas + host_gas <= result.used_gas);
+    }
+
+    #[test]
+    fn test_contract_events() {
+        let (mut db, _dir) = create_test_db();
+        let address = generate_contract_address();
+        let (enclave, exe_code, result, shared_key) = compile_deploy_execute(
+            &mut db,
+            "../../examples/eng_wasm_contracts/events",
+            address,
+            "construct()",
+            &[],
+            "transfer(uint256)",
+            &[Token::Uint(17.into())]
+        );
+
+        assert_eq!(result.events.len(), 2);
+        let (transfer, receipt) = (&result.events[0], &result.events[1]);
+
+        assert_eq!(transfer.topic, b"Transfer".to_vec());
+        assert_eq!(transfer.data, b"17".to_vec());
+        assert!(!transfer.encrypted);
+
+        assert_eq!(receipt.topic, b"Receipt".to_vec());
+        assert!(receipt.encrypted);
+        assert_eq!(symmetric::decrypt(&receipt.data, &shared_key).unwrap(), b"\"confidential\"".to_vec());
+
+        // The events are signed with the result, so they can't be changed outside the enclave
+        let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+        let callable = symmetric::encrypt(b"transfer(uint256)", &shared_key).unwrap();
+        let args = symmetric::encrypt(&ethabi::encode(&[Token::Uint(5.into())]), &shared_key).unwrap();
+        let (_, pre_delta): (DeltaKey, Vec<u8>) = db.get_tip(&address).unwrap();
+        let result = wasm::execute(
+            &mut db,
+            enclave.geteid(),
+            &exe_code,
+            &callable,
+            &args,
+            &keys.get_pubkey(),
+            &address,
+            GAS_LIMIT,
+            &TaskContext::default()
+        ).expect("Execution failed").unwrap_result();
+
+        // The events are the only optional field signed for this task, and it doesn't change the state so its delta hash is zeros
+        let inputs_hash = prepare_hash_multiple(&[&callable[..], &args[..], &address[..], &keys.get_pubkey()[..]]).keccak256();
+        let to_sign: &[&[u8]] = &[
+            &*exe_code.keccak256(),
+            &*inputs_hash,
+            &*pre_delta.keccak256(),
+            &[0u8; 32],
+            &*result.output.keccak256(),
+            &GAS_LIMIT.to_be_bytes(),
+            &result.used_gas.to_be_bytes(),
+            &result.eth_payload,
+            &result.eth_contract_addr,
+            &*ContractEvent::keccak256_all(&result.events),
+            &[ResultStatus::Ok as u8],
+        ];
+        let signer = KeyPair::recover(&prepare_hash_multiple(to_sign), result.signature).unwrap().keccak256();
+        assert_eq!(signer[12..], equote::get_register_signing_address(enclave.geteid()).unwrap()[..]);
+
+        let mut events = result.events.clone();
+        events[0].data = b"18".to_vec();
+        assert_ne!(ContractEvent::keccak256_all(&events), ContractEvent::keccak256_all(&result.events));
+    }
+
+    #[test]
+    fn test_revert_on_err() {
+        let (mut db, _dir) = create_test_db();
+        let address = generate_contract_address();
+        let (enclave, contract_code, result, shared_key) = compile_deploy_execute(
+            &mut db,
+            "../../examples/eng_wasm_contracts/revert",
+            address,
+            "construct(uint256)",
+            &[Token::Uint(10.into())],
+            "withdraw(uint256)",
+            &[Token::Uint(4.into())]
+        );
+        let output = symmetric::decrypt(&result.output, &shared_key).unwrap();
+        assert_eq!(ethabi::decode(&[ethabi::ParamType::Uint(256)], &output).unwrap(), vec![Token::Uint(6.into())]);
+
+        let execute = |db: &mut DB, func: &str, args: &[Token]| {
+            let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+            let encrypted_callable = symmetric::encrypt(func.as_bytes(), &shared_key).unwrap();
+            let encrypted_args = symmetric::encrypt(&ethabi::encode(args), &shared_key).unwrap();
+            let result = wasm::execute(
+                db,
+                enclave.geteid(),
+                &contract_code,
+                &encrypted_callable,
+                &encrypted_args,
+                &keys.get_pubkey(),
+                &address,
+                GAS_LIMIT,
+                &TaskContext::default()
+            ).expect("Execution failed");
+            let inputs_hash = prepare_hash_multiple(&[&encrypted_callable[..], &encrypted_args[..], &address[..], &keys.get_pubkey()[..]]);
+            (result, shared_key, inputs_hash.keccak256())
+        };
+
+        let (tip_key, tip_delta): (DeltaKey, Vec<u8>) = db.get_tip(&address).unwrap();
+        let (failure, shared_key, inputs_hash) = match execute(&mut db, "withdraw(uint256)", &[Token::Uint(100.into())]) {
+            (WasmResult::WasmTaskFailure(failure), shared_key, inputs_hash) => (failure, shared_key, inputs_hash),
+            (WasmResult::WasmTaskResult(_), _, _) => panic!("The task wasn't reverted"),
+        };
+        assert!(failure.reverted);
+        let error = symmetric::decrypt(&failure.output, &shared_key).unwrap();
+        assert_eq!(ethabi::decode(&[ethabi::ParamType::String], &error).unwrap(), vec![Token::String("insufficient balance".to_string())]);
+
+        // The signature covers the revert, so it can't be passed off as another failure
+        let to_sign: &[&[u8]] = &[
+            &*inputs_hash,
+            &*contract_code.keccak256(),
+            &GAS_LIMIT.to_be_bytes(),
+            &failure.used_gas.to_be_bytes(),
+            &[failure.reverted as u8],
+            &[ResultStatus::Failure as u8],
+        ];
+        let signer = KeyPair::recover(&prepare_hash_multiple(to_sign), failure.signature).unwrap().keccak256();
+        assert_eq!(signer[12..], equote::get_register_signing_address(enclave.geteid()).unwrap()[..]);
+
+        // The write done before returning `Err` was discarded, no delta was stored for it
+        let (key, delta): (DeltaKey, Vec<u8>) = db.get_tip(&address).unwrap();
+        assert_eq!((key, delta), (tip_key, tip_delta));
+        let (result, shared_key, _) = execute(&mut db, "balance()", &[]);
+        let output = symmetric::decrypt(&result.unwrap_result().output, &shared_key).unwrap();
+        assert_eq!(ethabi::decode(&[ethabi::ParamType::Uint(256)], &output).unwrap(), vec![Token::Uint(6.into())]);
+    }
+
+    #[test]
+    fn test_upgrade_contract() {
+        let (mut db, _dir) = create_test_db();
+        let address = generate_contract_address();
+        let admin_key = Token::String("admin".to_string());
+        let new_code = get_bytecode_from_path("../../examples/eng_wasm_contracts/upgradeable_v2");
+        let approval_args = [Token::FixedBytes(new_code.keccak256().to_vec()), admin_key.clone()];
+
+        let (enclave, contract_code, result, shared_key) = compile_deploy_execute(
+            &mut db,
+            "../../examples/eng_wasm_contracts/upgradeable",
+            address,
+            "construct(string)",
+            &[admin_key.clone()],
+            "increment()",
+            &[]
+        );
+        let output = symmetric::decrypt(&result.output, &shared_key).unwrap();
+        assert_eq!(ethabi::decode(&[ethabi::ParamType::Uint(64)], &output).unwrap(), vec![Token::Uint(1.into())]);
+
+        let execute = |db: &mut DB, code: &[u8], func: &str, args: &[Token]| {
+            let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+            let encrypted_callable = symmetric::encrypt(func.as_bytes(), &shared_key).unwrap();
+            let encrypted_args = symmetric::encrypt(&ethabi::encode(args), &shared_key).unwrap();
+            let result = wasm::execute(
+                db,
+                enclave.geteid(),
+                code,
+                &encrypted_callable,
+                &encrypted_args,
+                &keys.get_pubkey(),
+                &address,
+                GAS_LIMIT,
+                &TaskContext::default()
+            ).expect("Execution failed");
+            (result, shared_key)
+        };
+        let upgrade = |db: &mut DB, prev_code: &[u8]| {
+            let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+            let encrypted_migration = symmetric::encrypt(b"migrate()", &shared_key).unwrap();
+            let encrypted_args = symmetric::encrypt(&ethabi::encode(&[]), &shared_key).unwrap();
+            wasm::upgrade(
+                db,
+                enclave.geteid(),
+                &new_code,
+                prev_code,
+                &encrypted_migration,
+                &encrypted_args,
+                &address,
+                &keys.get_pubkey(),
+                GAS_LIMIT,
+                &TaskContext::default()
+            ).expect("Upgrade failed")
+        };
+
+        // The contract has to approve the upgrade, and only the admin can approve it
+        if let WasmResult::WasmTaskResult(_) = upgrade(&mut db, &contract_code) {
+            panic!("The upgrade wasn't approved");
+        }
+        let wrong_key_args = [approval_args[0].clone(), Token::String("guess".to_string())];
+        let (result, _) = execute(&mut db, &contract_code, "upgrade(bytes32,string)", &wrong_key_args);
+        if let WasmResult::WasmTaskResult(_) = result {
+            panic!("The upgrade was approved without the admin key");
+        }
+        let (result, _) = execute(&mut db, &contract_code, "upgrade(bytes32,string)", &approval_args);
+        result.unwrap_result();
+
+        let upgraded = upgrade(&mut db, &contract_code).unwrap_result();
+        assert_ne!(upgraded.output, contract_code);
+
+        // The state was kept, and the migration ran with the new code
+        let (result, shared_key) = execute(&mut db, &upgraded.output, "increment()", &[]);
+        let output = symmetric::decrypt(&result.unwrap_result().output, &shared_key).unwrap();
+        assert_eq!(ethabi::decode(&[ethabi::ParamType::Uint(64)], &output).unwrap(), vec![Token::Uint(3.into())]);
+        let (result, shared_key) = execute(&mut db, &upgraded.output, "version()", &[]);
+        let output = symmetric::decrypt(&result.unwrap_result().output, &shared_key).unwrap();
+        assert_eq!(ethabi::decode(&[ethabi::ParamType::Uint(32)], &output).unwrap(), vec![Token::Uint(2.into())]);
+
+        // The approval was consumed by the upgrade
+        if let WasmResult::WasmTaskResult(_) = upgrade(&mut db, &upgraded.output) {
+            panic!("The upgrade was approved twice");
+        }
+    }
+
+    #[test]
+    fn test_call_contract() {
+        let (mut db, _dir) = create_test_db();
+        let counter = generate_contract_address();
+        let caller = generate_contract_address();
+        let enclave = init_enclave_wrapper().unwrap();
+        instantiate_encryption_key(vec![counter, caller], enclave.geteid());
+
+        let deploy = |db: &mut DB, path: &str, address: &ContractAddress, constructor: &str, args: &[Token]| {
+            let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+            let encrypted_construct = symmetric::encrypt(constructor.as_bytes(), &shared_key).unwrap();
+            let encrypted_args = symmetric::encrypt(&ethabi::encode(args), &shared_key).unwrap();
+            let code = get_bytecode_from_path(path);
+            let result = wasm::deploy(&mut *db, enclave.geteid(), &code, &encrypted_construct, &encrypted_args, address,
+                                      &keys.get_pubkey(), GAS_LIMIT, &TaskContext::default()).expect("Deploy failed");
+            let exe_code = result.unwrap_result().output;
+            // The called contracts are loaded from the DB, like the node stores them after deploying
+            db.create(&DeltaKey::new(*address, Stype::ByteCode), &exe_code).unwrap();
+            exe_code
+        };
+        let execute = |db: &mut DB, code: &[u8], address: &ContractAddress, func: &str, args: &[Token]| {
+            let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+            let encrypted_callable = symmetric::encrypt(func.as_bytes(), &shared_key).unwrap();
+            let encrypted_args = symmetric::encrypt(&ethabi::encode(args), &shared_key).unwrap();
+            let result = wasm::execute(db, enclave.geteid(), code, &encrypted_callable, &encrypted_args, &keys.get_pubkey(),
+                                       address, GAS_LIMIT, &TaskContext::default()).expect("Execution failed");
+            (result, shared_key)
+        };
+
+        let counter_code = deploy(&mut db, "../../examples/eng_wasm_contracts/upgradeable", &counter, "construct(string)",
+                                  &[Token::String("admin".to_string())]);
+        let caller_code = deploy(&mut db, "../../examples/eng_wasm_contracts/caller", &caller, "construct()", &[]);
+
+        let (result, shared_key) = execute(&mut db, &caller_code, &caller, "increment_twice(bytes32)", &[Token::FixedBytes(counter.to_vec())]);
+        let result = result.unwrap_result();
+        let output = symmetric::decrypt(&result.output, &shared_key).unwrap();
+        assert_eq!(ethabi::decode(&[ethabi::ParamType::Uint(64)], &output).unwrap(), vec![Token::Uint(2.into())]);
+        // Both calls are committed as a single delta of the counter
+        assert_eq!(result.callee_deltas.len(), 1);
+        assert_eq!(result.callee_deltas[0].key, DeltaKey::new(counter, Stype::Delta(1)));
+
+        let (result, shared_key) = execute(&mut db, &counter_code, &counter, "counter()", &[]);
+        let output = symmetric::decrypt(&result.unwrap_result().output, &shared_key).unwrap();
+        assert_eq!(ethabi::decode(&[ethabi::ParamType::Uint(64)], &output).unwrap(), vec![Token::Uint(2.into())]);
+
+        // A contract can't call itself, and the failed call fails the whole task
+        let (result, _) = execute(&mut db, &caller_code, &caller, "increment_twice(bytes32)", &[Token::FixedBytes(caller.to_vec())]);
+        if let WasmResult::WasmTaskResult(_) = result {
+            panic!("The contract called itself");
+        }
+    }
+
+    #[test]
+    fn test_snapshot_and_prune() {
+        let (mut db, _dir) = create_test_db();
+        let address = generate_contract_address();
+        db.set_snapshot_config(SnapshotConfig { interval: None, retention: 1 });
+        let (enclave, code, _, _) = compile_deploy_execute(
+            &mut db,
+            "../../examples/eng_wasm_contracts/upgradeable",
+            address,
+            "construct(string)",
+            &[Token::String("admin".to_string())],
+            "increment()",
+            &[]
+        );
+        let execute = |db: &mut DB, func: &str| {
+            let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+            let encrypted_callable = symmetric::encrypt(func.as_bytes(), &shared_key).unwrap();
+            let encrypted_args = symmetric::encrypt(&ethabi::encode(&[]), &shared_key).unwrap();
+            let result = wasm::execute(db, enclave.geteid(), &code, &encrypted_callable, &encrypted_args, &keys.get_pubkey(),
+                                       &address, GAS_LIMIT, &TaskContext::default()).expect("Execution failed");
+            let output = symmetric::decrypt(&result.unwrap_result().output, &shared_key).unwrap();
+            ethabi::decode(&[ethabi::ParamType::Uint(64)], &output).unwrap()
+        };
+        execute(&mut db, "increment()");
+        execute(&mut db, "increment()");
+
+        // The deploy is delta 0, so the snapshot includes deltas 0 to 3 and only the last one is kept
+        assert_eq!(km_u::snapshot_state(&mut db, enclave.geteid(), address).unwrap(), 3);
+        assert_eq!(db.prune_deltas(&address).unwrap(), 3);
+        let err = db.get_deltas(DeltaKey::new(address, Stype::Delta(0)), DeltaKey::new(address, Stype::Delta(4))).unwrap_err();
+        assert!(err.to_string().contains("pruned"));
+
+        // Without the state it's built from the snapshot, instead of from the pruned deltas
+        db.delete(&DeltaKey::new(address, Stype::State)).unwrap();
+        assert!(km_u::ptt_build_state(&mut db, enclave.geteid()).unwrap().is_empty());
+        assert_eq!(execute(&mut db, "counter()"), vec![Token::Uint(3.into())]);
+    }
+
+    #[test]
+    fn test_verify_snapshot() {
+        let (mut db, _dir) = create_test_db();
+        let address = generate_contract_address();
+        let (enclave, code, _, _) = compile_deploy_execute(
+            &mut db,
+            "../../examples/eng_wasm_contracts/upgradeable",
+            address,
+            "construct(string)",
+            &[Token::String("admin".to_string())],
+            "increment()",
+            &[]
+        );
+        assert_eq!(km_u::snapshot_state(&mut db, enclave.geteid(), address).unwrap(), 1);
+        let (delta_index, snapshot) = db.get_latest_snapshot(&address).unwrap();
+        km_u::verify_snapshot(&mut db, enclave.geteid(), address, &snapshot, delta_index).unwrap();
+
+        // The snapshot is still valid once the next delta is built on it
+        let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+        let encrypted_callable = symmetric::encrypt(b"increment()", &shared_key).unwrap();
+        let encrypted_args = symmetric::encrypt(&ethabi::encode(&[]), &shared_key).unwrap();
+        wasm::execute(&mut db, enclave.geteid(), &code, &encrypted_callable, &encrypted_args, &keys.get_pubkey(),
+                      &address, GAS_LIMIT, &TaskContext::default()).expect("Execution failed").unwrap_result();
+        km_u::verify_snapshot(&mut db, enclave.geteid(), address, &snapshot, delta_index).unwrap();
+
+        // But not as a snapshot of the deltas after it, and not if it was changed
+        assert!(km_u::verify_snapshot(&mut db, enclave.geteid(), address, &snapshot, delta_index + 1).is_err());
+        let mut changed = snapshot.clone();
+        changed[0] ^= 1;
+        assert!(km_u::verify_snapshot(&mut db, enclave.geteid(), address, &changed, delta_index).is_err());
+    }
+
     #[test]
     fn test_charge_for_write() {
         let (mut db, _dir) = create_test_db();
@@ -222,7 +713,8 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed").unwrap_result();
 
         assert_eq!(used_gas_for_write_new_value - result.used_gas, 3);
@@ -240,7 +732,8 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed").unwrap_result();
 
         assert!(used_gas_for_write_new_value - result.used_gas >= 1);
@@ -275,7 +768,8 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed").unwrap_result();
 
         let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
@@ -289,7 +783,8 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed").unwrap_result();
 
         encoded_output = symmetric::decrypt(&result.output, &shared_key).unwrap();
@@ -348,7 +843,8 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &contract_address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed");
 
         if let WasmResult::WasmTaskResult(v) = result {
@@ -403,7 +899,8 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed").unwrap_result();
 
         let encoded_output = symmetric::decrypt(&result.output, &shared_key).unwrap();
@@ -411,6 +908,56 @@ mod tests {
         assert_eq!(decoded_output.clone().to_uint().unwrap().as_u64(), 0);
     }
 
+    #[test]
+    fn test_custom_types() {
+        use self::ethabi::ParamType;
+        let (mut db, _dir) = create_test_db();
+        let address = generate_contract_address();
+
+        let (enclave, contract_code, result, shared_key) = compile_deploy_execute(
+            &mut db,
+            "../../examples/eng_wasm_contracts/custom_types",
+            address,
+            "construct()",
+            &[],
+            "circle(uint64,uint64,uint64)",
+            &[Token::Uint(1.into()), Token::Uint(2.into()), Token::Uint(3.into())]
+        );
+        // Shape::Circle is the variant index followed by the fields, and the center is a nested Point
+        let encoded_output = symmetric::decrypt(&result.output, &shared_key).unwrap();
+        let shape = ethabi::decode(&[ParamType::Bytes], &encoded_output).unwrap()[0].clone().to_bytes().unwrap();
+        let circle = ethabi::decode(&[ParamType::Uint(32), ParamType::Bytes, ParamType::Uint(64)], &shape).unwrap();
+        assert_eq!(circle[0].clone().to_uint().unwrap().as_u32(), 1);
+        assert_eq!(circle[2].clone().to_uint().unwrap().as_u64(), 3);
+        let center = ethabi::decode(&[ParamType::Uint(64), ParamType::Uint(64)], &circle[1].clone().to_bytes().unwrap()).unwrap();
+        assert_eq!(center, vec![Token::Uint(1.into()), Token::Uint(2.into())]);
+
+        // Shape::Polygon(vec![Point { x: 1, y: 1 }, Point { x: 2, y: 2 }], None)
+        let points: Vec<Token> = (1..=2).map(|i| Token::Bytes(ethabi::encode(&[Token::Uint(i.into()), Token::Uint(i.into())]))).collect();
+        let vec_of_points = ethabi::encode(&[&[Token::Uint(2.into())], &points[..]].concat());
+        let none = ethabi::encode(&[Token::Bool(false)]);
+        let polygon = ethabi::encode(&[Token::Uint(2.into()), Token::Bytes(vec_of_points), Token::Bytes(none)]);
+
+        let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
+        let encrypted_callable = symmetric::encrypt(b"count_points(bytes)", &shared_key).unwrap();
+        let encrypted_args = symmetric::encrypt(&ethabi::encode(&[Token::Bytes(polygon)]), &shared_key).unwrap();
+        let result = wasm::execute(
+            &mut db,
+            enclave.geteid(),
+            &contract_code,
+            &encrypted_callable,
+            &encrypted_args,
+            &keys.get_pubkey(),
+            &address,
+            GAS_LIMIT,
+            &TaskContext::default()
+        ).expect("Execution failed").unwrap_result();
+
+        let encoded_output = symmetric::decrypt(&result.output, &shared_key).unwrap();
+        let decoded_output = &ethabi::decode(&[ParamType::Uint(64)], &encoded_output).unwrap()[0];
+        assert_eq!(decoded_output.clone().to_uint().unwrap().as_u64(), 2);
+    }
+
     #[test]
     fn test_sc_encryption() {
         let (mut db, _dir) = create_test_db();
@@ -427,6 +974,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_sc_encryption_with_aad() {
+        let (mut db, _dir) = create_test_db();
+        let message = b"Enigma";
+
+        compile_deploy_execute(
+            &mut db,
+            "../../examples/eng_wasm_contracts/encryption",
+            generate_contract_address(),
+            "construct()",
+            &[],
+            "encrypt_decrypt_with_aad(bytes,bytes)",
+            &[Token::Bytes(message.to_vec()), Token::Bytes(b"record 1".to_vec())]
+        );
+    }
+
     #[test]
     fn test_write_simple() {
         let (mut db, _dir) = create_test_db();
@@ -601,7 +1164,8 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed").unwrap_result();
 
         // deserialization of result
@@ -643,7 +1207,8 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed").unwrap_result();
 
         let result_balance_decrypted = symmetric::decrypt(&result_balance.output, &shared_key).unwrap();
@@ -691,7 +1256,8 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed");
 
         let (keys, shared_key, _, _) = exchange_keys(enclave.geteid());
@@ -705,7 +1271,8 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed").unwrap_result();
 
         let result_balance_decrypted = symmetric::decrypt(&result_balance.output, &shared_key).unwrap();
@@ -722,7 +1289,8 @@ mod tests {
             &encrypted_args,
             &keys.get_pubkey(),
             &address,
-            GAS_LIMIT
+            GAS_LIMIT,
+            &TaskContext::default()
         ).expect("Execution failed").unwrap_result();
 
         let result_allowance_decrypted = symmetric::decrypt(&result_allowance.output, &shared_key).unwrap();
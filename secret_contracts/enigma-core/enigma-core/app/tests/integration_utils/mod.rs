@@ -31,27 +31,37 @@ use self::ethabi::{Token};
 use self::enigma_crypto::{asymmetric::KeyPair, symmetric};
 use self::enigma_types::Hash256;
 use self::rand::{thread_rng, Rng};
-use app::db::DB;
+use app::db::{Backend, SnapshotConfig, DB};
 use self::tempfile::TempDir;
+use std::env;
 
+/// The tests run against the backend in `ENIGMA_TEST_DB_BACKEND`, or RocksDB if it isn't set.
 /// It's important to save TempDir too, because when it gets dropped the directory will be removed.
 pub fn create_test_db() -> (DB, TempDir) {
+    let backend: Backend = env::var("ENIGMA_TEST_DB_BACKEND").map(|backend| backend.parse().unwrap()).unwrap_or_default();
     let tempdir = tempfile::tempdir().unwrap();
-    let db = DB::new(tempdir.path(), true).unwrap();
+    let db = DB::open(tempdir.path(), backend, true).unwrap();
     (db, tempdir)
 }
 
 pub fn run_core(port: &'static str) {
+    run_core_with_snapshots(port, SnapshotConfig::default());
+}
+
+/// Runs the core with the states snapshotted and the deltas pruned as configured.
+pub fn run_core_with_snapshots(port: &'static str, config: SnapshotConfig) {
     thread::spawn(move || {
         let enclave = esgx::general::init_enclave_wrapper().expect("[-] Init Enclave Failed");
         let eid = enclave.geteid();
 
         let (mut db, _datadir) = create_test_db();
+        db.set_snapshot_config(config);
         let server = IpcListener::new(&format!("tcp://*:{}", port));
         let spid = "B0335FD3BC1CCA8F804EB98A6420592D";
         let retries = 10;
+        let worker = Worker::new(db, eid, spid, retries, 4).unwrap();
         server
-            .run(move |multi| ipc_listener::handle_message(&mut db, multi, spid, eid, retries))
+            .run(4, move |multi| ipc_listener::handle_message(&worker, multi))
             .wait()
             .unwrap();
 
@@ -115,6 +125,11 @@ pub fn get_compute_msg(task_id: &str, callable: &str, args: &str, user_pubkey: &
     "encryptedFn": callable, "userDHKey": user_pubkey, "gasLimit": gas_limit, "contractAddress": con_addr}})
 }
 
+pub fn get_inspect_state_msg(addr: &str, prefix: &str, values: bool, user_sig: &[u8]) -> Value {
+    json!({"id": &generate_job_id(), "type": "InspectState", "input": { "address": addr, "prefix": prefix,
+    "values": values, "userSig": user_sig.to_hex()}})
+}
+
 pub fn get_get_tips_msg(input: &[String]) -> Value {
     json!({"id": &generate_job_id(), "type": "GetTips", "input": input.to_vec()})
 }
@@ -171,8 +186,13 @@ pub fn run_ptt_round(port: &'static str, addrs: Vec<ContractAddress>) -> Value {
 }
 
 pub fn produce_shared_key(port: &'static str) -> ([u8; 32], [u8; 64]) {
-    // get core's pubkey
     let keys = KeyPair::new().unwrap();
+    (produce_shared_key_of(port, &keys), keys.get_pubkey())
+}
+
+/// returns the key the tasks of the user with `keys` are encrypted with.
+pub fn produce_shared_key_of(port: &'static str, keys: &KeyPair) -> [u8; 32] {
+    // get core's pubkey
     let msg = get_encryption_msg(keys.get_pubkey());
 
     let v: Value = conn_and_call_ipc(&msg.to_string(), port);
@@ -181,8 +201,7 @@ pub fn produce_shared_key(port: &'static str) -> ([u8; 32], [u8; 64]) {
     let mut pubkey_arr = [0u8; 64];
     pubkey_arr.copy_from_slice(&_pubkey_vec);
 
-    let shared_key = keys.derive_key(&pubkey_arr).unwrap();
-    (shared_key, keys.get_pubkey())
+    keys.derive_key(&pubkey_arr).unwrap()
 }
 
 pub fn full_erc20_deployment(port: &'static str, owner: ERC20UserAddress, total_supply: Option<u64>, gas_limit: Option<u64>) -> (Value, [u8; 32], [u8; 32]) {
@@ -243,6 +262,19 @@ pub fn full_simple_deployment(port: &'static str) -> (Value, [u8; 32]) {
     (v, address.into())
 }
 
+/// Deploys the state inspection example, the user with `owner` owns the contract.
+pub fn full_inspection_deployment(port: &'static str, owner: &KeyPair) -> (Value, [u8; 32]) {
+    let address = generate_contract_address();
+    let _ = run_ptt_round(port, vec![address]);
+    let shared_key = produce_shared_key_of(port, owner);
+
+    let pre_code = get_bytecode_from_path("../../examples/eng_wasm_contracts/state_inspection");
+    let (encrypted_callable, encrypted_args) = encrypt_args(&[Token::Uint(17.into())], "construct(uint)", shared_key);
+    let msg = get_deploy_msg(&pre_code, &encrypted_args.to_hex(),
+                             &encrypted_callable.to_hex(), &owner.get_pubkey().to_hex(), 100_000_000, &address.to_hex());
+    (conn_and_call_ipc(&msg.to_string(), port), address.into())
+}
+
 pub fn full_addition_compute(port: &'static str,  a: u64, b: u64) -> (Value, [u8; 32], [u8; 32]) {
     let (_, contract_addr): (_, [u8; 32]) = full_simple_deployment(port);
     let args = [Token::Uint(a.into()), Token::Uint(b.into())];
@@ -272,8 +304,14 @@ pub fn full_supply_compute(port: &'static str, supply: u64) -> (Value,  [u8;32],
 }
 
 pub fn contract_compute(port: &'static str,  contract_addr: [u8; 32], args: &[Token], callable: &str) -> (Value, [u8; 32]) {
+    contract_compute_as(port, &KeyPair::new().unwrap(), contract_addr, args, callable)
+}
+
+/// Sends a compute task of the user with `keys`.
+pub fn contract_compute_as(port: &'static str, keys: &KeyPair, contract_addr: [u8; 32], args: &[Token], callable: &str) -> (Value, [u8; 32]) {
     // WUKE- get the arguments encryption key
-    let (shared_key, user_pubkey) = produce_shared_key(port);
+    let shared_key = produce_shared_key_of(port, keys);
+    let user_pubkey = keys.get_pubkey();
 
     let task_id: String = generate_contract_address().to_hex();
     let (encrypted_callable, encrypted_args) = encrypt_args(args, callable, shared_key);
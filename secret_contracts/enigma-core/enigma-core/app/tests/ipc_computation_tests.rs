@@ -5,17 +5,20 @@ extern crate rustc_hex as hex;
 extern crate cross_test_utils;
 extern crate enigma_types;
 
-use integration_utils::{conn_and_call_ipc, is_hex, run_core, get_msg_format_update_contract,
+use integration_utils::{conn_and_call_ipc, is_hex, run_core, run_core_with_snapshots, get_msg_format_update_contract,
                         get_encryption_msg, full_simple_deployment, full_addition_compute,
                         send_update_contract, run_ptt_round, contract_compute, get_update_deltas_msg,
                         decrypt_addr_delta, encrypt_addr_delta, replace_previous_hash_in_delta_data,
-                        full_supply_compute, decrypt_output_to_uint};
+                        full_supply_compute, decrypt_output_to_uint, get_inspect_state_msg,
+                        full_inspection_deployment, contract_compute_as, get_msg_format_with_input};
 use cross_test_utils::generate_contract_address;
 use self::app::serde_json;
+use app::db::SnapshotConfig;
 use app::serde_json::*;
 use hex::{ToHex, FromHex};
 use integration_utils::ethabi::{Token};
-use integration_utils::enigma_crypto::{asymmetric::KeyPair, hash::Keccak256};
+use integration_utils::enigma_crypto::{asymmetric::KeyPair, hash::Keccak256, symmetric};
+use std::thread;
 
 #[test]
 fn test_new_task_encryption_key(){
@@ -81,6 +84,23 @@ fn test_compute_task_no_delta() {
     assert_eq!("ComputeTask", type_accepted);
 }
 
+#[test]
+fn test_compute_view_task() {
+    let port =  "5581";
+    run_core(port);
+
+    let (a, b) : (u64, u64) = (11, 31);
+    let (_, _, address): (Value, _, [u8;32]) = full_addition_compute(port, a, b);
+    let (res, key) = contract_compute(port, address, &[], "get_last_sum()");
+
+    let output: String = serde_json::from_value(res["result"]["output"].clone()).unwrap();
+    let accepted_sum: Token = decrypt_output_to_uint(&output.from_hex().unwrap(), &key);
+    assert_eq!(accepted_sum.to_uint().unwrap().as_u64(), a + b);
+    // A view returns no delta, so the node has nothing to store
+    assert_eq!(res["result"]["view"], Value::Bool(true));
+    assert!(res["result"].get("delta").is_none());
+}
+
 #[test]
 fn test_execute_on_existing_contract_with_constructor() {
     let port =  "5572";
@@ -137,4 +157,106 @@ fn test_execute_on_existing_contract_with_constructor() {
     assert_eq!(last_delta_key, expected_key + 1);
 
 
-}
\ No newline at end of file
+}
+fn inspect_state(port: &'static str, contract_addr: [u8; 32], inspector: &KeyPair) -> (Value, [u8; 32]) {
+    let v: Value = conn_and_call_ipc(&get_encryption_msg(inspector.get_pubkey()).to_string(), port);
+    let mut worker_pubkey = [0u8; 64];
+    worker_pubkey.copy_from_slice(&v["result"]["workerEncryptionKey"].as_str().unwrap().from_hex().unwrap());
+    let shared_key = inspector.derive_key(&worker_pubkey).unwrap();
+
+    let to_sign: [&[u8]; 5] = [&contract_addr, b"", b"", &0u32.to_be_bytes(), &[1u8]];
+    let sig = inspector.sign_multiple(&to_sign).unwrap();
+    let msg = get_inspect_state_msg(&contract_addr.to_hex(), "", true, &sig);
+    (conn_and_call_ipc(&msg.to_string(), port), shared_key)
+}
+
+#[test]
+fn test_inspect_state() {
+    let port = "5582";
+    run_core(port);
+
+    let owner = KeyPair::new().unwrap();
+    let (_, contract_addr): (_, [u8; 32]) = full_inspection_deployment(port, &owner);
+    let inspector = KeyPair::new().unwrap();
+    let pubkey_hash = inspector.get_pubkey().keccak256();
+    let inspector_address = &pubkey_hash[12..];
+    let args = [Token::Address(ethabi::Address::from_slice(inspector_address))];
+    // only the owner can allow inspecting the state
+    let (res, _) = contract_compute(port, contract_addr, &args, "allow_inspection(address)");
+    assert_eq!(res["type"].as_str().unwrap(), "FailedTask");
+    let (res, _) = contract_compute_as(port, &owner, contract_addr, &args, "allow_inspection(address)");
+    assert_eq!(res["type"].as_str().unwrap(), "ComputeTask");
+
+    let (res, key) = inspect_state(port, contract_addr, &inspector);
+    assert_eq!(res["type"].as_str().unwrap(), "InspectState");
+    assert!(is_hex(res["result"]["workerSig"].as_str().unwrap()));
+    let output: Vec<u8> = res["result"]["output"].as_str().unwrap().from_hex().unwrap();
+    let inspection: Value = serde_json::from_slice(&symmetric::decrypt(&output, &key).unwrap()).unwrap();
+    assert_eq!(inspection["values"]["value"], json!(17));
+    assert_eq!(inspection["values"]["__enigma_inspectors"], json!([inspector_address.to_hex()]));
+    assert!(inspection["next"].is_null());
+
+    // A user the contract didn't allow can't inspect the state
+    let (res, _) = inspect_state(port, contract_addr, &KeyPair::new().unwrap());
+    assert_eq!(res["type"].as_str().unwrap(), "Error");
+}
+
+#[test]
+fn test_concurrent_tasks_of_two_contracts() {
+    let port = "5583";
+    run_core(port);
+
+    let handles: Vec<_> = [(3u64, 4u64), (40, 2)].iter().map(|&(a, b)| {
+        thread::spawn(move || (full_addition_compute(port, a, b), a + b))
+    }).collect();
+    for handle in handles {
+        let ((res, key, address), sum) = handle.join().unwrap();
+        assert_eq!(res["type"].as_str().unwrap(), "ComputeTask");
+        let output: String = serde_json::from_value(res["result"]["output"].clone()).unwrap();
+        assert_eq!(decrypt_output_to_uint(&output.from_hex().unwrap(), &key).to_uint().unwrap().as_u64(), sum);
+        // the delta of the constructor and the delta of the task were both stored
+        let tip = conn_and_call_ipc(&get_msg_format_with_input("GetTip", &address.to_hex()).to_string(), port);
+        assert_eq!(tip["result"]["key"].as_u64().unwrap(), 1);
+    }
+}
+
+#[test]
+fn test_tasks_of_a_contract_in_order() {
+    let port = "5584";
+    run_core(port);
+
+    let (_, address): (_, [u8; 32]) = full_simple_deployment(port);
+    let handles: Vec<_> = (1..=4u64).map(|i| {
+        thread::spawn(move || contract_compute(port, address, &[Token::Uint(i.into()), Token::Uint(i.into())], "addition(uint,uint)").0)
+    }).collect();
+    let mut keys: Vec<u64> = handles.into_iter().map(|handle| {
+        let res = handle.join().unwrap();
+        assert_eq!(res["type"].as_str().unwrap(), "ComputeTask");
+        res["result"]["delta"]["key"].as_u64().unwrap()
+    }).collect();
+    // every task built on the delta of the one before it
+    keys.sort();
+    assert_eq!(keys, vec![1, 2, 3, 4]);
+    let tip = conn_and_call_ipc(&get_msg_format_with_input("GetTip", &address.to_hex()).to_string(), port);
+    assert_eq!(tip["result"]["key"].as_u64().unwrap(), 4);
+}
+
+#[test]
+fn test_concurrent_tasks_with_snapshots_due() {
+    let port = "5585";
+    // every task is snapshotted, while the task of the other contract runs.
+    run_core_with_snapshots(port, SnapshotConfig { interval: Some(1), retention: 0 });
+
+    let handles: Vec<_> = [(5u64, 6u64), (70, 8)].iter().map(|&(a, b)| {
+        thread::spawn(move || (full_addition_compute(port, a, b), a + b))
+    }).collect();
+    for handle in handles {
+        let ((res, key, address), sum) = handle.join().unwrap();
+        assert_eq!(res["type"].as_str().unwrap(), "ComputeTask");
+        let output: String = serde_json::from_value(res["result"]["output"].clone()).unwrap();
+        assert_eq!(decrypt_output_to_uint(&output.from_hex().unwrap(), &key).to_uint().unwrap().as_u64(), sum);
+        let snapshot = conn_and_call_ipc(&get_msg_format_with_input("GetSnapshot", &address.to_hex()).to_string(), port);
+        assert_eq!(snapshot["result"]["deltaIndex"].as_u64().unwrap(), 1);
+        assert_eq!(snapshot["result"]["firstDelta"].as_u64().unwrap(), 2);
+    }
+}
@@ -0,0 +1,126 @@
+use super::get_state;
+use crate::{get_io_key, SIGNING_KEY};
+use enigma_crypto::{asymmetric::KeyPair, hash::prepare_hash_multiple, symmetric};
+use enigma_runtime_t::data::ContractState;
+use enigma_tools_m::utils::EthereumAddress;
+use enigma_tools_t::common::errors_t::{EnclaveError, EnclaveError::SystemError, EnclaveSystemError::InspectionNotAllowed};
+use enigma_types::{ContractAddress, RawPointer};
+use serde_json::Value;
+use std::{str, string::String, vec::Vec};
+
+/// A scan over the keys of a decrypted contract state, in the order of the keys.
+pub struct StateQuery<'a> {
+    /// Only the keys starting with `prefix` are returned.
+    pub prefix: &'a str,
+    /// The first key to return, so a scan can continue from the `next` key of the previous one.
+    pub from: &'a str,
+    /// The maximum number of keys to return, 0 returns all of them.
+    pub limit: u32,
+    /// Return the values under the keys and not only the keys.
+    pub values: bool,
+}
+
+impl<'a> StateQuery<'a> {
+    pub fn new(prefix: &'a [u8], from: &'a [u8], limit: u32, values: bool) -> Result<Self, EnclaveError> {
+        Ok(StateQuery { prefix: str::from_utf8(prefix)?, from: str::from_utf8(from)?, limit, values })
+    }
+
+    /// The user requests the query by signing S(address, prefix, from, limit, values).
+    pub fn to_sign(&self, address: &ContractAddress) -> Vec<u8> {
+        let limit = self.limit.to_be_bytes();
+        let values = [self.values as u8];
+        let to_sign: [&[u8]; 5] = [&address[..], self.prefix.as_bytes(), self.from.as_bytes(), &limit, &values];
+        prepare_hash_multiple(&to_sign)
+    }
+
+    /// Returns `{"deltaIndex", "keys" | "values", "next"}`, `next` is the first key left out because of the limit.
+    pub fn run(&self, state: &ContractState) -> Value {
+        let mut keys: Vec<&String> = state.json.as_object().map_or_else(Vec::new, |obj| {
+            obj.keys().filter(|key| key.starts_with(self.prefix) && key.as_str() >= self.from).collect()
+        });
+        keys.sort();
+        let mut next = None;
+        if self.limit != 0 && keys.len() > self.limit as usize {
+            next = Some(keys[self.limit as usize].clone());
+            keys.truncate(self.limit as usize);
+        }
+
+        let mut result = json!({ "deltaIndex": state.delta_index, "next": next });
+        if self.values {
+            result["values"] = Value::Object(keys.into_iter().map(|key| (key.clone(), state.json[key].clone())).collect());
+        } else {
+            result["keys"] = json!(keys);
+        }
+        result
+    }
+}
+
+/// Run `query` on the latest state of the contract for the user who signed it with `user_sig`.
+/// The contract must allow the user to inspect its state, and the user must have a DH key with the enclave.
+/// Returns the result encrypted with the DH key, signed in `sig` with S(address, encrypted result).
+pub(crate) unsafe fn ecall_inspect_state_internal(
+    db_ptr: *const RawPointer,
+    address: ContractAddress,
+    query: &StateQuery,
+    user_sig: [u8; 65],
+    sig: &mut [u8; 65],
+) -> Result<Vec<u8>, EnclaveError>
+{
+    let user_pubkey = KeyPair::recover(&query.to_sign(&address), user_sig)?;
+    let state = get_state(db_ptr, address)?;
+    if !state.is_inspector(&user_pubkey.address()) {
+        return Err(SystemError(InspectionNotAllowed { address: user_pubkey.address_string() }));
+    }
+    let io_key = get_io_key(&user_pubkey)?;
+
+    let result = serde_json::to_vec(&query.run(&state)).expect("Failed converting Value to vec");
+    let encrypted = symmetric::encrypt(&result, &io_key)?;
+    *sig = SIGNING_KEY.sign_multiple(&[&address[..], &encrypted[..]])?;
+    Ok(encrypted)
+}
+
+#[cfg(debug_assertions)]
+pub mod tests {
+    use super::*;
+    use enigma_crypto::hash::Sha256;
+
+    fn new_state() -> ContractState {
+        let mut state = ContractState::new(b"inspected".sha256());
+        state.json = json!({ "ID_2": 2, "ID_1": 1, "0xabc": "a", "ID_3": 3 });
+        state.delta_index = 4;
+        state
+    }
+
+    pub fn test_inspect_state_keys() {
+        let state = new_state();
+        let all = StateQuery { prefix: "", from: "", limit: 0, values: false }.run(&state);
+        assert_eq!(all, json!({ "deltaIndex": 4, "keys": ["0xabc", "ID_1", "ID_2", "ID_3"], "next": null }));
+
+        let first = StateQuery { prefix: "ID_", from: "", limit: 2, values: false }.run(&state);
+        assert_eq!(first, json!({ "deltaIndex": 4, "keys": ["ID_1", "ID_2"], "next": "ID_3" }));
+        let rest = StateQuery { prefix: "ID_", from: "ID_3", limit: 2, values: false }.run(&state);
+        assert_eq!(rest, json!({ "deltaIndex": 4, "keys": ["ID_3"], "next": null }));
+    }
+
+    pub fn test_inspect_state_values() {
+        let state = new_state();
+        let values = StateQuery { prefix: "0x", from: "", limit: 0, values: true }.run(&state);
+        assert_eq!(values, json!({ "deltaIndex": 4, "values": { "0xabc": "a" }, "next": null }));
+
+        let empty = StateQuery { prefix: "missing", from: "", limit: 0, values: true }.run(&ContractState::new(ContractAddress::from([0u8; 32])));
+        assert_eq!(empty, json!({ "deltaIndex": 0, "values": {}, "next": null }));
+    }
+
+    pub fn test_inspect_state_signature() {
+        let address = b"inspected".sha256();
+        let keys = KeyPair::new().unwrap();
+        let query = StateQuery::new(b"ID_", b"", 10, true).unwrap();
+        let signed: [&[u8]; 5] = [&address[..], b"ID_", b"", &10u32.to_be_bytes(), &[1u8]];
+        let sig = keys.sign_multiple(&signed).unwrap();
+        assert_eq!(KeyPair::recover(&query.to_sign(&address), sig).unwrap(), keys.get_pubkey());
+
+        let other = StateQuery::new(b"ID_", b"", 10, false).unwrap();
+        assert_ne!(KeyPair::recover(&other.to_sign(&address), sig).ok(), Some(keys.get_pubkey()));
+        assert!(StateQuery::new(&[0xff], b"", 0, false).is_err());
+    }
+}
@@ -1,8 +1,11 @@
+pub(crate) mod inspect;
 pub(crate) mod principal;
 pub(crate) mod users;
 
-pub(crate) use self::principal::{ecall_build_state_internal, ecall_ptt_req_internal, ecall_ptt_res_internal};
+pub(crate) use self::principal::{ecall_build_state_internal, ecall_ptt_req_internal, ecall_ptt_res_internal, ecall_snapshot_state_internal,
+                                 ecall_verify_snapshot_internal};
 pub(crate) use self::users::ecall_get_user_key_internal;
+pub(crate) use self::inspect::{ecall_inspect_state_internal, StateQuery};
 
 use enigma_runtime_t::data::{ContractState, EncryptedContractState};
 use enigma_runtime_t::ocalls_t as runtime_ocalls_t;
@@ -17,6 +20,8 @@ lazy_static! {
     pub static ref STATE_KEYS: SgxMutex<HashMap<ContractAddress, StateKey>> = SgxMutex::new(HashMap::new());
 }
 
+/// The keys are copied out of `STATE_KEYS` and it's never held across an ocall,
+/// since the app can hold the DB during the ocall while another ecall waits for the keys.
 pub fn get_state_key(address: ContractAddress) -> Result<StateKey, EnclaveError> {
     let statekeys_guard = STATE_KEYS.lock_expect("State Keys");
     statekeys_guard
@@ -26,19 +31,15 @@ pub fn get_state_key(address: ContractAddress) -> Result<StateKey, EnclaveError>
 }
 
 pub fn encrypt_state(state: ContractState) -> Result<EncryptedContractState<u8>, EnclaveError> {
-    let state_keys_guard = STATE_KEYS.lock_expect("State Keys");
-    let key = state_keys_guard
-        .get(&state.contract_address)
-        .ok_or(CryptoError::MissingKeyError { key_type: "State Key" })?;
+    let key = get_state_key(state.contract_address)?;
     state.encrypt(&key)
 }
 
 pub fn get_state(db_ptr: *const RawPointer, addr: ContractAddress) -> Result<ContractState, EnclaveError> {
-    let guard = STATE_KEYS.lock_expect("State Keys");
-    let key = guard.get(&addr).ok_or(CryptoError::MissingKeyError { key_type: "State Key" })?;
+    let key = get_state_key(addr)?;
 
     let enc_state = runtime_ocalls_t::get_state(db_ptr, addr)?;
-    let state = ContractState::decrypt(enc_state, key)?;
+    let state = ContractState::decrypt(enc_state, &key)?;
 
     Ok(state)
-}
\ No newline at end of file
+}
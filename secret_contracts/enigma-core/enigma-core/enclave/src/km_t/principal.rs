@@ -1,8 +1,8 @@
 use super::STATE_KEYS;
 use crate::SIGNING_KEY;
-use enigma_runtime_t::data::{ContractState, DeltasInterface};
+use enigma_runtime_t::data::{ContractState, DeltasInterface, EncryptedContractState, StatePatch};
 use enigma_runtime_t::ocalls_t as runtime_ocalls_t;
-use enigma_tools_t::common::errors_t::EnclaveError;
+use enigma_tools_t::common::errors_t::{EnclaveError, EnclaveError::SystemError, EnclaveSystemError::StateError};
 use enigma_tools_m::utils::LockExpectMutex;
 use enigma_crypto::asymmetric::KeyPair;
 use enigma_crypto::{Encryption, CryptoError};
@@ -52,11 +52,12 @@ pub(crate) fn ecall_ptt_res_internal(msg_slice: &[u8]) -> Result<(), EnclaveErro
 }
 
 pub(crate) unsafe fn ecall_build_state_internal(db_ptr: *const RawPointer) -> Result<Vec<ContractAddress>, EnclaveError> {
-    let guard = STATE_KEYS.lock_expect("State Keys");
-    let mut failed_contracts = Vec::with_capacity(guard.len());
-    debug_println!("building state for {} contracts", guard.len());
+    // the keys are copied so they aren't locked during the ocalls.
+    let keys: Vec<(ContractAddress, StateKey)> = STATE_KEYS.lock_expect("State Keys").iter().map(|(addr, key)| (*addr, *key)).collect();
+    let mut failed_contracts = Vec::with_capacity(keys.len());
+    debug_println!("building state for {} contracts", keys.len());
 
-    'contract: for (addrs, key) in guard.iter() {
+    'contract: for (addrs, key) in keys.iter() {
         // Get the state and decrypt it.
         // if no state exists create a new one and if failed decrypting, push to failed_contracts and move on.
         let (mut start, mut state ) = match runtime_ocalls_t::get_state(db_ptr, *addrs) {
@@ -132,6 +133,47 @@ pub(crate) unsafe fn ecall_build_state_internal(db_ptr: *const RawPointer) -> Re
     Ok(failed_contracts)
 }
 
+/// Snapshots the state of the contract, it's decrypted to make sure it's valid and encrypted again.
+/// returns the index of the last delta included in the snapshot.
+pub(crate) unsafe fn ecall_snapshot_state_internal(db_ptr: *const RawPointer, address: ContractAddress) -> Result<u32, EnclaveError> {
+    let key = super::get_state_key(address)?;
+
+    let enc_state = runtime_ocalls_t::get_state(db_ptr, address)?;
+    let state = ContractState::decrypt(enc_state, &key)?;
+    let delta_index = state.delta_index;
+    let enc = state.encrypt(&key)?;
+    runtime_ocalls_t::save_snapshot(db_ptr, &enc, delta_index)?;
+    Ok(delta_index)
+}
+
+/// Verifies a snapshot received from a peer before it's stored, since it can replace the deltas it includes once they're pruned.
+/// It must be encrypted with the state key of the contract, include the deltas up to `delta_index`,
+/// and agree with the local deltas: the one at `delta_index` must be the last delta it includes
+/// and the one after it must be built on it. If neither of them is local, there's nothing to check it against yet.
+pub(crate) unsafe fn ecall_verify_snapshot_internal(db_ptr: *const RawPointer, address: ContractAddress, enc_state: &[u8],
+                                                    delta_index: u32) -> Result<(), EnclaveError> {
+    let key = super::get_state_key(address)?;
+    let state = ContractState::decrypt(EncryptedContractState { contract_address: address, json: enc_state.to_vec() }, &key)?;
+    let snapshot_error = |err: String| SystemError(StateError { err: format!("The snapshot of {:?} is invalid: {}", address, err) });
+    if state.delta_index != delta_index {
+        return Err(snapshot_error(format!("it includes the deltas up to {} and not {}", state.delta_index, delta_index)));
+    }
+    let next_index = delta_index.saturating_add(1);
+    if let Ok(deltas) = runtime_ocalls_t::get_deltas(db_ptr, address, delta_index, next_index) {
+        if deltas.iter().any(|delta| delta.keccak256_patch() != state.delta_hash) {
+            return Err(snapshot_error(format!("it doesn't include the local delta {}", delta_index)));
+        }
+    }
+    if let Ok(deltas) = runtime_ocalls_t::get_deltas(db_ptr, address, next_index, next_index.saturating_add(1)) {
+        for delta in deltas {
+            if StatePatch::decrypt(delta, &key)?.previous_hash != state.delta_hash {
+                return Err(snapshot_error(format!("the local delta {} isn't built on it", next_index)));
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(debug_assertions)]
 pub mod tests {
     use super::*;
@@ -37,13 +37,18 @@ extern crate sputnikvm_network_classic;
 mod km_t;
 
 use crate::{
-    km_t::{ecall_build_state_internal, ecall_get_user_key_internal, ecall_ptt_req_internal, ecall_ptt_res_internal},
+    km_t::{
+        ecall_build_state_internal, ecall_get_user_key_internal, ecall_inspect_state_internal, ecall_ptt_req_internal,
+        ecall_ptt_res_internal, ecall_snapshot_state_internal, ecall_verify_snapshot_internal, StateQuery,
+    },
 };
 use enigma_crypto::{asymmetric, hash::Keccak256, symmetric, CryptoError};
 use enigma_runtime_t::{
-    data::{ContractState, EncryptedPatch},
-    wasm_execution::WasmEngine,
-    EthereumData,
+    calls::{CallEnvironment, CalleeResult},
+    data::{ContractState, EncryptedContractState, EncryptedPatch},
+    profile::ExecutionProfile,
+    wasm_execution::{WasmEngine, MIGRATION_FUNC_NAME},
+    ContractEvent, EthereumData, ExecutionContext,
 };
 use enigma_tools_m::utils::{EthereumAddress, LockExpectMutex};
 use enigma_tools_t::{
@@ -57,6 +62,7 @@ use enigma_tools_t::{
 };
 use enigma_types::{
     traits::SliceCPtr, ContractAddress, DhKey, EnclaveReturn, ExecuteResult, Hash256, PubKey, RawPointer, ResultStatus,
+    TaskContext,
 };
 
 use sgx_types::*;
@@ -91,6 +97,7 @@ pub extern "C" fn ecall_get_signing_address(pubkey: &mut [u8; 20]) { pubkey.copy
 /// * `user_key` - the DH key of the user to decrypt `callable` and `args`
 /// * `contract_address` - the address of the deployed contract with code `bytecode`
 /// * `gas_limit` - the gas limit for the function execution
+/// * `context` - the block number and id of the task, exposed to the contract
 /// * `result` - the result of the function invocation
 // TODO: add arguments of callable.
 pub unsafe extern "C" fn ecall_execute(
@@ -103,6 +110,7 @@ pub unsafe extern "C" fn ecall_execute(
     user_key: &[u8; 64],
     contract_address: &ContractAddress,
     gas_limit: *const u64,
+    context: &TaskContext,
     db_ptr: *const RawPointer,
     result: &mut ExecuteResult,
 ) -> EnclaveReturn
@@ -127,6 +135,7 @@ pub unsafe extern "C" fn ecall_execute(
         &io_key,
         (*contract_address).into(),
         *gas_limit,
+        context,
         db_ptr,
         result,
     );
@@ -149,6 +158,7 @@ pub unsafe extern "C" fn ecall_execute(
 /// * `address` - the address of the contract to be deployed
 /// * `user_key` - the DH key of the user to decrypt `constructor` and `args`
 /// * `gas_limit` - the gas limit for the constructor execution
+/// * `context` - the block number and id of the task, exposed to the contract
 /// * `result` - the result of the deployment
 pub unsafe extern "C" fn ecall_deploy(
     bytecode: *const u8,
@@ -160,6 +170,7 @@ pub unsafe extern "C" fn ecall_deploy(
     address: &ContractAddress,
     user_key: &PubKey,
     gas_limit: *const u64,
+    context: &TaskContext,
     db_ptr: *const RawPointer,
     result: &mut ExecuteResult,
 ) -> EnclaveReturn
@@ -182,6 +193,7 @@ pub unsafe extern "C" fn ecall_deploy(
         user_key,
         &io_key,
         *gas_limit,
+        context,
         db_ptr,
         result,
     );
@@ -192,6 +204,69 @@ pub unsafe extern "C" fn ecall_deploy(
     internal_result.into()
 }
 
+#[no_mangle]
+/// Ecall for upgrading a deployed contract to new code, keeping its state.
+/// arguments:
+/// * `bytecode` - WASM pre-deployed bytecode of the new code.
+/// * `bytecode_len` - the length of `bytecode`.
+/// * `prev_code` - the current WASM bytecode of the contract.
+/// * `prev_code_len` - the length of `prev_code`.
+/// * `migration` - the encrypted migration signature
+/// * `migration_len` - the length of `migration`
+/// * `args` - the encrypted arguments for the migration
+/// * `args_len` - the length of `args`
+/// * `address` - the address of the contract to be upgraded
+/// * `user_key` - the DH key of the user to decrypt `migration` and `args`
+/// * `gas_limit` - the gas limit for the migration execution
+/// * `context` - the block number and id of the task, exposed to the contract
+/// * `result` - the result of the upgrade, with the new WASM bytecode as output
+pub unsafe extern "C" fn ecall_upgrade(
+    bytecode: *const u8,
+    bytecode_len: usize,
+    prev_code: *const u8,
+    prev_code_len: usize,
+    migration: *const u8,
+    migration_len: usize,
+    args: *const u8,
+    args_len: usize,
+    address: &ContractAddress,
+    user_key: &PubKey,
+    gas_limit: *const u64,
+    context: &TaskContext,
+    db_ptr: *const RawPointer,
+    result: &mut ExecuteResult,
+) -> EnclaveReturn
+{
+    let args = slice::from_raw_parts(args, args_len);
+    let bytecode = slice::from_raw_parts(bytecode, bytecode_len);
+    let prev_code = slice::from_raw_parts(prev_code, prev_code_len);
+    let migration = slice::from_raw_parts(migration, migration_len);
+    let mut pre_execution_data = vec![];
+    let io_key = match get_io_key(user_key) {
+        Ok(v) => v,
+        Err(e) => return e.into(),
+    };
+    let mut internal_result = ecall_upgrade_internal(
+        &mut pre_execution_data,
+        bytecode,
+        prev_code,
+        migration,
+        args,
+        (*address).into(),
+        user_key,
+        &io_key,
+        *gas_limit,
+        context,
+        db_ptr,
+        result,
+    );
+    if let Err(e) = &internal_result {
+        println!("Error in upgrade of secret contract: {}", e);
+        internal_result = output_task_failure(&pre_execution_data, *gas_limit, e, result, &io_key);
+    }
+    internal_result.into()
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn ecall_ptt_req(sig: &mut [u8; 65], serialized_ptr: *mut u64) -> EnclaveReturn {
     let msg = match ecall_ptt_req_internal(sig) {
@@ -225,6 +300,31 @@ pub unsafe extern "C" fn ecall_build_state(db_ptr: *const RawPointer, failed_ptr
     EnclaveReturn::Success
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn ecall_snapshot_state(db_ptr: *const RawPointer, address: &ContractAddress, delta_index: *mut u32) -> EnclaveReturn {
+    *delta_index = match ecall_snapshot_state_internal(db_ptr, *address) {
+        Ok(index) => index,
+        Err(e) => return e.into(),
+    };
+    EnclaveReturn::Success
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ecall_verify_snapshot(
+    db_ptr: *const RawPointer,
+    address: &ContractAddress,
+    enc_state: *const u8,
+    state_len: usize,
+    delta_index: u32,
+) -> EnclaveReturn
+{
+    let enc_state = slice::from_raw_parts(enc_state, state_len);
+    match ecall_verify_snapshot_internal(db_ptr, *address, enc_state, delta_index) {
+        Ok(()) => EnclaveReturn::Success,
+        Err(e) => e.into(),
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn ecall_get_user_key(sig: &mut [u8; 65], user_pubkey: &PubKey, serialized_ptr: *mut u64) -> EnclaveReturn {
     let msg = match ecall_get_user_key_internal(sig, user_pubkey) {
@@ -238,6 +338,38 @@ pub unsafe extern "C" fn ecall_get_user_key(sig: &mut [u8; 65], user_pubkey: &Pu
     EnclaveReturn::Success
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn ecall_inspect_state(
+    db_ptr: *const RawPointer,
+    address: &ContractAddress,
+    prefix: *const u8,
+    prefix_len: usize,
+    from: *const u8,
+    from_len: usize,
+    limit: u32,
+    values: u8,
+    user_sig: &[u8; 65],
+    sig: &mut [u8; 65],
+    serialized_ptr: *mut u64,
+) -> EnclaveReturn
+{
+    let prefix = slice::from_raw_parts(prefix, prefix_len);
+    let from = slice::from_raw_parts(from, from_len);
+    let query = match StateQuery::new(prefix, from, limit, values != 0) {
+        Ok(query) => query,
+        Err(e) => return e.into(),
+    };
+    let msg = match ecall_inspect_state_internal(db_ptr, *address, &query, *user_sig, sig) {
+        Ok(msg) => msg,
+        Err(e) => return e.into(),
+    };
+    *serialized_ptr = match ocalls_t::save_to_untrusted_memory(&msg[..]) {
+        Ok(ptr) => ptr,
+        Err(e) => return e.into(),
+    };
+    EnclaveReturn::Success
+}
+
 fn get_io_key(user_key: &PubKey) -> Result<DhKey, EnclaveError> {
     let io_key = km_t::users::DH_KEYS
         .lock_expect("User DH Key")
@@ -256,6 +388,21 @@ fn decrypt_inputs(callable: &[u8], args: &[u8], inputs_key: &DhKey) -> Result<(V
     Ok((decrypted_args, function_name))
 }
 
+/// Hash the inputs of a task along with the context the contract can read through `eng_wasm::TaskContext`,
+/// so a worker is held by the signature to the block number and task id it gave the contract.
+/// They're only hashed when the worker gives them, so a task without a context keeps the inputs hash of its inputs.
+fn task_inputs_hash(inputs: &[&[u8]], context: &TaskContext) -> Hash256 {
+    let block_number = context.block_number.to_be_bytes();
+    let mut to_hash: Vec<&[u8]> = inputs.to_vec();
+    if context.block_number != 0 {
+        to_hash.push(&block_number);
+    }
+    if context.has_task_id {
+        to_hash.push(&context.task_id[..]);
+    }
+    enigma_crypto::hash::prepare_hash_multiple(&to_hash).keccak256()
+}
+
 fn get_enc_delta(delta: &Option<EncryptedPatch>) -> Hash256 {
     if let Some(delta) = delta {
         delta.keccak256_patch()
@@ -266,20 +413,44 @@ fn get_enc_delta(delta: &Option<EncryptedPatch>) -> Hash256 {
 
 unsafe fn store_delta_and_state(
     db_ptr: *const RawPointer,
-    delta: &Option<EncryptedPatch>,
+    delta: &EncryptedPatch,
     state: &ContractState,
 ) -> Result<(), EnclaveError>
 {
-    match delta {
-        Some(d) => {
-            let enc_state = km_t::encrypt_state(state.clone())?;
-            enigma_runtime_t::ocalls_t::save_delta(db_ptr, d)?;
-            // if the state isn't able to be stored, then remove the delta as well and fail the task
-            enigma_runtime_t::ocalls_t::save_state(db_ptr, &enc_state).
-                or_else(|_| enigma_runtime_t::ocalls_t::remove_delta(db_ptr, d))
+    let enc_state = km_t::encrypt_state(state.clone())?;
+    enigma_runtime_t::ocalls_t::save_delta(db_ptr, delta)?;
+    // if the state isn't able to be stored, then remove the delta as well and fail the task
+    if let Err(e) = enigma_runtime_t::ocalls_t::save_state(db_ptr, &enc_state) {
+        enigma_runtime_t::ocalls_t::remove_delta(db_ptr, delta)?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Store the deltas and states of the executed contract and of the contracts it called, in this order.
+/// If one of them can't be stored, the ones that were already stored are rolled back: their deltas are removed
+/// and their previous states are written back, so the task fails as a whole with the error of the store.
+/// Only contracts that already had a state can be rolled back, so a deployment has to be the only contract stored.
+unsafe fn store_deltas_and_states(
+    db_ptr: *const RawPointer,
+    contracts: &[(&EncryptedPatch, &ContractState)],
+) -> Result<(), EnclaveError>
+{
+    let mut stored: Vec<(&EncryptedPatch, Option<EncryptedContractState<u8>>)> = Vec::with_capacity(contracts.len());
+    for (delta, state) in contracts {
+        let previous_state = enigma_runtime_t::ocalls_t::get_state(db_ptr, delta.contract_address).ok();
+        if let Err(e) = store_delta_and_state(db_ptr, delta, state) {
+            for (delta, previous_state) in stored.into_iter().rev() {
+                enigma_runtime_t::ocalls_t::remove_delta(db_ptr, delta)?;
+                if let Some(previous_state) = previous_state {
+                    enigma_runtime_t::ocalls_t::save_state(db_ptr, &previous_state)?;
+                }
+            }
+            return Err(e);
         }
-        None => Ok(()),
+        stored.push((delta, previous_state));
     }
+    Ok(())
 }
 
 fn create_eth_data_to_sign(input: Option<EthereumData>) -> (Vec<u8>, [u8; 20]) {
@@ -298,7 +469,9 @@ fn output_task_failure(
     key: &DhKey,
 ) -> Result<(), EnclaveError>
 {
-    // Signing: S(pre-execution data, usedGas, Failure)
+    // Signing: S(pre-execution data, gasLimit, usedGas, optionalReverted, Failure)
+    // A failed task has no events so their hash isn't signed, and the reverted flag is only signed if the contract reverted,
+    // so any other failure is signed as before.
     result.used_gas = 0;
     let return_error = match err {
         FailedTaskError(_) => err.clone(),
@@ -308,17 +481,28 @@ fn output_task_failure(
         }
         SystemError(e) => return Err(SystemError(e.clone())),
     };
+    // A reverted task returns the error payload of the contract instead of the error text
+    let output = match &return_error {
+        FailedTaskError(Reverted { payload }) => {
+            result.reverted = true;
+            payload.clone()
+        }
+        _ => format!("{}", return_error).into_bytes(),
+    };
     let used_gas = result.used_gas.to_be_bytes();
     let serialised_gas_limit = gas_limit.to_be_bytes();
+    let reverted = [result.reverted as u8];
     let failure = [ResultStatus::Failure as u8];
-    let mut to_sign: Vec<&[u8]> = Vec::with_capacity(pre_execution_data.len() + 2);
+    let mut to_sign: Vec<&[u8]> = Vec::with_capacity(pre_execution_data.len() + 4);
     pre_execution_data.into_iter().for_each(|x| to_sign.push(&x));
     to_sign.push(&serialised_gas_limit);
     to_sign.push(&used_gas);
+    if result.reverted {
+        to_sign.push(&reverted);
+    }
     to_sign.push(&failure);
     result.signature = SIGNING_KEY.sign_multiple(&to_sign)?;
-    let error_text = format!("{}", return_error);
-    let encrypted_result = symmetric::encrypt(error_text.as_bytes(), &key)?;
+    let encrypted_result = symmetric::encrypt(&output, &key)?;
     result.output = ocalls_t::save_to_untrusted_memory(&encrypted_result)? as *const u8;
     Err(return_error)
 }
@@ -332,6 +516,7 @@ unsafe fn ecall_execute_internal(
     io_key: &DhKey,
     address: ContractAddress,
     gas_limit: u64,
+    context: &TaskContext,
     db_ptr: *const RawPointer,
     result: &mut ExecuteResult,
 ) -> Result<(), EnclaveError>
@@ -339,7 +524,7 @@ unsafe fn ecall_execute_internal(
     // TODO: make sure the state is up to date.
     // TODO: Should this be here or on the untrusted side via build_state?;
 
-    let inputs_hash = enigma_crypto::hash::prepare_hash_multiple(&[callable, args, &*address, user_key]).keccak256();
+    let inputs_hash = task_inputs_hash(&[callable, args, &*address, user_key], context);
     let exe_code_hash = bytecode.keccak256();
     pre_execution_data.push(Box::new(*inputs_hash));
     pre_execution_data.push(Box::new(*exe_code_hash));
@@ -349,33 +534,57 @@ unsafe fn ecall_execute_internal(
         decrypt_inputs(callable, args, io_key).map_err(|e| FailedTaskError(InputError { message: format!("{}", e) }))?;
 
     let state_key = km_t::get_state_key(address)?;
-    let mut engine =
-        WasmEngine::new_compute(&bytecode, gas_limit, decrypted_args.clone(), pre_execution_state.clone(), function_name, state_key)?;
+    let context = ExecutionContext::new(context, user_key);
+    let mut engine = WasmEngine::new_compute(&bytecode, gas_limit, decrypted_args.clone(), pre_execution_state.clone(), function_name,
+                                             state_key, context)?;
+    engine.runtime.enable_calls(CallEnvironment::new(db_ptr, km_t::get_state_key, address));
     engine.compute()?;
     let exec_res = engine.into_result()?;
 
     let delta_hash = get_enc_delta(&exec_res.state_delta);
     let encrypted_output = symmetric::encrypt(&exec_res.result, io_key)?;
-    prepare_wasm_result(&exec_res.state_delta, &encrypted_output, exec_res.ethereum_bridge.clone(), exec_res.used_gas, result)?;
+    let events_hash = prepare_wasm_result(&exec_res.state_delta, &encrypted_output, exec_res.ethereum_bridge.clone(), &exec_res.events,
+                                          &exec_res.callees, &exec_res.profile, exec_res.used_gas, io_key, result)?;
+    result.view = exec_res.view;
 
     let (ethereum_payload, ethereum_address) = create_eth_data_to_sign(exec_res.ethereum_bridge);
-    // Signing: S(exeCodeHash, inputsHash, delta(X-1)Hash, deltaXHash, outputHash, gasLimit, usedGas, optionalEthereumData, Success)
+    // Signing: S(exeCodeHash, inputsHash, delta(X-1)Hash, deltaXHash, outputHash, gasLimit, usedGas, optionalEthereumData,
+    //             optionalEventsHash, optionalView, optionalCallees, Success)
+    // The events hash is only signed if the contract emitted events, and the view flag only if the function is a view.
+    // Every changed callee is signed as (address, codeHash, delta(X-1)Hash, deltaXHash),
+    // they're only signed if other contracts were changed. A task that has none of these is signed as before.
     let used_gas = result.used_gas.to_be_bytes();
     let output_hash = encrypted_output.keccak256();
-    let to_sign: &[&[u8]] = &[
+    let gas_limit = gas_limit.to_be_bytes();
+    let view = [exec_res.view as u8];
+    let callee_delta_hashes: Vec<Hash256> = exec_res.callees.iter().map(|callee| callee.state_delta.keccak256_patch()).collect();
+    let callees_to_sign = exec_res.callees.iter().zip(&callee_delta_hashes).flat_map(|(callee, delta_hash)| {
+        vec![&callee.state_delta.contract_address[..], &callee.code_hash[..], &callee.pre_delta_hash[..], &delta_hash[..]]
+    });
+    let mut to_sign: Vec<&[u8]> = vec![
         &*exe_code_hash,
         &*inputs_hash,
         &*pre_execution_state.delta_hash,
         &*delta_hash,
         &*output_hash,
-        &gas_limit.to_be_bytes(),
+        &gas_limit,
         &used_gas,
         &ethereum_payload,
         &ethereum_address,
-        &[ResultStatus::Ok as u8],
     ];
-    result.signature = SIGNING_KEY.sign_multiple(to_sign)?;
-    store_delta_and_state(db_ptr, &exec_res.state_delta, &exec_res.updated_state)?;
+    if !exec_res.events.is_empty() {
+        to_sign.push(&*events_hash);
+    }
+    if exec_res.view {
+        to_sign.push(&view);
+    }
+    to_sign.extend(callees_to_sign);
+    to_sign.push(&[ResultStatus::Ok as u8]);
+    result.signature = SIGNING_KEY.sign_multiple(&to_sign)?;
+    let mut contracts: Vec<(&EncryptedPatch, &ContractState)> =
+        exec_res.state_delta.iter().map(|delta| (delta, &exec_res.updated_state)).collect();
+    contracts.extend(exec_res.callees.iter().map(|callee| (&callee.state_delta, &callee.updated_state)));
+    store_deltas_and_states(db_ptr, &contracts)?;
     Ok(())
 }
 
@@ -388,21 +597,26 @@ unsafe fn ecall_deploy_internal(
     user_key: &PubKey,
     io_key: &DhKey,
     gas_limit: u64,
+    context: &TaskContext,
     db_ptr: *const RawPointer,
     result: &mut ExecuteResult,
 ) -> Result<(), EnclaveError>
 {
     let pre_code_hash = bytecode.keccak256();
-    let inputs_hash = enigma_crypto::hash::prepare_hash_multiple(&[constructor, args, &pre_code_hash[..], user_key][..]).keccak256();
+    let inputs_hash = task_inputs_hash(&[constructor, args, &pre_code_hash[..], user_key], context);
     pre_execution_data.push(Box::new(*inputs_hash));
 
     let (decrypted_args, function_name) =
         decrypt_inputs(constructor, args, io_key).map_err(|e| FailedTaskError(InputError { message: format!("{}", e) }))?;
+    if function_name == MIGRATION_FUNC_NAME {
+        return Err(FailedTaskError(InputError { message: "The migration can only run in an upgrade".to_string() }));
+    }
 
     let state = ContractState::new(address);
 
     let state_key = km_t::get_state_key(address)?;
-    let mut engine = WasmEngine::new_deploy(bytecode, gas_limit, decrypted_args.clone(), state, function_name, state_key)?;
+    let context = ExecutionContext::new(context, user_key);
+    let mut engine = WasmEngine::new_deploy(bytecode, gas_limit, decrypted_args.clone(), state, function_name, state_key, context)?;
     engine.deploy()?;
     let exec_res = engine.into_result()?;
 
@@ -410,35 +624,124 @@ unsafe fn ecall_deploy_internal(
 
     let delta_hash = get_enc_delta(&exec_res.state_delta);
 
-    prepare_wasm_result(&exec_res.state_delta, exe_code, exec_res.ethereum_bridge.clone(), exec_res.used_gas, result)?;
+    let events_hash = prepare_wasm_result(&exec_res.state_delta, exe_code, exec_res.ethereum_bridge.clone(), &exec_res.events,
+                                          &exec_res.callees, &exec_res.profile, exec_res.used_gas, io_key, result)?;
+
+    // Signing: S(inputsHash, exeCodeHash, delta0Hash, gasLimit, usedGas, optionalEthereumData, optionalEventsHash, Success)
+    // The events hash is only signed if the constructor emitted events.
+    let used_gas = result.used_gas.to_be_bytes();
+    let gas_limit = gas_limit.to_be_bytes();
+    let exe_code_hash = exec_res.result.keccak256();
+    let (ethereum_payload, ethereum_address) = create_eth_data_to_sign(exec_res.ethereum_bridge);
+    let mut to_sign: Vec<&[u8]> = vec![
+        &*inputs_hash,
+        &*exe_code_hash,
+        &*delta_hash,
+        &gas_limit,
+        &used_gas,
+        &ethereum_payload,
+        &ethereum_address,
+    ];
+    if !exec_res.events.is_empty() {
+        to_sign.push(&*events_hash);
+    }
+    to_sign.push(&[ResultStatus::Ok as u8]);
+    result.signature = SIGNING_KEY.sign_multiple(&to_sign)?;
+    if let Some(delta) = &exec_res.state_delta {
+        store_deltas_and_states(db_ptr, &[(delta, &exec_res.updated_state)])?;
+    }
+    Ok(())
+}
+
+unsafe fn ecall_upgrade_internal(
+    pre_execution_data: &mut Vec<Box<[u8]>>,
+    bytecode: &[u8],
+    prev_code: &[u8],
+    migration: &[u8],
+    args: &[u8],
+    address: ContractAddress,
+    user_key: &PubKey,
+    io_key: &DhKey,
+    gas_limit: u64,
+    context: &TaskContext,
+    db_ptr: *const RawPointer,
+    result: &mut ExecuteResult,
+) -> Result<(), EnclaveError>
+{
+    let pre_code_hash = bytecode.keccak256();
+    let prev_code_hash = prev_code.keccak256();
+    let inputs_hash = task_inputs_hash(&[migration, args, &pre_code_hash[..], &*address, user_key], context);
+    pre_execution_data.push(Box::new(*inputs_hash));
+    pre_execution_data.push(Box::new(*prev_code_hash));
+    let pre_execution_state = km_t::get_state(db_ptr, address)?;
+
+    let (decrypted_args, function_name) =
+        decrypt_inputs(migration, args, io_key).map_err(|e| FailedTaskError(InputError { message: format!("{}", e) }))?;
+    if function_name != MIGRATION_FUNC_NAME {
+        return Err(FailedTaskError(InputError { message: format!("An upgrade must call {}", MIGRATION_FUNC_NAME) }));
+    }
+
+    let state_key = km_t::get_state_key(address)?;
+    let context = ExecutionContext::new(context, user_key);
+    let mut engine = WasmEngine::new_deploy(bytecode, gas_limit, decrypted_args.clone(), pre_execution_state.clone(), function_name,
+                                            state_key, context)?;
+    engine.upgrade(&pre_code_hash, &prev_code_hash)?;
+    let exec_res = engine.into_result()?;
+
+    let exe_code = &exec_res.result[..];
+
+    let delta_hash = get_enc_delta(&exec_res.state_delta);
+
+    let events_hash = prepare_wasm_result(&exec_res.state_delta, exe_code, exec_res.ethereum_bridge.clone(), &exec_res.events,
+                                          &exec_res.callees, &exec_res.profile, exec_res.used_gas, io_key, result)?;
 
-    // Signing: S(inputsHash, exeCodeHash, delta0Hash, gasLimit, usedGas, optionalEthereumData, Success)
+    // Signing: S(inputsHash, prevExeCodeHash, exeCodeHash, delta(X-1)Hash, deltaXHash, gasLimit, usedGas, optionalEthereumData,
+    //             optionalEventsHash, Success)
+    // The events hash is only signed if the migration emitted events.
     let used_gas = result.used_gas.to_be_bytes();
+    let gas_limit = gas_limit.to_be_bytes();
+    let exe_code_hash = exec_res.result.keccak256();
     let (ethereum_payload, ethereum_address) = create_eth_data_to_sign(exec_res.ethereum_bridge);
-    let to_sign: &[&[u8]] = &[
+    let mut to_sign: Vec<&[u8]> = vec![
         &*inputs_hash,
-        &*exec_res.result.keccak256(),
+        &*prev_code_hash,
+        &*exe_code_hash,
+        &*pre_execution_state.delta_hash,
         &*delta_hash,
-        &gas_limit.to_be_bytes(),
+        &gas_limit,
         &used_gas,
         &ethereum_payload,
         &ethereum_address,
-        &[ResultStatus::Ok as u8],
     ];
-    result.signature = SIGNING_KEY.sign_multiple(to_sign)?;
-    store_delta_and_state(db_ptr, &exec_res.state_delta, &exec_res.updated_state)?;
+    if !exec_res.events.is_empty() {
+        to_sign.push(&*events_hash);
+    }
+    to_sign.push(&[ResultStatus::Ok as u8]);
+    result.signature = SIGNING_KEY.sign_multiple(&to_sign)?;
+    if let Some(delta) = &exec_res.state_delta {
+        store_deltas_and_states(db_ptr, &[(delta, &exec_res.updated_state)])?;
+    }
     Ok(())
 }
 
+/// Returns the hash of the sealed events, it's signed with the result of the task.
 unsafe fn prepare_wasm_result(
     delta_option: &Option<EncryptedPatch>,
     execute_result: &[u8],
     ethereum_bridge: Option<EthereumData>,
+    events: &[ContractEvent],
+    callees: &[CalleeResult],
+    profile: &Option<ExecutionProfile>,
     used_gas: u64,
+    io_key: &DhKey,
     result: &mut ExecuteResult,
-) -> Result<(), EnclaveError>
+) -> Result<Hash256, EnclaveError>
 {
     result.output = ocalls_t::save_to_untrusted_memory(&execute_result)? as *const u8;
+    let (sealed_events, events_hash) = ContractEvent::seal_all(events, io_key)?;
+    result.events_ptr = ocalls_t::save_to_untrusted_memory(&sealed_events)? as *const u8;
+    result.callee_deltas_ptr = ocalls_t::save_to_untrusted_memory(&CalleeResult::serialize_deltas(callees)?)? as *const u8;
+    result.profile_ptr = ocalls_t::save_to_untrusted_memory(&ExecutionProfile::seal(profile, io_key)?)? as *const u8;
     result.used_gas = used_gas;
     match delta_option {
         Some(enc_delta) => {
@@ -461,7 +764,7 @@ unsafe fn prepare_wasm_result(
             result.ethereum_address = [0u8; 20];
         }
     }
-    Ok(())
+    Ok(events_hash)
 }
 
 fn get_sealed_keys_wrapper() -> asymmetric::KeyPair {
@@ -487,8 +790,9 @@ pub mod tests {
         extern crate sgx_tunittest;
 
         use self::sgx_tunittest::*;
+        use crate::km_t::inspect::tests::*;
         use crate::km_t::principal::tests::*;
-        use enigma_runtime_t::{data::tests::*, ocalls_t::tests::*, wasm_execution::tests::*};
+        use enigma_runtime_t::{data::tests::*, ocalls_t::tests::*, tests::*, wasm_execution::tests::*};
         use enigma_tools_t::storage_t::tests::*;
         use enigma_types::{RawPointer, ResultStatus};
         use std::{panic::UnwindSafe, string::String, vec::Vec};
@@ -511,6 +815,26 @@ pub mod tests {
             core_unitests(&mut ctr, &mut failures, test_encrypt_decrypt_patch, "test_encrypt_decrypt_patch");
             core_unitests(&mut ctr, &mut failures, test_apply_delta, "test_apply_delta");
             core_unitests(&mut ctr, &mut failures, test_generate_delta, "test_generate_delta");
+            core_unitests(&mut ctr, &mut failures, test_encoding_round_trip, "test_encoding_round_trip");
+            core_unitests(&mut ctr, &mut failures, test_msgpack_encoding_is_smaller, "test_msgpack_encoding_is_smaller");
+            core_unitests(&mut ctr, &mut failures, test_encoding_is_fixed_after_write, "test_encoding_is_fixed_after_write");
+            core_unitests(&mut ctr, &mut failures, test_encoding_survives_delta, "test_encoding_survives_delta");
+            core_unitests(&mut ctr, &mut failures, test_native_hashes, "test_native_hashes");
+            core_unitests(&mut ctr, &mut failures, test_native_ecrecover, "test_native_ecrecover");
+            core_unitests(&mut ctr, &mut failures, test_decrypt_with_aad_status, "test_decrypt_with_aad_status");
+            core_unitests(&mut ctr, &mut failures, test_emit_event, "test_emit_event");
+            core_unitests(&mut ctr, &mut failures, test_revert, "test_revert");
+            core_unitests(&mut ctr, &mut failures, test_view, "test_view");
+            core_unitests(&mut ctr, &mut failures, test_upgrade_approval, "test_upgrade_approval");
+            core_unitests(&mut ctr, &mut failures, test_state_inspectors, "test_state_inspectors");
+            core_unitests(&mut ctr, &mut failures, test_inspect_state_keys, "test_inspect_state_keys");
+            core_unitests(&mut ctr, &mut failures, test_inspect_state_values, "test_inspect_state_values");
+            core_unitests(&mut ctr, &mut failures, test_inspect_state_signature, "test_inspect_state_signature");
+            core_unitests(&mut ctr, &mut failures, test_state_gas, "test_state_gas");
+            core_unitests(&mut ctr, &mut failures, test_refund_cap, "test_refund_cap");
+            core_unitests(&mut ctr, &mut failures, test_msgpack_state_gas, "test_msgpack_state_gas");
+            core_unitests(&mut ctr, &mut failures, test_execution_profile, "test_execution_profile");
+            core_unitests(&mut ctr, &mut failures, test_call_contract_limits, "test_call_contract_limits");
             core_unitests(&mut ctr, &mut failures, || test_me(db_ptr), "test_me");
             core_unitests(&mut ctr, &mut failures, test_execute_contract, "test_execute_contract");
             core_unitests(&mut ctr, &mut failures, || test_get_deltas(db_ptr), "test_get_deltas");
@@ -9,6 +9,7 @@
 use enigma_types::SymmetricKey;
 use crate::error::CryptoError;
 use ring::aead::{self, Nonce, Aad};
+use ring::{digest, hkdf, hmac};
 use crate::localstd::borrow::ToOwned;
 use crate::localstd::option::Option;
 use crate::localstd::vec::Vec;
@@ -18,9 +19,9 @@ use crate::rand;
 static AES_MODE: &aead::Algorithm = &aead::AES_256_GCM;
 
 /// The IV key byte size
-const IV_SIZE: usize = 96/8;
+pub const IV_SIZE: usize = 96/8;
 /// Type alias for the IV byte array
-type IV = [u8; IV_SIZE];
+pub type IV = [u8; IV_SIZE];
 
 /// This function get's a key and a slice of data and encrypts the data using the key.
 /// the IV/nonce is appended to the cipher text after the MAC tag.
@@ -30,6 +31,12 @@ pub fn encrypt(message: &[u8], key: &SymmetricKey) -> Result<Vec<u8>, CryptoErro
 /// This function does the same as [`self::encrypt`] but accepts an IV.
 /// it *shouldn't* be called directly. only from tests or [`crate::Encryption::encrypt_with_nonce`] implementations.
 pub fn encrypt_with_nonce(message: &[u8], key: &SymmetricKey, _iv: Option<IV>) -> Result<Vec<u8>, CryptoError> {
+    encrypt_with_aad(message, key, &[], _iv)
+}
+
+/// This function does the same as [`self::encrypt_with_nonce`] but also authenticates `aad` (Associated Data),
+/// which isn't part of the cipher text, so the same `aad` must be given to [`self::decrypt_with_aad`].
+pub fn encrypt_with_aad(message: &[u8], key: &SymmetricKey, aad: &[u8], _iv: Option<IV>) -> Result<Vec<u8>, CryptoError> {
     let iv = match _iv {
         Some(x) => x,
         None => {
@@ -46,7 +53,7 @@ pub fn encrypt_with_nonce(message: &[u8], key: &SymmetricKey, _iv: Option<IV>) -
     in_out.extend(vec![0u8; tag_size]);
     let seal_size = {
         let iv = Nonce::assume_unique_for_key(iv);
-        aead::seal_in_place(&aes_encrypt, iv, Aad::empty(), &mut in_out, tag_size)
+        aead::seal_in_place(&aes_encrypt, iv, Aad::from(aad), &mut in_out, tag_size)
             .map_err(|_| CryptoError::EncryptionError)
     }?;
 
@@ -58,7 +65,11 @@ pub fn encrypt_with_nonce(message: &[u8], key: &SymmetricKey, _iv: Option<IV>) -
 /// This function will decrypt a cipher text only if it was encrypted with the `encrypt` function above.
 /// Because it will try to get the IV from the last 12 bytes in the cipher text,
 /// then ring will take the last 16 bytes as a MAC to check the integrity of the cipher text.
-pub fn decrypt(cipheriv: &[u8], key: &SymmetricKey) -> Result<Vec<u8>, CryptoError> {
+pub fn decrypt(cipheriv: &[u8], key: &SymmetricKey) -> Result<Vec<u8>, CryptoError> { decrypt_with_aad(cipheriv, key, &[]) }
+
+/// This function will decrypt a cipher text encrypted with [`self::encrypt_with_aad`],
+/// it fails with `DecryptionError` if the MAC doesn't match the cipher text and `aad`.
+pub fn decrypt_with_aad(cipheriv: &[u8], key: &SymmetricKey, aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
     if cipheriv.len() < IV_SIZE {
         return Err(CryptoError::ImproperEncryption);
     }
@@ -68,18 +79,28 @@ pub fn decrypt(cipheriv: &[u8], key: &SymmetricKey) -> Result<Vec<u8>, CryptoErr
     let (ciphertext, iv) = cipheriv.split_at(cipheriv.len()-12);
     let nonce = aead::Nonce::try_assume_unique_for_key(&iv).unwrap(); // This Cannot fail because split_at promises that iv.len()==12
     let mut ciphertext = ciphertext.to_owned();
-    let decrypted_data = aead::open_in_place(&aes_decrypt, nonce, Aad::empty(), 0, &mut ciphertext);
+    let decrypted_data = aead::open_in_place(&aes_decrypt, nonce, Aad::from(aad), 0, &mut ciphertext);
     let decrypted_data = decrypted_data.map_err(|_| CryptoError::DecryptionError)?;
 
     Ok(decrypted_data.to_vec())
 }
 
+/// This function derives a new key from `key` using HKDF-SHA256 (RFC 5869),
+/// so different `info` (e.g. a record id) give independent keys from the same master key.
+pub fn derive_key(key: &SymmetricKey, salt: &[u8], info: &[u8]) -> SymmetricKey {
+    let salt = hmac::SigningKey::new(&digest::SHA256, salt);
+    let mut derived: SymmetricKey = [0u8; 32];
+    hkdf::extract_and_expand(&salt, key, info, &mut derived);
+    derived
+}
+
 #[cfg(test)]
 mod tests {
     use crate::rand;
     use rustc_hex::{ToHex, FromHex};
     use crate::hash::Sha256;
-    use super::{decrypt, encrypt_with_nonce};
+    use crate::error::CryptoError;
+    use super::{decrypt, decrypt_with_aad, derive_key, encrypt_with_aad, encrypt_with_nonce};
 
     #[test]
     fn test_rand_encrypt_decrypt() {
@@ -119,4 +140,32 @@ mod tests {
 //        let enc = encrypt_with_nonce(&msg, &key, Some(iv)).unwrap();
 
     }
+
+    #[test]
+    fn test_encrypt_decrypt_with_aad() {
+        let key = b"EnigmaMPC".sha256();
+        let msg = b"This Is Enigma".to_vec();
+        let aad = b"record 1";
+        let iv = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+        let ciphertext = encrypt_with_aad(&msg, &key, aad, Some(iv)).unwrap();
+        assert_eq!(decrypt_with_aad(&ciphertext, &key, aad).unwrap(), msg);
+        match decrypt_with_aad(&ciphertext, &key, b"record 2") {
+            Err(CryptoError::DecryptionError) => (),
+            _ => panic!("Decrypted with the wrong aad"),
+        }
+        assert!(decrypt(&ciphertext, &key).is_err());
+        // Without aad it's the same as the regular encryption.
+        assert_eq!(encrypt_with_aad(&msg, &key, &[], Some(iv)).unwrap(), encrypt_with_nonce(&msg, &key, Some(iv)).unwrap());
+    }
+
+    #[test]
+    fn test_derive_key() {
+        let key = b"EnigmaMPC".sha256();
+        let salt: Vec<u8> = "000102030405060708090a0b0c".from_hex().unwrap();
+        let info: Vec<u8> = "f0f1f2f3f4f5f6f7f8f9".from_hex().unwrap();
+        let derived = derive_key(&key, &salt, &info);
+        assert_ne!(derived, *key);
+        assert_ne!(derived, derive_key(&key, &salt, b"other"));
+        assert_eq!(derived, derive_key(&key, &salt, &info));
+    }
 }
@@ -0,0 +1,184 @@
+//! Calls between secret contracts within one task.
+//!
+//! A contract calls another contract with `call_contract`, which runs the callee in a nested `WasmEngine`
+//! with the gas left to the caller. The states of the callees are kept in the runtime of the caller until
+//! the task ends, so a contract that is called several times sees its previous changes, and the deltas of
+//! all the contracts are generated together when the task succeeds.
+use crate::data::{ContractState, DeltasInterface, EncryptedPatch};
+use crate::wasm_execution::WasmEngine;
+use crate::{ocalls_t, to_hex, GasKind, Runtime};
+use enigma_tools_t::common::errors_t::{EnclaveError, EnclaveError::*, FailedTaskError::*, WasmError};
+use enigma_crypto::{hash::Keccak256, Encryption};
+use enigma_types::{ContractAddress, Hash256, RawPointer, StateKey};
+use std::string::{String, ToString};
+use std::vec::Vec;
+use std::{mem, str};
+use wasmi::RuntimeArgs;
+
+/// The maximum number of contracts that can run at the same time in one task, including the called one.
+pub const MAX_CALL_DEPTH: usize = 8;
+
+/// Looks up the state key of a contract, the keys are held by the enclave.
+pub type StateKeyLookup = fn(ContractAddress) -> Result<StateKey, EnclaveError>;
+
+/// Everything a contract needs to call other contracts.
+#[derive(Debug, Clone)]
+pub struct CallEnvironment {
+    pub db_ptr: *const RawPointer,
+    pub state_key: StateKeyLookup,
+    /// The contracts that are running, the last one is the current contract.
+    /// They can't be called again until they return.
+    pub stack: Vec<ContractAddress>,
+}
+
+impl CallEnvironment {
+    pub fn new(db_ptr: *const RawPointer, state_key: StateKeyLookup, address: ContractAddress) -> Self {
+        CallEnvironment { db_ptr, state_key, stack: vec![address] }
+    }
+}
+
+/// The state of a contract called during the task, before its first call and after its last one.
+#[derive(Debug, Clone)]
+pub struct CalleeState {
+    pub pre_execution_state: ContractState,
+    pub post_execution_state: ContractState,
+    pub key: StateKey,
+    /// The hash of the code the callee ran.
+    pub code_hash: Hash256,
+}
+
+/// The delta of a contract called during the task and its updated state.
+#[derive(Debug, Clone)]
+pub struct CalleeResult {
+    pub state_delta: EncryptedPatch,
+    pub updated_state: ContractState,
+    pub code_hash: Hash256,
+    /// The hash of the delta the callee's state was at before the task.
+    pub pre_delta_hash: Hash256,
+}
+
+impl CalleeState {
+    /// Generate the delta of the callee, if its state was changed.
+    pub fn into_result(mut self) -> Result<Option<CalleeResult>, EnclaveError> {
+        if self.pre_execution_state == self.post_execution_state {
+            return Ok(None);
+        }
+        let state_delta = ContractState::generate_delta_and_update_state(&self.pre_execution_state, &mut self.post_execution_state, &self.key)?;
+        let pre_delta_hash = self.pre_execution_state.delta_hash;
+        Ok(Some(CalleeResult { state_delta, updated_state: self.post_execution_state, code_hash: self.code_hash, pre_delta_hash }))
+    }
+}
+
+impl CalleeResult {
+    /// Serialize the deltas of the callees to be returned from the enclave.
+    pub fn serialize_deltas(callees: &[CalleeResult]) -> Result<Vec<u8>, EnclaveError> {
+        let deltas: Vec<&EncryptedPatch> = callees.iter().map(|callee| &callee.state_delta).collect();
+        Ok(rmps::to_vec(&deltas)?)
+    }
+}
+
+fn call_error(message: String) -> WasmError { WasmError::EnclaveError(FailedTaskError(ContractCallError { message })) }
+
+impl Runtime {
+    /// args:
+    /// * `address` - the address in memory of the 32 bytes address of the called contract
+    /// * `function` - the start address of the name of the called function in memory
+    /// * `function_len` - the length of the name
+    /// * `args` - the start address of the ABI encoded arguments in memory
+    /// * `args_len` - the length of the arguments
+    ///
+    /// Call a function of another secret contract and return the length of its output,
+    /// which is then copied into the memory with `fetch_call_result`.
+    /// If the called function fails, the task fails with its error.
+    pub fn call_contract(&mut self, args: RuntimeArgs) -> Result<i32, WasmError> {
+        let address_ptr: u32 = args.nth_checked(0)?;
+        let mut address = ContractAddress::default();
+        self.memory.get_into(address_ptr, &mut address[..])?;
+        let function_ptr: u32 = args.nth_checked(1)?;
+        let function_len: u32 = args.nth_checked(2)?;
+        let function = self.memory.get(function_ptr, function_len as usize)?;
+        let call_args_ptr: u32 = args.nth_checked(3)?;
+        let call_args_len: u32 = args.nth_checked(4)?;
+        let call_args = self.memory.get(call_args_ptr, call_args_len as usize)?;
+
+        // The function can be given with its types, as in the signature of a task
+        let function_name = str::from_utf8(&function).unwrap_or_default();
+        let function_name = function_name.split('(').next().unwrap_or_default().to_string();
+
+        let missing = || call_error(format!("the contract {} doesn't exist", to_hex(&address[..])));
+        let mut calls = self.calls.clone().ok_or_else(|| call_error("contracts can only be called in compute tasks".to_string()))?;
+        if calls.stack.len() >= MAX_CALL_DEPTH {
+            return Err(call_error(format!("the call depth is limited to {} contracts", MAX_CALL_DEPTH)));
+        }
+        if calls.stack.contains(&address) {
+            return Err(call_error(format!("the contract {} is already running", to_hex(&address[..]))));
+        }
+        let gas_amount = self.gas.costs.call_contract;
+        self.charge_gas(GasKind::Call, gas_amount)?;
+
+        // A callee that was already called in this task continues from its changed state
+        let position = self.callees.iter().position(|callee| callee.post_execution_state.contract_address == address);
+        let (pre_execution_state, state, key) = match position {
+            Some(position) => {
+                let callee = self.callees.remove(position);
+                (callee.pre_execution_state, callee.post_execution_state, callee.key)
+            }
+            None => {
+                let key = (calls.state_key)(address).map_err(|_| missing())?;
+                let enc_state = ocalls_t::get_state(calls.db_ptr, address).map_err(|_| missing())?;
+                let state = ContractState::decrypt(enc_state, &key)?;
+                (state.clone(), state, key)
+            }
+        };
+        let code = ocalls_t::get_contract(calls.db_ptr, address).map_err(|_| missing())?;
+        let code_hash = code.keccak256();
+
+        calls.stack.push(address);
+        let gas_left = self.gas.limit - self.gas.counter;
+        let mut engine = WasmEngine::new_compute(&code, gas_left, call_args, state, function_name, key, self.context)?;
+        engine.runtime.calls = Some(calls);
+        // The callees are handed to the nested call so it sees their changes, and are taken back when it returns
+        engine.runtime.callees = mem::replace(&mut self.callees, Vec::new());
+        if self.view {
+            engine.runtime.begin_view();
+        }
+
+        let mut callee = match engine.compute() {
+            Ok(()) => engine.runtime,
+            Err(FailedTaskErrorWithGas { used_gas, err }) => {
+                self.charge_gas(GasKind::Call, used_gas)?;
+                return Err(WasmError::EnclaveError(FailedTaskError(err)));
+            }
+            Err(e) => return Err(WasmError::EnclaveError(e)),
+        };
+        let used_gas = callee.gas.used();
+        self.charge_gas(GasKind::Call, used_gas)?;
+
+        self.callees = mem::replace(&mut callee.callees, Vec::new());
+        self.callees.push(CalleeState { pre_execution_state, post_execution_state: callee.post_execution_state, key: callee.key, code_hash });
+        self.result.events.append(&mut callee.result.events);
+        // A task has one Ethereum payload, so only one of the contracts can write it
+        if let Some(bridge) = callee.result.ethereum_bridge.take() {
+            if self.result.ethereum_bridge.is_some() {
+                let message = format!("the contract {} wrote to the Ethereum bridge, which was already written in this task", to_hex(&address[..]));
+                return Err(call_error(message));
+            }
+            self.result.ethereum_bridge = Some(bridge);
+            self.callee_bridge = true;
+        }
+        self.call_result = callee.result.result;
+        Ok(self.call_result.len() as i32)
+    }
+
+    /// args:
+    /// * `ptr` - the address in memory to copy the output of the last `call_contract` into
+    pub fn fetch_call_result(&mut self, args: RuntimeArgs) -> Result<(), WasmError> {
+        let ptr: u32 = args.nth_checked(0)?;
+
+        self.memory.set(ptr, &self.call_result)?;
+        Ok(())
+    }
+
+    /// Allow the contract to call other contracts, compute tasks enable it before running the contract.
+    pub fn enable_calls(&mut self, calls: CallEnvironment) { self.calls = Some(calls); }
+}
@@ -6,6 +6,7 @@ use json_patch;
 use rmps::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
 use std::vec::Vec;
+use data::StateEncoding;
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct StatePatch {
@@ -15,6 +16,8 @@ pub struct StatePatch {
     pub contract_address: ContractAddress,
     #[serde(skip)]
     pub index: u32,
+    #[serde(default, skip_serializing_if = "StateEncoding::is_json")]
+    pub encoding: StateEncoding,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Default)]
@@ -0,0 +1,58 @@
+use enigma_tools_t::common::errors_t::{EnclaveError, EnclaveError::*, FailedTaskError::*};
+use rmps;
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+use std::vec::Vec;
+
+/// The encoding a contract uses for the values it writes to and reads from its state.
+///
+/// Values are always kept as `serde_json::Value` inside the enclave (so deltas stay JSON-patches),
+/// the encoding only decides how a value crosses the wasm boundary and therefore how many bytes
+/// are charged for writing it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum StateEncoding {
+    Json,
+    MsgPack,
+}
+
+impl Default for StateEncoding {
+    fn default() -> Self { StateEncoding::Json }
+}
+
+impl StateEncoding {
+    pub fn is_json(&self) -> bool { *self == StateEncoding::Json }
+
+    /// Serialize a state value the way the contract expects to receive it.
+    pub fn encode(&self, value: &Value) -> Result<Vec<u8>, EnclaveError> {
+        match self {
+            StateEncoding::Json => Ok(serde_json::to_vec(value).expect("Failed converting Value to vec")),
+            StateEncoding::MsgPack => Ok(rmps::to_vec(value)?),
+        }
+    }
+
+    /// Deserialize a value written by the contract.
+    /// Note that MessagePack `bin` values are rejected since they have no JSON representation,
+    /// byte arrays should be serialized as sequences (which is the serde default for `Vec<u8>`).
+    pub fn decode(&self, bytes: &[u8]) -> Result<Value, EnclaveError> {
+        match self {
+            StateEncoding::Json => serde_json::from_slice(bytes)
+                .map_err(|e| FailedTaskError(WasmCodeExecutionError { err: format!("Failed decoding a JSON state value: {}", e) })),
+            StateEncoding::MsgPack => rmps::from_slice(bytes)
+                .map_err(|e| FailedTaskError(WasmCodeExecutionError { err: format!("Failed decoding a MessagePack state value: {:?}", e) })),
+        }
+    }
+
+    /// The length of `value` once encoded, the null value is considered empty.
+    pub fn encoded_len(&self, value: &Value) -> Result<u64, EnclaveError> {
+        if value.is_null() {
+            return Ok(0);
+        }
+        Ok(self.encode(value)?.len() as u64)
+    }
+
+    pub(crate) fn mismatch_error(&self, requested: StateEncoding) -> EnclaveError {
+        FailedTaskError(WasmCodeExecutionError {
+            err: format!("The contract state is encoded as {:?} and can't be accessed as {:?}", self, requested),
+        })
+    }
+}
@@ -1,7 +1,9 @@
 mod delta;
+mod encoding;
 mod state;
 
 pub use data::delta::{EncryptedPatch, StatePatch};
+pub use data::encoding::StateEncoding;
 pub use data::state::{ContractState, EncryptedContractState};
 use serde::Deserialize;
 use serde_json::{Error, Value};
@@ -99,7 +101,7 @@ pub mod tests {
         let before = json!({ "title": "Goodbye!","author" : { "name1" : "John", "name2" : "Doe"}, "tags":[ "first", "second" ] });
         let after = json!({ "author" : {"name1" : "John", "name2" : "Lennon"},"tags": [ "first", "second", "third"] });
         let patch =
-            StatePatch { patch: json_patch::diff(&before, &after), previous_hash: [0u8; 32].into(), contract_address: [1u8; 32].into(), index: 0 };
+            StatePatch { patch: json_patch::diff(&before, &after), previous_hash: [0u8; 32].into(), contract_address: [1u8; 32].into(), index: 0, encoding: StateEncoding::Json };
         assert_eq!(serde_json::to_string(&patch.patch).unwrap(), "[{\"op\":\"replace\",\"path\":\"/author/name2\",\"value\":\"Lennon\"},{\"op\":\"add\",\"path\":\"/tags/2\",\"value\":\"third\"},{\"op\":\"remove\",\"path\":\"/title\"}]");
     }
 
@@ -107,7 +109,7 @@ pub mod tests {
         let s = "[{\"op\":\"replace\",\"path\":\"/author/name2\",\"value\":\"Lennon\"},{\"op\":\"add\",\"path\":\"/tags/2\",\"value\":\"third\"},{\"op\":\"remove\",\"path\":\"/title\"}]";
         let contract_address: ContractAddress = [1u8; 32].into();
         let index = 99;
-        let patch = StatePatch { patch: serde_json::from_str(s).unwrap(), previous_hash: [0u8; 32].into(), contract_address, index };
+        let patch = StatePatch { patch: serde_json::from_str(s).unwrap(), previous_hash: [0u8; 32].into(), contract_address, index, encoding: StateEncoding::Json };
 
         let key = b"EnigmaMPC".sha256();
         let iv = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
@@ -121,7 +123,7 @@ pub mod tests {
     pub fn test_decrypt_patch() {
         let s = "[{\"op\":\"replace\",\"path\":\"/author/name2\",\"value\":\"Lennon\"},{\"op\":\"add\",\"path\":\"/tags/2\",\"value\":\"third\"},{\"op\":\"remove\",\"path\":\"/title\"}]";
         let contract_address: ContractAddress = [1u8; 32].into();
-        let patch = StatePatch { patch: serde_json::from_str(s).unwrap(), previous_hash: [0u8; 32].into(), contract_address, index: 0 };
+        let patch = StatePatch { patch: serde_json::from_str(s).unwrap(), previous_hash: [0u8; 32].into(), contract_address, index: 0, encoding: StateEncoding::Json };
 
         let key = b"EnigmaMPC".sha256();
         let enc_data = vec![196, 39, 143, 237, 10, 117, 249, 235, 174, 84, 130, 219, 214, 92, 182, 148, 87, 171, 131, 69, 32, 201, 192, 190, 253, 176, 230, 5, 20, 221, 171, 31, 37, 51, 29, 231, 134, 147, 234, 255, 104, 144, 161, 110, 192, 28, 187, 143, 184, 188, 211, 219, 36, 117, 28, 51, 160, 204, 97, 250, 153, 193, 86, 194, 169, 111, 124, 202, 195, 44, 170, 109, 98, 164, 203, 177, 27, 246, 129, 8, 132, 12, 232, 104, 130, 98, 155, 7, 137, 89, 113, 187, 197, 211, 191, 246, 97, 112, 71, 240, 162, 35, 176, 216, 26, 97, 90, 218, 197, 244, 94, 225, 184, 235, 75, 198, 205, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
@@ -133,7 +135,7 @@ pub mod tests {
 
     pub fn test_encrypt_decrypt_patch() {
         let s = "[{\"op\":\"replace\",\"path\":\"/author/name2\",\"value\":\"Lennon\"},{\"op\":\"add\",\"path\":\"/tags/2\",\"value\":\"third\"},{\"op\":\"remove\",\"path\":\"/title\"}]";
-        let patch = StatePatch { patch: serde_json::from_str(s).unwrap(), previous_hash: [0u8; 32].into(), contract_address: [1u8; 32].into(), index: 0 };
+        let patch = StatePatch { patch: serde_json::from_str(s).unwrap(), previous_hash: [0u8; 32].into(), contract_address: [1u8; 32].into(), index: 0, encoding: StateEncoding::Json };
 
         let key = b"EnigmaMPC".sha256();
         let iv = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
@@ -146,7 +148,7 @@ pub mod tests {
         let p = "[{\"op\":\"replace\",\"path\":\"/author/name2\",\"value\":\"Lennon\"},{\"op\":\"add\",\"path\":\"/tags/2\",\"value\":\"third\"},{\"op\":\"remove\",\"path\":\"/title\"}]";
         let contract_address = b"Enigma".sha256();
         let key = [1u8; 32];
-        let patch = StatePatch { patch: serde_json::from_str(p).unwrap(), previous_hash: [4u8; 32].into(), contract_address, index: 1 };
+        let patch = StatePatch { patch: serde_json::from_str(p).unwrap(), previous_hash: [4u8; 32].into(), contract_address, index: 1, encoding: StateEncoding::Json };
         let enc_patch = patch.encrypt(&key).unwrap();
         let delta_hash = enc_patch.keccak256_patch();
         let mut contract = ContractState {
@@ -154,6 +156,7 @@ pub mod tests {
             json: json!({ "title": "Goodbye!","author" : { "name1" : "John", "name2" : "Doe"}, "tags":[ "first", "second" ] }),
             delta_hash: [4u8; 32].into(),
             delta_index: 0,
+            encoding: StateEncoding::Json,
         };
         contract.apply_delta(enc_patch, &key).unwrap();
         assert_eq!(
@@ -163,6 +166,7 @@ pub mod tests {
                 json: json!({ "author" : {"name1" : "John", "name2" : "Lennon"},"tags": [ "first", "second", "third"] }),
                 delta_hash,
                 delta_index: 1,
+                encoding: StateEncoding::Json,
             }
         );
     }
@@ -171,22 +175,66 @@ pub mod tests {
         let p = "[{\"op\":\"replace\",\"path\":\"/author/name2\",\"value\":\"Lennon\"},{\"op\":\"add\",\"path\":\"/tags/2\",\"value\":\"third\"},{\"op\":\"remove\",\"path\":\"/title\"}]";
         let contract_address = b"Enigma".sha256();
         let key = [1u8; 32];
-        let result = StatePatch { patch: serde_json::from_str(p).unwrap(), previous_hash: [4u8; 32].into(), contract_address, index: 1 };
+        let result = StatePatch { patch: serde_json::from_str(p).unwrap(), previous_hash: [4u8; 32].into(), contract_address, index: 1, encoding: StateEncoding::Json };
         let before = ContractState {
             contract_address,
             json: json!({ "title": "Goodbye!","author" : { "name1" : "John", "name2" : "Doe"}, "tags":[ "first", "second" ] }),
             delta_hash: [4u8; 32].into(),
             delta_index: 0,
+            encoding: StateEncoding::Json,
         };
         let mut after = ContractState {
             contract_address,
             json: json!({ "author" : {"name1" : "John", "name2" : "Lennon"},"tags": [ "first", "second", "third"] }),
             delta_hash: [4u8; 32].into(),
             delta_index: 0,
+            encoding: StateEncoding::Json,
         };
 
         let delta = ContractState::generate_delta_and_update_state(&before, &mut after, &key).unwrap();
         let delta = StatePatch::decrypt(delta, &key).unwrap();
         assert_eq!(delta, result);
     }
+
+    pub fn test_encoding_round_trip() {
+        let value = json!({"id": "enigma", "address": [17u8; 20], "sig": vec![200u8; 65], "nonce": 42, "active": true});
+        for encoding in &[StateEncoding::Json, StateEncoding::MsgPack] {
+            let encoded = encoding.encode(&value).unwrap();
+            assert_eq!(encoding.decode(&encoded).unwrap(), value);
+        }
+    }
+
+    pub fn test_msgpack_encoding_is_smaller() {
+        // Byte arrays are the main offender in JSON since every byte can take up to 4 characters.
+        let value = json!({"address": [200u8; 20], "sig": vec![255u8; 65]});
+        let json_len = StateEncoding::Json.encoded_len(&value).unwrap();
+        let msgpack_len = StateEncoding::MsgPack.encoded_len(&value).unwrap();
+        assert!(msgpack_len < json_len, "msgpack: {}, json: {}", msgpack_len, json_len);
+        assert_eq!(StateEncoding::MsgPack.encoded_len(&Value::Null).unwrap(), 0);
+    }
+
+    pub fn test_encoding_is_fixed_after_write() {
+        let mut con = ContractState::new(b"Enigma".sha256());
+        con.ensure_encoding(StateEncoding::MsgPack).unwrap();
+        con.write_key("code", &json!(200)).unwrap();
+        assert!(con.ensure_encoding(StateEncoding::Json).is_err());
+        con.remove_key("code");
+        con.ensure_encoding(StateEncoding::Json).unwrap();
+        assert_eq!(con.encoding, StateEncoding::Json);
+    }
+
+    pub fn test_encoding_survives_delta() {
+        let contract_address = b"Enigma".sha256();
+        let key = [1u8; 32];
+        let before = ContractState::new(contract_address);
+        let mut after = before.clone();
+        after.ensure_encoding(StateEncoding::MsgPack).unwrap();
+        after.write_key("code", &json!(200)).unwrap();
+
+        let delta = ContractState::generate_delta_and_update_state(&before, &mut after, &key).unwrap();
+        let mut rebuilt = ContractState::new(contract_address);
+        rebuilt.apply_delta(delta, &key).unwrap();
+        assert_eq!(rebuilt, after);
+        assert_eq!(rebuilt.encoding, StateEncoding::MsgPack);
+    }
 }
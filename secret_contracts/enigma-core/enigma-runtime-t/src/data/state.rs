@@ -1,4 +1,4 @@
-use crate::data::{DeltasInterface, IOInterface, StatePatch};
+use crate::data::{DeltasInterface, IOInterface, StatePatch, StateEncoding};
 use enigma_tools_t::common::errors_t::{EnclaveError, EnclaveError::*, EnclaveSystemError::*};
 use enigma_types::{ContractAddress, StateKey};
 use enigma_crypto::{symmetric, Encryption};
@@ -6,8 +6,8 @@ use enigma_types::Hash256;
 use json_patch;
 use rmps::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
-use serde_json::{from_value, Error, Value};
-use std::string::ToString;
+use serde_json::{from_value, Error, Map, Value};
+use std::string::{String, ToString};
 use std::vec::Vec;
 use data::EncryptedPatch;
 
@@ -18,6 +18,9 @@ pub struct ContractState {
     pub json: Value,
     pub delta_hash: Hash256,
     pub delta_index: u32,
+    // Kept last and skipped for JSON so states serialized before binary encodings existed still decode.
+    #[serde(default, skip_serializing_if = "StateEncoding::is_json")]
+    pub encoding: StateEncoding,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -35,6 +38,44 @@ impl ContractState {
     pub fn is_initial(&self) -> bool{
         self.delta_index == 0 && self.delta_hash.is_zero()
     }
+
+    /// Returns true if no key was ever written to the state (or all of them were removed).
+    pub fn is_empty(&self) -> bool {
+        self.json.as_object().map_or(true, |obj| obj.is_empty())
+    }
+
+    /// Returns true if the contract allowed the owner of the Ethereum `address` to inspect its state.
+    pub fn is_inspector(&self, address: &[u8; 20]) -> bool {
+        let address = crate::to_hex(address);
+        self.json[crate::INSPECTORS_KEY]
+            .as_array()
+            .map_or(false, |inspectors| inspectors.iter().any(|inspector| inspector.as_str() == Some(&address)))
+    }
+
+    /// Make sure the state can be written with `encoding`.
+    /// The encoding can only change while the state is empty, after that it's fixed for the contract.
+    pub fn ensure_encoding(&mut self, encoding: StateEncoding) -> Result<(), EnclaveError> {
+        self.check_encoding(encoding)?;
+        self.encoding = encoding;
+        Ok(())
+    }
+
+    /// Make sure the state can be read with `encoding`, without changing the encoding of an empty state.
+    pub fn check_encoding(&self, encoding: StateEncoding) -> Result<(), EnclaveError> {
+        if self.encoding == encoding || self.is_empty() {
+            Ok(())
+        } else {
+            Err(self.encoding.mismatch_error(encoding))
+        }
+    }
+
+    /// Returns a key that has another value in `other`, or `None` if they have the same keys and values.
+    pub fn changed_key(&self, other: &ContractState) -> Option<String> {
+        let empty = Map::new();
+        let keys = self.json.as_object().unwrap_or(&empty);
+        let other_keys = other.json.as_object().unwrap_or(&empty);
+        keys.keys().chain(other_keys.keys()).find(|key| keys.get(*key) != other_keys.get(*key)).cloned()
+    }
 }
 
 impl IOInterface<EnclaveError, u8> for ContractState {
@@ -63,6 +104,7 @@ impl<'a> DeltasInterface<EnclaveError, EncryptedPatch, &'a StateKey> for Contrac
             return Err(SystemError(StateError { err: "Hashes don't match, Failed Applying the delta".to_string() }));
         }
         json_patch::patch(&mut self.json, &dec_delta.patch)?;
+        self.encoding = dec_delta.encoding;
         self.delta_hash = delta_hash;
         self.delta_index = dec_delta.index;
         Ok(())
@@ -79,6 +121,7 @@ impl<'a> DeltasInterface<EnclaveError, EncryptedPatch, &'a StateKey> for Contrac
             previous_hash: old.delta_hash,
             contract_address: old.contract_address,
             index: new.delta_index,
+            encoding: new.encoding,
         };
         let enc_delta = delta.encrypt(key)?;
         new.delta_hash = enc_delta.keccak256_patch();
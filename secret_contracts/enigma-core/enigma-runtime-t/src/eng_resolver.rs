@@ -22,6 +22,70 @@ pub mod ids {
     pub const RAND_FUNC: usize = 15;
     pub const ENCRYPT_FUNC: usize = 16;
     pub const DECRYPT_FUNC: usize = 17;
+    pub const WRITE_STATE_MSGPACK_FUNC: usize = 18;
+    pub const READ_STATE_MSGPACK_FUNC: usize = 19;
+    pub const READ_STATE_LEN_MSGPACK_FUNC: usize = 20;
+    pub const BLOCK_NUMBER_FUNC: usize = 21;
+    pub const CONTRACT_ADDRESS_FUNC: usize = 22;
+    pub const TASK_ID_FUNC: usize = 23;
+    pub const USER_PUBKEY_FUNC: usize = 24;
+    pub const KECCAK256_FUNC: usize = 25;
+    pub const SHA256_FUNC: usize = 26;
+    pub const ECRECOVER_FUNC: usize = 27;
+    pub const ENCRYPT_WITH_AAD_FUNC: usize = 28;
+    pub const DECRYPT_WITH_AAD_FUNC: usize = 29;
+    pub const DERIVE_KEY_FUNC: usize = 30;
+    pub const DECRYPT_WITH_STATUS_FUNC: usize = 31;
+    pub const EMIT_EVENT_FUNC: usize = 32;
+    pub const REVERT_FUNC: usize = 33;
+    pub const BEGIN_VIEW_FUNC: usize = 34;
+    pub const APPROVE_UPGRADE_FUNC: usize = 35;
+    pub const CALL_CONTRACT_FUNC: usize = 36;
+    pub const FETCH_CALL_RESULT_FUNC: usize = 37;
+    pub const SET_STATE_INSPECTORS_FUNC: usize = 38;
+
+    /// The name the host function with `index` is imported by.
+    pub fn name(index: usize) -> &'static str {
+        match index {
+            RET_FUNC => "ret",
+            WRITE_STATE_FUNC => "write_state",
+            READ_STATE_FUNC => "read_state",
+            READ_STATE_LEN_FUNC => "read_state_len",
+            EPRINT_FUNC => "eprint",
+            NAME_LENGTH_FUNC => "fetch_function_name_length",
+            NAME_FUNC => "fetch_function_name",
+            ARGS_FUNC => "fetch_args",
+            ARGS_LENGTH_FUNC => "fetch_args_length",
+            WRITE_ETH_BRIDGE_FUNC => "write_eth_bridge",
+            REMOVE_STATE_FUNC => "remove_from_state",
+            GAS_FUNC => "gas",
+            RAND_FUNC => "rand",
+            ENCRYPT_FUNC => "encrypt",
+            DECRYPT_FUNC => "decrypt",
+            WRITE_STATE_MSGPACK_FUNC => "write_state_msgpack",
+            READ_STATE_MSGPACK_FUNC => "read_state_msgpack",
+            READ_STATE_LEN_MSGPACK_FUNC => "read_state_len_msgpack",
+            BLOCK_NUMBER_FUNC => "fetch_block_number",
+            CONTRACT_ADDRESS_FUNC => "fetch_contract_address",
+            TASK_ID_FUNC => "fetch_task_id",
+            USER_PUBKEY_FUNC => "fetch_user_pubkey",
+            KECCAK256_FUNC => "keccak256",
+            SHA256_FUNC => "sha256",
+            ECRECOVER_FUNC => "ecrecover",
+            ENCRYPT_WITH_AAD_FUNC => "encrypt_with_aad",
+            DECRYPT_WITH_AAD_FUNC => "decrypt_with_aad",
+            DERIVE_KEY_FUNC => "derive_key",
+            DECRYPT_WITH_STATUS_FUNC => "decrypt",
+            EMIT_EVENT_FUNC => "emit_event",
+            REVERT_FUNC => "revert",
+            BEGIN_VIEW_FUNC => "begin_view",
+            APPROVE_UPGRADE_FUNC => "approve_upgrade",
+            CALL_CONTRACT_FUNC => "call_contract",
+            FETCH_CALL_RESULT_FUNC => "fetch_call_result",
+            SET_STATE_INSPECTORS_FUNC => "set_state_inspectors",
+            _ => "unknown",
+        }
+    }
 }
 
 pub mod signatures {
@@ -52,6 +116,20 @@ pub mod signatures {
 
     pub const WRITE_ETH_BRIDGE: StaticSignature = StaticSignature(&[I32, I32, I32], None);
 
+    pub const EMIT_EVENT: StaticSignature = StaticSignature(&[I32, I32, I32, I32, I32], None);
+
+    pub const REVERT: StaticSignature = StaticSignature(&[I32, I32], None);
+
+    pub const BEGIN_VIEW: StaticSignature = StaticSignature(&[], None);
+
+    pub const APPROVE_UPGRADE: StaticSignature = StaticSignature(&[I32], None);
+
+    pub const CALL_CONTRACT: StaticSignature = StaticSignature(&[I32, I32, I32, I32, I32], Some(I32));
+
+    pub const FETCH_CALL_RESULT: StaticSignature = StaticSignature(&[I32], None);
+
+    pub const SET_STATE_INSPECTORS: StaticSignature = StaticSignature(&[I32, I32], None);
+
     pub const GAS: StaticSignature = StaticSignature(&[I32], None);
 
     pub const RAND: StaticSignature = StaticSignature(&[I32, I32], None);
@@ -60,6 +138,28 @@ pub mod signatures {
 
     pub const DECRYPT: StaticSignature = StaticSignature(&[I32, I32, I32, I32], None);
 
+    pub const DECRYPT_WITH_STATUS: StaticSignature = StaticSignature(&[I32, I32, I32, I32], Some(I32));
+
+    pub const BLOCK_NUMBER: StaticSignature = StaticSignature(&[], Some(I64));
+
+    pub const CONTRACT_ADDRESS: StaticSignature = StaticSignature(&[I32], None);
+
+    pub const TASK_ID: StaticSignature = StaticSignature(&[I32], Some(I32));
+
+    pub const USER_PUBKEY: StaticSignature = StaticSignature(&[I32], None);
+
+    pub const KECCAK256: StaticSignature = StaticSignature(&[I32, I32, I32], None);
+
+    pub const SHA256: StaticSignature = StaticSignature(&[I32, I32, I32], None);
+
+    pub const ECRECOVER: StaticSignature = StaticSignature(&[I32, I32, I32, I32], Some(I32));
+
+    pub const ENCRYPT_WITH_AAD: StaticSignature = StaticSignature(&[I32, I32, I32, I32, I32, I32, I32], None);
+
+    pub const DECRYPT_WITH_AAD: StaticSignature = StaticSignature(&[I32, I32, I32, I32, I32, I32], Some(I32));
+
+    pub const DERIVE_KEY: StaticSignature = StaticSignature(&[I32, I32, I32, I32, I32, I32], None);
+
     impl Into<wasmi::Signature> for StaticSignature {
         fn into(self) -> wasmi::Signature { wasmi::Signature::new(self.0, self.1) }
     }
@@ -102,7 +202,7 @@ impl ImportResolver {
 }
 
 impl ModuleImportResolver for ImportResolver {
-    fn resolve_func(&self, field_name: &str, _signature: &Signature) -> Result<FuncRef, Error> {
+    fn resolve_func(&self, field_name: &str, signature: &Signature) -> Result<FuncRef, Error> {
         let func_ref = match field_name {
             "ret" => FuncInstance::alloc_host(signatures::RET.into(), ids::RET_FUNC),
             "write_state" => FuncInstance::alloc_host(signatures::WRITE_STATE.into(), ids::WRITE_STATE_FUNC),
@@ -118,7 +218,30 @@ impl ModuleImportResolver for ImportResolver {
             "gas" => FuncInstance::alloc_host(signatures::GAS.into(), ids::GAS_FUNC),
             "rand" => FuncInstance::alloc_host(signatures::RAND.into(), ids::RAND_FUNC),
             "encrypt" => FuncInstance::alloc_host(signatures::ENCRYPT.into(), ids::ENCRYPT_FUNC),
+            // Contracts built before `decrypt` reported authentication failures import it without a return value.
+            "decrypt" if signature.return_type().is_some() =>
+                FuncInstance::alloc_host(signatures::DECRYPT_WITH_STATUS.into(), ids::DECRYPT_WITH_STATUS_FUNC),
             "decrypt" => FuncInstance::alloc_host(signatures::DECRYPT.into(), ids::DECRYPT_FUNC),
+            "write_state_msgpack" => FuncInstance::alloc_host(signatures::WRITE_STATE.into(), ids::WRITE_STATE_MSGPACK_FUNC),
+            "read_state_len_msgpack" => FuncInstance::alloc_host(signatures::READ_STATE_LEN.into(), ids::READ_STATE_LEN_MSGPACK_FUNC),
+            "read_state_msgpack" => FuncInstance::alloc_host(signatures::READ_STATE.into(), ids::READ_STATE_MSGPACK_FUNC),
+            "fetch_block_number" => FuncInstance::alloc_host(signatures::BLOCK_NUMBER.into(), ids::BLOCK_NUMBER_FUNC),
+            "fetch_contract_address" => FuncInstance::alloc_host(signatures::CONTRACT_ADDRESS.into(), ids::CONTRACT_ADDRESS_FUNC),
+            "fetch_task_id" => FuncInstance::alloc_host(signatures::TASK_ID.into(), ids::TASK_ID_FUNC),
+            "fetch_user_pubkey" => FuncInstance::alloc_host(signatures::USER_PUBKEY.into(), ids::USER_PUBKEY_FUNC),
+            "keccak256" => FuncInstance::alloc_host(signatures::KECCAK256.into(), ids::KECCAK256_FUNC),
+            "sha256" => FuncInstance::alloc_host(signatures::SHA256.into(), ids::SHA256_FUNC),
+            "ecrecover" => FuncInstance::alloc_host(signatures::ECRECOVER.into(), ids::ECRECOVER_FUNC),
+            "encrypt_with_aad" => FuncInstance::alloc_host(signatures::ENCRYPT_WITH_AAD.into(), ids::ENCRYPT_WITH_AAD_FUNC),
+            "decrypt_with_aad" => FuncInstance::alloc_host(signatures::DECRYPT_WITH_AAD.into(), ids::DECRYPT_WITH_AAD_FUNC),
+            "derive_key" => FuncInstance::alloc_host(signatures::DERIVE_KEY.into(), ids::DERIVE_KEY_FUNC),
+            "emit_event" => FuncInstance::alloc_host(signatures::EMIT_EVENT.into(), ids::EMIT_EVENT_FUNC),
+            "revert" => FuncInstance::alloc_host(signatures::REVERT.into(), ids::REVERT_FUNC),
+            "begin_view" => FuncInstance::alloc_host(signatures::BEGIN_VIEW.into(), ids::BEGIN_VIEW_FUNC),
+            "approve_upgrade" => FuncInstance::alloc_host(signatures::APPROVE_UPGRADE.into(), ids::APPROVE_UPGRADE_FUNC),
+            "call_contract" => FuncInstance::alloc_host(signatures::CALL_CONTRACT.into(), ids::CALL_CONTRACT_FUNC),
+            "fetch_call_result" => FuncInstance::alloc_host(signatures::FETCH_CALL_RESULT.into(), ids::FETCH_CALL_RESULT_FUNC),
+            "set_state_inspectors" => FuncInstance::alloc_host(signatures::SET_STATE_INSPECTORS.into(), ids::SET_STATE_INSPECTORS_FUNC),
             _ => return Err(wasmi::Error::Instantiation(format!("Export {} not found", field_name))),
         };
 
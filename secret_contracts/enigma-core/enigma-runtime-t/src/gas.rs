@@ -1,5 +1,6 @@
 
 pub use pwasm_utils::{inject_gas_counter, rules};
+use std::cmp;
 
 /// Wasm cost table
 pub struct WasmCosts {
@@ -67,6 +68,22 @@ pub struct RuntimeWasmCosts {
     pub write_additional_byte: u64,
     pub deploy_byte: u64,
     pub execution: u64,
+    pub keccak256: u64,
+    pub sha256: u64,
+    pub ecrecover: u64,
+    pub emit_event: u64,
+    pub emit_event_byte: u64,
+    pub call_contract: u64,
+    pub read_value: u64,
+    pub read_byte: u64,
+    pub remove_value: u64,
+    pub hash_byte: u64,
+    pub rand: u64,
+    pub rand_byte: u64,
+    pub encrypt: u64,
+    pub decrypt: u64,
+    pub crypto_byte: u64,
+    pub derive_key: u64,
 }
 
 impl Default for RuntimeWasmCosts {
@@ -76,6 +93,22 @@ impl Default for RuntimeWasmCosts {
             write_additional_byte: 1,
             deploy_byte: 1,
             execution: 10_000,
+            keccak256: 30,
+            sha256: 60,
+            ecrecover: 3_000,
+            emit_event: 10,
+            emit_event_byte: 1,
+            call_contract: 700,
+            read_value: 5,
+            read_byte: 1,
+            remove_value: 5,
+            hash_byte: 1,
+            rand: 20,
+            rand_byte: 1,
+            encrypt: 50,
+            decrypt: 50,
+            crypto_byte: 1,
+            derive_key: 60,
         }
     }
 }
@@ -86,4 +119,72 @@ pub struct RuntimeGas {
     pub limit: u64,
     pub refund: u64,
     pub costs: RuntimeWasmCosts,
+    pub breakdown: GasBreakdown,
+}
+
+/// At most `1 / MAX_REFUND_QUOTIENT` of the charged gas is refunded, the same cap EIP-3529 puts on Ethereum refunds,
+/// so freeing storage can make a task cheaper but never free.
+pub const MAX_REFUND_QUOTIENT: u64 = 5;
+
+impl RuntimeGas {
+    /// The part of `refund` that is deducted from the charged gas.
+    pub fn capped_refund(&self) -> u64 { cmp::min(self.refund, self.counter / MAX_REFUND_QUOTIENT) }
+
+    /// The gas charged minus the capped refund.
+    pub fn used(&self) -> u64 { self.counter - self.capped_refund() }
+}
+
+/// What the gas is charged for, every charge is accounted to one kind in the `GasBreakdown`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GasKind {
+    Execution,
+    Deployment,
+    Instructions,
+    StateWrite,
+    StateRead,
+    StateRemoval,
+    Crypto,
+    Event,
+    Call,
+}
+
+/// The gas charged by an execution for each kind of operation.
+/// The used gas is the sum of all of them minus the `refund` for freeing storage, which is capped by `MAX_REFUND_QUOTIENT`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GasBreakdown {
+    pub execution: u64,
+    pub deployment: u64,
+    pub instructions: u64,
+    pub state_writes: u64,
+    pub state_reads: u64,
+    pub state_removals: u64,
+    pub crypto: u64,
+    pub events: u64,
+    pub calls: u64,
+    pub refund: u64,
+}
+
+impl GasBreakdown {
+    pub fn add(&mut self, kind: GasKind, amount: u64) {
+        let counter = match kind {
+            GasKind::Execution => &mut self.execution,
+            GasKind::Deployment => &mut self.deployment,
+            GasKind::Instructions => &mut self.instructions,
+            GasKind::StateWrite => &mut self.state_writes,
+            GasKind::StateRead => &mut self.state_reads,
+            GasKind::StateRemoval => &mut self.state_removals,
+            GasKind::Crypto => &mut self.crypto,
+            GasKind::Event => &mut self.events,
+            GasKind::Call => &mut self.calls,
+        };
+        *counter = counter.saturating_add(amount);
+    }
+
+    /// The total gas charged before the refund.
+    pub fn total(&self) -> u64 {
+        [self.execution, self.deployment, self.instructions, self.state_writes, self.state_reads, self.state_removals,
+         self.crypto, self.events, self.calls]
+            .iter()
+            .fold(0u64, |total, amount| total.saturating_add(*amount))
+    }
 }
\ No newline at end of file
@@ -23,37 +23,144 @@ extern crate parity_wasm;
 /// The code is based on Parity wasm_utils::cli.
 extern crate pwasm_utils;
 
-use crate::data::{ContractState, DeltasInterface, IOInterface, EncryptedPatch};
-use enigma_types::{StateKey, SymmetricKey, SYMMETRIC_KEY_SIZE};
-use enigma_tools_t::common::errors_t::{EnclaveError, EnclaveError::*, EnclaveSystemError::*, WasmError};
-
-use std::{str, vec::Vec};
+use crate::calls::{CallEnvironment, CalleeResult, CalleeState};
+use crate::profile::ExecutionProfile;
+use crate::data::{ContractState, DeltasInterface, IOInterface, EncryptedPatch, StateEncoding};
+use enigma_types::{DhKey, Hash256, PubKey, StateKey, SymmetricKey, TaskContext, SYMMETRIC_KEY_SIZE};
+use enigma_tools_t::common::errors_t::{EnclaveError, EnclaveError::*, EnclaveSystemError::*, FailedTaskError::{ContractCallError, Reverted, ReservedStateKey, StateChangeInView, TooManyStateInspectors, UpgradeNotApproved}, WasmError};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fmt, str, vec::Vec};
 use std::string::{String, ToString};
 use wasmi::{MemoryRef, RuntimeArgs, RuntimeValue};
 use sgx_trts::trts::rsgx_read_rand;
-use enigma_crypto::symmetric::{encrypt, decrypt};
+use enigma_crypto::symmetric::{encrypt, decrypt, encrypt_with_aad, decrypt_with_aad, derive_key, IV, IV_SIZE};
+use enigma_crypto::{KeyPair, hash::{Keccak256, Sha256}};
 
+pub mod calls;
 pub mod data;
 pub mod eng_resolver;
 pub mod ocalls_t;
 pub mod gas;
+pub mod profile;
 pub mod wasm_execution;
 
 pub use gas::*;
 
+/// The state key under which `approve_upgrade` stores the hash of the code the contract can be upgraded to.
+pub const UPGRADE_APPROVAL_KEY: &str = "__enigma_upgrade_approval";
+/// The state key holding the code hashes of the last upgrade, so every upgrade is recorded in its delta.
+pub const UPGRADE_RECORD_KEY: &str = "__enigma_upgrade";
+/// The state key under which `set_state_inspectors` stores the Ethereum addresses allowed to inspect the state.
+pub const INSPECTORS_KEY: &str = "__enigma_inspectors";
+/// The maximum number of addresses a contract can allow to inspect its state.
+pub const MAX_STATE_INSPECTORS: u32 = 32;
+/// Contracts can't write or remove state keys with this prefix.
+const RESERVED_KEY_PREFIX: &str = "__enigma_";
+
+fn to_hex(bytes: &[u8]) -> String { bytes.iter().map(|byte| format!("{:02x}", byte)).collect() }
+
+/// The cost of an operation on `len` bytes, fails with `GasLimit` on overflow.
+fn byte_cost(base: u64, per_byte: u64, len: u64) -> Result<u64> {
+    len.checked_mul(per_byte).and_then(|v| v.checked_add(base)).ok_or(WasmError::GasLimit)
+}
+
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct EthereumData {
     pub ethereum_payload: Vec<u8>,
     pub ethereum_contract_addr: [u8; 20],
 }
 
+/// An event emitted by the contract through `emit_event`.
+/// If `encrypted` is set the `data` is encrypted with the task's user key before leaving the enclave,
+/// the `topic` is always in plain text so it can be filtered on.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ContractEvent {
+    pub topic: Vec<u8>,
+    pub data: Vec<u8>,
+    pub encrypted: bool,
+}
+
+impl ContractEvent {
+    /// Encrypt the data of the events that asked for it, and serialize all the events to be returned from the enclave.
+    /// Returns the serialized events with their hash, which is signed with the result of the task.
+    pub fn seal_all(events: &[ContractEvent], key: &DhKey) -> ::std::result::Result<(Vec<u8>, Hash256), EnclaveError> {
+        let mut sealed = Vec::with_capacity(events.len());
+        for event in events {
+            let mut event = event.clone();
+            if event.encrypted {
+                event.data = encrypt(&event.data, key)?;
+            }
+            sealed.push(event);
+        }
+        Ok((rmps::to_vec(&sealed)?, ContractEvent::keccak256_all(&sealed)))
+    }
+
+    /// The hash of the sealed events as they're returned, it's `H(H(topic, data, encrypted) for every event)`.
+    pub fn keccak256_all(sealed: &[ContractEvent]) -> Hash256 {
+        let hashes: Vec<Hash256> = sealed
+            .iter()
+            .map(|event| {
+                let encrypted = [event.encrypted as u8];
+                enigma_crypto::hash::prepare_hash_multiple(&[&event.topic[..], &event.data[..], &encrypted[..]]).keccak256()
+            })
+            .collect();
+        enigma_crypto::hash::prepare_hash_multiple(&hashes).keccak256()
+    }
+}
+
+/// The context of the task the contract is executed in.
+/// The contract can read it through the `fetch_*` functions, the contract address is taken from the state.
+#[derive(Clone, Copy)]
+pub struct ExecutionContext {
+    pub block_number: u64,
+    pub task_id: Option<Hash256>,
+    pub user_pubkey: PubKey,
+    /// Record an `ExecutionProfile` of the execution, it isn't exposed to the contract.
+    pub profile: bool,
+}
+
+impl ExecutionContext {
+    pub fn new(task: &TaskContext, user_pubkey: &PubKey) -> Self {
+        let task_id = if task.has_task_id { Some(task.task_id) } else { None };
+        ExecutionContext { block_number: task.block_number, task_id, user_pubkey: *user_pubkey, profile: task.profile }
+    }
+}
+
+impl Default for ExecutionContext {
+    fn default() -> Self {
+        ExecutionContext { block_number: 0, task_id: None, user_pubkey: [0u8; 64], profile: false }
+    }
+}
+
+impl fmt::Debug for ExecutionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExecutionContext")
+            .field("block_number", &self.block_number)
+            .field("task_id", &self.task_id)
+            .field("user_pubkey", &&self.user_pubkey[..])
+            .field("profile", &self.profile)
+            .finish()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RuntimeResult {
     pub state_delta: Option<EncryptedPatch>,
     pub updated_state: ContractState,
     pub result: Vec<u8>,
     pub ethereum_bridge: Option<EthereumData>,
+    pub events: Vec<ContractEvent>,
     pub used_gas: u64,
+    /// The executed function is a view, so the execution could not change the state.
+    pub view: bool,
+    /// The deltas of the other contracts whose state was changed by calls during the execution.
+    pub callees: Vec<CalleeResult>,
+    /// The gas charged for each kind of operation, `used_gas` is their total minus the refund.
+    pub gas_breakdown: GasBreakdown,
+    /// The profile of the execution, if the task asked for it.
+    pub profile: Option<ExecutionProfile>,
 }
 
 #[derive(Debug, Clone)]
@@ -66,6 +173,14 @@ pub struct Runtime {
     post_execution_state: ContractState,
     key: StateKey,
     gas : RuntimeGas,
+    context: ExecutionContext,
+    view: bool,
+    calls: Option<CallEnvironment>,
+    callees: Vec<CalleeState>,
+    call_result: Vec<u8>,
+    /// The Ethereum bridge was written by a called contract.
+    callee_bridge: bool,
+    profile: Option<ExecutionProfile>,
 }
 
 type Result<T> = ::std::result::Result<T, WasmError>;
@@ -73,7 +188,7 @@ type Result<T> = ::std::result::Result<T, WasmError>;
 impl Runtime {
 
     pub fn new(memory: MemoryRef, gas_limit: u64, args: Vec<u8>, state: ContractState,
-                          function_name: String, key: StateKey, costs: RuntimeWasmCosts) -> Runtime {
+                          function_name: String, key: StateKey, costs: RuntimeWasmCosts, context: ExecutionContext) -> Runtime {
         let pre_execution_state = state.clone();
         let post_execution_state = state;
         let result = RuntimeResult {
@@ -81,15 +196,25 @@ impl Runtime {
             state_delta: None,
             updated_state: Default::default(),
             ethereum_bridge: Default::default(),
+            events: Vec::new(),
             used_gas: 0,
+            view: false,
+            callees: Vec::new(),
+            gas_breakdown: Default::default(),
+            profile: None,
         };
         let gas = RuntimeGas{
             counter: 0,
             limit: gas_limit,
             refund: 0,
             costs,
+            breakdown: Default::default(),
         };
-        Runtime { memory, function_name, args, result, pre_execution_state, post_execution_state, key, gas }
+        Runtime {
+            memory, function_name, args, result, pre_execution_state, post_execution_state, key, gas, context, view: false,
+            calls: None, callees: Vec::new(), call_result: Vec::new(), callee_bridge: false,
+            profile: if context.profile { Some(ExecutionProfile::default()) } else { None },
+        }
     }
 
     pub fn get_used_gas(&self) -> u64 {
@@ -114,6 +239,42 @@ impl Runtime {
         Ok(())
     }
 
+    fn fetch_block_number(&mut self) -> RuntimeValue { RuntimeValue::I64(self.context.block_number as i64) }
+
+    /// args:
+    /// * `ptr` - the address in memory to copy the 32 bytes of the contract address into
+    fn fetch_contract_address(&mut self, args: RuntimeArgs) -> Result<()> {
+        let ptr: u32 = args.nth_checked(0)?;
+
+        self.memory.set(ptr, &*self.post_execution_state.contract_address)?;
+        Ok(())
+    }
+
+    /// args:
+    /// * `ptr` - the address in memory to copy the 32 bytes of the task id into
+    ///
+    /// returns 1 if the task id was copied, and 0 if the task has no id.
+    fn fetch_task_id(&mut self, args: RuntimeArgs) -> Result<i32> {
+        let ptr: u32 = args.nth_checked(0)?;
+
+        match self.context.task_id {
+            Some(task_id) => {
+                self.memory.set(ptr, &*task_id)?;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// args:
+    /// * `ptr` - the address in memory to copy the 64 bytes of the user's DH public key into
+    fn fetch_user_pubkey(&mut self, args: RuntimeArgs) -> Result<()> {
+        let ptr: u32 = args.nth_checked(0)?;
+
+        self.memory.set(ptr, &self.context.user_pubkey[..])?;
+        Ok(())
+    }
+
     fn read_state_key_from_memory(&self, args: &RuntimeArgs, arg_index: usize, arg_len_index: usize) -> Result<String>{
         let key = args.nth_checked(arg_index)?;
         let key_len: u32 = args.nth_checked(arg_len_index)?;
@@ -125,12 +286,18 @@ impl Runtime {
         Ok(key_str.to_string())
     }
 
-    pub fn read_state_len(&self, args: RuntimeArgs) -> Result<i32> {
+    /// args:
+    /// * `key` - the start address of key in memory
+    /// * `key_len` - the length of key
+    ///
+    /// Return the length of the value under `key` serialized with `encoding`, for `read_value` gas.
+    pub fn read_state_len(&mut self, args: RuntimeArgs, encoding: StateEncoding) -> Result<i32> {
         // TODO: Handle the error here, should we return len=0?;
         let key = self.read_state_key_from_memory(&args, 0, 1)?;
-        let value_vec =
-            serde_json::to_vec(&self.post_execution_state.json[&key]).
-                expect("Failed converting Value to vec in Runtime while reading state");
+        let gas_amount = self.gas.costs.read_value;
+        self.charge_gas(GasKind::StateRead, gas_amount)?;
+        self.post_execution_state.check_encoding(encoding)?;
+        let value_vec = encoding.encode(&self.post_execution_state.json[&key])?;
         Ok(value_vec.len() as i32)
     }
 
@@ -140,14 +307,20 @@ impl Runtime {
     /// * `key_len` - the length of key
     ///
     /// Read `key` from the memory, then read from the state the value under the `key`
-    /// and copy it to `value_holder`.
-    pub fn read_state(&mut self, args: RuntimeArgs) -> Result<()> {
+    /// and copy it to `value_holder` serialized with `encoding`.
+    /// The cost is `read_byte` for every byte of the serialized value, `read_value` was charged by `read_state_len`.
+    pub fn read_state(&mut self, args: RuntimeArgs, encoding: StateEncoding) -> Result<()> {
         // TODO: Handle the error here, should we return len=0?;
         let key = self.read_state_key_from_memory(&args, 0, 1)?;
         let value_holder: u32 = args.nth_checked(2)?;
+        self.post_execution_state.check_encoding(encoding)?;
 
-        let value_vec =
-            serde_json::to_vec(&self.post_execution_state.json[key]).expect("Failed converting Value to vec in Runtime while reading state");
+        let value_vec = encoding.encode(&self.post_execution_state.json[key])?;
+        let gas_amount = byte_cost(0, self.gas.costs.read_byte, value_vec.len() as u64)?;
+        self.charge_gas(GasKind::StateRead, gas_amount)?;
+        if let Some(profile) = &mut self.profile {
+            profile.state_bytes_read += value_vec.len() as u64;
+        }
         self.memory.set(value_holder, &value_vec)?;
         Ok(())
     }
@@ -157,9 +330,17 @@ impl Runtime {
     /// * `key_len` - the length of key
     ///
     /// Read `key` from the memory, then remove the `key` from the state
+    /// The cost is `remove_value`, and `write_additional_byte` is refunded for every byte of the removed value
+    /// (the refund of an execution is capped by `MAX_REFUND_QUOTIENT`).
     pub fn remove_from_state(&mut self, args: RuntimeArgs) -> Result<()> {
         let key = self.read_state_key_from_memory(&args, 0, 1)?;
+        self.ensure_not_view(&key)?;
+        self.ensure_not_reserved(&key)?;
 
+        let gas_amount = self.gas.costs.remove_value;
+        self.charge_gas(GasKind::StateRemoval, gas_amount)?;
+        let old_value_len = self.post_execution_state.encoding.encoded_len(&self.post_execution_state.json[&key])?;
+        self.refund_gas(old_value_len.saturating_mul(self.gas.costs.write_additional_byte));
         self.post_execution_state.remove_key(&key);
         Ok(())
     }
@@ -171,23 +352,110 @@ impl Runtime {
     /// * `value_len` - the length of the value
     ///
     /// Read `key` and `value` from memory, and write (key, value) pair to the state
+    /// `value` is deserialized according to `encoding`, which must match the encoding of the state.
     /// the cost of writing into the state is calculated by `calculate_gas_for_writing`
-    pub fn write_state(&mut self, args: RuntimeArgs) -> Result<()> {
+    pub fn write_state(&mut self, args: RuntimeArgs, encoding: StateEncoding) -> Result<()> {
         let key = self.read_state_key_from_memory(&args, 0, 1)?;
+        self.ensure_not_view(&key)?;
+        self.ensure_not_reserved(&key)?;
         let value: u32 = args.nth_checked(2)?;
         let value_len: u32 = args.nth_checked(3)?;
+        self.post_execution_state.ensure_encoding(encoding)?;
 
         let mut val = vec![0u8; value_len as usize];
         let gas_amount = self.calculate_gas_for_writing(value_len as u64, &key)?;
-        self.charge_gas(gas_amount)?;
+        self.charge_gas(GasKind::StateWrite, gas_amount)?;
+        if let Some(profile) = &mut self.profile {
+            profile.state_bytes_written += value_len as u64;
+        }
         self.memory.get_into(value, &mut val[..])?;
 
-        let value: serde_json::Value =
-            serde_json::from_slice(&val).expect("Failed converting into Value while writing state in Runtime");
+        let value = encoding.decode(&val)?;
         self.post_execution_state.write_key(&key, &value)?;
         Ok(())
     }
 
+    /// Mark the execution as a view, from now on any change to the state fails the task.
+    /// A view can't be ended, so the contract calls this before running the view function.
+    pub fn begin_view(&mut self) { self.view = true; }
+
+    fn ensure_not_view(&self, key: &str) -> Result<()> {
+        if self.view {
+            Err(FailedTaskError(StateChangeInView { key: key.to_string() }))?
+        }
+        Ok(())
+    }
+
+    fn ensure_not_reserved(&self, key: &str) -> Result<()> {
+        if key.starts_with(RESERVED_KEY_PREFIX) {
+            Err(FailedTaskError(ReservedStateKey { key: key.to_string() }))?
+        }
+        Ok(())
+    }
+
+    /// args:
+    /// * `hash` - the address in memory of the 32 bytes Keccak256 hash of the new pre-deployed code
+    ///
+    /// Approve upgrading the contract to the code with `hash`, replacing any previous approval.
+    /// The approval is written to the state, so it's discarded if the task fails.
+    pub fn approve_upgrade(&mut self, args: RuntimeArgs) -> Result<()> {
+        self.ensure_not_view(UPGRADE_APPROVAL_KEY)?;
+        let hash_ptr: u32 = args.nth_checked(0)?;
+        let mut hash = [0u8; 32];
+        self.memory.get_into(hash_ptr, &mut hash)?;
+
+        let value = Value::String(to_hex(&hash));
+        let value_len = self.post_execution_state.encoding.encoded_len(&value)?;
+        let gas_amount = self.calculate_gas_for_writing(value_len, UPGRADE_APPROVAL_KEY)?;
+        self.charge_gas(GasKind::StateWrite, gas_amount)?;
+        self.post_execution_state.write_key(UPGRADE_APPROVAL_KEY, &value)?;
+        Ok(())
+    }
+
+    /// args:
+    /// * `addresses` - the address in memory of the 20 bytes Ethereum addresses
+    /// * `count` - the number of addresses
+    ///
+    /// Allow the owners of the addresses to inspect the decrypted state of the contract, replacing the previous ones.
+    /// An empty list disallows inspecting the state.
+    pub fn set_state_inspectors(&mut self, args: RuntimeArgs) -> Result<()> {
+        self.ensure_not_view(INSPECTORS_KEY)?;
+        let addresses_ptr: u32 = args.nth_checked(0)?;
+        let count: u32 = args.nth_checked(1)?;
+        if count > MAX_STATE_INSPECTORS {
+            Err(FailedTaskError(TooManyStateInspectors { max: MAX_STATE_INSPECTORS, count }))?
+        }
+        let mut addresses = vec![0u8; count as usize * 20];
+        self.memory.get_into(addresses_ptr, &mut addresses)?;
+
+        let value = Value::Array(addresses.chunks(20).map(|address| Value::String(to_hex(address))).collect());
+        let value_len = self.post_execution_state.encoding.encoded_len(&value)?;
+        let gas_amount = self.calculate_gas_for_writing(value_len, INSPECTORS_KEY)?;
+        self.charge_gas(GasKind::StateWrite, gas_amount)?;
+        self.post_execution_state.write_key(INSPECTORS_KEY, &value)?;
+        Ok(())
+    }
+
+    /// Consume the approval of upgrading to the pre-deployed code with `pre_code_hash`,
+    /// before running the migration of that code. Fails the task if the contract didn't approve it.
+    pub fn begin_upgrade(&mut self, pre_code_hash: &Hash256) -> ::std::result::Result<(), EnclaveError> {
+        let code_hash = to_hex(&pre_code_hash[..]);
+        if self.post_execution_state.json[UPGRADE_APPROVAL_KEY].as_str() != Some(&code_hash) {
+            return Err(FailedTaskError(UpgradeNotApproved { code_hash }));
+        }
+        self.post_execution_state.remove_key(UPGRADE_APPROVAL_KEY);
+        Ok(())
+    }
+
+    /// Record the hash of the code before the upgrade and of the code returned by the migration.
+    pub fn record_upgrade(&mut self, previous_code_hash: &Hash256) -> ::std::result::Result<(), EnclaveError> {
+        let record = json!({
+            "previousCodeHash": to_hex(&previous_code_hash[..]),
+            "codeHash": to_hex(&*self.result.result.keccak256()),
+        });
+        self.post_execution_state.write_key(UPGRADE_RECORD_KEY, &record)
+    }
+
     fn treat_gas_overflow(&mut self, val: &Option<u64>) -> Result<()> {
         if val.is_none() {
             self.gas.counter = self.gas.limit;
@@ -214,16 +482,11 @@ impl Runtime {
    /// There is an initial constant value charged for the writing
    /// If the new value is larger than the old one, then gas is charged for the new bytes.
    /// If the new value is smaller than the old one, then the gas is returned for the removed bytes.
+    /// Both lengths are measured in the encoding of the state.
     fn calculate_gas_for_writing(&mut self, new_value_len: u64, key: &str) -> Result<u64> {
         let mut result = Some(0);
-        let val = self.post_execution_state.json[key].clone();
-        let mut old_value_len = 0;
         // forcing the length of Null value to be 0, since it is not 0.
-        if !val.is_null() {
-            let old_value_vec =
-                serde_json::to_vec(&val).expect("Failed converting Value to vec in Runtime while reading state");
-            old_value_len = old_value_vec.len() as u64;
-        }
+        let old_value_len = self.post_execution_state.encoding.encoded_len(&self.post_execution_state.json[key])?;
         // If the new value is larger than the old one, the gas should be charged
         if new_value_len >= old_value_len {
             let checked_val = (new_value_len - old_value_len).checked_mul(self.gas.costs.write_additional_byte);
@@ -250,8 +513,14 @@ impl Runtime {
     /// * `payload_len` - the length of the payload
     /// * `address` - the start address of address in memory
     ///
-    /// Read `payload` and `address` from memory, and write it to result
+    /// Read `payload` and `address` from memory, and write it to result.
+    /// The payload written by a called contract can't be replaced.
     pub fn write_eth_bridge(&mut self, args: RuntimeArgs) -> Result<()> {
+        if self.callee_bridge {
+            return Err(WasmError::EnclaveError(FailedTaskError(ContractCallError {
+                message: "the Ethereum bridge was already written by a called contract".to_string(),
+            })));
+        }
         let payload = args.nth_checked(0)?;
         let payload_len: u32 = args.nth_checked(1)?;
         let address = args.nth_checked(2)?;
@@ -264,6 +533,44 @@ impl Runtime {
         Ok(())
     }
 
+    /// args:
+    /// * `topic` - the start address of the topic in memory
+    /// * `topic_len` - the length of the topic
+    /// * `data` - the start address of the data in memory
+    /// * `data_len` - the length of the data
+    /// * `encrypted` - non zero if the data should be encrypted with the user's key
+    ///
+    /// Read the event from memory and append it to the result,
+    /// the cost is `emit_event` and `emit_event_byte` for every byte of the topic and data.
+    pub fn emit_event(&mut self, args: RuntimeArgs) -> Result<()> {
+        let topic_ptr: u32 = args.nth_checked(0)?;
+        let topic_len: u32 = args.nth_checked(1)?;
+        let data_ptr: u32 = args.nth_checked(2)?;
+        let data_len: u32 = args.nth_checked(3)?;
+        let encrypted: u32 = args.nth_checked(4)?;
+
+        let gas_amount = byte_cost(self.gas.costs.emit_event, self.gas.costs.emit_event_byte, topic_len as u64 + data_len as u64)?;
+        self.charge_gas(GasKind::Event, gas_amount)?;
+
+        let topic = self.memory.get(topic_ptr, topic_len as usize)?;
+        let data = self.memory.get(data_ptr, data_len as usize)?;
+        self.result.events.push(ContractEvent { topic, data, encrypted: encrypted != 0 });
+        Ok(())
+    }
+
+    /// args:
+    /// * `ptr` - the start address of the payload in memory
+    /// * `len` - the length of the payload
+    ///
+    /// Abort the execution, the state changes are discarded and the payload is returned as the output of the failed task
+    pub fn revert(&mut self, args: RuntimeArgs) -> Result<()> {
+        let ptr: u32 = args.nth_checked(0)?;
+        let len: u32 = args.nth_checked(1)?;
+
+        let payload = self.memory.get(ptr, len as usize)?;
+        Err(FailedTaskError(Reverted { payload }))?
+    }
+
     /// args:
     /// * `ptr` - the start address in memory
     /// * `len` - the length
@@ -277,9 +584,16 @@ impl Runtime {
         Ok(())
     }
 
+    /// args:
+    /// * `ptr` - the address in memory to copy the random bytes into
+    /// * `len` - the number of random bytes
+    ///
+    /// The cost is `rand` and `rand_byte` for every random byte.
     pub fn rand(&mut self, args: RuntimeArgs) -> Result<()> {
         let ptr: u32 = args.nth_checked(0)?;
         let len: u32 = args.nth_checked(1)?;
+        let gas_amount = byte_cost(self.gas.costs.rand, self.gas.costs.rand_byte, len as u64)?;
+        self.charge_gas(GasKind::Crypto, gas_amount)?;
 
         let mut buf = vec![0u8; len as usize];
         match rsgx_read_rand(&mut buf[..]) {
@@ -293,10 +607,17 @@ impl Runtime {
 
     /// Destroy the runtime, create state delta, update the used gas and return result of the execution
     pub fn into_result(mut self) -> ::std::result::Result<RuntimeResult, EnclaveError> {
-        if self.gas.counter >= self.gas.refund {
-            self.result.used_gas = self.gas.counter - self.gas.refund;
-        } else {
-            self.result.used_gas = 0;
+        self.result.used_gas = self.gas.used();
+        self.result.gas_breakdown = GasBreakdown { refund: self.gas.capped_refund(), ..self.gas.breakdown.clone() };
+        if let Some(mut profile) = self.profile.take() {
+            profile.record_memory(self.memory.current_size().0 as u32);
+            self.result.profile = Some(profile);
+        }
+        // The view flag is signed and tells the node there's no delta to store, so rather than trusting the contract
+        // to begin the view before changing anything, the flag is checked against the state the execution ended with.
+        if self.view && self.pre_execution_state != self.post_execution_state {
+            let key = self.pre_execution_state.changed_key(&self.post_execution_state).unwrap_or_default();
+            Err(FailedTaskError(StateChangeInView { key }))?
         }
         self.result.state_delta = {
             // The delta is always generated after a deployment.
@@ -308,6 +629,12 @@ impl Runtime {
             }
         };
         self.result.updated_state = self.post_execution_state;
+        self.result.view = self.view;
+        for callee in self.callees {
+            if let Some(callee_result) = callee.into_result()? {
+                self.result.callees.push(callee_result);
+            }
+        }
         Ok(self.result)
     }
 
@@ -323,22 +650,23 @@ impl Runtime {
 
     pub fn gas(&mut self, args: RuntimeArgs) -> Result<()> {
         let amount: u32 = args.nth_checked(0)?;
-        self.charge_gas(amount as u64)
+        self.charge_gas(GasKind::Instructions, amount as u64)
     }
 
     pub fn charge_deployment(&mut self) -> Result<()> {
         let deployed_bytecode_len = self.result.result.len() as u64;
         let gas_for_byte = self.gas.costs.deploy_byte;
-        self.charge_gas(deployed_bytecode_len * gas_for_byte)
+        self.charge_gas(GasKind::Deployment, deployed_bytecode_len * gas_for_byte)
     }
 
     pub fn charge_execution(&mut self) -> Result<()> {
         let initial_execution_gas = self.gas.costs.execution;
-        self.charge_gas(initial_execution_gas)
+        self.charge_gas(GasKind::Execution, initial_execution_gas)
     }
 
-    fn charge_gas(&mut self, amount: u64) -> Result<()> {
+    fn charge_gas(&mut self, kind: GasKind, amount: u64) -> Result<()> {
         if self.charge_gas_if_enough(amount) {
+            self.gas.breakdown.add(kind, amount);
             Ok(())
         } else {
             self.gas.counter = self.gas.limit;
@@ -358,9 +686,19 @@ impl Runtime {
         }
     }
 
+    /// Charge for a symmetric crypto operation on `len` bytes, `crypto_byte` for every byte.
+    fn charge_crypto(&mut self, base: u64, len: u32) -> Result<()> {
+        let gas_amount = byte_cost(base, self.gas.costs.crypto_byte, len as u64)?;
+        self.charge_gas(GasKind::Crypto, gas_amount)
+    }
+
+    /// Return gas for freed storage, it's subtracted from the used gas when the execution ends.
+    fn refund_gas(&mut self, amount: u64) { self.gas.refund = self.gas.refund.saturating_add(amount); }
+
     pub fn encrypt(&mut self, args: RuntimeArgs) -> Result<()> {
         let message_ptr: u32 = args.nth_checked(0)?;
         let message_len: u32 = args.nth_checked(1)?;
+        self.charge_crypto(self.gas.costs.encrypt, message_len)?;
         let message = self.memory.get(message_ptr, message_len as usize)?;
         debug_println!("In encrypt: {:?}", message);
 
@@ -378,6 +716,7 @@ impl Runtime {
     pub fn decrypt(&mut self, args: RuntimeArgs) -> Result<()> {
         let cipheriv_ptr: u32 = args.nth_checked(0)?;
         let cipheriv_len: u32 = args.nth_checked(1)?;
+        self.charge_crypto(self.gas.costs.decrypt, cipheriv_len)?;
         let cipheriv = self.memory.get(cipheriv_ptr, cipheriv_len as usize)?;
 
         let key_ptr: u32 = args.nth_checked(2)?;
@@ -389,31 +728,273 @@ impl Runtime {
         self.memory.set(ptr, &message[..])?;
         Ok(())
     }
+
+    /// Same as `decrypt` but an authentication failure is reported to the contract instead of failing the task.
+    /// Returns 1 if the message was decrypted and 0 otherwise, in which case nothing is written.
+    pub fn decrypt_with_status(&mut self, args: RuntimeArgs) -> Result<i32> {
+        let cipheriv_ptr: u32 = args.nth_checked(0)?;
+        let cipheriv_len: u32 = args.nth_checked(1)?;
+        self.charge_crypto(self.gas.costs.decrypt, cipheriv_len)?;
+        let cipheriv = self.memory.get(cipheriv_ptr, cipheriv_len as usize)?;
+
+        let key_ptr: u32 = args.nth_checked(2)?;
+        let mut key: SymmetricKey = [0u8; SYMMETRIC_KEY_SIZE];
+        self.memory.get_into(key_ptr, &mut key)?;
+
+        let ptr: u32 = args.nth_checked(3)?;
+        match decrypt(&cipheriv, &key) {
+            Ok(message) => {
+                self.memory.set(ptr, &message[..])?;
+                Ok(1)
+            }
+            Err(_) => Ok(0),
+        }
+    }
+
+    /// args:
+    /// * `message` - the start address of the message in memory
+    /// * `message_len` - the length of the message
+    /// * `key` - the address of the symmetric key in memory
+    /// * `aad` - the start address of the associated data in memory
+    /// * `aad_len` - the length of the associated data
+    /// * `iv` - the address of the 12 bytes IV in memory
+    /// * `ptr` - the address in memory to copy the cipher text (with the tag and IV) into
+    pub fn encrypt_with_aad(&mut self, args: RuntimeArgs) -> Result<()> {
+        let message_ptr: u32 = args.nth_checked(0)?;
+        let message_len: u32 = args.nth_checked(1)?;
+        let aad_len: u32 = args.nth_checked(4)?;
+        self.charge_crypto(self.gas.costs.encrypt, message_len.saturating_add(aad_len))?;
+        let message = self.memory.get(message_ptr, message_len as usize)?;
+
+        let key_ptr: u32 = args.nth_checked(2)?;
+        let mut key: SymmetricKey = [0u8; SYMMETRIC_KEY_SIZE];
+        self.memory.get_into(key_ptr, &mut key)?;
+
+        let aad_ptr: u32 = args.nth_checked(3)?;
+        let aad_len: u32 = args.nth_checked(4)?;
+        let aad = self.memory.get(aad_ptr, aad_len as usize)?;
+
+        let iv_ptr: u32 = args.nth_checked(5)?;
+        let mut iv: IV = [0u8; IV_SIZE];
+        self.memory.get_into(iv_ptr, &mut iv)?;
+
+        let ptr: u32 = args.nth_checked(6)?;
+        let enc_message = encrypt_with_aad(&message, &key, &aad, Some(iv))?;
+        self.memory.set(ptr, &enc_message)?;
+        Ok(())
+    }
+
+    /// args:
+    /// * `cipheriv` - the start address of the cipher text in memory
+    /// * `cipheriv_len` - the length of the cipher text
+    /// * `key` - the address of the symmetric key in memory
+    /// * `aad` - the start address of the associated data in memory
+    /// * `aad_len` - the length of the associated data
+    /// * `ptr` - the address in memory to copy the decrypted message into
+    ///
+    /// Returns 1 if the message was decrypted and 0 if the authentication failed, in which case nothing is written.
+    pub fn decrypt_with_aad(&mut self, args: RuntimeArgs) -> Result<i32> {
+        let cipheriv_ptr: u32 = args.nth_checked(0)?;
+        let cipheriv_len: u32 = args.nth_checked(1)?;
+        let aad_len: u32 = args.nth_checked(4)?;
+        self.charge_crypto(self.gas.costs.decrypt, cipheriv_len.saturating_add(aad_len))?;
+        let cipheriv = self.memory.get(cipheriv_ptr, cipheriv_len as usize)?;
+
+        let key_ptr: u32 = args.nth_checked(2)?;
+        let mut key: SymmetricKey = [0u8; SYMMETRIC_KEY_SIZE];
+        self.memory.get_into(key_ptr, &mut key)?;
+
+        let aad_ptr: u32 = args.nth_checked(3)?;
+        let aad_len: u32 = args.nth_checked(4)?;
+        let aad = self.memory.get(aad_ptr, aad_len as usize)?;
+
+        let ptr: u32 = args.nth_checked(5)?;
+        match decrypt_with_aad(&cipheriv, &key, &aad) {
+            Ok(message) => {
+                self.memory.set(ptr, &message[..])?;
+                Ok(1)
+            }
+            Err(_) => Ok(0),
+        }
+    }
+
+    /// args:
+    /// * `key` - the address of the master symmetric key in memory
+    /// * `salt` - the start address of the salt in memory
+    /// * `salt_len` - the length of the salt
+    /// * `info` - the start address of the info in memory
+    /// * `info_len` - the length of the info
+    /// * `ptr` - the address in memory to copy the derived key into
+    ///
+    /// Derive a key from the master key with HKDF-SHA256.
+    /// The cost is `derive_key` and `crypto_byte` for every byte of the salt and info.
+    pub fn derive_key(&mut self, args: RuntimeArgs) -> Result<()> {
+        let salt_len: u32 = args.nth_checked(2)?;
+        let info_len: u32 = args.nth_checked(4)?;
+        self.charge_crypto(self.gas.costs.derive_key, salt_len.saturating_add(info_len))?;
+        let key_ptr: u32 = args.nth_checked(0)?;
+        let mut key: SymmetricKey = [0u8; SYMMETRIC_KEY_SIZE];
+        self.memory.get_into(key_ptr, &mut key)?;
+
+        let salt_ptr: u32 = args.nth_checked(1)?;
+        let salt = self.memory.get(salt_ptr, salt_len as usize)?;
+
+        let info_ptr: u32 = args.nth_checked(3)?;
+        let info = self.memory.get(info_ptr, info_len as usize)?;
+
+        let ptr: u32 = args.nth_checked(5)?;
+        self.memory.set(ptr, &derive_key(&key, &salt, &info))?;
+        Ok(())
+    }
+
+    /// args:
+    /// * `data` - the start address of the data in memory
+    /// * `data_len` - the length of the data
+    /// * `ptr` - the address in memory to copy the 32 bytes of the hash into
+    ///
+    /// Hash the data with Keccak256 natively, the cost is `keccak256` and `hash_byte` for every byte of the data.
+    pub fn keccak256(&mut self, args: RuntimeArgs) -> Result<()> {
+        let data_ptr: u32 = args.nth_checked(0)?;
+        let data_len: u32 = args.nth_checked(1)?;
+        let gas_amount = byte_cost(self.gas.costs.keccak256, self.gas.costs.hash_byte, data_len as u64)?;
+        self.charge_gas(GasKind::Crypto, gas_amount)?;
+        let data = self.memory.get(data_ptr, data_len as usize)?;
+
+        let ptr: u32 = args.nth_checked(2)?;
+        self.memory.set(ptr, &*data.keccak256())?;
+        Ok(())
+    }
+
+    /// args:
+    /// * `data` - the start address of the data in memory
+    /// * `data_len` - the length of the data
+    /// * `ptr` - the address in memory to copy the 32 bytes of the hash into
+    ///
+    /// Hash the data with Sha256 natively, the cost is `sha256` and `hash_byte` for every byte of the data.
+    pub fn sha256(&mut self, args: RuntimeArgs) -> Result<()> {
+        let data_ptr: u32 = args.nth_checked(0)?;
+        let data_len: u32 = args.nth_checked(1)?;
+        let gas_amount = byte_cost(self.gas.costs.sha256, self.gas.costs.hash_byte, data_len as u64)?;
+        self.charge_gas(GasKind::Crypto, gas_amount)?;
+        let data = self.memory.get(data_ptr, data_len as usize)?;
+
+        let ptr: u32 = args.nth_checked(2)?;
+        self.memory.set(ptr, &*data.sha256())?;
+        Ok(())
+    }
+
+    /// args:
+    /// * `message` - the start address of the signed message in memory
+    /// * `message_len` - the length of the message
+    /// * `sig` - the address of the 65 bytes signature (r, s, v) in memory
+    /// * `ptr` - the address in memory to copy the 64 bytes of the recovered public key into
+    ///
+    /// Recover the public key that signed the Keccak256 of `message`, same as `KeyPair::recover`.
+    /// Returns 1 if the key was recovered and 0 if the signature is invalid, in which case nothing is written.
+    pub fn ecrecover(&mut self, args: RuntimeArgs) -> Result<i32> {
+        let message_ptr: u32 = args.nth_checked(0)?;
+        let message_len: u32 = args.nth_checked(1)?;
+        // The message is hashed before recovering the key
+        let gas_amount = byte_cost(self.gas.costs.ecrecover, self.gas.costs.hash_byte, message_len as u64)?;
+        self.charge_gas(GasKind::Crypto, gas_amount)?;
+        let message = self.memory.get(message_ptr, message_len as usize)?;
+
+        let sig_ptr: u32 = args.nth_checked(2)?;
+        let mut sig = [0u8; 65];
+        self.memory.get_into(sig_ptr, &mut sig)?;
+        // `KeyPair::recover` expects an Ethereum style `v` (27/28).
+        if sig[64] < 27 {
+            return Ok(0);
+        }
+
+        let ptr: u32 = args.nth_checked(3)?;
+        match KeyPair::recover(&message, sig) {
+            Ok(pubkey) => {
+                self.memory.set(ptr, &pubkey[..])?;
+                Ok(1)
+            }
+            Err(_) => Ok(0),
+        }
+    }
 }
 
 mod ext_impl {
-    use super::{eng_resolver, Runtime};
+    use super::{eng_resolver, Runtime, StateEncoding};
     use wasmi::{Externals, RuntimeArgs, RuntimeValue, Trap};
 
     impl Externals for Runtime {
         fn invoke_index(&mut self, index: usize, args: RuntimeArgs) -> Result<Option<RuntimeValue>, Trap> {
+            if self.profile.is_none() {
+                return self.invoke_host(index, args);
+            }
+            let gas_before = self.gas.counter;
+            let res = self.invoke_host(index, args);
+            let gas = self.gas.counter.saturating_sub(gas_before);
+            let pages = self.memory.current_size().0 as u32;
+            if let Some(profile) = &mut self.profile {
+                profile.record_host_call(index, gas);
+                profile.record_memory(pages);
+            }
+            res
+        }
+    }
+
+    impl Runtime {
+        fn invoke_host(&mut self, index: usize, args: RuntimeArgs) -> Result<Option<RuntimeValue>, Trap> {
             match index {
                 eng_resolver::ids::RET_FUNC => {
                     Runtime::ret(self, args)?;
                     Ok(None)
                 }
                 eng_resolver::ids::WRITE_STATE_FUNC => {
-                    Runtime::write_state(self, args)?;
+                    Runtime::write_state(self, args, StateEncoding::Json)?;
                     Ok(None)
                 }
                 eng_resolver::ids::READ_STATE_LEN_FUNC => {
-                    let res = Runtime::read_state_len(self, args)?;
+                    let res = Runtime::read_state_len(self, args, StateEncoding::Json)?;
                     Ok(Some(RuntimeValue::I32(res)))
                 }
                 eng_resolver::ids::READ_STATE_FUNC => {
-                    Runtime::read_state(self, args)?;
+                    Runtime::read_state(self, args, StateEncoding::Json)?;
+                    Ok(None)
+                }
+                eng_resolver::ids::WRITE_STATE_MSGPACK_FUNC => {
+                    Runtime::write_state(self, args, StateEncoding::MsgPack)?;
+                    Ok(None)
+                }
+                eng_resolver::ids::READ_STATE_LEN_MSGPACK_FUNC => {
+                    let res = Runtime::read_state_len(self, args, StateEncoding::MsgPack)?;
+                    Ok(Some(RuntimeValue::I32(res)))
+                }
+                eng_resolver::ids::READ_STATE_MSGPACK_FUNC => {
+                    Runtime::read_state(self, args, StateEncoding::MsgPack)?;
+                    Ok(None)
+                }
+                eng_resolver::ids::BLOCK_NUMBER_FUNC => Ok(Some(Runtime::fetch_block_number(self))),
+                eng_resolver::ids::CONTRACT_ADDRESS_FUNC => {
+                    Runtime::fetch_contract_address(self, args)?;
+                    Ok(None)
+                }
+                eng_resolver::ids::TASK_ID_FUNC => {
+                    let res = Runtime::fetch_task_id(self, args)?;
+                    Ok(Some(RuntimeValue::I32(res)))
+                }
+                eng_resolver::ids::USER_PUBKEY_FUNC => {
+                    Runtime::fetch_user_pubkey(self, args)?;
+                    Ok(None)
+                }
+                eng_resolver::ids::KECCAK256_FUNC => {
+                    Runtime::keccak256(self, args)?;
+                    Ok(None)
+                }
+                eng_resolver::ids::SHA256_FUNC => {
+                    Runtime::sha256(self, args)?;
                     Ok(None)
                 }
+                eng_resolver::ids::ECRECOVER_FUNC => {
+                    let res = Runtime::ecrecover(self, args)?;
+                    Ok(Some(RuntimeValue::I32(res)))
+                }
                 eng_resolver::ids::REMOVE_STATE_FUNC => {
                     Runtime::remove_from_state(self, args)?;
                     Ok(None)
@@ -442,6 +1023,41 @@ mod ext_impl {
                     Ok(None)
                 }
 
+                eng_resolver::ids::BEGIN_VIEW_FUNC => {
+                    Runtime::begin_view(self);
+                    Ok(None)
+                }
+
+                eng_resolver::ids::CALL_CONTRACT_FUNC => {
+                    let res = Runtime::call_contract(self, args)?;
+                    Ok(Some(RuntimeValue::I32(res)))
+                }
+
+                eng_resolver::ids::FETCH_CALL_RESULT_FUNC => {
+                    Runtime::fetch_call_result(self, args)?;
+                    Ok(None)
+                }
+
+                eng_resolver::ids::APPROVE_UPGRADE_FUNC => {
+                    Runtime::approve_upgrade(self, args)?;
+                    Ok(None)
+                }
+
+                eng_resolver::ids::SET_STATE_INSPECTORS_FUNC => {
+                    Runtime::set_state_inspectors(self, args)?;
+                    Ok(None)
+                }
+
+                eng_resolver::ids::EMIT_EVENT_FUNC => {
+                    Runtime::emit_event(self, args)?;
+                    Ok(None)
+                }
+
+                eng_resolver::ids::REVERT_FUNC => {
+                    Runtime::revert(self, args)?;
+                    Ok(None)
+                }
+
                 eng_resolver::ids::GAS_FUNC => {
                     Runtime::gas(self, args)?;
                     Ok(None)
@@ -462,8 +1078,413 @@ mod ext_impl {
                     Ok(None)
                 }
 
+                eng_resolver::ids::DECRYPT_WITH_STATUS_FUNC => {
+                    let res = Runtime::decrypt_with_status(self, args)?;
+                    Ok(Some(RuntimeValue::I32(res)))
+                }
+
+                eng_resolver::ids::ENCRYPT_WITH_AAD_FUNC => {
+                    Runtime::encrypt_with_aad(self, args)?;
+                    Ok(None)
+                }
+
+                eng_resolver::ids::DECRYPT_WITH_AAD_FUNC => {
+                    let res = Runtime::decrypt_with_aad(self, args)?;
+                    Ok(Some(RuntimeValue::I32(res)))
+                }
+
+                eng_resolver::ids::DERIVE_KEY_FUNC => {
+                    Runtime::derive_key(self, args)?;
+                    Ok(None)
+                }
+
                 _ => unimplemented!("Unimplemented function at {}", index),
             }
         }
     }
 }
+
+#[cfg(debug_assertions)]
+pub mod tests {
+    use super::{eng_resolver::ids, gas::MAX_REFUND_QUOTIENT, ContractEvent, ContractState, ExecutionContext, Runtime, RuntimeWasmCosts, StateEncoding,
+                INSPECTORS_KEY, MAX_STATE_INSPECTORS, UPGRADE_APPROVAL_KEY, UPGRADE_RECORD_KEY};
+    use crate::calls::{CallEnvironment, CalleeState, MAX_CALL_DEPTH};
+    use crate::data::IOInterface;
+    use enigma_types::{ContractAddress, StateKey};
+    use enigma_tools_t::common::errors_t::{EnclaveError, FailedTaskError, WasmError};
+    use enigma_crypto::symmetric::decrypt;
+    use rmps;
+    use serde_json;
+    use enigma_crypto::{KeyPair, hash::{Keccak256, Sha256}};
+    use std::string::String;
+    use std::vec::Vec;
+    use wasmi::{memory_units::Pages, Externals, MemoryInstance, RuntimeArgs, RuntimeValue};
+
+    fn new_runtime() -> Runtime {
+        let memory = MemoryInstance::alloc(Pages(1), None).unwrap();
+        let state = ContractState::new(b"enigma".sha256());
+        Runtime::new(memory, 100_000, Vec::new(), state, String::new(), [1u8; 32], RuntimeWasmCosts::default(), Default::default())
+    }
+
+    fn args(values: &[i32]) -> Vec<RuntimeValue> { values.iter().map(|v| RuntimeValue::I32(*v)).collect() }
+
+    pub fn test_native_hashes() {
+        let mut runtime = new_runtime();
+        let data = b"Enigma";
+        runtime.memory.set(0, data).unwrap();
+
+        runtime.keccak256(RuntimeArgs::from(&args(&[0, data.len() as i32, 100])[..])).unwrap();
+        assert_eq!(runtime.memory.get(100, 32).unwrap(), data.keccak256().to_vec());
+
+        runtime.sha256(RuntimeArgs::from(&args(&[0, data.len() as i32, 200])[..])).unwrap();
+        assert_eq!(runtime.memory.get(200, 32).unwrap(), data.sha256().to_vec());
+
+        let costs = RuntimeWasmCosts::default();
+        let used_gas = costs.keccak256 + costs.sha256 + 2 * data.len() as u64 * costs.hash_byte;
+        assert_eq!(runtime.get_used_gas(), used_gas);
+        assert_eq!(runtime.into_result().unwrap().gas_breakdown.crypto, used_gas);
+    }
+
+    pub fn test_native_ecrecover() {
+        let mut runtime = new_runtime();
+        let keys = KeyPair::new().unwrap();
+        let message = b"Enigma";
+        let mut sig = keys.sign(message).unwrap();
+        runtime.memory.set(0, message).unwrap();
+        runtime.memory.set(100, &sig[..]).unwrap();
+
+        let res = runtime.ecrecover(RuntimeArgs::from(&args(&[0, message.len() as i32, 100, 200])[..])).unwrap();
+        assert_eq!(res, 1);
+        assert_eq!(runtime.memory.get(200, 64).unwrap(), keys.get_pubkey().to_vec());
+
+        sig[64] = 0;
+        runtime.memory.set(100, &sig[..]).unwrap();
+        let res = runtime.ecrecover(RuntimeArgs::from(&args(&[0, message.len() as i32, 100, 300])[..])).unwrap();
+        assert_eq!(res, 0);
+        let costs = RuntimeWasmCosts::default();
+        assert_eq!(runtime.get_used_gas(), 2 * (costs.ecrecover + message.len() as u64 * costs.hash_byte));
+    }
+
+    pub fn test_decrypt_with_aad_status() {
+        let mut runtime = new_runtime();
+        let key = b"EnigmaMPC".sha256();
+        let message = b"This Is Enigma";
+        let enc_len = message.len() as i32 + 16 + 12;
+        runtime.memory.set(0, &*key).unwrap();
+        runtime.memory.set(100, message).unwrap();
+        runtime.memory.set(200, b"record 1").unwrap();
+        runtime.memory.set(300, &[7u8; 12]).unwrap();
+
+        runtime.encrypt_with_aad(RuntimeArgs::from(&args(&[100, message.len() as i32, 0, 200, 8, 300, 400])[..])).unwrap();
+
+        let res = runtime.decrypt_with_aad(RuntimeArgs::from(&args(&[400, enc_len, 0, 200, 8, 600])[..])).unwrap();
+        assert_eq!(res, 1);
+        assert_eq!(runtime.memory.get(600, message.len()).unwrap(), message.to_vec());
+
+        // A different aad fails the authentication, and so does decrypting without it.
+        runtime.memory.set(200, b"record 2").unwrap();
+        let res = runtime.decrypt_with_aad(RuntimeArgs::from(&args(&[400, enc_len, 0, 200, 8, 700])[..])).unwrap();
+        assert_eq!(res, 0);
+        let res = runtime.decrypt_with_status(RuntimeArgs::from(&args(&[400, enc_len, 0, 700])[..])).unwrap();
+        assert_eq!(res, 0);
+    }
+
+    pub fn test_emit_event() {
+        let mut runtime = new_runtime();
+        runtime.memory.set(0, b"rebind").unwrap();
+        runtime.memory.set(100, b"{\"id\":1}").unwrap();
+        runtime.emit_event(RuntimeArgs::from(&args(&[0, 6, 100, 8, 0])[..])).unwrap();
+        runtime.emit_event(RuntimeArgs::from(&args(&[0, 6, 100, 8, 1])[..])).unwrap();
+
+        let costs = RuntimeWasmCosts::default();
+        assert_eq!(runtime.get_used_gas(), 2 * (costs.emit_event + 14 * costs.emit_event_byte));
+
+        let events = runtime.into_result().unwrap().events;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], ContractEvent { topic: b"rebind".to_vec(), data: b"{\"id\":1}".to_vec(), encrypted: false });
+        assert!(events[1].encrypted);
+
+        let key = [3u8; 32];
+        let (serialized, events_hash) = ContractEvent::seal_all(&events, &key).unwrap();
+        let sealed: Vec<ContractEvent> = rmps::from_slice(&serialized).unwrap();
+        assert_eq!(sealed[0], events[0]);
+        assert_eq!(decrypt(&sealed[1].data, &key).unwrap(), events[1].data);
+        assert_eq!(events_hash, ContractEvent::keccak256_all(&sealed));
+        assert_ne!(events_hash, ContractEvent::keccak256_all(&sealed[..1]));
+    }
+
+    pub fn test_revert() {
+        let mut runtime = new_runtime();
+        runtime.memory.set(0, b"not enough funds").unwrap();
+
+        match runtime.revert(RuntimeArgs::from(&args(&[0, 16])[..])) {
+            Err(WasmError::EnclaveError(EnclaveError::FailedTaskError(FailedTaskError::Reverted { payload }))) => {
+                assert_eq!(payload, b"not enough funds".to_vec())
+            }
+            other => panic!("Expected the execution to be reverted, got: {:?}", other),
+        }
+    }
+
+    pub fn test_view() {
+        let mut runtime = new_runtime();
+        runtime.memory.set(0, b"counter").unwrap();
+        runtime.memory.set(100, b"1").unwrap();
+        let write_args = args(&[0, 7, 100, 1]);
+        runtime.write_state(RuntimeArgs::from(&write_args[..]), StateEncoding::Json).unwrap();
+
+        runtime.begin_view();
+        let used_gas = runtime.get_used_gas();
+        for res in vec![runtime.write_state(RuntimeArgs::from(&write_args[..]), StateEncoding::Json),
+                        runtime.remove_from_state(RuntimeArgs::from(&args(&[0, 7])[..]))] {
+            match res {
+                Err(WasmError::EnclaveError(EnclaveError::FailedTaskError(FailedTaskError::StateChangeInView { key }))) => {
+                    assert_eq!(key, "counter")
+                }
+                other => panic!("Expected the state change to be rejected, got: {:?}", other),
+            }
+        }
+        // Reading is still allowed, and nothing was charged for the rejected writes
+        runtime.read_state(RuntimeArgs::from(&args(&[0, 7, 200])[..]), StateEncoding::Json).unwrap();
+        assert_eq!(runtime.memory.get(200, 1).unwrap(), b"1".to_vec());
+        let costs = RuntimeWasmCosts::default();
+        assert_eq!(runtime.get_used_gas(), used_gas + costs.read_byte);
+        assert!(runtime.into_result().unwrap().view);
+
+        // Reading an empty state with another encoding doesn't change its encoding, so the view still has no delta
+        let mut runtime = new_runtime();
+        runtime.memory.set(0, b"counter").unwrap();
+        runtime.begin_view();
+        let len = runtime.read_state_len(RuntimeArgs::from(&args(&[0, 7])[..]), StateEncoding::MsgPack).unwrap();
+        runtime.read_state(RuntimeArgs::from(&args(&[0, 7, 200])[..]), StateEncoding::MsgPack).unwrap();
+        // A missing key is read as the MessagePack nil
+        assert_eq!(runtime.memory.get(200, len as usize).unwrap(), vec![0xc0]);
+        let result = runtime.into_result().unwrap();
+        assert!(result.view);
+        assert!(result.state_delta.is_none());
+        assert_eq!(result.updated_state.encoding, StateEncoding::Json);
+
+        // A contract can't write and only then claim to be a view
+        let mut runtime = new_runtime();
+        runtime.memory.set(0, b"counter").unwrap();
+        runtime.memory.set(100, b"1").unwrap();
+        runtime.write_state(RuntimeArgs::from(&write_args[..]), StateEncoding::Json).unwrap();
+        runtime.begin_view();
+        match runtime.into_result() {
+            Err(EnclaveError::FailedTaskError(FailedTaskError::StateChangeInView { key })) => assert_eq!(key, "counter"),
+            other => panic!("Expected the view to be rejected, got: {:?}", other.map(|res| res.view)),
+        }
+    }
+
+    pub fn test_state_inspectors() {
+        let mut runtime = new_runtime();
+        runtime.memory.set(0, &[1u8; 20]).unwrap();
+        runtime.memory.set(20, &[2u8; 20]).unwrap();
+        runtime.set_state_inspectors(RuntimeArgs::from(&args(&[0, 2])[..])).unwrap();
+        assert!(runtime.get_used_gas() > 0);
+        assert!(runtime.post_execution_state.is_inspector(&[1u8; 20]));
+        assert!(runtime.post_execution_state.is_inspector(&[2u8; 20]));
+        assert!(!runtime.post_execution_state.is_inspector(&[3u8; 20]));
+
+        // The inspectors replace the previous ones, and can't be written by the contract directly
+        runtime.set_state_inspectors(RuntimeArgs::from(&args(&[20, 1])[..])).unwrap();
+        assert!(!runtime.post_execution_state.is_inspector(&[1u8; 20]));
+        assert_eq!(runtime.post_execution_state.json[INSPECTORS_KEY], json!([super::to_hex(&[2u8; 20])]));
+        runtime.memory.set(100, INSPECTORS_KEY.as_bytes()).unwrap();
+        assert!(runtime.remove_from_state(RuntimeArgs::from(&args(&[100, INSPECTORS_KEY.len() as i32])[..])).is_err());
+
+        match runtime.set_state_inspectors(RuntimeArgs::from(&args(&[0, MAX_STATE_INSPECTORS as i32 + 1])[..])) {
+            Err(WasmError::EnclaveError(EnclaveError::FailedTaskError(FailedTaskError::TooManyStateInspectors { count, .. }))) => {
+                assert_eq!(count, MAX_STATE_INSPECTORS + 1)
+            }
+            other => panic!("Expected too many inspectors to be rejected, got: {:?}", other),
+        }
+
+        runtime.set_state_inspectors(RuntimeArgs::from(&args(&[0, 0])[..])).unwrap();
+        assert!(!runtime.post_execution_state.is_inspector(&[2u8; 20]));
+    }
+
+    pub fn test_state_gas() {
+        let mut runtime = new_runtime();
+        let costs = RuntimeWasmCosts::default();
+        runtime.memory.set(0, b"counter").unwrap();
+        runtime.memory.set(100, b"12345").unwrap();
+        runtime.write_state(RuntimeArgs::from(&args(&[0, 7, 100, 5])[..]), StateEncoding::Json).unwrap();
+
+        let len = runtime.read_state_len(RuntimeArgs::from(&args(&[0, 7])[..]), StateEncoding::Json).unwrap();
+        assert_eq!(len, 5);
+        runtime.read_state(RuntimeArgs::from(&args(&[0, 7, 200])[..]), StateEncoding::Json).unwrap();
+        runtime.memory.set(300, &[0u8; 32]).unwrap();
+        runtime.rand(RuntimeArgs::from(&args(&[300, 32])[..])).unwrap();
+
+        // Removing the value refunds the bytes it took in the state
+        runtime.remove_from_state(RuntimeArgs::from(&args(&[0, 7])[..])).unwrap();
+        let charged = runtime.get_used_gas();
+        let breakdown = runtime.into_result().unwrap().gas_breakdown;
+        assert_eq!(breakdown.state_writes, costs.write_value + 5 * costs.write_additional_byte);
+        assert_eq!(breakdown.state_reads, costs.read_value + 5 * costs.read_byte);
+        assert_eq!(breakdown.state_removals, costs.remove_value);
+        assert_eq!(breakdown.crypto, costs.rand + 32 * costs.rand_byte);
+        assert_eq!(breakdown.refund, 5 * costs.write_additional_byte);
+        assert_eq!(breakdown.total(), charged);
+    }
+
+    pub fn test_refund_cap() {
+        let mut runtime = new_runtime();
+        let costs = RuntimeWasmCosts::default();
+        runtime.memory.set(0, b"data").unwrap();
+        // A JSON string of 60 bytes
+        let value: Vec<u8> = [b'"'].iter().chain(&[b'a'; 58]).chain(&[b'"']).cloned().collect();
+        runtime.memory.set(100, &value).unwrap();
+        runtime.write_state(RuntimeArgs::from(&args(&[0, 4, 100, 60])[..]), StateEncoding::Json).unwrap();
+
+        // Removing the value refunds more than a fifth of what was charged, so only a fifth is deducted
+        runtime.remove_from_state(RuntimeArgs::from(&args(&[0, 4])[..])).unwrap();
+        let charged = runtime.get_used_gas();
+        assert_eq!(charged, costs.write_value + 60 * costs.write_additional_byte + costs.remove_value);
+        let result = runtime.into_result().unwrap();
+        assert_eq!(result.gas_breakdown.refund, charged / MAX_REFUND_QUOTIENT);
+        assert_eq!(result.used_gas, charged - charged / MAX_REFUND_QUOTIENT);
+        assert!(result.used_gas > 0);
+    }
+
+    pub fn test_msgpack_state_gas() {
+        // An H256 and a signature the way a contract writes them, byte arrays are where JSON is the most wasteful.
+        let values = [("hash", vec![200u8; 32]), ("sig", vec![255u8; 65])];
+        let used_gas: Vec<u64> = [StateEncoding::Json, StateEncoding::MsgPack].iter().map(|&encoding| {
+            let mut runtime = new_runtime();
+            for (key, value) in &values {
+                let encoded = match encoding {
+                    StateEncoding::Json => serde_json::to_vec(value).unwrap(),
+                    StateEncoding::MsgPack => rmps::to_vec(value).unwrap(),
+                };
+                runtime.memory.set(0, key.as_bytes()).unwrap();
+                runtime.memory.set(100, &encoded).unwrap();
+                let key_len = key.len() as i32;
+                runtime.write_state(RuntimeArgs::from(&args(&[0, key_len, 100, encoded.len() as i32])[..]), encoding).unwrap();
+                let len = runtime.read_state_len(RuntimeArgs::from(&args(&[0, key_len])[..]), encoding).unwrap();
+                assert_eq!(len as usize, encoded.len());
+                runtime.read_state(RuntimeArgs::from(&args(&[0, key_len, 400])[..]), encoding).unwrap();
+            }
+            runtime.into_result().unwrap().used_gas
+        }).collect();
+        assert!(used_gas[1] < used_gas[0], "msgpack: {}, json: {}", used_gas[1], used_gas[0]);
+    }
+
+    pub fn test_execution_profile() {
+        let memory = MemoryInstance::alloc(Pages(2), None).unwrap();
+        let state = ContractState::new(b"enigma".sha256());
+        let context = ExecutionContext { profile: true, ..Default::default() };
+        let mut runtime = Runtime::new(memory, 100_000, Vec::new(), state, String::new(), [1u8; 32], RuntimeWasmCosts::default(), context);
+        runtime.memory.set(0, b"counter").unwrap();
+        runtime.memory.set(100, b"12345").unwrap();
+
+        runtime.invoke_index(ids::GAS_FUNC, RuntimeArgs::from(&args(&[40])[..])).unwrap();
+        runtime.invoke_index(ids::WRITE_STATE_FUNC, RuntimeArgs::from(&args(&[0, 7, 100, 5])[..])).unwrap();
+        runtime.invoke_index(ids::READ_STATE_FUNC, RuntimeArgs::from(&args(&[0, 7, 200])[..])).unwrap();
+        runtime.invoke_index(ids::READ_STATE_FUNC, RuntimeArgs::from(&args(&[0, 7, 200])[..])).unwrap();
+
+        let costs = RuntimeWasmCosts::default();
+        let profile = runtime.into_result().unwrap().profile.unwrap();
+        assert_eq!(profile.instructions_gas, 40);
+        assert_eq!(profile.host_calls, 3);
+        assert_eq!(profile.host_functions[0].name, "write_state");
+        assert_eq!(profile.host_functions[0].gas, costs.write_value + 5 * costs.write_additional_byte);
+        assert_eq!(profile.host_functions[1].name, "read_state");
+        assert_eq!(profile.host_functions[1].calls, 2);
+        assert_eq!(profile.state_bytes_written, 5);
+        assert_eq!(profile.state_bytes_read, 10);
+        assert_eq!(profile.peak_memory_pages, 2);
+
+        // Without profiling nothing is recorded
+        assert!(new_runtime().into_result().unwrap().profile.is_none());
+    }
+
+    pub fn test_upgrade_approval() {
+        let mut runtime = new_runtime();
+        let code_hash = b"new code".keccak256();
+        runtime.memory.set(0, &*code_hash).unwrap();
+        runtime.approve_upgrade(RuntimeArgs::from(&args(&[0])[..])).unwrap();
+        assert!(runtime.get_used_gas() > 0);
+
+        // The approval can't be changed by the contract, and only pins the approved code
+        runtime.memory.set(100, UPGRADE_APPROVAL_KEY.as_bytes()).unwrap();
+        match runtime.remove_from_state(RuntimeArgs::from(&args(&[100, UPGRADE_APPROVAL_KEY.len() as i32])[..])) {
+            Err(WasmError::EnclaveError(EnclaveError::FailedTaskError(FailedTaskError::ReservedStateKey { key }))) => {
+                assert_eq!(key, UPGRADE_APPROVAL_KEY)
+            }
+            other => panic!("Expected the reserved key to be rejected, got: {:?}", other),
+        }
+        match runtime.begin_upgrade(&b"other code".keccak256()) {
+            Err(EnclaveError::FailedTaskError(FailedTaskError::UpgradeNotApproved { .. })) => {}
+            other => panic!("Expected the upgrade to be rejected, got: {:?}", other),
+        }
+
+        // The approval is consumed by the upgrade
+        runtime.begin_upgrade(&code_hash).unwrap();
+        assert!(runtime.begin_upgrade(&code_hash).is_err());
+
+        runtime.result.result = b"deployed code".to_vec();
+        runtime.record_upgrade(&b"old code".keccak256()).unwrap();
+        let state = runtime.into_result().unwrap().updated_state;
+        assert!(state.json[UPGRADE_APPROVAL_KEY].is_null());
+        assert_eq!(state.json[UPGRADE_RECORD_KEY]["previousCodeHash"], json!(super::to_hex(&*b"old code".keccak256())));
+        assert_eq!(state.json[UPGRADE_RECORD_KEY]["codeHash"], json!(super::to_hex(&*b"deployed code".keccak256())));
+    }
+
+    fn any_state_key(_address: ContractAddress) -> Result<StateKey, EnclaveError> { Ok([2u8; 32]) }
+
+    pub fn test_call_contract_limits() {
+        let mut runtime = new_runtime();
+        let address = runtime.post_execution_state.contract_address;
+        let callee = ContractAddress::from([7u8; 32]);
+        runtime.memory.set(0, &*address).unwrap();
+        runtime.memory.set(32, &*callee).unwrap();
+        runtime.memory.set(64, b"get()").unwrap();
+        let call_self = args(&[0, 64, 5, 0, 0]);
+        let call_callee = args(&[32, 64, 5, 0, 0]);
+        let assert_call_error = |res: Result<i32, WasmError>, expected: &str| match res {
+            Err(WasmError::EnclaveError(EnclaveError::FailedTaskError(FailedTaskError::ContractCallError { message }))) => {
+                assert!(message.contains(expected), "{}", message)
+            }
+            other => panic!("Expected the call to be rejected, got: {:?}", other),
+        };
+
+        // Deployments can't call other contracts
+        assert_call_error(runtime.call_contract(RuntimeArgs::from(&call_callee[..])), "compute tasks");
+
+        // A running contract can't be called again
+        runtime.enable_calls(CallEnvironment::new(std::ptr::null(), any_state_key, address));
+        assert_call_error(runtime.call_contract(RuntimeArgs::from(&call_self[..])), "already running");
+
+        let mut calls = CallEnvironment::new(std::ptr::null(), any_state_key, address);
+        calls.stack.resize(MAX_CALL_DEPTH, ContractAddress::from([9u8; 32]));
+        runtime.enable_calls(calls);
+        assert_call_error(runtime.call_contract(RuntimeArgs::from(&call_callee[..])), "call depth");
+        assert_eq!(runtime.get_used_gas(), 0);
+
+        // The Ethereum payload of a callee isn't overwritten by the caller
+        runtime.memory.set(100, &[1u8; 20]).unwrap();
+        let write_bridge = args(&[100, 4, 100]);
+        runtime.write_eth_bridge(RuntimeArgs::from(&write_bridge[..])).unwrap();
+        runtime.callee_bridge = true;
+        match runtime.write_eth_bridge(RuntimeArgs::from(&write_bridge[..])) {
+            Err(WasmError::EnclaveError(EnclaveError::FailedTaskError(FailedTaskError::ContractCallError { .. }))) => (),
+            other => panic!("Expected the write to be rejected, got: {:?}", other),
+        }
+
+        // Only the callees whose state changed have a delta
+        let state = ContractState::new(callee);
+        let mut changed = state.clone();
+        changed.write_key("balance", &json!(10)).unwrap();
+        let code_hash = b"callee code".keccak256();
+        let unchanged = CalleeState { pre_execution_state: state.clone(), post_execution_state: state.clone(), key: [2u8; 32], code_hash };
+        assert!(unchanged.into_result().unwrap().is_none());
+        let callee_result = CalleeState { pre_execution_state: state.clone(), post_execution_state: changed, key: [2u8; 32], code_hash }
+            .into_result()
+            .unwrap()
+            .unwrap();
+        assert_eq!(callee_result.state_delta.contract_address, callee);
+        assert_eq!((callee_result.code_hash, callee_result.pre_delta_hash), (code_hash, state.delta_hash));
+        assert_eq!(callee_result.updated_state.delta_hash, callee_result.state_delta.keccak256_patch());
+    }
+}
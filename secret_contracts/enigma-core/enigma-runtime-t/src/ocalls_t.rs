@@ -21,6 +21,11 @@ extern "C" {
     fn ocall_get_state_size(retval: *mut EnclaveReturn, db_ptr: *const RawPointer, addr: &ContractAddress, state_len: *mut usize) -> sgx_status_t;
     fn ocall_get_state(retval: *mut EnclaveReturn, db_ptr: *const RawPointer, addr: &ContractAddress, state_ptr: *mut u8, state_len: usize) -> sgx_status_t;
     fn ocall_update_state(retval: *mut EnclaveReturn, db_ptr: *const RawPointer, id: &ContractAddress, enc_delta: *const u8, delta_len: usize) -> sgx_status_t;
+    fn ocall_save_snapshot(retval: *mut EnclaveReturn, db_ptr: *const RawPointer, id: &ContractAddress, delta_index: *const u32,
+                           enc_state: *const u8, state_len: usize) -> sgx_status_t;
+
+    fn ocall_get_contract_size(retval: *mut EnclaveReturn, db_ptr: *const RawPointer, addr: &ContractAddress, code_len: *mut usize) -> sgx_status_t;
+    fn ocall_get_contract(retval: *mut EnclaveReturn, db_ptr: *const RawPointer, addr: &ContractAddress, code_ptr: *mut u8, code_len: usize) -> sgx_status_t;
 }
 
 pub unsafe fn save_state(db_ptr: *const RawPointer, enc: &EncryptedContractState<u8>) -> Result<(), EnclaveError> {
@@ -36,6 +41,21 @@ pub unsafe fn save_state(db_ptr: *const RawPointer, enc: &EncryptedContractState
     }
 }
 
+/// Saves the state as a snapshot that includes all the deltas up to `delta_index`, the untrusted side can then prune them.
+pub unsafe fn save_snapshot(db_ptr: *const RawPointer, enc: &EncryptedContractState<u8>, delta_index: u32) -> Result<(), EnclaveError> {
+    let mut retval = EnclaveReturn::default();
+    let res_status: sgx_status_t =
+        ocall_save_snapshot(&mut retval, db_ptr, &enc.contract_address, &delta_index as *const u32, enc.json.as_c_ptr(), enc.json.len());
+    match retval {
+        EnclaveReturn::Success => (), // 0 is the OK result
+        _ => return Err(SystemError(OcallError { command: "ocall_save_snapshot".to_string(), err: format!("return result is: {}", &retval) })),
+    }
+    match res_status {
+        sgx_status_t::SGX_SUCCESS => Ok(()),
+        _ => Err(SystemError(OcallError { command: "ocall_save_snapshot".to_string(), err: res_status.__description().to_string() })),
+    }
+}
+
 pub fn save_delta(db_ptr: *const RawPointer, enc: &EncryptedPatch) -> Result<(), EnclaveError> {
     let mut res = EnclaveReturn::default();
     let res_status =
@@ -96,6 +116,29 @@ pub fn get_state(db_ptr: *const RawPointer, contract_address: ContractAddress) -
     Ok(EncryptedContractState { contract_address, json: state })
 }
 
+/// Get the bytecode of a deployed contract.
+pub fn get_contract(db_ptr: *const RawPointer, contract_address: ContractAddress) -> Result<Vec<u8>, EnclaveError> {
+    let mut retval = EnclaveReturn::default();
+    let mut code_len = 0usize;
+    let status = unsafe { ocall_get_contract_size(&mut retval, db_ptr, &contract_address, &mut code_len) };
+    if retval != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        return Err(SystemError(OcallError {
+            command: "get_contract_size".to_string(),
+            err: format!("Error with SGX, retval: {}, status: {:?}", retval, status),
+        }));
+    }
+    let mut code = vec![0u8; code_len];
+    let status = unsafe { ocall_get_contract(&mut retval, db_ptr, &contract_address, code.as_mut_ptr(), code_len) };
+    if retval != EnclaveReturn::Success || status != sgx_status_t::SGX_SUCCESS {
+        return Err(SystemError(OcallError {
+            command: "get_contract".to_string(),
+            err: format!("Error with SGX, retval: {}, status: {:?}", retval, status),
+        }));
+    }
+
+    Ok(code)
+}
+
 pub fn get_deltas(db_ptr: *const RawPointer, contract_address: ContractAddress, start: u32, end: u32) -> Result<Vec<EncryptedPatch>, EnclaveError> {
     let len = (end - start) as usize;
     let mut deltas_buff = vec![0usize; len];
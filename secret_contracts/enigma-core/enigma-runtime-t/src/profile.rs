@@ -0,0 +1,70 @@
+//! Profiling of the gas and resources used by an execution.
+//!
+//! Profiling is enabled per task through `TaskContext::profile`, it doesn't change the gas charged
+//! or the result of the task. The gas of the contracts called with `call_contract` is accounted to `call_contract`.
+//! The profile tells which paths the contract took with the inputs of the user, so it's encrypted with the user key like the output.
+use crate::eng_resolver;
+use enigma_crypto::symmetric::encrypt;
+use enigma_tools_t::common::errors_t::EnclaveError;
+use enigma_types::DhKey;
+use serde::{Deserialize, Serialize};
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+/// The number of calls to a host function and the gas they were charged.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostFunctionProfile {
+    pub name: String,
+    pub calls: u64,
+    pub gas: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionProfile {
+    /// The gas charged for the wasm instructions, through the injected `gas` calls.
+    pub instructions_gas: u64,
+    /// The host functions called by the contract, in the order of their first call.
+    pub host_functions: Vec<HostFunctionProfile>,
+    /// The number of calls to all the host functions, not including `gas`.
+    pub host_calls: u64,
+    /// The bytes of serialized values read from the state.
+    pub state_bytes_read: u64,
+    /// The bytes of serialized values written to the state.
+    pub state_bytes_written: u64,
+    /// The largest size of the memory, in 64KB wasm pages.
+    pub peak_memory_pages: u32,
+}
+
+impl ExecutionProfile {
+    /// Record a call to the host function with `index` that was charged `gas`.
+    pub fn record_host_call(&mut self, index: usize, gas: u64) {
+        if index == eng_resolver::ids::GAS_FUNC {
+            self.instructions_gas = self.instructions_gas.saturating_add(gas);
+            return;
+        }
+        self.host_calls += 1;
+        let name = eng_resolver::ids::name(index);
+        match self.host_functions.iter_mut().find(|function| function.name == name) {
+            Some(function) => {
+                function.calls += 1;
+                function.gas = function.gas.saturating_add(gas);
+            }
+            None => self.host_functions.push(HostFunctionProfile { name: name.to_string(), calls: 1, gas }),
+        }
+    }
+
+    pub fn record_memory(&mut self, pages: u32) {
+        if pages > self.peak_memory_pages {
+            self.peak_memory_pages = pages;
+        }
+    }
+
+    /// Serialize and encrypt the profile with the task's user key to be returned from the enclave,
+    /// it's empty if profiling wasn't enabled.
+    pub fn seal(profile: &Option<ExecutionProfile>, key: &DhKey) -> Result<Vec<u8>, EnclaveError> {
+        match profile {
+            Some(profile) => Ok(encrypt(&rmps::to_vec(profile)?, key)?),
+            None => Ok(Vec::new()),
+        }
+    }
+}
@@ -6,6 +6,7 @@ use sgx_types::sgx_status_t;
 use enigma_crypto::CryptoError;
 use std::str;
 use std::string::{String, ToString};
+use std::vec::Vec;
 use wasmi::{self, TrapKind};
 use parity_wasm;
 
@@ -141,6 +142,25 @@ pub enum FailedTaskError {
 
     #[fail(display = "Invocation resulted in gas limit violated")]
     GasLimitError,
+
+    /// The contract aborted the execution through `revert`, the payload is returned to the user as the output.
+    #[fail(display = "The execution was reverted by the contract")]
+    Reverted { payload: Vec<u8> },
+
+    #[fail(display = "A view function can't change the state, tried to change the key: {}", key)]
+    StateChangeInView { key: String },
+
+    #[fail(display = "The contract did not approve an upgrade to the code with hash: {}", code_hash)]
+    UpgradeNotApproved { code_hash: String },
+
+    #[fail(display = "The state key is reserved for the runtime: {}", key)]
+    ReservedStateKey { key: String },
+
+    #[fail(display = "Error in a call to another contract: {}", message)]
+    ContractCallError { message: String },
+
+    #[fail(display = "A contract can have at most {} state inspectors, got: {}", max, count)]
+    TooManyStateInspectors { max: u32, count: u32 },
 }
 
 #[derive(Debug, Fail, Clone)]
@@ -168,6 +188,9 @@ pub enum EnclaveSystemError {
 
     #[fail(display = "Failed to provide state key: {}", err)]
     KeyProvisionError { err: String },
+
+    #[fail(display = "The contract doesn't allow {} to inspect its state", address)]
+    InspectionNotAllowed { address: String },
 }
 
 impl From<CryptoError> for EnclaveError {
@@ -228,7 +251,7 @@ impl Into<EnclaveReturn> for EnclaveError {
                 use self::EnclaveSystemError::*;
                 use self::CryptoError::*;
                 match e {
-                    PermissionError { .. } => EnclaveReturn::PermissionError,
+                    PermissionError { .. } | InspectionNotAllowed { .. } => EnclaveReturn::PermissionError,
                     SgxError { .. } => EnclaveReturn::SgxError,
                     StateError { .. } => EnclaveReturn::StateError,
                     OcallError { .. } => EnclaveReturn::OcallError,
@@ -94,12 +94,37 @@ pub struct ExecuteResult {
     pub ethereum_payload_ptr: *const u8,
     /// The ethereum address that the payload belongs to.
     pub ethereum_address: [u8; 20],
+    /// A pointer to the serialized events emitted by the contract using [`ocall_save_to_memory`](../replace_me) (on the untrusted stack)
+    pub events_ptr: *const u8,
+    /// A pointer to the serialized deltas of the other contracts called by the contract using [`ocall_save_to_memory`](../replace_me) (on the untrusted stack)
+    pub callee_deltas_ptr: *const u8,
+    /// A pointer to the serialized execution profile, if the task asked for one, using [`ocall_save_to_memory`](../replace_me) (on the untrusted stack)
+    pub profile_ptr: *const u8,
+    /// True if the task failed because the contract reverted it, then `output` holds the error payload returned by the contract.
+    pub reverted: bool,
+    /// True if the executed function is a view, then the execution didn't produce a delta.
+    pub view: bool,
     /// A signature by the enclave on all of the results.
     pub signature: [u8; 65],
     /// The gas used by the execution.
     pub used_gas: u64,
 }
 
+/// This struct holds the context of a Deploy/Compute task that isn't part of its encrypted inputs,
+/// it's passed into the enclave so the secret contract can read it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TaskContext {
+    /// The Ethereum block number the task was submitted in.
+    pub block_number: u64,
+    /// The id of the task, all zeros if it wasn't given.
+    pub task_id: Hash256,
+    /// The task id was given, otherwise the contract gets none.
+    pub has_task_id: bool,
+    /// Return an execution profile with the result, it isn't exposed to the contract.
+    pub profile: bool,
+}
+
 /// This struct is a wrapper to a raw pointer.
 /// when you pass a pointer through the SGX bridge(EDL) the SGX Edger8r will copy the data that it's pointing to
 /// using `memalloc` and `memset` to the other side of the bridge, then it changes the pointer to point to the new data.
@@ -182,6 +207,9 @@ impl Default for ExecuteResult {
             output: ptr::null(),
             delta_ptr: ptr::null(),
             ethereum_payload_ptr: ptr::null(),
+            events_ptr: ptr::null(),
+            callee_deltas_ptr: ptr::null(),
+            profile_ptr: ptr::null(),
             .. unsafe { mem::zeroed() }
         }
     }
@@ -195,6 +223,11 @@ impl fmt::Debug for ExecuteResult {
         debug_trait_builder.field("delta_index", &(self.delta_index));
         debug_trait_builder.field("ethereum_payload_ptr", &(self.ethereum_payload_ptr));
         debug_trait_builder.field("ethereum_address", &(self.ethereum_address));
+        debug_trait_builder.field("events_ptr", &(self.events_ptr));
+        debug_trait_builder.field("callee_deltas_ptr", &(self.callee_deltas_ptr));
+        debug_trait_builder.field("profile_ptr", &(self.profile_ptr));
+        debug_trait_builder.field("reverted", &(self.reverted));
+        debug_trait_builder.field("view", &(self.view));
         debug_trait_builder.field("signature", &(&self.signature[..]));
         debug_trait_builder.field("used_gas", &(self.used_gas));
         debug_trait_builder.finish()
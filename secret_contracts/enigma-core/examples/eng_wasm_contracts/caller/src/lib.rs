@@ -0,0 +1,29 @@
+#![no_std]
+
+extern crate eng_wasm;
+extern crate eng_wasm_derive;
+
+use eng_wasm::*;
+use eng_wasm::eng_pwasm_abi::eth::Stream;
+use eng_wasm_derive::pub_interface;
+
+static CALLS: &str = "calls";
+
+pub struct Contract;
+
+/// Calls the `upgradeable` counter contract, whose changes are committed with the ones of this contract.
+#[pub_interface]
+impl Contract {
+    pub fn construct() {
+        write_state!(CALLS => 0u64);
+    }
+
+    /// Increment the counter at `counter` twice and return its new value.
+    pub fn increment_twice(counter: H256) -> u64 {
+        call_contract(&counter, "increment()", &[]);
+        let output = call_contract(&counter, "increment()", &[]);
+        let calls: u64 = read_state!(CALLS).unwrap_or_default();
+        write_state!(CALLS => calls + 2);
+        Stream::new(&output).pop::<u64>().expect("the counter returned a bad value")
+    }
+}
@@ -0,0 +1,43 @@
+#![no_std]
+
+extern crate eng_wasm;
+extern crate eng_wasm_derive;
+
+use eng_wasm::*;
+use eng_wasm_derive::{pub_interface, EngAbi};
+
+#[derive(EngAbi)]
+pub struct Point {
+    x: u64,
+    y: u64,
+}
+
+#[derive(EngAbi)]
+pub enum Shape {
+    Empty,
+    Circle { center: Point, radius: u64 },
+    Polygon(Vec<Point>, Option<String>),
+}
+
+#[pub_interface]
+pub trait ContractInterface {
+    fn construct();
+    fn circle(x: u64, y: u64, radius: u64) -> Shape;
+    fn count_points(shape: Shape) -> u64;
+}
+
+pub struct Contract;
+
+impl ContractInterface for Contract {
+    fn construct() {}
+
+    fn circle(x: u64, y: u64, radius: u64) -> Shape { Shape::Circle { center: Point { x, y }, radius } }
+
+    fn count_points(shape: Shape) -> u64 {
+        match shape {
+            Shape::Empty => 0,
+            Shape::Circle { .. } => 1,
+            Shape::Polygon(points, _) => points.len() as u64,
+        }
+    }
+}
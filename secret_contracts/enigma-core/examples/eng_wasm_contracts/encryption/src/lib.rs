@@ -38,6 +38,7 @@ static ENCRYPTION_KEY: &str = "encryption_key";
 pub trait ContractInterface {
     fn construct();
     fn encrypt_decrypt(plaintext_msg: Vec<u8>);
+    fn encrypt_decrypt_with_aad(plaintext_msg: Vec<u8>, record_id: Vec<u8>);
 }
 
 // The implementation of the exported ESC functions should be defined in the trait implementation
@@ -88,4 +89,18 @@ impl ContractInterface for Contract {
             panic!("Mismatching message after decryption");
         }
     }
+
+    #[no_mangle]
+    fn encrypt_decrypt_with_aad(plaintext_msg: Vec<u8>, record_id: Vec<u8>) {
+        // Every record is encrypted with its own key, bound to the record id.
+        let key = derive_key(&Self::get_pkey(), b"records", &record_id);
+        let enc_msg = encrypt_with_aad(&plaintext_msg, &key, &record_id).unwrap();
+        let msg = decrypt_with_aad(&enc_msg, &key, &record_id).unwrap();
+        if plaintext_msg != msg {
+            panic!("Mismatching message after decryption");
+        }
+        if decrypt_with_aad(&enc_msg, &key, b"another record") != Err(CryptoError::DecryptionError) {
+            panic!("Decrypted with mismatching associated data");
+        }
+    }
 }
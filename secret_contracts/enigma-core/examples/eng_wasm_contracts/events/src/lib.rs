@@ -0,0 +1,24 @@
+#![no_std]
+
+extern crate eng_wasm;
+extern crate eng_wasm_derive;
+
+use eng_wasm::*;
+use eng_wasm_derive::pub_interface;
+
+#[pub_interface]
+pub trait ContractInterface {
+    fn construct();
+    fn transfer(amount: U256);
+}
+
+pub struct Contract;
+
+impl ContractInterface for Contract {
+    fn construct() {}
+
+    fn transfer(amount: U256) {
+        emit_event!("Transfer", amount.as_u64());
+        emit_encrypted_event!("Receipt", "confidential");
+    }
+}
@@ -0,0 +1,39 @@
+#![no_std]
+
+extern crate eng_wasm;
+extern crate eng_wasm_derive;
+
+use eng_wasm::*;
+use eng_wasm_derive::pub_interface;
+
+static BALANCE: &str = "balance";
+
+#[pub_interface]
+pub trait ContractInterface {
+    fn construct(balance: U256);
+    fn withdraw(amount: U256) -> Result<U256, String>;
+    fn balance() -> U256;
+}
+
+pub struct Contract;
+
+impl ContractInterface for Contract {
+    fn construct(balance: U256) {
+        write_state!(BALANCE => balance);
+    }
+
+    /// Writes the new balance before checking it, so a revert has to discard the write.
+    fn withdraw(amount: U256) -> Result<U256, String> {
+        let balance: U256 = read_state!(BALANCE).unwrap_or_default();
+        let new_balance = balance.overflowing_sub(amount).0;
+        write_state!(BALANCE => new_balance);
+        if amount > balance {
+            return Err(String::from("insufficient balance"));
+        }
+        Ok(new_balance)
+    }
+
+    fn balance() -> U256 {
+        read_state!(BALANCE).unwrap_or_default()
+    }
+}
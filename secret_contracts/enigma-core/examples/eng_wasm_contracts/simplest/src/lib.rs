@@ -14,6 +14,7 @@ pub trait ContractInterface{
     fn choose_rand_color() -> Vec<u8>;
     fn get_scrambled_vec();
     fn addition(x: U256, y: U256) -> U256;
+    #[view]
     fn get_last_sum() -> U256;
     fn print_test(x: U256, y: U256);
     fn dynamic_types(bytes_arr: Vec<Vec<u8>>, string_arr: Vec<String>, fixed_arr: Vec<H256>);
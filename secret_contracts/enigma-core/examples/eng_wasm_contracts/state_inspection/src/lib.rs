@@ -0,0 +1,37 @@
+#![no_std]
+
+extern crate eng_wasm;
+extern crate eng_wasm_derive;
+
+use eng_wasm::*;
+use eng_wasm_derive::pub_interface;
+
+static OWNER: &str = "owner";
+static VALUE: &str = "value";
+
+#[pub_interface]
+pub trait ContractInterface {
+    /// The user who deploys the contract owns it.
+    fn construct(value: U256);
+    /// Allows `inspector` to read the decrypted state with `InspectState`, only the owner can call it.
+    fn allow_inspection(inspector: H160) -> Result<(), String>;
+}
+
+pub struct Contract;
+
+impl ContractInterface for Contract {
+    fn construct(value: U256) {
+        write_state!(OWNER => TaskContext::user_pubkey().to_vec(), VALUE => value.as_u64());
+    }
+
+    /// The arguments of a task are encrypted with a key derived from the user key of the task,
+    /// so only the holder of the private key of the owner can send a task with the key of the owner.
+    fn allow_inspection(inspector: H160) -> Result<(), String> {
+        let owner: Vec<u8> = read_state!(OWNER).unwrap_or_default();
+        if owner[..] != TaskContext::user_pubkey()[..] {
+            return Err(String::from("only the owner can allow inspecting the state"));
+        }
+        set_state_inspectors(&[inspector]);
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+#![no_std]
+
+extern crate eng_wasm;
+extern crate eng_wasm_derive;
+
+use eng_wasm::*;
+use eng_wasm_derive::pub_interface;
+
+#[pub_interface]
+pub trait ContractInterface {
+    fn construct();
+    fn block_number() -> U256;
+    fn contract_address() -> H256;
+    fn task_id() -> Result<H256, String>;
+    fn user_pubkey() -> Vec<u8>;
+}
+
+pub struct Contract;
+
+impl ContractInterface for Contract {
+    fn construct() {}
+
+    fn block_number() -> U256 { TaskContext::block_number().into() }
+
+    fn contract_address() -> H256 { TaskContext::contract_address() }
+
+    fn task_id() -> Result<H256, String> { TaskContext::task_id().ok_or_else(|| String::from("the task has no id")) }
+
+    fn user_pubkey() -> Vec<u8> { TaskContext::user_pubkey().to_vec() }
+}
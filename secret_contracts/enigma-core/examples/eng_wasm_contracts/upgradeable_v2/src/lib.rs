@@ -0,0 +1,51 @@
+#![no_std]
+
+extern crate eng_wasm;
+extern crate eng_wasm_derive;
+
+use eng_wasm::*;
+use eng_wasm_derive::pub_interface;
+
+static ADMIN_KEY: &str = "admin_key";
+static COUNTER: &str = "counter";
+static VERSION: &str = "version";
+
+pub struct Contract;
+
+/// The second version of `upgradeable`, which counts in steps of 2.
+#[pub_interface]
+impl Contract {
+    pub fn construct(admin_key: String) {
+        write_state!(ADMIN_KEY => admin_key, COUNTER => 0u64, VERSION => 2u32);
+    }
+
+    /// Runs once when `upgradeable` is upgraded to this code, the state of the contract is kept.
+    pub fn migrate() {
+        write_state!(VERSION => 2u32);
+    }
+
+    pub fn increment() -> u64 {
+        let counter = Self::counter() + 2;
+        write_state!(COUNTER => counter);
+        counter
+    }
+
+    pub fn counter() -> u64 {
+        read_state!(COUNTER).unwrap_or_default()
+    }
+
+    pub fn version() -> u32 {
+        read_state!(VERSION).unwrap_or(1)
+    }
+
+    /// Allow the admin to upgrade the contract to the code with `new_code_hash`.
+    #[guard(Self::is_admin(admin_key))]
+    pub fn upgrade(new_code_hash: H256, admin_key: String) {
+        approve_upgrade(&new_code_hash);
+    }
+
+    fn is_admin(admin_key: &String) -> bool {
+        let stored: Option<String> = read_state!(ADMIN_KEY);
+        stored.as_ref() == Some(admin_key)
+    }
+}